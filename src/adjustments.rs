@@ -0,0 +1,1283 @@
+//! Adjustments to the colors of an image. Tonal adjustments (like levels and
+//! curves) map every value of each color channel to a new value, so they're
+//! worked out once per value as lookup tables and then applied to every pixel.
+//! Color adjustments (like hue/saturation and the channel mixer) work on whole
+//! colors, so they're worked out pixel by pixel
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color_mode::{luma, ColorMode},
+    pixel_buffer::PixelBuffer,
+};
+
+/// A lookup table for each of the red, green, and blue channels, giving the
+/// new value for every old value
+pub type Tables = [[u8; 256]; 3];
+
+/// The lookup tables that leave every value alone
+const IDENTITY: Tables = {
+    let mut table = [0; 256];
+    let mut value = 0;
+    while value < 256 {
+        table[value] = value as u8;
+        value += 1;
+    }
+    [table; 3]
+};
+
+/// Which color channels part of an adjustment applies to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Channel {
+    /// Red, green, and blue alike
+    #[default]
+    Rgb,
+
+    /// Just red
+    Red,
+
+    /// Just green
+    Green,
+
+    /// Just blue
+    Blue,
+}
+
+impl Channel {
+    /// Every channel, in the order they should be shown in the UI
+    pub const ALL: [Channel; 4] = [Self::Rgb, Self::Red, Self::Green, Self::Blue];
+
+    /// The index of the channel's table in [`Tables`] (None for all of them)
+    fn index(self) -> Option<usize> {
+        match self {
+            Self::Rgb => None,
+            Self::Red => Some(0),
+            Self::Green => Some(1),
+            Self::Blue => Some(2),
+        }
+    }
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Rgb => "RGB",
+                Self::Red => "Red",
+                Self::Green => "Green",
+                Self::Blue => "Blue",
+            }
+        )
+    }
+}
+
+/// Something that applies to all the channels together, then to each one
+/// separately (like the levels or curve of each channel)
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PerChannel<T> {
+    /// Applied to red, green, and blue alike, after each channel's own
+    pub rgb: T,
+
+    /// Applied to just the red channel
+    pub red: T,
+
+    /// Applied to just the green channel
+    pub green: T,
+
+    /// Applied to just the blue channel
+    pub blue: T,
+}
+
+impl<T> PerChannel<T> {
+    /// The part that applies to a channel
+    pub fn get(&self, channel: Channel) -> &T {
+        match channel {
+            Channel::Rgb => &self.rgb,
+            Channel::Red => &self.red,
+            Channel::Green => &self.green,
+            Channel::Blue => &self.blue,
+        }
+    }
+
+    /// The part that applies to a channel
+    pub fn get_mut(&mut self, channel: Channel) -> &mut T {
+        match channel {
+            Channel::Rgb => &mut self.rgb,
+            Channel::Red => &mut self.red,
+            Channel::Green => &mut self.green,
+            Channel::Blue => &mut self.blue,
+        }
+    }
+
+    /// Builds lookup tables from a function mapping each value of a channel
+    /// (from 0 to 1) using the part that applies to it
+    fn map_tables(&self, map: impl Fn(&T, f32) -> f32) -> Tables {
+        let mut tables = IDENTITY;
+        for channel in [Channel::Red, Channel::Green, Channel::Blue] {
+            let table = &mut tables[channel.index().expect("not every channel")];
+            for entry in table.iter_mut() {
+                let value = map(self.get(channel), *entry as f32 / 255.0);
+                let value = map(&self.rgb, value.clamp(0.0, 1.0));
+                *entry = to_u8(value);
+            }
+        }
+        tables
+    }
+}
+
+/// Converts a channel value from 0 to 1 back to a byte
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Moves a value (from 0 to 1) towards 0 (for negative amounts) or 1 (for
+/// positive ones), all the way there for an amount of -1 or 1
+fn lighten(value: f32, amount: f32) -> f32 {
+    let amount = amount.clamp(-1.0, 1.0);
+    if amount < 0.0 {
+        value * (1.0 + amount)
+    } else {
+        value + (1.0 - value) * amount
+    }
+}
+
+/// Converts a color (with channels from 0 to 1) to its hue (in degrees, from
+/// 0 to 360), saturation, and lightness (from 0 to 1)
+///
+/// See https://en.wikipedia.org/wiki/HSL_and_HSV#From_RGB
+fn rgb_to_hsl([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+    let lightness = (max + min) / 2.0;
+    if chroma == 0.0 {
+        return [0.0, 0.0, lightness];
+    }
+
+    let hue = if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+    [hue * 60.0, saturation.clamp(0.0, 1.0), lightness]
+}
+
+/// Converts a hue (in degrees), saturation, and lightness (from 0 to 1) back to
+/// a color (with channels from 0 to 1)
+///
+/// See https://en.wikipedia.org/wiki/HSL_and_HSV#HSL_to_RGB
+fn hsl_to_rgb([hue, saturation, lightness]: [f32; 3]) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let [r, g, b] = match sector as u8 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    let min = lightness - chroma / 2.0;
+    [r + min, g + min, b + min]
+}
+
+/// Brightens or darkens, and increases or decreases contrast
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrightnessContrast {
+    /// From -1 (black) to 1 (white), 0 leaving the image alone
+    pub brightness: f32,
+
+    /// From -1 (flat gray) to 1 (only black and white), 0 leaving the image
+    /// alone
+    pub contrast: f32,
+}
+
+impl BrightnessContrast {
+    /// Maps a channel value (from 0 to 1)
+    fn map(&self, value: f32) -> f32 {
+        let value = lighten(value, self.brightness);
+
+        // Contrast changes the slope around middle gray, from flat (0) through
+        // unchanged (1) to vertical (infinite)
+        let slope = ((self.contrast.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4).tan();
+        (value - 0.5) * slope + 0.5
+    }
+
+    /// The lookup tables for the adjustment
+    pub fn tables(&self) -> Tables {
+        let mut tables = IDENTITY;
+        for table in &mut tables {
+            for entry in table.iter_mut() {
+                *entry = to_u8(self.map(*entry as f32 / 255.0));
+            }
+        }
+        tables
+    }
+}
+
+/// Stretches (or squashes) a range of values, and brightens or darkens the
+/// midtones, for a single channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelLevels {
+    /// Values this dark (or darker) become `output_black`
+    pub input_black: u8,
+
+    /// Values this light (or lighter) become `output_white`
+    pub input_white: u8,
+
+    /// Brightens (above 1) or darkens (below 1) the values in between
+    pub gamma: f32,
+
+    /// The darkest value to end up with
+    pub output_black: u8,
+
+    /// The lightest value to end up with
+    pub output_white: u8,
+}
+
+impl Default for ChannelLevels {
+    fn default() -> Self {
+        Self {
+            input_black: 0,
+            input_white: 255,
+            gamma: 1.0,
+            output_black: 0,
+            output_white: 255,
+        }
+    }
+}
+
+impl ChannelLevels {
+    /// The smallest and largest gamma that can be used
+    pub const GAMMA_RANGE: std::ops::RangeInclusive<f32> = 0.1..=10.0;
+
+    /// Maps a channel value (from 0 to 1)
+    fn map(&self, value: f32) -> f32 {
+        let input_black = self.input_black as f32 / 255.0;
+        let input_white =
+            (self.input_white.max(self.input_black) as f32 / 255.0).max(input_black + f32::EPSILON);
+        let output_black = self.output_black as f32 / 255.0;
+        let output_white = self.output_white as f32 / 255.0;
+
+        let value = ((value - input_black) / (input_white - input_black)).clamp(0.0, 1.0);
+        let gamma = self
+            .gamma
+            .clamp(*Self::GAMMA_RANGE.start(), *Self::GAMMA_RANGE.end());
+        output_black + value.powf(1.0 / gamma) * (output_white - output_black)
+    }
+}
+
+/// The levels of each channel
+pub type Levels = PerChannel<ChannelLevels>;
+
+impl Levels {
+    /// The lookup tables for the adjustment
+    pub fn tables(&self) -> Tables {
+        self.map_tables(ChannelLevels::map)
+    }
+}
+
+/// A smooth curve through points, mapping each value of a channel to a new
+/// one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Curve {
+    /// The points the curve goes through (input value, output value), in
+    /// order of their input values. There are always at least two
+    pub points: Vec<[u8; 2]>,
+}
+
+impl Default for Curve {
+    /// A straight line, leaving every value alone
+    fn default() -> Self {
+        Self {
+            points: vec![[0, 0], [255, 255]],
+        }
+    }
+}
+
+impl Curve {
+    /// Works out the value of the curve at every input value. The curve is a
+    /// monotone cubic spline, so it doesn't overshoot between points (values
+    /// before the first point and after the last one stay level)
+    ///
+    /// See https://en.wikipedia.org/wiki/Monotone_cubic_interpolation
+    pub fn values(&self) -> [f32; 256] {
+        let mut points: Vec<[f32; 2]> = self
+            .points
+            .iter()
+            .map(|&[x, y]| [x as f32, y as f32 / 255.0])
+            .collect();
+        points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+        points.dedup_by(|a, b| a[0] == b[0]);
+
+        let mut values = [0.0; 256];
+        match points.as_slice() {
+            [] => return values.map(|_| 0.0),
+            [[_, y]] => return values.map(|_| *y),
+            _ => {}
+        }
+
+        // The slopes between each pair of points, and the tangents at each
+        // point (limited so the curve doesn't overshoot)
+        let secants: Vec<f32> = points
+            .windows(2)
+            .map(|pair| (pair[1][1] - pair[0][1]) / (pair[1][0] - pair[0][0]))
+            .collect();
+        let mut tangents: Vec<f32> = (0..points.len())
+            .map(|index| match index {
+                0 => secants[0],
+                _ if index == points.len() - 1 => secants[index - 1],
+                _ if secants[index - 1] * secants[index] <= 0.0 => 0.0,
+                _ => (secants[index - 1] + secants[index]) / 2.0,
+            })
+            .collect();
+        for (index, secant) in secants.iter().enumerate() {
+            if *secant == 0.0 {
+                tangents[index] = 0.0;
+                tangents[index + 1] = 0.0;
+                continue;
+            }
+            let a = tangents[index] / secant;
+            let b = tangents[index + 1] / secant;
+            let magnitude = a * a + b * b;
+            if magnitude > 9.0 {
+                let scale = 3.0 / magnitude.sqrt();
+                tangents[index] = scale * a * secant;
+                tangents[index + 1] = scale * b * secant;
+            }
+        }
+
+        let first = points[0];
+        let last = points[points.len() - 1];
+        for (x, value) in values.iter_mut().enumerate() {
+            let x = x as f32;
+            *value = if x <= first[0] {
+                first[1]
+            } else if x >= last[0] {
+                last[1]
+            } else {
+                let index = points.partition_point(|point| point[0] <= x) - 1;
+                let ([x0, y0], [x1, y1]) = (points[index], points[index + 1]);
+                let width = x1 - x0;
+                let t = (x - x0) / width;
+                let (t2, t3) = (t * t, t * t * t);
+                (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+                    + (t3 - 2.0 * t2 + t) * width * tangents[index]
+                    + (-2.0 * t3 + 3.0 * t2) * y1
+                    + (t3 - t2) * width * tangents[index + 1]
+            };
+        }
+        values.map(|value| value.clamp(0.0, 1.0))
+    }
+}
+
+/// The curve of each channel
+pub type Curves = PerChannel<Curve>;
+
+impl Curves {
+    /// The lookup tables for the adjustment
+    pub fn tables(&self) -> Tables {
+        let values = PerChannel {
+            rgb: self.rgb.values(),
+            red: self.red.values(),
+            green: self.green.values(),
+            blue: self.blue.values(),
+        };
+        values.map_tables(|values, value| values[to_u8(value) as usize])
+    }
+}
+
+/// A change to the hue, saturation, and lightness of colors
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HslShift {
+    /// How far to rotate the hue, from -180 to 180 degrees
+    pub hue: f32,
+
+    /// From -1 (gray) to 1 (twice as saturated), 0 leaving colors alone
+    pub saturation: f32,
+
+    /// From -1 (black) to 1 (white), 0 leaving colors alone
+    pub lightness: f32,
+}
+
+/// Which colors part of a hue/saturation adjustment applies to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HueRange {
+    /// Every color
+    #[default]
+    Master,
+
+    /// Colors with hues around 0 degrees
+    Reds,
+
+    /// Colors with hues around 60 degrees
+    Yellows,
+
+    /// Colors with hues around 120 degrees
+    Greens,
+
+    /// Colors with hues around 180 degrees
+    Cyans,
+
+    /// Colors with hues around 240 degrees
+    Blues,
+
+    /// Colors with hues around 300 degrees
+    Magentas,
+}
+
+impl HueRange {
+    /// Every range, in the order they should be shown in the UI
+    pub const ALL: [HueRange; 7] = [
+        Self::Master,
+        Self::Reds,
+        Self::Yellows,
+        Self::Greens,
+        Self::Cyans,
+        Self::Blues,
+        Self::Magentas,
+    ];
+
+    /// How much a color with a hue (in degrees) is affected by changes to the
+    /// range, from 0 to 1. Colors halfway between two ranges are affected by
+    /// both equally, and the weights of every range always add up to 1
+    fn weight(self, hue: f32) -> f32 {
+        let index = match self {
+            Self::Master => return 1.0,
+            Self::Reds => 0,
+            Self::Yellows => 1,
+            Self::Greens => 2,
+            Self::Cyans => 3,
+            Self::Blues => 4,
+            Self::Magentas => 5,
+        };
+        let distance = (hue - index as f32 * 60.0).rem_euclid(360.0);
+        let distance = distance.min(360.0 - distance);
+        (1.0 - (distance - 15.0) / 30.0).clamp(0.0, 1.0)
+    }
+}
+
+impl Display for HueRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Master => "Master",
+                Self::Reds => "Reds",
+                Self::Yellows => "Yellows",
+                Self::Greens => "Greens",
+                Self::Cyans => "Cyans",
+                Self::Blues => "Blues",
+                Self::Magentas => "Magentas",
+            }
+        )
+    }
+}
+
+/// Changes the hue, saturation, and lightness of every color, and then of
+/// each range of hues separately
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HueSaturation {
+    /// The change to every color
+    pub master: HslShift,
+
+    /// The change to each range of hues, from reds to magentas
+    pub ranges: [HslShift; 6],
+}
+
+impl HueSaturation {
+    /// The change to a range of hues
+    pub fn get_mut(&mut self, range: HueRange) -> &mut HslShift {
+        let index = match range {
+            HueRange::Master => return &mut self.master,
+            HueRange::Reds => 0,
+            HueRange::Yellows => 1,
+            HueRange::Greens => 2,
+            HueRange::Cyans => 3,
+            HueRange::Blues => 4,
+            HueRange::Magentas => 5,
+        };
+        &mut self.ranges[index]
+    }
+
+    /// Maps a color (with channels from 0 to 1)
+    fn map(&self, color: [f32; 3]) -> [f32; 3] {
+        let [hue, saturation, lightness] = rgb_to_hsl(color);
+
+        // Grays don't really have a hue, so the less saturated a color is, the
+        // less it's affected by the changes to its hue range
+        let mut shift = self.master;
+        for (range, range_shift) in HueRange::ALL[1..].iter().zip(&self.ranges) {
+            let weight = range.weight(hue) * saturation;
+            shift.hue += range_shift.hue * weight;
+            shift.saturation += range_shift.saturation * weight;
+            shift.lightness += range_shift.lightness * weight;
+        }
+
+        hsl_to_rgb([
+            hue + shift.hue,
+            (saturation * (1.0 + shift.saturation.max(-1.0))).min(1.0),
+            lighten(lightness, shift.lightness),
+        ])
+    }
+}
+
+/// The shadows, midtones, or highlights of an image
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ToneRange {
+    /// Dark colors
+    Shadows,
+
+    /// Colors in between
+    #[default]
+    Midtones,
+
+    /// Light colors
+    Highlights,
+}
+
+impl ToneRange {
+    /// Every range, in the order they should be shown in the UI
+    pub const ALL: [ToneRange; 3] = [Self::Shadows, Self::Midtones, Self::Highlights];
+}
+
+impl Display for ToneRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Shadows => "Shadows",
+                Self::Midtones => "Midtones",
+                Self::Highlights => "Highlights",
+            }
+        )
+    }
+}
+
+/// Shifts the colors of the shadows, midtones, and highlights separately. Each
+/// shift is from cyan to red, magenta to green, and yellow to blue, with each
+/// amount from -1 to 1
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColorBalance {
+    /// The shift of dark colors
+    pub shadows: [f32; 3],
+
+    /// The shift of colors in between
+    pub midtones: [f32; 3],
+
+    /// The shift of light colors
+    pub highlights: [f32; 3],
+
+    /// Whether to keep the lightness of every color the same
+    pub preserve_luminosity: bool,
+}
+
+impl Default for ColorBalance {
+    fn default() -> Self {
+        Self {
+            shadows: [0.0; 3],
+            midtones: [0.0; 3],
+            highlights: [0.0; 3],
+            preserve_luminosity: true,
+        }
+    }
+}
+
+impl ColorBalance {
+    /// The shift of a range of tones
+    pub fn get_mut(&mut self, range: ToneRange) -> &mut [f32; 3] {
+        match range {
+            ToneRange::Shadows => &mut self.shadows,
+            ToneRange::Midtones => &mut self.midtones,
+            ToneRange::Highlights => &mut self.highlights,
+        }
+    }
+
+    /// Maps a color (with channels from 0 to 1)
+    ///
+    /// This works the same way as GIMP's Color Balance tool: each range is
+    /// shifted according to the lightness of the color, with the ranges
+    /// overlapping a little so there are no sudden jumps between them
+    fn map(&self, color: [f32; 3]) -> [f32; 3] {
+        const RAMP_WIDTH: f32 = 0.25;
+        const RAMP_CENTER: f32 = 0.333;
+        const SCALE: f32 = 0.7;
+
+        let [_, _, lightness] = rgb_to_hsl(color);
+        let shadows = ((lightness - RAMP_CENTER) / -RAMP_WIDTH + 0.5).clamp(0.0, 1.0) * SCALE;
+        let midtones = ((lightness - RAMP_CENTER) / RAMP_WIDTH + 0.5).clamp(0.0, 1.0)
+            * ((lightness + RAMP_CENTER - 1.0) / -RAMP_WIDTH + 0.5).clamp(0.0, 1.0)
+            * SCALE;
+        let highlights =
+            ((lightness + RAMP_CENTER - 1.0) / RAMP_WIDTH + 0.5).clamp(0.0, 1.0) * SCALE;
+
+        let mut balanced = color;
+        for (channel, value) in balanced.iter_mut().enumerate() {
+            *value = (*value
+                + self.shadows[channel] * shadows
+                + self.midtones[channel] * midtones
+                + self.highlights[channel] * highlights)
+                .clamp(0.0, 1.0);
+        }
+
+        if self.preserve_luminosity {
+            let [hue, saturation, _] = rgb_to_hsl(balanced);
+            balanced = hsl_to_rgb([hue, saturation, lightness]);
+        }
+        balanced
+    }
+}
+
+/// Makes each color channel from a mix of all three. Each output channel has
+/// how much of the red, green, and blue channels go into it (usually from 0
+/// to 1, but from -2 to 2 in the UI)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelMixer {
+    /// The mix that makes the red channel
+    pub red: [f32; 3],
+
+    /// The mix that makes the green channel
+    pub green: [f32; 3],
+
+    /// The mix that makes the blue channel
+    pub blue: [f32; 3],
+}
+
+impl Default for ChannelMixer {
+    /// Each channel made of only itself, leaving every color alone
+    fn default() -> Self {
+        Self {
+            red: [1.0, 0.0, 0.0],
+            green: [0.0, 1.0, 0.0],
+            blue: [0.0, 0.0, 1.0],
+        }
+    }
+}
+
+impl ChannelMixer {
+    /// The mix that makes an output channel (the red one for
+    /// [`Channel::Rgb`], which can't be mixed separately)
+    pub fn get_mut(&mut self, channel: Channel) -> &mut [f32; 3] {
+        match channel {
+            Channel::Rgb | Channel::Red => &mut self.red,
+            Channel::Green => &mut self.green,
+            Channel::Blue => &mut self.blue,
+        }
+    }
+
+    /// Maps a color (with channels from 0 to 1)
+    fn map(&self, [r, g, b]: [f32; 3]) -> [f32; 3] {
+        [self.red, self.green, self.blue].map(|mix| mix[0] * r + mix[1] * g + mix[2] * b)
+    }
+}
+
+/// Boosts (or mutes) the saturation of dull colors more than that of colors
+/// that are already saturated, from -1 to 1
+fn vibrance(amount: f32, color: [f32; 3]) -> [f32; 3] {
+    let [hue, saturation, lightness] = rgb_to_hsl(color);
+    let saturation = saturation * (1.0 + amount.clamp(-1.0, 1.0) * (1.0 - saturation));
+    hsl_to_rgb([hue, saturation.min(1.0), lightness])
+}
+
+/// Tints colors brown like an old photograph, from 0 (not at all) to 1
+/// (fully)
+///
+/// See https://learn.microsoft.com/en-us/archive/msdn-magazine/2005/january/net-matters-sepia-tone-stringlogicalcomparer-and-more
+fn sepia(amount: f32, [r, g, b]: [f32; 3]) -> [f32; 3] {
+    let toned = [
+        0.393 * r + 0.769 * g + 0.189 * b,
+        0.349 * r + 0.686 * g + 0.168 * b,
+        0.272 * r + 0.534 * g + 0.131 * b,
+    ];
+    let amount = amount.clamp(0.0, 1.0);
+    [0, 1, 2].map(|channel| {
+        let before = [r, g, b][channel];
+        before + (toned[channel].min(1.0) - before) * amount
+    })
+}
+
+/// How to turn colors into grays
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DesaturateMethod {
+    /// Keeps the perceived brightness of each color
+    #[default]
+    Luminosity,
+
+    /// Averages the red, green, and blue channels
+    Average,
+
+    /// Averages the lightest and darkest of the red, green, and blue channels
+    Lightness,
+}
+
+impl DesaturateMethod {
+    /// Every method, in the order they should be shown in the UI
+    pub const ALL: [DesaturateMethod; 3] = [Self::Luminosity, Self::Average, Self::Lightness];
+
+    /// The gray a color becomes
+    fn gray(self, [r, g, b]: [u8; 3]) -> u8 {
+        match self {
+            Self::Luminosity => luma(r, g, b),
+            Self::Average => ((r as f32 + g as f32 + b as f32) / 3.0).round() as u8,
+            Self::Lightness => {
+                ((r.max(g).max(b) as f32 + r.min(g).min(b) as f32) / 2.0).round() as u8
+            }
+        }
+    }
+}
+
+impl Display for DesaturateMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Luminosity => "Luminosity",
+                Self::Average => "Average",
+                Self::Lightness => "Lightness",
+            }
+        )
+    }
+}
+
+/// An adjustment, with its settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Adjustment {
+    /// Brightens or darkens, and increases or decreases contrast
+    BrightnessContrast(BrightnessContrast),
+
+    /// Stretches ranges of values, and brightens or darkens midtones, for each
+    /// channel
+    Levels(Levels),
+
+    /// Maps values through a curve, for each channel
+    Curves(Curves),
+
+    /// Changes the hue, saturation, and lightness of colors
+    HueSaturation(HueSaturation),
+
+    /// Shifts the colors of the shadows, midtones, and highlights
+    ColorBalance(ColorBalance),
+
+    /// Makes each channel from a mix of all three
+    ChannelMixer(ChannelMixer),
+
+    /// Boosts the saturation of dull colors, by an amount from -1 to 1
+    Vibrance(f32),
+
+    /// Swaps every color for its opposite (black for white, etc.)
+    Invert,
+
+    /// Turns colors into grays
+    Desaturate(DesaturateMethod),
+
+    /// Tints colors brown, by an amount from 0 to 1
+    Sepia(f32),
+
+    /// Reduces each channel to a number of evenly spaced values (at least 2)
+    Posterize(u8),
+
+    /// Turns colors at least as bright as a level white, and the rest black
+    Threshold(u8),
+}
+
+/// The kinds of adjustments, without their settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdjustmentKind {
+    /// See [`Adjustment::BrightnessContrast`]
+    BrightnessContrast,
+
+    /// See [`Adjustment::Levels`]
+    Levels,
+
+    /// See [`Adjustment::Curves`]
+    Curves,
+
+    /// See [`Adjustment::HueSaturation`]
+    HueSaturation,
+
+    /// See [`Adjustment::ColorBalance`]
+    ColorBalance,
+
+    /// See [`Adjustment::ChannelMixer`]
+    ChannelMixer,
+
+    /// See [`Adjustment::Vibrance`]
+    Vibrance,
+
+    /// See [`Adjustment::Invert`]
+    Invert,
+
+    /// See [`Adjustment::Desaturate`]
+    Desaturate,
+
+    /// See [`Adjustment::Sepia`]
+    Sepia,
+
+    /// See [`Adjustment::Posterize`]
+    Posterize,
+
+    /// See [`Adjustment::Threshold`]
+    Threshold,
+}
+
+impl AdjustmentKind {
+    /// Every kind of adjustment, in the order they should be shown in the UI
+    /// (tonal adjustments first)
+    pub const ALL: [AdjustmentKind; 12] = [
+        Self::BrightnessContrast,
+        Self::Levels,
+        Self::Curves,
+        Self::HueSaturation,
+        Self::ColorBalance,
+        Self::ChannelMixer,
+        Self::Vibrance,
+        Self::Invert,
+        Self::Desaturate,
+        Self::Sepia,
+        Self::Posterize,
+        Self::Threshold,
+    ];
+
+    /// The adjustment with its default settings, which leave the image alone
+    /// for adjustments that can
+    pub fn defaults(self) -> Adjustment {
+        match self {
+            Self::BrightnessContrast => Adjustment::BrightnessContrast(Default::default()),
+            Self::Levels => Adjustment::Levels(Default::default()),
+            Self::Curves => Adjustment::Curves(Default::default()),
+            Self::HueSaturation => Adjustment::HueSaturation(Default::default()),
+            Self::ColorBalance => Adjustment::ColorBalance(Default::default()),
+            Self::ChannelMixer => Adjustment::ChannelMixer(Default::default()),
+            Self::Vibrance => Adjustment::Vibrance(0.0),
+            Self::Invert => Adjustment::Invert,
+            Self::Desaturate => Adjustment::Desaturate(Default::default()),
+            Self::Sepia => Adjustment::Sepia(1.0),
+            Self::Posterize => Adjustment::Posterize(4),
+            Self::Threshold => Adjustment::Threshold(128),
+        }
+    }
+}
+
+impl Display for AdjustmentKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::BrightnessContrast => "Brightness/Contrast",
+                Self::Levels => "Levels",
+                Self::Curves => "Curves",
+                Self::HueSaturation => "Hue/Saturation",
+                Self::ColorBalance => "Color Balance",
+                Self::ChannelMixer => "Channel Mixer",
+                Self::Vibrance => "Vibrance",
+                Self::Invert => "Invert",
+                Self::Desaturate => "Desaturate",
+                Self::Sepia => "Sepia",
+                Self::Posterize => "Posterize",
+                Self::Threshold => "Threshold",
+            }
+        )
+    }
+}
+
+impl Adjustment {
+    /// What kind of adjustment this is
+    pub fn kind(&self) -> AdjustmentKind {
+        match self {
+            Self::BrightnessContrast(_) => AdjustmentKind::BrightnessContrast,
+            Self::Levels(_) => AdjustmentKind::Levels,
+            Self::Curves(_) => AdjustmentKind::Curves,
+            Self::HueSaturation(_) => AdjustmentKind::HueSaturation,
+            Self::ColorBalance(_) => AdjustmentKind::ColorBalance,
+            Self::ChannelMixer(_) => AdjustmentKind::ChannelMixer,
+            Self::Vibrance(_) => AdjustmentKind::Vibrance,
+            Self::Invert => AdjustmentKind::Invert,
+            Self::Desaturate(_) => AdjustmentKind::Desaturate,
+            Self::Sepia(_) => AdjustmentKind::Sepia,
+            Self::Posterize(_) => AdjustmentKind::Posterize,
+            Self::Threshold(_) => AdjustmentKind::Threshold,
+        }
+    }
+
+    /// The lookup tables for the adjustment, or None if it's a color
+    /// adjustment that has to be worked out for each color
+    pub fn tables(&self) -> Option<Tables> {
+        match self {
+            Self::BrightnessContrast(settings) => Some(settings.tables()),
+            Self::Levels(levels) => Some(levels.tables()),
+            Self::Curves(curves) => Some(curves.tables()),
+            Self::Invert => Some(IDENTITY.map(|table| table.map(|value| 255 - value))),
+            Self::Posterize(levels) => {
+                let steps = (*levels).max(2) as f32 - 1.0;
+                let posterize = |value: u8| to_u8((value as f32 / 255.0 * steps).round() / steps);
+                Some(IDENTITY.map(|table| table.map(posterize)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Maps a color, for adjustments that don't have lookup tables
+    fn map_color(&self, color: [u8; 3]) -> [u8; 3] {
+        let unit = color.map(|value| value as f32 / 255.0);
+        match self {
+            Self::HueSaturation(settings) => settings.map(unit).map(to_u8),
+            Self::ColorBalance(settings) => settings.map(unit).map(to_u8),
+            Self::ChannelMixer(mixer) => mixer.map(unit).map(to_u8),
+            Self::Vibrance(amount) => vibrance(*amount, unit).map(to_u8),
+            Self::Desaturate(method) => [method.gray(color); 3],
+            Self::Sepia(amount) => sepia(*amount, unit).map(to_u8),
+            Self::Threshold(level) => {
+                let [r, g, b] = color;
+                [if luma(r, g, b) >= *level { 255 } else { 0 }; 3]
+            }
+            // (These are all applied with lookup tables instead)
+            Self::BrightnessContrast(_)
+            | Self::Levels(_)
+            | Self::Curves(_)
+            | Self::Invert
+            | Self::Posterize(_) => color,
+        }
+    }
+
+    /// Adjusts the colors of pixels (leaving their transparency alone),
+    /// keeping them to colors that can be represented in a color mode
+    pub fn apply(&self, buffer: &mut PixelBuffer, color_mode: &ColorMode) {
+        let tables = self.tables();
+        for pixel in buffer.pixels.chunks_exact_mut(4) {
+            let [r, g, b] = match &tables {
+                Some(tables) => [
+                    tables[0][pixel[0] as usize],
+                    tables[1][pixel[1] as usize],
+                    tables[2][pixel[2] as usize],
+                ],
+                None => self.map_color([pixel[0], pixel[1], pixel[2]]),
+            };
+            pixel.copy_from_slice(&color_mode.constrain([r, g, b, pixel[3]]));
+        }
+    }
+}
+
+/// How many pixels have each value, for each channel (with the RGB channel
+/// counting brightness), ignoring fully transparent pixels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram(pub PerChannel<[u32; 256]>);
+
+impl Histogram {
+    /// Counts the values of every pixel
+    pub fn new(buffer: &PixelBuffer) -> Self {
+        let mut counts = PerChannel {
+            rgb: [0; 256],
+            red: [0; 256],
+            green: [0; 256],
+            blue: [0; 256],
+        };
+        for pixel in buffer.pixels.chunks_exact(4).filter(|pixel| pixel[3] != 0) {
+            counts.rgb[luma(pixel[0], pixel[1], pixel[2]) as usize] += 1;
+            counts.red[pixel[0] as usize] += 1;
+            counts.green[pixel[1] as usize] += 1;
+            counts.blue[pixel[2] as usize] += 1;
+        }
+        Self(counts)
+    }
+
+    /// How many pixels have each value of a channel
+    pub fn counts(&self, channel: Channel) -> &[u32; 256] {
+        self.0.get(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A row of pixels, one for each of the given colors (all opaque)
+    fn pixels(colors: &[[u8; 3]]) -> PixelBuffer {
+        PixelBuffer {
+            pixels: colors
+                .iter()
+                .flat_map(|&[r, g, b]| [r, g, b, 255])
+                .collect(),
+            width: colors.len(),
+            height: 1,
+        }
+    }
+
+    /// Applies an adjustment to a row of colors, returning the adjusted colors
+    fn adjust(adjustment: Adjustment, colors: &[[u8; 3]]) -> Vec<[u8; 3]> {
+        let mut buffer = pixels(colors);
+        adjustment.apply(&mut buffer, &ColorMode::Rgba);
+        buffer
+            .pixels
+            .chunks_exact(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect()
+    }
+
+    const GRAYS: [[u8; 3]; 3] = [[0, 0, 0], [128, 128, 128], [255, 255, 255]];
+
+    #[test]
+    fn default_adjustments_change_nothing() {
+        let colors = [[0, 0, 0], [12, 200, 99], [255, 128, 1], [255, 255, 255]];
+        for kind in [
+            AdjustmentKind::BrightnessContrast,
+            AdjustmentKind::Levels,
+            AdjustmentKind::Curves,
+            AdjustmentKind::HueSaturation,
+            AdjustmentKind::ColorBalance,
+            AdjustmentKind::ChannelMixer,
+            AdjustmentKind::Vibrance,
+        ] {
+            assert_eq!(adjust(kind.defaults(), &colors), colors, "{kind}");
+        }
+    }
+
+    #[test]
+    fn brightness_and_contrast() {
+        let brighter = Adjustment::BrightnessContrast(BrightnessContrast {
+            brightness: 0.5,
+            contrast: 0.0,
+        });
+        assert_eq!(
+            adjust(brighter, &[[0, 0, 0], [51, 51, 51], [255, 255, 255]]),
+            [[128, 128, 128], [153, 153, 153], [255, 255, 255]]
+        );
+
+        let flat = Adjustment::BrightnessContrast(BrightnessContrast {
+            brightness: 0.0,
+            contrast: -1.0,
+        });
+        assert_eq!(adjust(flat, &GRAYS), [[128, 128, 128]; 3]);
+    }
+
+    #[test]
+    fn levels_stretch_each_channel() {
+        let mut levels = Levels::default();
+        levels.rgb.input_black = 50;
+        levels.rgb.input_white = 205;
+        levels.red.output_white = 0;
+        let adjusted = adjust(
+            Adjustment::Levels(levels),
+            &[[50, 50, 50], [128, 128, 128], [205, 205, 205]],
+        );
+        assert_eq!(adjusted, [[0, 0, 0], [0, 128, 128], [0, 255, 255]]);
+
+        let mut brighter = Levels::default();
+        brighter.rgb.gamma = 2.0;
+        // (64/255)^(1/2) * 255 = 127.75...
+        assert_eq!(
+            adjust(Adjustment::Levels(brighter), &[[64, 64, 64]]),
+            [[128, 128, 128]]
+        );
+    }
+
+    #[test]
+    fn curves_go_through_their_points_without_overshooting() {
+        let curve = Curve {
+            points: vec![[0, 0], [64, 128], [192, 128], [255, 255]],
+        };
+        let values = curve.values();
+        assert_eq!(to_u8(values[64]), 128);
+        assert_eq!(to_u8(values[192]), 128);
+        // Level between two points at the same height
+        assert!(values[64..=192].iter().all(|value| to_u8(*value) == 128));
+
+        let curves = Curves {
+            blue: Curve {
+                points: vec![[0, 255], [255, 0]],
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            adjust(Adjustment::Curves(curves), &GRAYS),
+            [[0, 0, 255], [128, 128, 127], [255, 255, 0]]
+        );
+    }
+
+    #[test]
+    fn histograms_count_values_of_visible_pixels() {
+        let mut buffer = pixels(&[[255, 0, 0], [255, 255, 255]]);
+        buffer.pixels.extend([10, 10, 10, 0]);
+        buffer.width += 1;
+
+        let histogram = Histogram::new(&buffer);
+        assert_eq!(histogram.counts(Channel::Red)[255], 2);
+        assert_eq!(histogram.counts(Channel::Green)[0], 1);
+        assert_eq!(histogram.counts(Channel::Rgb)[255], 1);
+        assert_eq!(histogram.counts(Channel::Blue)[10], 0);
+    }
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GREEN: [u8; 3] = [0, 255, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    #[test]
+    fn hue_saturation_and_lightness() {
+        let rotate = Adjustment::HueSaturation(HueSaturation {
+            master: HslShift {
+                hue: 120.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(adjust(rotate, &[RED, GREEN, BLUE]), [GREEN, BLUE, RED]);
+
+        let gray = Adjustment::HueSaturation(HueSaturation {
+            master: HslShift {
+                saturation: -1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(adjust(gray, &[RED, BLUE]), [[128, 128, 128]; 2]);
+
+        let white = Adjustment::HueSaturation(HueSaturation {
+            master: HslShift {
+                lightness: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert_eq!(adjust(white, &[RED, [12, 34, 56]]), [[255, 255, 255]; 2]);
+    }
+
+    #[test]
+    fn hue_ranges_only_change_their_own_colors() {
+        let mut settings = HueSaturation::default();
+        settings.get_mut(HueRange::Reds).hue = 120.0;
+        assert_eq!(
+            adjust(
+                Adjustment::HueSaturation(settings),
+                &[RED, BLUE, [128, 128, 128]]
+            ),
+            [GREEN, BLUE, [128, 128, 128]]
+        );
+
+        // Orange is halfway between reds and yellows
+        let mut settings = HueSaturation::default();
+        settings.get_mut(HueRange::Yellows).saturation = -1.0;
+        assert_eq!(
+            adjust(Adjustment::HueSaturation(settings), &[[255, 128, 0]]),
+            [[191, 128, 64]]
+        );
+    }
+
+    #[test]
+    fn color_balance_shifts_each_range_of_tones() {
+        let redder_midtones = ColorBalance {
+            midtones: [1.0, 0.0, 0.0],
+            preserve_luminosity: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            adjust(Adjustment::ColorBalance(redder_midtones.clone()), &GRAYS),
+            [[0, 0, 0], [255, 128, 128], [255, 255, 255]]
+        );
+
+        // Keeping the lightness the same makes the color darker to make up for
+        // the extra red
+        let preserved = ColorBalance {
+            preserve_luminosity: true,
+            ..redder_midtones
+        };
+        assert_eq!(
+            adjust(Adjustment::ColorBalance(preserved), &GRAYS),
+            [[0, 0, 0], [255, 1, 1], [255, 255, 255]]
+        );
+    }
+
+    #[test]
+    fn channel_mixer_mixes_channels() {
+        let swapped = ChannelMixer {
+            red: [0.0, 0.0, 1.0],
+            green: [0.0, 1.0, 0.0],
+            blue: [1.0, 0.0, 0.0],
+        };
+        assert_eq!(
+            adjust(Adjustment::ChannelMixer(swapped), &[RED, [10, 20, 30]]),
+            [BLUE, [30, 20, 10]]
+        );
+
+        let mut mixed = ChannelMixer::default();
+        *mixed.get_mut(Channel::Red) = [0.5, 0.5, 0.0];
+        *mixed.get_mut(Channel::Blue) = [0.0, 0.0, -1.0];
+        assert_eq!(
+            adjust(Adjustment::ChannelMixer(mixed), &[[200, 100, 50]]),
+            [[150, 100, 0]]
+        );
+    }
+
+    #[test]
+    fn vibrance_boosts_dull_colors_more() {
+        assert_eq!(
+            adjust(
+                Adjustment::Vibrance(1.0),
+                &[RED, [191, 64, 64], [128, 128, 128]]
+            ),
+            [RED, [223, 32, 32], [128, 128, 128]]
+        );
+    }
+
+    #[test]
+    fn inverting_and_desaturating() {
+        assert_eq!(
+            adjust(Adjustment::Invert, &[[0, 100, 255]]),
+            [[255, 155, 0]]
+        );
+
+        for (method, gray) in [
+            (DesaturateMethod::Luminosity, 76),
+            (DesaturateMethod::Average, 85),
+            (DesaturateMethod::Lightness, 128),
+        ] {
+            assert_eq!(
+                adjust(Adjustment::Desaturate(method), &[RED]),
+                [[gray; 3]],
+                "{method}"
+            );
+        }
+    }
+
+    #[test]
+    fn sepia_tints_colors_brown() {
+        assert_eq!(
+            adjust(Adjustment::Sepia(1.0), &[[255, 255, 255], [100, 100, 100]]),
+            [[255, 255, 239], [135, 120, 94]]
+        );
+        assert_eq!(
+            adjust(Adjustment::Sepia(0.0), &[[100, 100, 100]]),
+            [[100, 100, 100]]
+        );
+    }
+
+    #[test]
+    fn posterize_and_threshold() {
+        assert_eq!(
+            adjust(Adjustment::Posterize(2), &[[0, 100, 200]]),
+            [[0, 0, 255]]
+        );
+        assert_eq!(
+            adjust(Adjustment::Posterize(3), &[[0, 100, 200]]),
+            [[0, 128, 255]]
+        );
+
+        assert_eq!(
+            adjust(
+                Adjustment::Threshold(128),
+                &[[127, 127, 127], [128, 128, 128], RED]
+            ),
+            [[0, 0, 0], [255, 255, 255], [0, 0, 0]]
+        );
+    }
+}
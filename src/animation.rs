@@ -0,0 +1,228 @@
+//! Multi-frame (animated) images, like animated GIFs, APNGs, and WebPs
+
+use std::fmt::Display;
+
+use crate::{layers::LayeredImage, pixel_buffer::PixelBuffer};
+
+/// How long new frames (and frames from files that don't say) are shown for
+pub const DEFAULT_FRAME_DELAY_MS: u32 = 100;
+
+/// How many times an animation plays before stopping on its last frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LoopCount {
+    /// Plays forever
+    #[default]
+    Infinite,
+
+    /// Plays this many times in total (at least once)
+    Finite(u16),
+}
+
+impl Display for LoopCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Infinite => write!(f, "Forever"),
+            Self::Finite(1) => write!(f, "Once"),
+            Self::Finite(plays) => write!(f, "{plays} times"),
+        }
+    }
+}
+
+/// A single frame of an animation
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationFrame {
+    /// The layers of the frame (every frame has its own layers)
+    pub image: LayeredImage,
+
+    /// How long the frame is shown for, in milliseconds
+    pub delay_ms: u32,
+}
+
+/// A flattened frame, ready to be saved
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatFrame {
+    /// The frame's layers, flattened
+    pub pixels: PixelBuffer,
+
+    /// How long the frame is shown for, in milliseconds
+    pub delay_ms: u32,
+}
+
+/// The frames of an image. Still images are just an animation with a single
+/// frame
+///
+/// The frame being edited lives in `TrametesApp::image` (so everything that
+/// edits images doesn't need to know about frames), and its entry in `frames`
+/// is an empty placeholder until another frame is selected. Methods that need
+/// the current frame's image take it as a parameter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    /// The frames of the animation, in order. There is always at least one
+    /// frame
+    pub frames: Vec<AnimationFrame>,
+
+    /// The index of the frame currently being edited
+    pub current_frame: usize,
+
+    /// How many times the animation plays
+    pub loop_count: LoopCount,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            frames: vec![AnimationFrame {
+                image: LayeredImage::default(),
+                delay_ms: DEFAULT_FRAME_DELAY_MS,
+            }],
+            current_frame: 0,
+            loop_count: LoopCount::default(),
+        }
+    }
+}
+
+impl Animation {
+    /// Makes an animation out of some frames, returning the first frame's
+    /// image (to be edited) separately. Returns None if there are no frames
+    pub fn from_frames(
+        mut frames: Vec<AnimationFrame>,
+        loop_count: LoopCount,
+    ) -> Option<(LayeredImage, Self)> {
+        let first = std::mem::take(&mut frames.first_mut()?.image);
+
+        Some((
+            first,
+            Self {
+                frames,
+                current_frame: 0,
+                loop_count,
+            },
+        ))
+    }
+
+    /// Whether or not there is more than one frame
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+
+    /// The image of a frame, given the image of the current frame
+    pub fn frame_image<'a>(&'a self, index: usize, current: &'a LayeredImage) -> &'a LayeredImage {
+        if index == self.current_frame {
+            current
+        } else {
+            &self.frames[index].image
+        }
+    }
+
+    /// Switches to editing another frame, storing the current frame's image
+    /// away and swapping the other frame's image in
+    pub fn select_frame(&mut self, current: &mut LayeredImage, index: usize) {
+        if index == self.current_frame || index >= self.frames.len() {
+            return;
+        }
+
+        self.frames[self.current_frame].image = std::mem::take(current);
+        *current = std::mem::take(&mut self.frames[index].image);
+        self.current_frame = index;
+    }
+
+    /// Adds a frame after the current one (either a copy of the current frame
+    /// or a blank, transparent one), and switches to it
+    pub fn add_frame(&mut self, current: &mut LayeredImage, duplicate: bool) {
+        let image = if duplicate {
+            current.clone()
+        } else {
+            LayeredImage::from_flat(PixelBuffer {
+                pixels: vec![0; current.width * current.height * 4],
+                width: current.width,
+                height: current.height,
+            })
+        };
+        let index = self.current_frame + 1;
+
+        self.frames.insert(
+            index,
+            AnimationFrame {
+                image,
+                delay_ms: self.frames[self.current_frame].delay_ms,
+            },
+        );
+        self.select_frame(current, index);
+    }
+
+    /// Deletes the current frame (unless it's the only one), switching to the
+    /// frame after it (or before it, if it was the last frame)
+    pub fn delete_frame(&mut self, current: &mut LayeredImage) {
+        if !self.is_animated() {
+            return;
+        }
+
+        self.frames.remove(self.current_frame);
+        self.current_frame = self.current_frame.min(self.frames.len() - 1);
+        *current = std::mem::take(&mut self.frames[self.current_frame].image);
+    }
+
+    /// The length of one play through the animation, in milliseconds
+    pub fn duration_ms(&self) -> u64 {
+        self.frames.iter().map(|frame| frame.delay_ms as u64).sum()
+    }
+
+    /// Flattens every frame, for saving
+    pub fn flatten(&self, current: &LayeredImage) -> Vec<FlatFrame> {
+        (0..self.frames.len())
+            .map(|index| FlatFrame {
+                pixels: self.frame_image(index, current).flatten(),
+                delay_ms: self.frames[index].delay_ms,
+            })
+            .collect()
+    }
+
+    /// Every image other than the current frame's, for reading every frame
+    /// (the current frame's image has to be handled separately)
+    pub fn other_images(&self) -> impl Iterator<Item = &LayeredImage> {
+        self.frames
+            .iter()
+            .enumerate()
+            .filter(move |(index, _)| *index != self.current_frame)
+            .map(|(_, frame)| &frame.image)
+    }
+
+    /// Every image other than the current frame's, for operations that affect
+    /// every frame (the current frame's image has to be handled separately)
+    pub fn other_images_mut(&mut self) -> impl Iterator<Item = &mut LayeredImage> {
+        let current_frame = self.current_frame;
+        self.frames
+            .iter_mut()
+            .enumerate()
+            .filter(move |(index, _)| *index != current_frame)
+            .map(|(_, frame)| &mut frame.image)
+    }
+}
+
+/// Settings for showing the frames around the current one as faint, tinted
+/// overlays while drawing ("onion skinning")
+#[derive(Debug)]
+pub struct OnionSkin {
+    /// Whether or not onion skins are shown at all
+    pub enabled: bool,
+
+    /// How many previous frames to show
+    pub frames_before: usize,
+
+    /// How many following frames to show
+    pub frames_after: usize,
+
+    /// How opaque the nearest frames are (farther frames fade out)
+    pub opacity: f32,
+}
+
+impl Default for OnionSkin {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            frames_before: 1,
+            frames_after: 1,
+            opacity: 0.3,
+        }
+    }
+}
@@ -1,8 +1,41 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    time::Duration,
+};
 
 use eframe::{App, CreationContext, Frame};
+use egui::TextureHandle;
 
-use crate::{pixel_buffer::PixelBuffer, tools::ToolState, ui::draw_ui};
+use crate::{
+    animation::OnionSkin,
+    color_management::ColorProfile,
+    commands,
+    document::{Document, Tabs},
+    export::ExportOptions,
+    jobs::Jobs,
+    metadata::MetadataSummary,
+    notifications::Notifications,
+    pixel_buffer::PixelBuffer,
+    presets::Presets,
+    quantize::{Dithering, QuantizationMethod},
+    recent_files::RecentFiles,
+    recovery::{self, RecoveredDocument},
+    sprite_sheet,
+    tools::ToolState,
+    ui::{
+        draw_ui, AdjustmentDialog, ExportDialog, SpriteSheetExportDialog, SpriteSheetImportDialog,
+    },
+};
+
+#[cfg(target_arch = "wasm32")]
+use crate::web::Completion;
+
+/// How often the files of open documents are checked for changes made by
+/// other programs, in seconds
+#[cfg(not(target_arch = "wasm32"))]
+const FILE_CHECK_INTERVAL_SECS: f64 = 1.0;
 
 #[derive(Debug)]
 pub struct VisibleWindows {
@@ -10,6 +43,11 @@ pub struct VisibleWindows {
     pub colors: bool,
     pub history: bool,
     pub layers: bool,
+    pub palette: bool,
+    pub settings: bool,
+    pub image_properties: bool,
+    pub timeline: bool,
+    pub notifications: bool,
 }
 
 impl Default for VisibleWindows {
@@ -19,75 +57,349 @@ impl Default for VisibleWindows {
             colors: true,
             history: true,
             layers: true,
+            palette: true,
+            settings: false,
+            image_properties: false,
+            timeline: false,
+            notifications: false,
         }
     }
 }
 
 #[derive(Debug)]
-pub struct ImageTransformations {
-    pub x_translation: f32,
-    pub y_translation: f32,
-    pub scale: f32,
+pub struct Colors {
+    pub primary: [u8; 4],
+    pub secondary: [u8; 4],
 }
 
-impl Default for ImageTransformations {
+impl Default for Colors {
     fn default() -> Self {
         Self {
-            x_translation: 0.0,
-            y_translation: 0.0,
-            scale: 1.0,
+            primary: [0, 0, 0, 255],
+            secondary: [255, 255, 255, 255],
         }
     }
 }
 
+/// User preferences that apply to every image
+#[derive(Debug, Default)]
+pub struct Settings {
+    /// Whether to blend colors (when painting, resizing, etc.) in linear light
+    /// rather than directly on the sRGB-encoded values. This is more
+    /// physically accurate, and avoids dark fringes between very different
+    /// colors
+    pub blend_in_linear_light: bool,
+}
+
+/// The options for converting an image to indexed color
 #[derive(Debug)]
-pub struct Colors {
-    pub primary: [u8; 4],
-    pub secondary: [u8; 4],
+pub struct IndexedConversionSettings {
+    pub max_colors: usize,
+    pub method: QuantizationMethod,
+    pub dithering: Dithering,
 }
 
-impl Default for Colors {
+impl Default for IndexedConversionSettings {
     fn default() -> Self {
         Self {
-            primary: [0, 0, 0, 255],
-            secondary: [255, 255, 255, 255],
+            max_colors: 256,
+            method: QuantizationMethod::default(),
+            dithering: Dithering::default(),
         }
     }
 }
 
+/// The state of the Timeline panel
+#[derive(Debug, Default)]
+pub struct TimelineState {
+    /// While the animation is playing, when (on egui's clock, in seconds) the
+    /// current frame started being shown. None while paused
+    pub frame_shown_at: Option<f64>,
+
+    /// The onion skinning settings
+    pub onion_skin: OnionSkin,
+}
+
+/// Something waiting on the user to decide whether to save their changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingClose {
+    /// Closing the document in a tab
+    Tab(usize),
+
+    /// Quitting (closing every document)
+    Quit,
+}
+
+/// The thumbnail of a recent file, which is read in the background
+pub enum RecentThumbnail {
+    /// The file is still being read
+    Loading,
+
+    /// The file was read, but the thumbnail hasn't been shown yet
+    Read(PixelBuffer, Option<ColorProfile>),
+
+    /// The thumbnail is ready to show
+    Loaded(TextureHandle),
+
+    /// The file couldn't be read
+    Unreadable,
+}
+
+/// Thumbnails of recent files, by path
+#[derive(Default)]
+pub struct RecentThumbnails(pub HashMap<PathBuf, RecentThumbnail>);
+
+impl std::fmt::Debug for RecentThumbnails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Textures are not very interesting to look at
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
 /// The persistant state of an instance of Trametes
 #[derive(Debug, Default)]
 pub struct TrametesApp {
     /// Keeps track of which draggable windows are currently visible
     pub(crate) windows: VisibleWindows,
 
-    /// A buffer containing the actual pixels of the image
-    pub(crate) image: PixelBuffer,
+    /// The document being edited (the active tab)
+    pub(crate) document: Document,
+
+    /// Every open document
+    pub(crate) tabs: Tabs,
+
+    /// The state of the Timeline panel
+    pub(crate) timeline: TimelineState,
+
+    /// What's waiting on the "Save changes?" prompt, if it's open
+    pub(crate) pending_close: Option<PendingClose>,
+
+    /// Whether the user has dealt with every unsaved document, so the app can
+    /// actually quit
+    pub(crate) quit_confirmed: bool,
+
+    /// The title last given to the window, to avoid setting it every frame
+    pub(crate) window_title: String,
+
+    /// When (on egui's clock, in seconds) unsaved documents were last
+    /// autosaved
+    pub(crate) last_autosave: f64,
+
+    /// When (on egui's clock, in seconds) the files of open documents were
+    /// last checked for changes made by other programs (there's no file
+    /// system to check on the web)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) last_file_check: f64,
+
+    /// Documents with unsaved changes whose files were changed by another
+    /// program, waiting for the user to decide whether to reload them (by ID)
+    pub(crate) changed_on_disk: Vec<u64>,
+
+    /// Autosaves left behind by a previous run that crashed, waiting for the
+    /// user to restore or discard them
+    pub(crate) recovered: Vec<RecoveredDocument>,
+
+    /// Files to open once the app is running (like ones passed on the command
+    /// line)
+    pub(crate) files_to_open: Vec<PathBuf>,
+
+    /// Recently opened and saved files, for the Open Recent menu
+    pub(crate) recent_files: RecentFiles,
+
+    /// Thumbnails of recent files, loaded as they're first shown
+    pub(crate) recent_thumbnails: RecentThumbnails,
+
+    /// The settings for converting to indexed color, if the user is currently
+    /// choosing them
+    pub(crate) indexed_conversion: Option<IndexedConversionSettings>,
 
-    /// The file path of the image we're editing, if it came from a file
-    pub(crate) path: Option<PathBuf>,
+    /// The state of the dialog for an adjustment (like Levels), if the user is
+    /// currently adjusting the image
+    pub(crate) adjustment: Option<AdjustmentDialog>,
 
-    /// The relative visual transformations applied to the image (zooming,
-    /// panning, etc.)
-    pub(crate) image_relative_pos: ImageTransformations,
+    /// The metadata shown in the Image Properties window, worked out the first
+    /// time it's shown (and again whenever the metadata changes)
+    pub(crate) metadata_summary: Option<MetadataSummary>,
+
+    /// Saved settings for adjustments
+    pub(crate) adjustment_presets: Presets,
+
+    /// The last-used options for each format images can be saved in
+    pub(crate) export_options: ExportOptions,
+
+    /// The state of the Export dialog, if the user is currently exporting
+    pub(crate) export: Option<ExportDialog>,
+
+    /// The state of the Import Sprite Sheet dialog, if the user is currently
+    /// importing a sprite sheet
+    pub(crate) sprite_sheet_import: Option<SpriteSheetImportDialog>,
+
+    /// The last-used options for exporting sprite sheets
+    pub(crate) sprite_sheet_options: sprite_sheet::ExportSettings,
+
+    /// The state of the Export Sprite Sheet dialog, if the user is currently
+    /// exporting a sprite sheet
+    pub(crate) sprite_sheet_export: Option<SpriteSheetExportDialog>,
 
     /// The state of the tools (pan, brush, etc.)
     pub(crate) tools: ToolState,
 
     /// The primary and secondary colors
     pub(crate) colors: Colors,
+
+    /// User preferences
+    pub(crate) settings: Settings,
+
+    /// Messages for the user, and which ones they haven't seen yet
+    pub(crate) notifications: Notifications,
+
+    /// Files being opened and saved in the background
+    pub(crate) jobs: Jobs,
 }
 
 impl TrametesApp {
     /// Called once before the first frame.
-    pub fn new(_cc: &CreationContext<'_>) -> Self {
-        Default::default()
+    #[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
+    pub fn new(cc: &CreationContext<'_>) -> Self {
+        #[cfg(target_arch = "wasm32")]
+        crate::web::init(&cc.egui_ctx);
+
+        Self {
+            recent_files: RecentFiles::load(),
+            adjustment_presets: Presets::load(),
+            export_options: ExportOptions::load(),
+            recovered: recovery::find_recovered(),
+            ..Default::default()
+        }
+    }
+
+    /// Opens files (each in its own tab) as soon as the app starts
+    pub fn with_files_to_open(mut self, files: Vec<PathBuf>) -> Self {
+        self.files_to_open = files;
+        self
+    }
+}
+
+/// Autosaves documents that have changed since they were last autosaved, every
+/// so often
+fn autosave(app: &mut TrametesApp, ctx: &egui::Context) {
+    let now = ctx.input(|input| input.time);
+    let remaining = app.last_autosave + recovery::AUTOSAVE_INTERVAL_SECS - now;
+    if remaining > 0.0 {
+        ctx.request_repaint_after(Duration::from_secs_f64(remaining));
+        return;
+    }
+
+    let documents = std::iter::once(&mut app.document).chain(&mut app.tabs.documents);
+    for document in documents.filter(|document| document.dirty && !document.autosaved) {
+        document.autosaved = recovery::autosave(document);
+    }
+    app.last_autosave = now;
+    ctx.request_repaint_after(Duration::from_secs_f64(recovery::AUTOSAVE_INTERVAL_SECS));
+}
+
+/// Every so often, checks whether other programs have changed the files of open
+/// documents (there's no file system on the web, so nothing to check)
+#[cfg(not(target_arch = "wasm32"))]
+fn watch_files(app: &mut TrametesApp, ctx: &egui::Context) {
+    let now = ctx.input(|input| input.time);
+    let remaining = app.last_file_check + FILE_CHECK_INTERVAL_SECS - now;
+    if remaining > 0.0 {
+        ctx.request_repaint_after(Duration::from_secs_f64(remaining));
+        return;
+    }
+
+    commands::check_for_changed_files(app);
+    app.last_file_check = now;
+    ctx.request_repaint_after(Duration::from_secs_f64(FILE_CHECK_INTERVAL_SECS));
+}
+
+/// Runs part of the app, trying to save unsaved changes before going down if
+/// anything in it panics
+fn saving_on_panic<T>(app: &mut TrametesApp, run: impl FnOnce(&mut TrametesApp) -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(|| run(&mut *app))) {
+        Ok(result) => result,
+        Err(panic) => {
+            let documents = std::iter::once(&app.document).chain(&app.tabs.documents);
+            recovery::emergency_save(documents.filter(|document| document.dirty));
+            panic::resume_unwind(panic);
+        }
     }
 }
 
 impl App for TrametesApp {
     /// Called each time the UI needs repainting
     fn update(&mut self, ctx: &egui::Context, frame: &mut Frame) {
-        draw_ui(self, ctx, frame);
+        saving_on_panic(self, |app| update(app, ctx, frame));
+    }
+
+    /// Called when the user tries to close the window (or picks File > Quit).
+    /// Returning false keeps it open
+    #[cfg(not(target_arch = "wasm32"))]
+    fn on_close_event(&mut self) -> bool {
+        saving_on_panic(self, |app| {
+            let has_unsaved_changes =
+                app.document.dirty || app.tabs.documents.iter().any(|document| document.dirty);
+            if app.quit_confirmed || !(has_unsaved_changes || app.jobs.is_saving_any()) {
+                return true;
+            }
+
+            // Ask about saving each unsaved document first (and wait for any
+            // that are still being saved)
+            app.pending_close = Some(PendingClose::Quit);
+            false
+        })
+    }
+
+    /// Called once the app is quitting normally (every unsaved document has
+    /// been saved or thrown away), so there's nothing left to recover
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        for document in std::iter::once(&self.document).chain(&self.tabs.documents) {
+            recovery::remove_autosave(document.id);
+        }
+    }
+}
+
+/// Updates the app and draws its UI, each time it needs repainting
+fn update(app: &mut TrametesApp, ctx: &egui::Context, frame: &mut Frame) {
+    // Show the name of the image being edited (with an asterisk if it has
+    // unsaved changes) in the title bar
+    let dirty_marker = if app.document.dirty { "*" } else { "" };
+    let title = format!("{}{dirty_marker} - Trametes", app.document.title());
+    if title != app.window_title {
+        #[cfg(not(target_arch = "wasm32"))]
+        frame.set_window_title(&title);
+        #[cfg(target_arch = "wasm32")]
+        crate::web::set_title(&title);
+        app.window_title = title;
+    }
+
+    for path in std::mem::take(&mut app.files_to_open) {
+        commands::open_path(app, path);
     }
+
+    // Files picked and images pasted on the web arrive in the background
+    #[cfg(target_arch = "wasm32")]
+    for completion in crate::web::take_completions() {
+        match completion {
+            Completion::FilePicked(purpose, file) => {
+                commands::open_picked_file(app, purpose, file);
+            }
+            Completion::ImagePasted {
+                pixels,
+                into_new_image,
+            } => commands::paste_image(app, pixels, into_new_image),
+            Completion::Failed(message) => app.notifications.error(message),
+        }
+    }
+
+    for finished in app.jobs.take_finished() {
+        commands::finish_job(app, finished);
+    }
+
+    draw_ui(app, ctx, frame);
+
+    autosave(app, ctx);
+    #[cfg(not(target_arch = "wasm32"))]
+    watch_files(app, ctx);
 }
@@ -0,0 +1,468 @@
+//! The headless command-line mode, for converting and transforming lots of
+//! images at once without opening a window (like
+//! `trametes batch --resize 50% --flip-h --format webp in/*.png -o out/`)
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use image::ImageFormat;
+
+use crate::{
+    error::Error,
+    export::ExportOptions,
+    fileio::{self, LoadedImage},
+    pixel_buffer::PixelBuffer,
+};
+
+const USAGE: &str = "\
+Usage: trametes batch [OPTIONS] FILES... -o DIRECTORY
+
+Applies operations to images (in the order they're given) and saves the
+results in DIRECTORY, keeping their file names
+
+Options:
+  --resize N%       Scales images by a percentage
+  --resize WxH      Scales images to a size (leave out W or H to keep the
+                    aspect ratio, like 640x)
+  --flip-h          Mirrors images left to right
+  --flip-v          Mirrors images top to bottom
+  --rotate DEGREES  Rotates images clockwise by 90, 180, or 270 degrees
+  --linear-light    Resizes in linear light, so fine detail doesn't get darker
+  --format EXT      Saves images in another format (like png, jpg, or webp)
+  -o, --output DIR  The directory to save images in (created if needed)
+  -h, --help        Shows this message";
+
+/// The exit code when some files couldn't be processed
+const EXIT_FAILURE: i32 = 1;
+
+/// The exit code when the command line doesn't make sense
+const EXIT_USAGE: i32 = 2;
+
+/// How to resize an image
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resize {
+    /// Scales both dimensions by a percentage
+    Percent(f64),
+
+    /// Scales to a size. A missing dimension is calculated from the other one,
+    /// keeping the aspect ratio
+    Size(Option<usize>, Option<usize>),
+}
+
+/// Something to do to every image
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operation {
+    Resize(Resize),
+    FlipHorizontal,
+    FlipVertical,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Operation {
+    /// Applies the operation to an image (resampling in linear light if asked
+    /// to), failing if it would make the image too large
+    fn apply(self, image: &mut PixelBuffer, linear_light: bool) -> Result<(), Error> {
+        match self {
+            Self::Resize(resize) => {
+                let (width, height) = resize.new_size(image.width, image.height);
+                fileio::check_dimensions(width, height)?;
+                *image = image.resize(width, height, linear_light);
+            }
+            Self::FlipHorizontal => image.flip_horizontal(),
+            Self::FlipVertical => image.flip_vertical(),
+            Self::Rotate90 => *image = image.rotate_90(),
+            Self::Rotate180 => *image = image.rotate_180(),
+            Self::Rotate270 => *image = image.rotate_270(),
+        }
+        Ok(())
+    }
+}
+
+impl Resize {
+    fn parse(arg: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid size '{arg}' (expected something like 50% or 640x480)");
+
+        if let Some(percent) = arg.strip_suffix('%') {
+            return match percent.parse::<f64>() {
+                Ok(percent) if percent.is_finite() && percent > 0.0 => Ok(Self::Percent(percent)),
+                _ => Err(invalid()),
+            };
+        }
+
+        let (width, height) = arg.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let parse_dimension = |dimension: &str| match dimension {
+            "" => Ok(None),
+            _ => match dimension.parse::<usize>() {
+                Ok(dimension) if dimension > 0 => Ok(Some(dimension)),
+                _ => Err(invalid()),
+            },
+        };
+
+        match (parse_dimension(width)?, parse_dimension(height)?) {
+            (None, None) => Err(invalid()),
+            (width, height) => Ok(Self::Size(width, height)),
+        }
+    }
+
+    /// The size to scale an image of the given size to
+    fn new_size(self, width: usize, height: usize) -> (usize, usize) {
+        let scale = |size: usize, by: f64| ((size as f64 * by).round() as usize).max(1);
+        match self {
+            Self::Percent(percent) => (
+                scale(width, percent / 100.0),
+                scale(height, percent / 100.0),
+            ),
+            Self::Size(Some(new_width), Some(new_height)) => (new_width, new_height),
+            Self::Size(Some(new_width), None) => {
+                (new_width, scale(height, new_width as f64 / width as f64))
+            }
+            Self::Size(None, Some(new_height)) => {
+                (scale(width, new_height as f64 / height as f64), new_height)
+            }
+            Self::Size(None, None) => (width, height),
+        }
+    }
+}
+
+/// Everything the command line asked for
+#[derive(Debug, Default)]
+struct Options {
+    operations: Vec<Operation>,
+    format: Option<(String, ImageFormat)>,
+    output: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+    linear_light: bool,
+    help: bool,
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = OsString>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.to_str().filter(|arg| arg.starts_with('-')) else {
+                options.inputs.push(PathBuf::from(arg));
+                continue;
+            };
+
+            let mut value = || args.next().ok_or_else(|| format!("{flag} needs a value"));
+
+            match flag {
+                "-h" | "--help" => options.help = true,
+                "--flip-h" => options.operations.push(Operation::FlipHorizontal),
+                "--flip-v" => options.operations.push(Operation::FlipVertical),
+                "--linear-light" => options.linear_light = true,
+                "--resize" => {
+                    let resize = Resize::parse(&value()?.to_string_lossy())?;
+                    options.operations.push(Operation::Resize(resize));
+                }
+                "--rotate" => {
+                    let operation = match value()?.to_string_lossy().as_ref() {
+                        "90" => Operation::Rotate90,
+                        "180" => Operation::Rotate180,
+                        "270" => Operation::Rotate270,
+                        other => {
+                            return Err(format!(
+                                "invalid rotation '{other}' (expected 90, 180, or 270)"
+                            ))
+                        }
+                    };
+                    options.operations.push(operation);
+                }
+                "--format" => {
+                    let extension = value()?
+                        .to_string_lossy()
+                        .trim_start_matches('.')
+                        .to_lowercase();
+                    let format = ImageFormat::from_extension(&extension)
+                        .filter(|format| format.can_write())
+                        .ok_or_else(|| format!("can't save images as '{extension}'"))?;
+                    options.format = Some((extension, format));
+                }
+                "-o" | "--output" => options.output = Some(PathBuf::from(value()?)),
+                _ => return Err(format!("unknown option '{flag}'")),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Runs the batch command with the arguments after `batch`, printing what
+/// happens to each file. Returns the exit code for the process: zero if every
+/// file was processed, nonzero otherwise
+pub fn run(args: impl IntoIterator<Item = OsString>) -> i32 {
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return EXIT_USAGE;
+        }
+    };
+
+    if options.help {
+        println!("{USAGE}");
+        return 0;
+    }
+
+    let Some(output) = &options.output else {
+        eprintln!("error: no output directory given (use -o DIRECTORY)\n\n{USAGE}");
+        return EXIT_USAGE;
+    };
+    if options.inputs.is_empty() {
+        eprintln!("error: no input files given\n\n{USAGE}");
+        return EXIT_USAGE;
+    }
+
+    if let Err(err) = std::fs::create_dir_all(output) {
+        eprintln!(
+            "error: failed to create output directory {}: {err}",
+            output.display()
+        );
+        return EXIT_FAILURE;
+    }
+
+    // Files with the same name from different directories would overwrite
+    // each other, so only the first one gets to use the name
+    let mut claimed: HashMap<PathBuf, &Path> = HashMap::new();
+    let mut failures = 0;
+    for input in &options.inputs {
+        let result = output_path(input, output, &options).and_then(|(path, format)| {
+            if let Some(earlier) = claimed.get(&path) {
+                return Err(format!(
+                    "{} would overwrite the result of {}",
+                    path.display(),
+                    earlier.display()
+                )
+                .into());
+            }
+            claimed.insert(path.clone(), input);
+            process_file(input, &path, format, &options)
+        });
+        match result {
+            Ok(saved) => println!("{} -> {}", input.display(), saved.display()),
+            Err(err) => {
+                eprintln!("error: {}: {err}", input.display());
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        eprintln!("{failures} of {} files failed", options.inputs.len());
+        EXIT_FAILURE
+    } else {
+        0
+    }
+}
+
+/// Where the result of processing a file is saved (a file with the same name
+/// in the output directory, with the extension changed if the format is), and
+/// in what format
+fn output_path(
+    input: &Path,
+    output: &Path,
+    options: &Options,
+) -> Result<(PathBuf, ImageFormat), Box<dyn std::error::Error>> {
+    let (extension, format) = match &options.format {
+        Some((extension, format)) => (extension.clone(), *format),
+        None => {
+            let format = ImageFormat::from_path(input)
+                .ok()
+                .filter(|format| format.can_write())
+                .ok_or("can't save in the same format as the original, use --format")?;
+            let extension = input.extension().unwrap_or_default();
+            (extension.to_string_lossy().into_owned(), format)
+        }
+    };
+
+    let name = input.file_stem().ok_or("not a file")?;
+    Ok((output.join(name).with_extension(extension), format))
+}
+
+/// Reads a file, applies the operations to it, and saves it to `path`,
+/// returning where it was saved
+fn process_file(
+    input: &Path,
+    path: &Path,
+    format: ImageFormat,
+    options: &Options,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if path.exists() && path.canonicalize().ok() == input.canonicalize().ok() {
+        return Err("refusing to overwrite the original file".into());
+    }
+
+    let LoadedImage {
+        image,
+        animation,
+        color_profile,
+        color_mode,
+        metadata,
+    } = fileio::read_image_from_file(input)?;
+
+    let export_options = ExportOptions::default();
+    let transform = |pixels: &mut PixelBuffer| -> Result<(), Error> {
+        for operation in &options.operations {
+            operation.apply(pixels, options.linear_light)?;
+        }
+        // Resizing blends colors, so they might not fit the color mode anymore
+        for pixel in pixels.pixels.chunks_exact_mut(4) {
+            let color = color_mode.constrain([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            pixel.copy_from_slice(&color);
+        }
+        Ok(())
+    };
+
+    let bytes = if animation.is_animated() && fileio::supports_animation(format) {
+        let mut frames = animation.flatten(&image);
+        for frame in &mut frames {
+            transform(&mut frame.pixels)?;
+        }
+        fileio::encode_animation(
+            &frames,
+            animation.loop_count,
+            format,
+            color_profile.as_ref(),
+            &color_mode,
+            &metadata,
+            &export_options,
+        )?
+    } else {
+        if animation.is_animated() {
+            eprintln!(
+                "warning: {}: {format:?} files can't store animations, only the first frame \
+                was saved",
+                input.display()
+            );
+        }
+        let mut pixels = image.flatten();
+        transform(&mut pixels)?;
+        fileio::encode_image(
+            &pixels,
+            format,
+            color_profile.as_ref(),
+            &color_mode,
+            &metadata,
+            &export_options,
+            [255, 255, 255],
+        )?
+    };
+
+    std::fs::write(path, bytes)?;
+    Ok(path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn options_are_parsed_in_order() {
+        let options = Options::parse(args(&[
+            "--resize", "50%", "--flip-h", "a.png", "--rotate", "90", "--format", ".WebP", "b.jpg",
+            "-o", "out",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            options.operations,
+            [
+                Operation::Resize(Resize::Percent(50.0)),
+                Operation::FlipHorizontal,
+                Operation::Rotate90,
+            ]
+        );
+        assert_eq!(
+            options.format,
+            Some(("webp".to_string(), ImageFormat::WebP))
+        );
+        assert_eq!(options.output, Some(PathBuf::from("out")));
+        assert_eq!(
+            options.inputs,
+            [PathBuf::from("a.png"), PathBuf::from("b.jpg")]
+        );
+        assert!(!options.help);
+    }
+
+    #[test]
+    fn bad_command_lines_are_usage_errors() {
+        assert_eq!(run(args(&["--bogus", "a.png", "-o", "out"])), EXIT_USAGE);
+        assert_eq!(run(args(&["a.png", "-o"])), EXIT_USAGE);
+        assert_eq!(
+            run(args(&["--resize", "inf%", "a.png", "-o", "out"])),
+            EXIT_USAGE
+        );
+        assert_eq!(run(args(&["a.png"])), EXIT_USAGE);
+    }
+
+    #[test]
+    fn resizing_keeps_the_aspect_ratio_of_missing_dimensions() {
+        assert_eq!(
+            Resize::parse("640x").unwrap().new_size(1280, 960),
+            (640, 480)
+        );
+        assert_eq!(
+            Resize::parse("X480").unwrap().new_size(1280, 960),
+            (640, 480)
+        );
+        assert_eq!(Resize::parse("50%").unwrap().new_size(3, 3), (2, 2));
+
+        for invalid in ["x", "0x10", "-5%", "NaN%", "inf%", "640"] {
+            assert!(Resize::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn resizing_too_large_fails() {
+        let mut image = PixelBuffer {
+            pixels: vec![0; 4],
+            width: 1,
+            height: 1,
+        };
+
+        let result = Operation::Resize(Resize::Percent(1e12)).apply(&mut image, false);
+
+        assert!(matches!(result, Err(Error::DimensionsTooLarge)));
+        assert_eq!(image.width, 1);
+    }
+
+    #[test]
+    fn files_with_the_same_name_dont_overwrite_each_other() {
+        let dir = std::env::temp_dir().join(format!("trametes-batch-{}", std::process::id()));
+        let pixels = PixelBuffer {
+            pixels: vec![255, 0, 0, 255],
+            width: 1,
+            height: 1,
+        };
+        let mut inputs = Vec::new();
+        for subdirectory in ["a", "b"] {
+            let input = dir.join(subdirectory).join("image.png");
+            std::fs::create_dir_all(input.parent().unwrap()).unwrap();
+            std::fs::write(&input, fileio::encode_png(&pixels).unwrap()).unwrap();
+            inputs.push(input);
+        }
+        let output = dir.join("out");
+
+        let mut command_line = inputs.clone();
+        command_line.extend([PathBuf::from("-o"), output.clone()]);
+        let exit_code = run(command_line.into_iter().map(OsString::from));
+
+        assert_eq!(exit_code, EXIT_FAILURE);
+        assert_eq!(
+            std::fs::read_dir(&output).unwrap().count(),
+            1,
+            "only the first image should be saved"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
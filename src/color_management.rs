@@ -0,0 +1,220 @@
+//! ICC color profiles, and converting colors between them
+
+use std::sync::{Arc, OnceLock};
+
+use moxcms::{
+    CmsError, DataColorSpace, Layout, ProfileText, Transform8BitExecutor, TransformOptions, Xyzd,
+};
+
+/// An ICC color profile, describing how the pixel values of an image map to
+/// actual colors
+#[derive(Clone)]
+pub struct ColorProfile {
+    /// A human-readable name for the profile (from the profile's description)
+    pub name: String,
+
+    /// The raw ICC profile data, kept around so it can be re-embedded exactly
+    /// as it was read
+    pub icc: Vec<u8>,
+
+    /// Whether or not the profile describes the same colors as sRGB
+    srgb: bool,
+
+    /// The transform from this profile to sRGB, made the first time the image
+    /// is shown and reused after that (shared between copies of the profile)
+    to_srgb: Arc<OnceLock<Box<Transform8BitExecutor>>>,
+}
+
+impl std::fmt::Debug for ColorProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The raw ICC data is not very interesting to look at
+        f.debug_struct("ColorProfile")
+            .field("name", &self.name)
+            .field("icc", &format_args!("[{} bytes]", self.icc.len()))
+            .finish()
+    }
+}
+
+/// Gets a human-readable name out of a profile's description tag
+fn profile_name(profile: &moxcms::ColorProfile) -> Option<String> {
+    let name = match profile.description.as_ref()? {
+        ProfileText::PlainString(name) => name.clone(),
+        ProfileText::Localizable(strings) => strings.first()?.value.clone(),
+        ProfileText::Description(description) => description.ascii_string.clone(),
+    };
+    let name = name.trim_end_matches('\0').trim();
+
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Whether or not a profile describes the same colors as sRGB: it has the same
+/// primaries and white point, and its tone curves map every 8-bit value to
+/// (within rounding of) the same value in sRGB
+fn matches_srgb(profile: &moxcms::ColorProfile) -> bool {
+    let srgb = moxcms::ColorProfile::new_srgb();
+    let close = |a: Xyzd, b: Xyzd| {
+        (a.x - b.x).abs() < 1e-3 && (a.y - b.y).abs() < 1e-3 && (a.z - b.z).abs() < 1e-3
+    };
+    if profile.color_space != DataColorSpace::Rgb
+        || !close(profile.red_colorant, srgb.red_colorant)
+        || !close(profile.green_colorant, srgb.green_colorant)
+        || !close(profile.blue_colorant, srgb.blue_colorant)
+        || !close(profile.white_point, srgb.white_point)
+    {
+        return false;
+    }
+
+    let Ok(transform) =
+        profile.create_transform_8bit(Layout::Rgb, &srgb, Layout::Rgb, TransformOptions::default())
+    else {
+        return false;
+    };
+    let ramps: Vec<u8> = (0..=255)
+        .flat_map(|value| [value, 0, 0, 0, value, 0, 0, 0, value])
+        .collect();
+    let mut converted = vec![0; ramps.len()];
+    transform.transform(&ramps, &mut converted).is_ok()
+        && ramps
+            .iter()
+            .zip(&converted)
+            .all(|(value, converted)| value.abs_diff(*converted) <= 1)
+}
+
+impl ColorProfile {
+    /// Reads a color profile from raw ICC profile data, returning None if the
+    /// data isn't a valid (or supported) ICC profile
+    pub fn from_icc(icc: Vec<u8>) -> Option<Self> {
+        let profile = moxcms::ColorProfile::new_from_slice(&icc).ok()?;
+
+        Some(Self::new(&profile, icc))
+    }
+
+    fn from_builtin(profile: moxcms::ColorProfile) -> Self {
+        let icc = profile
+            .encode()
+            .expect("built-in color profiles should always be encodable");
+
+        Self::new(&profile, icc)
+    }
+
+    fn new(profile: &moxcms::ColorProfile, icc: Vec<u8>) -> Self {
+        Self {
+            name: profile_name(profile).unwrap_or_else(|| "Unnamed profile".to_string()),
+            icc,
+            srgb: matches_srgb(profile),
+            to_srgb: Arc::default(),
+        }
+    }
+
+    /// The standard sRGB color space (what untagged images are assumed to be)
+    pub fn srgb() -> Self {
+        Self::from_builtin(moxcms::ColorProfile::new_srgb())
+    }
+
+    /// The Display P3 color space (common on newer phones and cameras)
+    pub fn display_p3() -> Self {
+        Self::from_builtin(moxcms::ColorProfile::new_display_p3())
+    }
+
+    /// The Adobe RGB (1998) color space (common on professional cameras)
+    pub fn adobe_rgb() -> Self {
+        Self::from_builtin(moxcms::ColorProfile::new_adobe_rgb())
+    }
+
+    /// The built-in profiles offered in the UI, alongside loading one from a
+    /// file
+    pub fn builtins() -> Vec<Self> {
+        vec![Self::srgb(), Self::display_p3(), Self::adobe_rgb()]
+    }
+
+    /// Whether or not this profile describes the same colors as sRGB (in which
+    /// case no conversion is needed to display it). Other programs' sRGB
+    /// profiles count, not just our own
+    pub fn is_srgb(&self) -> bool {
+        self.srgb
+    }
+
+    /// Converts RGBA pixels in-place from this profile to sRGB (alpha is left
+    /// untouched), reusing the same transform each time
+    pub fn convert_to_srgb(&self, pixels: &mut [u8]) -> Result<(), CmsError> {
+        if self.srgb {
+            return Ok(());
+        }
+
+        let transform = match self.to_srgb.get() {
+            Some(transform) => transform,
+            None => {
+                let transform = self.parse()?.create_transform_8bit(
+                    Layout::Rgba,
+                    &moxcms::ColorProfile::new_srgb(),
+                    Layout::Rgba,
+                    TransformOptions::default(),
+                )?;
+                self.to_srgb.get_or_init(|| transform)
+            }
+        };
+
+        let src = pixels.to_vec();
+        transform.transform(&src, pixels)
+    }
+
+    fn parse(&self) -> Result<moxcms::ColorProfile, CmsError> {
+        moxcms::ColorProfile::new_from_slice(&self.icc)
+    }
+}
+
+/// Converts RGBA pixels in-place from one color profile to another (alpha is
+/// left untouched)
+pub fn convert_pixels(
+    pixels: &mut [u8],
+    from: &ColorProfile,
+    to: &ColorProfile,
+) -> Result<(), CmsError> {
+    if from.icc == to.icc {
+        return Ok(());
+    }
+
+    let transform = from.parse()?.create_transform_8bit(
+        Layout::Rgba,
+        &to.parse()?,
+        Layout::Rgba,
+        TransformOptions::default(),
+    )?;
+
+    let src = pixels.to_vec();
+    transform.transform(&src, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_srgb_profiles_are_srgb() {
+        let mut profile = moxcms::ColorProfile::new_srgb();
+        profile.description = Some(ProfileText::PlainString("Another sRGB".to_string()));
+        let other_srgb = ColorProfile::from_icc(profile.encode().unwrap()).unwrap();
+
+        assert_ne!(other_srgb.icc, ColorProfile::srgb().icc);
+        assert!(other_srgb.is_srgb());
+        assert!(ColorProfile::srgb().is_srgb());
+        assert!(!ColorProfile::display_p3().is_srgb());
+        assert!(!ColorProfile::adobe_rgb().is_srgb());
+    }
+
+    #[test]
+    fn converting_to_srgb_reuses_the_transform() {
+        let profile = ColorProfile::display_p3();
+        let original = vec![200, 100, 50, 255, 0, 255, 0, 128];
+
+        let mut expected = original.clone();
+        convert_pixels(&mut expected, &profile, &ColorProfile::srgb()).unwrap();
+
+        for _ in 0..2 {
+            let mut pixels = original.clone();
+            profile.clone().convert_to_srgb(&mut pixels).unwrap();
+            assert_eq!(pixels, expected);
+        }
+        assert!(profile.to_srgb.get().is_some());
+    }
+}
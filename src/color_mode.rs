@@ -0,0 +1,113 @@
+//! How the pixels of an image are represented (RGB, grayscale, or indexed)
+
+use std::{collections::HashMap, fmt::Display};
+
+/// The maximum number of colors in a palette (so indices fit in a byte)
+pub const MAX_PALETTE_SIZE: usize = 256;
+
+/// A list of colors (RGBA) used by an indexed-color image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    /// The colors in the palette, at most `MAX_PALETTE_SIZE` of them
+    pub colors: Vec<[u8; 4]>,
+}
+
+impl Palette {
+    /// Finds the index of the palette color closest to the given color
+    ///
+    /// Panics if the palette is empty
+    pub fn nearest_index(&self, color: [u8; 4]) -> usize {
+        self.colors
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, palette_color)| color_dist_sq(color, **palette_color))
+            .map(|(index, _)| index)
+            .expect("palettes should never be empty")
+    }
+
+    /// Finds the palette color closest to the given color
+    ///
+    /// Panics if the palette is empty
+    pub fn nearest(&self, color: [u8; 4]) -> [u8; 4] {
+        self.colors[self.nearest_index(color)]
+    }
+
+    /// Maps every pixel of an RGBA image to the index of its nearest palette
+    /// color
+    pub fn index_pixels(&self, pixels: &[u8]) -> Vec<u8> {
+        // Images tend to reuse the same few colors a lot, so remember the ones
+        // we've already looked up
+        let mut cache: HashMap<[u8; 4], u8> = HashMap::new();
+
+        pixels
+            .chunks_exact(4)
+            .map(|pixel| {
+                let color = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                *cache
+                    .entry(color)
+                    .or_insert_with(|| self.nearest_index(color) as u8)
+            })
+            .collect()
+    }
+}
+
+/// The squared distance between two colors (treating RGBA as a 4D space)
+pub fn color_dist_sq(a: [u8; 4], b: [u8; 4]) -> u32 {
+    // All fully transparent colors look the same, no matter their RGB values
+    if a[3] == 0 && b[3] == 0 {
+        return 0;
+    }
+
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+        .sum()
+}
+
+/// The luma (perceived brightness) of an sRGB color, using the Rec. 601
+/// weights
+pub fn luma(r: u8, g: u8, b: u8) -> u8 {
+    (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32).round() as u8
+}
+
+/// How the pixels of an image are represented
+///
+/// NOTE: pixels are always stored as RGBA in memory (so the rest of the editor
+/// doesn't need to care), the color mode just restricts which colors they can
+/// be
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Full color, with transparency
+    #[default]
+    Rgba,
+
+    /// Shades of gray only (with transparency)
+    Grayscale,
+
+    /// Only the colors in a palette
+    Indexed(Palette),
+}
+
+impl Display for ColorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rgba => write!(f, "RGB"),
+            Self::Grayscale => write!(f, "Grayscale"),
+            Self::Indexed(palette) => write!(f, "Indexed ({} colors)", palette.colors.len()),
+        }
+    }
+}
+
+impl ColorMode {
+    /// Restricts a color to one that can be represented in this color mode
+    pub fn constrain(&self, color: [u8; 4]) -> [u8; 4] {
+        match self {
+            Self::Rgba => color,
+            Self::Grayscale => {
+                let gray = luma(color[0], color[1], color[2]);
+                [gray, gray, gray, color[3]]
+            }
+            Self::Indexed(palette) => palette.nearest(color),
+        }
+    }
+}
@@ -1,67 +1,474 @@
+#[cfg(not(target_arch = "wasm32"))]
 use std::borrow::Cow;
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
+#[cfg(not(target_arch = "wasm32"))]
 use arboard::{Clipboard, ImageData};
-use egui::egui_assert;
-use native_dialog::MessageType;
+use image::ImageFormat;
 
-use crate::{app::ImageTransformations, fileio, pixel_buffer::PixelBuffer, ui, TrametesApp};
+use crate::{
+    adjustments::Adjustment,
+    animation::{Animation, AnimationFrame, LoopCount, DEFAULT_FRAME_DELAY_MS},
+    app::{IndexedConversionSettings, PendingClose, RecentThumbnail},
+    color_management::ColorProfile,
+    color_mode::ColorMode,
+    document::Document,
+    edit,
+    error::Error,
+    export::ExportOptions,
+    fileio::{self, FilePurpose, LoadedImage, PickedFile},
+    jobs::Finished,
+    layers::{Layer, LayeredImage},
+    metadata::Metadata,
+    notifications::Notifications,
+    pixel_buffer::PixelBuffer,
+    recovery::{self, RecoveredDocument},
+    sprite_sheet::{self, SpriteSheet, SpriteSource},
+    ui::{ExportDialog, SpriteSheetExportDialog, SpriteSheetImportDialog},
+    TrametesApp,
+};
 
-/// Shows a "feature not available" popup box
-pub fn todo(feature_name: &str) {
+/// Tells the user that a feature isn't available yet
+pub fn todo(app: &mut TrametesApp, feature_name: &str) {
     let msg = format!("Feature '{feature_name}' is not yet available");
-    ui::message_popup(&msg, MessageType::Info);
+    app.notifications.info(msg);
 }
 
-/// Creates a new image (erasing anything previously drawn)
+/// Tells the user why a file couldn't be read or written (`action` being what
+/// was being done to it, like "open")
+fn show_file_error(notifications: &mut Notifications, action: &str, path: &Path, err: &Error) {
+    eprintln!("failed to {action} {path:?}: {err:?}");
+    notifications.error(format!("Failed to {action} {}:\n{err}", path.display()));
+}
+
+/// Creates a new image in a new tab
 pub fn new(app: &mut TrametesApp) {
     // TODO let the user choose the new width and height, and whatever else
-    app.image = PixelBuffer::default();
-    app.image_relative_pos = ImageTransformations::default();
-    app.path = None;
+    open_document(app, Document::default());
 }
 
-/// Prompts the user for an image to open, then opens it
-pub fn open(app: &mut TrametesApp) {
+/// Shows a document in a new tab (replacing the active document if it's blank
+/// and untouched)
+fn open_document(app: &mut TrametesApp, document: Document) {
+    app.tabs.open(&mut app.document, document);
+    app.timeline.frame_shown_at = None;
+    if app.document.animation.is_animated() {
+        app.windows.timeline = true;
+    }
+}
+
+/// Finds an open document (in any tab) by its ID
+fn document_by_id(app: &mut TrametesApp, id: u64) -> Option<&mut Document> {
+    std::iter::once(&mut app.document)
+        .chain(&mut app.tabs.documents)
+        .find(|document| document.id == id)
+}
+
+/// Switches to editing the document in another tab
+pub fn select_tab(app: &mut TrametesApp, index: usize) {
+    app.tabs.select(&mut app.document, index);
+    app.timeline.frame_shown_at = None;
+}
+
+/// Closes the document in a tab, first asking whether to save it if it has
+/// unsaved changes
+pub fn close_tab(app: &mut TrametesApp, index: usize) {
+    if app.tabs.document(index, &app.document).dirty {
+        app.pending_close = Some(PendingClose::Tab(index));
+    } else {
+        discard_tab(app, index);
+    }
+}
+
+/// Closes the document in a tab, throwing away any unsaved changes
+pub fn discard_tab(app: &mut TrametesApp, index: usize) {
+    recovery::remove_autosave(app.tabs.document(index, &app.document).id);
+    app.tabs.close(&mut app.document, index);
+    app.timeline.frame_shown_at = None;
+}
+
+/// Prompts the user for a file, then does whatever it was asked for with it
+/// (see [`open_picked_file`]). On the web, the browser's file picker doesn't
+/// block, so that happens later, once the file has been read
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+fn pick_file(app: &mut TrametesApp, purpose: FilePurpose) {
+    #[cfg(not(target_arch = "wasm32"))]
     // I like "explicitly" handling None for readability here :)
-    #[allow(clippy::single_match)]
-    match fileio::get_image_path_to_open() {
-        Some(path) => {
-            match fileio::read_image_from_file(&path) {
-                Some(((width, height), pixels)) => {
-                    app.image = PixelBuffer {
-                        pixels,
-                        width: width as usize,
-                        height: height as usize,
-                    };
-                    app.image_relative_pos = ImageTransformations::default();
-                    app.path = Some(path);
+    match fileio::get_path_to_open(purpose) {
+        Ok(Some(path)) => read_path(app, path, purpose),
+        Ok(None) => {
+            // The user likely hit "cancel", do nothing and
+            // carry on
+        }
+        Err(err) => app.notifications.report_error(&err),
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    crate::web::pick_file(purpose);
+}
+
+/// What's being done to a file read for a purpose, for error messages
+fn reading_action(purpose: FilePurpose) -> &'static str {
+    match purpose {
+        FilePurpose::Open => "open",
+        FilePurpose::Reload { .. } => "reload",
+        _ => "read",
+    }
+}
+
+/// Reads a file, then does whatever it's for with it. Images are read and
+/// decoded in the background, and dealt with once they're done (see
+/// [`finish_job`])
+fn read_path(app: &mut TrametesApp, path: PathBuf, purpose: FilePurpose) {
+    if !purpose.is_icc_profile() {
+        app.jobs.open(path, purpose);
+        return;
+    }
+
+    match std::fs::read(&path) {
+        Ok(bytes) => open_picked_file(app, purpose, PickedFile { path, bytes }),
+        Err(err) => show_file_error(
+            &mut app.notifications,
+            reading_action(purpose),
+            &path,
+            &err.into(),
+        ),
+    }
+}
+
+/// Does whatever a file the user picked (or dropped onto the window) is for
+pub fn open_picked_file(app: &mut TrametesApp, purpose: FilePurpose, file: PickedFile) {
+    let PickedFile { path, bytes } = file;
+    match purpose {
+        FilePurpose::AssignProfile => {
+            if let Some(profile) = profile_from_icc(app, bytes) {
+                assign_profile(app, Some(profile));
+            }
+        }
+        FilePurpose::ConvertToProfile => {
+            if let Some(profile) = profile_from_icc(app, bytes) {
+                convert_to_profile(app, profile);
+            }
+        }
+        _ => {
+            let result = fileio::read_image(&path, &bytes);
+            open_loaded_image(app, purpose, &path, None, result);
+        }
+    }
+}
+
+/// Does whatever an image read from a file is for (or tells the user why it
+/// couldn't be read), with `modified` being when the file was last modified
+fn open_loaded_image(
+    app: &mut TrametesApp,
+    purpose: FilePurpose,
+    path: &Path,
+    modified: Option<SystemTime>,
+    result: Result<LoadedImage, Error>,
+) {
+    let loaded = match result {
+        Ok(loaded) => loaded,
+        Err(Error::Cancelled) => return,
+        Err(err) => {
+            show_file_error(&mut app.notifications, reading_action(purpose), path, &err);
+            return;
+        }
+    };
+
+    match purpose {
+        FilePurpose::Open => {
+            // Files on the web are only names, so they can't be reopened
+            if cfg!(not(target_arch = "wasm32")) {
+                remember_recent_file(app, path);
+            }
+            let document = Document {
+                modified_on_disk: modified,
+                ..Document::from_loaded_image(path, loaded)
+            };
+            open_document(app, document);
+        }
+        FilePurpose::AddLayer => add_layer_from_image(app, path, loaded),
+        FilePurpose::ImportSpriteSheet => {
+            app.sprite_sheet_import = Some(SpriteSheetImportDialog::new(loaded));
+        }
+        FilePurpose::Reload {
+            document_id,
+            revision,
+        } => {
+            let is_active = document_id == app.document.id;
+            // (The document may have been closed in the meantime)
+            let Some(document) = document_by_id(app, document_id) else {
+                return;
+            };
+            // Don't throw away changes made while the file was being read
+            // without asking
+            if document.dirty && revision.is_some_and(|revision| revision != document.revision) {
+                if !app.changed_on_disk.contains(&document_id) {
+                    app.changed_on_disk.push(document_id);
                 }
-                None => {
-                    eprintln!("failed to read image from file path: {path:?}");
-                    ui::message_popup("Failed to read file", MessageType::Error);
+                return;
+            }
+            document.reload(loaded, modified);
+            recovery::remove_autosave(document_id);
+            if revision.is_some() {
+                let title = document.title();
+                app.notifications.info(format!(
+                    "{title} was changed by another program, so it was reloaded"
+                ));
+            }
+            if is_active {
+                app.timeline.frame_shown_at = None;
+            }
+        }
+        FilePurpose::AssignProfile | FilePurpose::ConvertToProfile => {
+            // ICC profiles aren't images, they're dealt with in
+            // `open_picked_file`
+        }
+    }
+}
+
+/// Deals with the result of a file that finished opening or saving in the
+/// background
+pub fn finish_job(app: &mut TrametesApp, finished: Finished) {
+    match finished {
+        Finished::Opened {
+            purpose,
+            path,
+            modified,
+            result,
+        } => open_loaded_image(app, purpose, &path, modified, result),
+        Finished::Saved {
+            document_id,
+            path,
+            revision,
+            modified,
+            remember,
+            result,
+        } => match result {
+            Ok(warnings) => {
+                for warning in warnings {
+                    app.notifications.warning(warning);
                 }
-            };
+                if remember {
+                    remember_recent_file(app, &path);
+                }
+
+                // (The document may have been closed in the meantime)
+                if let Some(document) = document_by_id(app, document_id) {
+                    if document.finish_save(path, revision, modified) {
+                        recovery::remove_autosave(document_id);
+                    }
+                }
+            }
+            Err(Error::Cancelled) => {}
+            Err(err) => show_file_error(&mut app.notifications, "save", &path, &err),
+        },
+        Finished::RecentThumbnail { path, result } => {
+            // (The thumbnail is no longer wanted if the file was forgotten or
+            // changed in the meantime)
+            if let Some(thumbnail @ RecentThumbnail::Loading) =
+                app.recent_thumbnails.0.get_mut(&path)
+            {
+                *thumbnail = match result {
+                    Ok((pixels, color_profile)) => RecentThumbnail::Read(pixels, color_profile),
+                    Err(_) => RecentThumbnail::Unreadable,
+                };
+            }
         }
-        None => {
-            // The user likely hit "cancel", do nothing and
-            // carry on
+        Finished::ExportPreview { request, result } => {
+            // (The dialog may have been closed in the meantime)
+            if let Some(dialog) = &mut app.export {
+                dialog.finish_preview(request, result);
+            }
+        }
+    }
+}
+
+/// Prompts the user for an image to open, then opens it
+pub fn open(app: &mut TrametesApp) {
+    pick_file(app, FilePurpose::Open);
+}
+
+/// Opens the image at a file path in a new tab
+pub fn open_path(app: &mut TrametesApp, path: PathBuf) {
+    read_path(app, path, FilePurpose::Open);
+}
+
+/// Reads a document's file again, replacing what's in the document (which can
+/// be undone)
+pub fn reload(app: &mut TrametesApp, document_id: u64) {
+    start_reload(app, document_id, None);
+}
+
+/// Starts reading a document's file again, with `revision` being the
+/// document's revision if it's only to be replaced if it doesn't change in the
+/// meantime
+fn start_reload(app: &mut TrametesApp, document_id: u64, revision: Option<u64>) {
+    let Some(path) = document_by_id(app, document_id).and_then(|document| document.path.clone())
+    else {
+        return;
+    };
+    app.jobs.open(
+        path,
+        FilePurpose::Reload {
+            document_id,
+            revision,
+        },
+    );
+}
+
+/// Throws away the current image's unsaved changes by reading its file again
+/// (which can be undone)
+pub fn revert(app: &mut TrametesApp) {
+    reload(app, app.document.id);
+}
+
+/// Checks whether other programs have changed the files of open documents.
+/// Once a file stops changing, documents without unsaved changes are reloaded
+/// right away, and the user is asked about the others
+#[cfg(not(target_arch = "wasm32"))]
+pub fn check_for_changed_files(app: &mut TrametesApp) {
+    let mut changed = Vec::new();
+    let documents = std::iter::once(&mut app.document).chain(&mut app.tabs.documents);
+    for document in documents {
+        let Some(path) = &document.path else {
+            continue;
+        };
+        // Saving changes the file too, and is noted once it's done
+        if app.jobs.is_saving(document.id) {
+            continue;
+        }
+
+        // (Files that were deleted are left alone, they can be saved again)
+        let modified = fileio::modified_time(path);
+        if modified.is_none() || modified == document.modified_on_disk {
+            document.changing_on_disk = None;
+            continue;
+        }
+        // If there's nothing to compare against (like for recovered
+        // documents), only changes from now on count
+        if document.modified_on_disk.is_none() {
+            document.modified_on_disk = modified;
+            continue;
+        }
+        // Wait until the file hasn't changed since the last check, so it isn't
+        // read while the other program is still writing it
+        if document.changing_on_disk != modified {
+            document.changing_on_disk = modified;
+            continue;
+        }
+        // Only deal with each change once
+        document.changing_on_disk = None;
+        document.modified_on_disk = modified;
+        changed.push((document.id, document.dirty, document.revision));
+    }
+
+    for (document_id, dirty, revision) in changed {
+        if dirty {
+            if !app.changed_on_disk.contains(&document_id) {
+                app.changed_on_disk.push(document_id);
+            }
+        } else {
+            start_reload(app, document_id, Some(revision));
         }
     }
 }
 
+/// Opens a file from the Open Recent menu, forgetting it if it no longer
+/// exists
+pub fn open_recent(app: &mut TrametesApp, path: &Path) {
+    if !path.exists() {
+        app.recent_files.remove(path);
+        app.recent_thumbnails.0.remove(path);
+        app.notifications.warning(format!(
+            "{} no longer exists, so it was removed from the recent files",
+            path.display()
+        ));
+        return;
+    }
+
+    open_path(app, path.to_path_buf());
+}
+
+/// Opens an autosave left behind by a previous run in a new tab (with the
+/// changes still unsaved)
+pub fn restore_recovered(app: &mut TrametesApp, recovered: &RecoveredDocument) {
+    match Document::open(&recovered.file) {
+        Ok(document) => {
+            open_document(
+                app,
+                Document {
+                    path: recovered.info.path.clone(),
+                    dirty: true,
+                    autosaved: false,
+                    ..document
+                },
+            );
+            // The document will be autosaved again under its new ID
+            recovery::remove_recovered(&recovered.file);
+        }
+        Err(err) => show_file_error(
+            &mut app.notifications,
+            "read the recovered file",
+            &recovered.file,
+            &err,
+        ),
+    }
+}
+
+/// Forgets every recently opened and saved file
+pub fn clear_recent_files(app: &mut TrametesApp) {
+    app.recent_files.clear();
+    app.recent_thumbnails.0.clear();
+}
+
+/// Moves a file to the top of the Open Recent menu
+fn remember_recent_file(app: &mut TrametesApp, path: &Path) {
+    app.recent_files.add(path);
+
+    // The file may have changed, so its thumbnail needs to be reloaded
+    if let Some(path) = app.recent_files.paths().first() {
+        app.recent_thumbnails.0.remove(path);
+    }
+}
+
+/// The color to fill transparent areas with when saving to formats that can't
+/// store transparency
+pub fn matte_color(app: &TrametesApp) -> [u8; 3] {
+    let [r, g, b, _] = app.colors.secondary;
+    [r, g, b]
+}
+
+/// Starts saving the current image to a file path in the background, keeping
+/// every frame if the format can store animations (and just the current frame
+/// otherwise). Once it's saved, the user is warned about anything that wasn't,
+/// and the file is added to the recent files if `remember` is true
+fn start_saving(app: &mut TrametesApp, path: PathBuf, remember: bool) {
+    if app.jobs.is_saving(app.document.id) {
+        app.notifications
+            .info("The image is already being saved, wait for that to finish first");
+        return;
+    }
+
+    let data = app
+        .document
+        .save_data(&path, &app.export_options, matte_color(app));
+    app.jobs.save(app.document.id, data, remember);
+}
+
 /// Prompts the user for a file path to save the current image to, then saves it
 pub fn save_as(app: &mut TrametesApp) {
     // I like "explicitly" handling None for readability here :)
-    #[allow(clippy::single_match)]
     match fileio::get_image_path_to_save_as() {
-        Some(path) => {
-            fileio::save_image_to_file(&path, &app.image);
-            app.path = Some(path);
-        }
-        None => {
+        Ok(Some(path)) => start_saving(app, path, true),
+        Ok(None) => {
             // The user likely hit "cancel", do nothing and
             // carry on
         }
+        Err(err) => app.notifications.report_error(&err),
     }
 }
 
@@ -69,47 +476,465 @@ pub fn save_as(app: &mut TrametesApp) {
 /// for a file path if the current image didn't come from a file, then saves the
 /// image to that path
 pub fn save(app: &mut TrametesApp) {
-    match &app.path {
-        Some(path) => fileio::save_image_to_file(path, &app.image),
+    match app.document.path.clone() {
+        Some(path) => start_saving(app, path, false),
         None => save_as(app),
     }
 }
 
+/// Removes the GPS location from the image's metadata (it will still be in the
+/// original file until the image is saved)
+pub fn remove_gps_metadata(app: &mut TrametesApp) {
+    edit::remove_gps_metadata(&mut app.document);
+}
+
+/// Removes all of the image's metadata (it will still be in the original file
+/// until the image is saved)
+pub fn remove_all_metadata(app: &mut TrametesApp) {
+    edit::remove_all_metadata(&mut app.document);
+}
+
+/// Prompts the user for a file path to export a flattened copy of the current
+/// image to, then shows the options for that file's format
+pub fn export(app: &mut TrametesApp) {
+    // I like "explicitly" handling None for readability here :)
+    match fileio::get_image_path_to_save_as() {
+        Ok(Some(path)) => match ImageFormat::from_path(&path) {
+            Ok(format) => {
+                app.export = Some(ExportDialog::new(
+                    path,
+                    format,
+                    app.document.animation.flatten(&app.document.image),
+                    app.document.animation.current_frame,
+                    app.document.animation.loop_count,
+                    app.export_options.clone(),
+                    matte_color(app),
+                ));
+            }
+            Err(err) => {
+                eprintln!("can't export to {path:?}: {err}");
+                app.notifications
+                    .error("Unsupported file type for exporting (use Save As for layered formats)");
+            }
+        },
+        Ok(None) => {
+            // The user likely hit "cancel", do nothing and
+            // carry on
+        }
+        Err(err) => app.notifications.report_error(&err),
+    }
+}
+
+/// Writes an exported (already encoded) image to a file, and remembers the
+/// options used for next time
+pub fn finish_export(app: &mut TrametesApp, path: &Path, bytes: &[u8], options: ExportOptions) {
+    match fileio::write_file(path, bytes) {
+        Ok(()) => {
+            options.save();
+            app.export_options = options;
+        }
+        Err(err) => show_file_error(&mut app.notifications, "export to", path, &err),
+    }
+}
+
+/// Prompts the user for a sprite sheet to import, then shows the options for
+/// splitting it up
+pub fn import_sprite_sheet(app: &mut TrametesApp) {
+    pick_file(app, FilePurpose::ImportSpriteSheet);
+}
+
+/// Splits a sprite sheet up into the frames or layers of a new image
+pub fn finish_sprite_sheet_import(
+    app: &mut TrametesApp,
+    sheet: LoadedImage,
+    settings: &sprite_sheet::ImportSettings,
+) {
+    let sprites = sprite_sheet::split(&sheet.image.flatten(), settings);
+    let Some(first) = sprites.first() else {
+        app.notifications
+            .error("No sprites found in the sprite sheet");
+        return;
+    };
+    let (width, height) = (first.width, first.height);
+
+    let (image, animation) = match settings.import_as {
+        SpriteSource::Frames => {
+            let frames = sprites
+                .into_iter()
+                .map(|pixels| AnimationFrame {
+                    image: LayeredImage::from_flat(pixels),
+                    delay_ms: DEFAULT_FRAME_DELAY_MS,
+                })
+                .collect();
+            Animation::from_frames(frames, LoopCount::default())
+                .expect("there is at least one sprite")
+        }
+        SpriteSource::Layers => {
+            let layers: Vec<Layer> = sprites
+                .into_iter()
+                .enumerate()
+                .map(|(index, pixels)| Layer::new(format!("Sprite {}", index + 1), pixels))
+                .collect();
+            let image = LayeredImage {
+                width,
+                height,
+                active_layer: layers.len() - 1,
+                layers,
+            };
+            (image, Animation::default())
+        }
+    };
+
+    open_document(
+        app,
+        Document {
+            color_profile: sheet.color_profile,
+            color_mode: sheet.color_mode,
+            ..Document::new(image, animation)
+        },
+    );
+}
+
+/// Prompts the user for a file path to export a sprite sheet of the current
+/// image's frames or layers to, then shows the options for packing it
+pub fn export_sprite_sheet(app: &mut TrametesApp) {
+    // I like "explicitly" handling None for readability here :)
+    match fileio::get_image_path_to_save_as() {
+        Ok(Some(path)) => match ImageFormat::from_path(&path) {
+            Ok(format) => {
+                app.sprite_sheet_export = Some(SpriteSheetExportDialog::new(
+                    path,
+                    format,
+                    app.sprite_sheet_options.clone(),
+                ));
+            }
+            Err(err) => {
+                eprintln!("can't export sprite sheet to {path:?}: {err}");
+                app.notifications
+                    .error("Unsupported file type for sprite sheets");
+            }
+        },
+        Ok(None) => {
+            // The user likely hit "cancel", do nothing and
+            // carry on
+        }
+        Err(err) => app.notifications.report_error(&err),
+    }
+}
+
+/// Saves a packed sprite sheet to a file path, along with a JSON description
+/// of where each sprite is (next to it, with a `.json` extension)
+pub fn finish_sprite_sheet_export(
+    app: &mut TrametesApp,
+    path: &Path,
+    format: ImageFormat,
+    sheet: &SpriteSheet,
+    settings: sprite_sheet::ExportSettings,
+) {
+    // The transparent space between sprites might not be in the palette, so
+    // indexed images are saved in full color
+    let color_mode = match &app.document.color_mode {
+        ColorMode::Indexed(_) => ColorMode::Rgba,
+        color_mode => color_mode.clone(),
+    };
+    let image_file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let result = fileio::encode_image(
+        &sheet.image,
+        format,
+        app.document.color_profile.as_ref(),
+        &color_mode,
+        &Metadata::default(),
+        &app.export_options,
+        matte_color(app),
+    )
+    .and_then(|bytes| fileio::write_file(path, &bytes))
+    .and_then(|()| {
+        let json = sheet
+            .to_json(&image_file_name)
+            .map_err(|err| Error::Encode(err.to_string()))?;
+        fileio::write_file(&path.with_extension("json"), json.as_bytes())
+    });
+
+    match result {
+        Ok(()) => app.sprite_sheet_options = settings,
+        Err(err) => show_file_error(
+            &mut app.notifications,
+            "export the sprite sheet to",
+            path,
+            &err,
+        ),
+    }
+}
+
 /// Copies the selected part of the image into the clipboard, or the entire
 /// image if there is no active selection
 pub fn copy(app: &mut TrametesApp) {
     // TODO only copy selection if there is an active selection
+    let flattened = app.document.image.flatten();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        // TODO spawn a process to persist with the clipboard even if Trametes
+        // is closed (see https://docs.rs/arboard/latest/arboard/trait.SetExtLinux.html#tymethod.wait)
+        // (only necessary on linux)
+        let image_data = ImageData {
+            width: flattened.width,
+            height: flattened.height,
+            bytes: Cow::from(&flattened.pixels),
+        };
+        let result = Clipboard::new().and_then(|mut clipboard| clipboard.set_image(image_data));
+        if let Err(err) = result {
+            eprintln!("failed to copy: {err:?}");
+            app.notifications
+                .error(format!("Failed to copy the image:\n{err}"));
+        }
+    }
 
-    // TODO spawn a process to persist with the clipboard even if Trametes is
-    // closed (see https://docs.rs/arboard/latest/arboard/trait.SetExtLinux.html#tymethod.wait)
-    // (only necessary on linux)
+    // The browser's clipboard is asynchronous, so any errors show up later
+    #[cfg(target_arch = "wasm32")]
+    crate::web::copy_image(&flattened);
+}
 
-    let mut clipboard = Clipboard::new().unwrap(); // TODO handle errors here
+/// Reads an image from the clipboard, then pastes it (see [`paste_image`]). On
+/// the web, the clipboard is asynchronous, so that happens later
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+fn read_clipboard(app: &mut TrametesApp, into_new_image: bool) {
+    #[cfg(not(target_arch = "wasm32"))]
+    match Clipboard::new().and_then(|mut clipboard| clipboard.get_image()) {
+        Ok(image) => {
+            debug_assert!(image.bytes.len() == image.width * image.height * 4);
+            let pixels = PixelBuffer {
+                pixels: image.bytes.into_owned(),
+                width: image.width,
+                height: image.height,
+            };
+            paste_image(app, pixels, into_new_image);
+        }
+        Err(err) => {
+            eprintln!("failed to paste: {err:?}");
+            app.notifications
+                .error(format!("Failed to paste an image:\n{err}"));
+        }
+    }
 
-    let image_data = ImageData {
-        width: app.image.width,
-        height: app.image.height,
-        bytes: Cow::from(&app.image.pixels),
-    };
+    #[cfg(target_arch = "wasm32")]
+    crate::web::paste_image(into_new_image);
+}
 
-    clipboard.set_image(image_data).unwrap(); // TODO handle errors here
+/// Pastes an image from the clipboard into a new layer of the current image
+/// (which is how images are copied between tabs)
+pub fn paste(app: &mut TrametesApp) {
+    read_clipboard(app, false);
 }
 
 /// Pastes an image from the clipboard into a new image
 pub fn paste_into_new_image(app: &mut TrametesApp) {
-    let mut clipboard = Clipboard::new().unwrap(); // TODO handle errors here
+    read_clipboard(app, true);
+}
+
+/// Pastes an image read from the clipboard, either into a new layer of the
+/// current image or into a new image
+pub fn paste_image(app: &mut TrametesApp, pixels: PixelBuffer, into_new_image: bool) {
+    if into_new_image {
+        let image = LayeredImage::from_flat(pixels);
+        open_document(app, Document::new(image, Animation::default()));
+        return;
+    }
+
+    // The clipboard is assumed to be sRGB, like untagged images
+    app.notifications.report(edit::insert_layer(
+        &mut app.document,
+        "Paste",
+        Layer::new("Pasted Layer", pixels),
+        None,
+    ));
+}
+
+/// Adds the image in a file (flattened) as a new layer of the current image
+pub fn add_layer_from_path(app: &mut TrametesApp, path: PathBuf) {
+    read_path(app, path, FilePurpose::AddLayer);
+}
+
+/// Adds an image read from a file (flattened) as a new layer of the current
+/// image, named after the file
+fn add_layer_from_image(app: &mut TrametesApp, path: &Path, loaded: LoadedImage) {
+    let name = path.file_stem().map_or_else(
+        || "Layer".to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    );
+    let layer = Layer::new(name, loaded.image.flatten());
+    app.notifications.report(edit::insert_layer(
+        &mut app.document,
+        "Add Layer from File",
+        layer,
+        loaded.color_profile.as_ref(),
+    ));
+}
+
+/// Tags the image with a color profile without changing its pixels (so the
+/// colors will look different, but the pixel values stay the same). Passing
+/// None removes the profile, leaving the image untagged (assumed to be sRGB)
+pub fn assign_profile(app: &mut TrametesApp, profile: Option<ColorProfile>) {
+    edit::assign_profile(&mut app.document, profile);
+}
+
+/// Converts the pixels of the image from its current color profile to another
+/// one (so the colors look the same, but the pixel values change), then tags
+/// the image with the new profile
+pub fn convert_to_profile(app: &mut TrametesApp, profile: ColorProfile) {
+    app.notifications
+        .report(edit::convert_to_profile(&mut app.document, profile));
+}
+
+/// Prompts the user for an ICC profile file, then tags the image with it (see
+/// [`assign_profile`])
+pub fn assign_profile_from_file(app: &mut TrametesApp) {
+    pick_file(app, FilePurpose::AssignProfile);
+}
+
+/// Prompts the user for an ICC profile file, then converts the image to it
+/// (see [`convert_to_profile`])
+pub fn convert_to_profile_from_file(app: &mut TrametesApp) {
+    pick_file(app, FilePurpose::ConvertToProfile);
+}
+
+/// Reads the contents of an ICC profile file as a color profile (or returns
+/// None, after telling the user, if it isn't a valid ICC profile)
+fn profile_from_icc(app: &mut TrametesApp, icc: Vec<u8>) -> Option<ColorProfile> {
+    let profile = ColorProfile::from_icc(icc);
+    if profile.is_none() {
+        app.notifications.error("Not a valid ICC profile");
+    }
+    profile
+}
+
+/// Switches the image to full RGB color (the pixels don't need to change, since
+/// grayscale and palette colors are all valid RGB colors)
+pub fn convert_to_rgb(app: &mut TrametesApp) {
+    edit::convert_to_rgb(&mut app.document);
+}
+
+/// Converts the image to grayscale
+pub fn convert_to_grayscale(app: &mut TrametesApp) {
+    app.notifications
+        .report(edit::convert_to_grayscale(&mut app.document));
+}
+
+/// Converts the image to indexed color, picking a palette with the given
+/// settings
+pub fn convert_to_indexed(app: &mut TrametesApp, settings: &IndexedConversionSettings) {
+    edit::convert_to_indexed(
+        &mut app.document,
+        settings.max_colors,
+        settings.method,
+        settings.dithering,
+    );
+}
+
+/// Adjusts the colors of the active layer of a document (which doesn't have
+/// to be the active one, if the user switched tabs while choosing settings)
+pub fn adjust(app: &mut TrametesApp, document_id: u64, adjustment: &Adjustment) {
+    if let Some(document) = document_by_id(app, document_id) {
+        edit::adjust(document, adjustment);
+    }
+}
+
+/// Changes a color in the palette of an indexed image, recoloring every pixel
+/// that used it
+pub fn set_palette_color(app: &mut TrametesApp, index: usize, color: [u8; 4]) {
+    app.notifications
+        .report(edit::set_palette_color(&mut app.document, index, color));
+}
+
+/// Adds a color to the palette of an indexed image
+pub fn add_palette_color(app: &mut TrametesApp, color: [u8; 4]) {
+    app.notifications
+        .report(edit::add_palette_color(&mut app.document, color));
+}
+
+/// Removes a color from the palette of an indexed image, switching every pixel
+/// that used it to the nearest remaining color
+pub fn remove_palette_color(app: &mut TrametesApp, index: usize) {
+    app.notifications
+        .report(edit::remove_palette_color(&mut app.document, index));
+}
+
+/// Adds a new, empty (transparent) layer above the active layer
+pub fn new_layer(app: &mut TrametesApp) {
+    edit::new_layer(&mut app.document);
+}
+
+/// Adds a copy of the active layer above it
+pub fn duplicate_layer(app: &mut TrametesApp) {
+    edit::duplicate_layer(&mut app.document);
+}
+
+/// Deletes the active layer (unless it's the only one)
+pub fn delete_layer(app: &mut TrametesApp) {
+    app.notifications
+        .report(edit::delete_layer(&mut app.document));
+}
+
+/// Moves the active layer one step up (towards the top of the stack)
+pub fn move_layer_up(app: &mut TrametesApp) {
+    edit::move_layer_up(&mut app.document);
+}
+
+/// Moves the active layer one step down (towards the bottom of the stack)
+pub fn move_layer_down(app: &mut TrametesApp) {
+    edit::move_layer_down(&mut app.document);
+}
 
-    let image = clipboard.get_image().unwrap(); // TODO handle errors here
+/// Mirrors every frame of the image left to right
+pub fn flip_horizontal(app: &mut TrametesApp) {
+    edit::flip_horizontal(&mut app.document);
+}
+
+/// Mirrors every frame of the image top to bottom
+pub fn flip_vertical(app: &mut TrametesApp) {
+    edit::flip_vertical(&mut app.document);
+}
 
-    let width = image.width;
-    let height = image.height;
-    let bytes = image.bytes;
+/// Combines all the visible layers into a single layer
+pub fn flatten_image(app: &mut TrametesApp) {
+    edit::flatten_image(&mut app.document);
+}
 
-    egui_assert!(bytes.len() == width * height * 4);
+/// Switches to editing another frame of the animation
+pub fn select_frame(app: &mut TrametesApp, index: usize) {
+    app.document
+        .animation
+        .select_frame(&mut app.document.image, index);
+}
+
+/// Adds a blank frame after the current one
+pub fn add_frame(app: &mut TrametesApp) {
+    edit::add_frame(&mut app.document);
+}
+
+/// Adds a copy of the current frame after it
+pub fn duplicate_frame(app: &mut TrametesApp) {
+    edit::duplicate_frame(&mut app.document);
+}
+
+/// Deletes the current frame (unless it's the only one)
+pub fn delete_frame(app: &mut TrametesApp) {
+    app.notifications
+        .report(edit::delete_frame(&mut app.document));
+}
+
+/// Undoes the most recent change to the current image
+pub fn undo(app: &mut TrametesApp) {
+    app.document.undo();
+    app.timeline.frame_shown_at = None;
+}
 
-    app.image.width = image.width;
-    app.image.height = image.height;
-    app.image.pixels = bytes.to_vec();
-    app.image_relative_pos = Default::default();
-    app.path = None;
+/// Redoes the most recently undone change to the current image
+pub fn redo(app: &mut TrametesApp) {
+    app.document.redo();
+    app.timeline.frame_shown_at = None;
 }
@@ -0,0 +1,683 @@
+//! Open images ("documents"), each shown in its own tab with its own undo
+//! history
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+use image::ImageFormat;
+
+use crate::{
+    animation::Animation,
+    color_management::ColorProfile,
+    color_mode::ColorMode,
+    error::Error,
+    export::ExportOptions,
+    fileio::{self, LoadedImage},
+    jobs::Progress,
+    layers::LayeredImage,
+    metadata::Metadata,
+};
+
+/// The most steps that can be undone (every step keeps a full copy of the
+/// image, so this can't be too large)
+pub const MAX_HISTORY: usize = 30;
+
+/// Used to give each document a different ID
+static NEXT_DOCUMENT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An open image, along with everything needed to edit and save it
+#[derive(Debug)]
+pub struct Document {
+    /// Identifies the document while the app is running (used to name its
+    /// autosave)
+    pub id: u64,
+
+    /// The layers (and actual pixels) of the image (the current frame, for
+    /// animated images)
+    pub image: LayeredImage,
+
+    /// The frames of the image (just the one for still images)
+    pub animation: Animation,
+
+    /// The file path of the image, if it came from (or has been saved to) a
+    /// file
+    pub path: Option<PathBuf>,
+
+    /// The working color space of the image (the color profile its pixels are
+    /// encoded in), or None if the image is untagged (and assumed to be sRGB)
+    pub color_profile: Option<ColorProfile>,
+
+    /// How the pixels of the image are represented (RGB, grayscale, indexed)
+    pub color_mode: ColorMode,
+
+    /// The EXIF and XMP metadata that came with the image, kept so it can be
+    /// saved again
+    pub metadata: Metadata,
+
+    /// The relative visual transformations applied to the image (zooming,
+    /// panning, etc.)
+    pub view: ImageTransformations,
+
+    /// The steps that can be undone and redone
+    pub history: History,
+
+    /// Whether or not the image has been changed since it was last saved (or
+    /// opened)
+    pub dirty: bool,
+
+    /// Whether or not the image has been autosaved since it was last changed
+    pub autosaved: bool,
+
+    /// How many times the image has been changed, so a save that finishes in
+    /// the background can tell whether the image changed while it was being
+    /// written
+    pub revision: u64,
+
+    /// When the file at `path` was last modified, as of when the document last
+    /// read or wrote it (to notice when another program changes the file)
+    pub modified_on_disk: Option<SystemTime>,
+
+    /// When the file at `path` was last modified, as of the last check, if it
+    /// had changed since `modified_on_disk` (changes are only dealt with once
+    /// the file stops changing, so files still being written aren't read)
+    pub changing_on_disk: Option<SystemTime>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            id: NEXT_DOCUMENT_ID.fetch_add(1, Ordering::Relaxed),
+            image: LayeredImage::default(),
+            animation: Animation::default(),
+            path: None,
+            color_profile: None,
+            color_mode: ColorMode::default(),
+            metadata: Metadata::default(),
+            view: ImageTransformations::default(),
+            history: History::default(),
+            dirty: false,
+            autosaved: true,
+            revision: 0,
+            modified_on_disk: None,
+            changing_on_disk: None,
+        }
+    }
+}
+
+/// How a document is shown on screen (zoomed, panned, etc.), relative to being
+/// centered at its actual size
+#[derive(Debug)]
+pub struct ImageTransformations {
+    /// How far the image is moved right, in screen points
+    pub x_translation: f32,
+
+    /// How far the image is moved down, in screen points
+    pub y_translation: f32,
+
+    /// How much the image is zoomed in (1 is actual size)
+    pub scale: f32,
+}
+
+impl Default for ImageTransformations {
+    fn default() -> Self {
+        Self {
+            x_translation: 0.0,
+            y_translation: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+/// A copy of everything about a document that editing can change, so it can
+/// be restored later
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// What was done after the snapshot was taken (like "Brush Stroke")
+    pub name: String,
+
+    image: LayeredImage,
+    animation: Animation,
+    color_profile: Option<ColorProfile>,
+    color_mode: ColorMode,
+    metadata: Metadata,
+}
+
+/// A copy of everything needed to save a document, taken when saving starts
+/// so the file can be encoded and written in the background while the
+/// document carries on being edited
+#[derive(Debug)]
+pub(crate) struct SaveData {
+    /// Where the document is being saved to
+    pub path: PathBuf,
+
+    /// The document's revision when the copy was taken
+    pub revision: u64,
+
+    image: LayeredImage,
+
+    /// Every frame, if they're all being saved (otherwise just `image` is)
+    animation: Option<Animation>,
+
+    /// Whether the document is animated, but only the current frame is being
+    /// saved (because the format can't store animations)
+    loses_frames: bool,
+
+    color_profile: Option<ColorProfile>,
+    color_mode: ColorMode,
+    metadata: Metadata,
+    export_options: ExportOptions,
+    matte: [u8; 3],
+}
+
+impl SaveData {
+    /// Encodes the copy of the document, then writes it to its file. Returns
+    /// warnings about anything the file couldn't store
+    pub fn write(&self, progress: &Progress) -> Result<Vec<String>, Error> {
+        progress.start_stage("Encoding", None);
+        let bytes = match &self.animation {
+            Some(animation) if fileio::is_project(&self.path) => fileio::encode_project(
+                &self.image,
+                animation,
+                self.color_profile.as_ref(),
+                &self.color_mode,
+                &self.metadata,
+            ),
+            Some(animation) => fileio::encode_animation_for_path(
+                &self.path,
+                &animation.flatten(&self.image),
+                animation.loop_count,
+                self.color_profile.as_ref(),
+                &self.color_mode,
+                &self.metadata,
+                &self.export_options,
+            ),
+            None => fileio::encode_image_for_path(
+                &self.path,
+                &self.image,
+                self.color_profile.as_ref(),
+                &self.color_mode,
+                &self.metadata,
+                &self.export_options,
+                self.matte,
+            ),
+        }?;
+
+        // Encoding can't be stopped partway, but nothing has been written yet
+        progress.check_cancelled()?;
+        fileio::write_file_with_progress(&self.path, &bytes, progress)?;
+
+        Ok(self.warnings())
+    }
+
+    /// Warnings about anything the file couldn't store
+    fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if fileio::is_openraster(&self.path) {
+            if let Some(profile) = self.color_profile.as_ref().filter(|p| !p.is_srgb()) {
+                warnings.push(format!(
+                    "OpenRaster files can't store color profiles, so the image was saved \
+                    without {} (its colors may look different in other programs)",
+                    profile.name
+                ));
+            }
+            if self.color_mode != ColorMode::Rgba {
+                warnings.push(
+                    "OpenRaster files can't store grayscale or indexed images, so the image was \
+                    saved as RGB"
+                        .to_string(),
+                );
+            }
+        }
+
+        if self.loses_frames {
+            warnings.push(
+                "This format can't store animations, so only the current frame was saved"
+                    .to_string(),
+            );
+        } else if let Ok(format) = ImageFormat::from_path(&self.path) {
+            if self.animation.is_none() && fileio::needs_matte(&self.image.flatten(), format) {
+                warnings.push(format!(
+                    "{format:?} files can't store transparency, so transparent areas were \
+                    filled with the secondary color"
+                ));
+            }
+        }
+
+        warnings
+    }
+}
+
+/// The undo and redo stacks of a document
+#[derive(Debug, Default)]
+pub struct History {
+    /// The states before each undoable step, oldest first
+    pub undo: Vec<Snapshot>,
+
+    /// The states after each undone step, most recently undone last
+    pub redo: Vec<Snapshot>,
+
+    /// Whether repeats of the last step are still being merged into it (until
+    /// the user starts interacting with something again)
+    merging: bool,
+}
+
+impl Document {
+    /// A new, unsaved document containing an image
+    pub fn new(image: LayeredImage, animation: Animation) -> Self {
+        Self {
+            image,
+            animation,
+            ..Default::default()
+        }
+    }
+
+    /// Opens an image file as a document
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        // (Checked first, so changes made while the file is read aren't missed)
+        let modified_on_disk = fileio::modified_time(path);
+        Ok(Self {
+            modified_on_disk,
+            ..Self::from_bytes(path, &std::fs::read(path)?)?
+        })
+    }
+
+    /// Opens an image from the contents of a file, with `path` being where the
+    /// file came from (which decides how it's read, and where it's saved)
+    pub fn from_bytes(path: &Path, bytes: &[u8]) -> Result<Self, Error> {
+        Ok(Self::from_loaded_image(
+            path,
+            fileio::read_image(path, bytes)?,
+        ))
+    }
+
+    /// A document for an image that was read from a file path
+    pub(crate) fn from_loaded_image(path: &Path, loaded: LoadedImage) -> Self {
+        Self {
+            path: Some(path.to_path_buf()),
+            color_profile: loaded.color_profile,
+            color_mode: loaded.color_mode,
+            metadata: loaded.metadata,
+            ..Self::new(loaded.image, loaded.animation)
+        }
+    }
+
+    /// Replaces the image with what's in its file now (read again after
+    /// another program changed it, or to throw away unsaved changes). This can
+    /// be undone like any other step, and leaves the document with no unsaved
+    /// changes
+    pub(crate) fn reload(&mut self, loaded: LoadedImage, modified_on_disk: Option<SystemTime>) {
+        self.checkpoint("Revert");
+        self.image = loaded.image;
+        self.animation = loaded.animation;
+        self.color_profile = loaded.color_profile;
+        self.color_mode = loaded.color_mode;
+        self.metadata = loaded.metadata;
+        self.dirty = false;
+        self.modified_on_disk = modified_on_disk;
+    }
+
+    /// Whether or not saving to a file path would only keep the current frame
+    /// (because the document is animated, but the format can't store
+    /// animations)
+    pub fn loses_frames_when_saved_to(&self, path: &Path) -> bool {
+        self.animation.is_animated()
+            && !fileio::is_project(path)
+            && !ImageFormat::from_path(path).is_ok_and(fileio::supports_animation)
+    }
+
+    /// Saves the document to a file path (in the format implied by its
+    /// extension, using the options for that format), keeping every frame if
+    /// the format can store animations and just the current frame otherwise.
+    /// Transparent areas are filled with the matte color if the format can't
+    /// store transparency. The document then belongs to that file, with no
+    /// unsaved changes
+    pub fn save(
+        &mut self,
+        path: &Path,
+        export_options: &ExportOptions,
+        matte: [u8; 3],
+    ) -> Result<(), Error> {
+        let data = self.save_data(path, export_options, matte);
+        data.write(&Progress::default())?;
+        let modified_on_disk = fileio::modified_time(&data.path);
+        self.finish_save(data.path, data.revision, modified_on_disk);
+        Ok(())
+    }
+
+    /// Copies everything needed to save the document to a file path (see
+    /// [`Document::save`]), so it can be saved in the background
+    pub(crate) fn save_data(
+        &self,
+        path: &Path,
+        export_options: &ExportOptions,
+        matte: [u8; 3],
+    ) -> SaveData {
+        let loses_frames = self.loses_frames_when_saved_to(path);
+        let keeps_frames = self.animation.is_animated() && !loses_frames;
+        SaveData {
+            path: path.to_path_buf(),
+            revision: self.revision,
+            image: self.image.clone(),
+            animation: keeps_frames.then(|| self.animation.clone()),
+            loses_frames,
+            color_profile: self.color_profile.clone(),
+            color_mode: self.color_mode.clone(),
+            metadata: self.metadata.clone(),
+            export_options: export_options.clone(),
+            matte,
+        }
+    }
+
+    /// Records that the document was saved to a file path, as it was at a
+    /// revision. The document then belongs to that file, and has no unsaved
+    /// changes unless it was changed while it was being saved. Returns whether
+    /// it has no unsaved changes
+    pub(crate) fn finish_save(
+        &mut self,
+        path: PathBuf,
+        revision: u64,
+        modified_on_disk: Option<SystemTime>,
+    ) -> bool {
+        self.modified_on_disk = modified_on_disk;
+        self.path = Some(path);
+        if self.revision == revision {
+            self.dirty = false;
+        }
+        !self.dirty
+    }
+
+    /// The name to show in the document's tab
+    pub fn title(&self) -> String {
+        match self.path.as_ref().and_then(|path| path.file_name()) {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => "Untitled".to_string(),
+        }
+    }
+
+    /// Whether or not this is a blank, untouched document (like the one shown
+    /// at startup), which can be replaced by an opened image without losing
+    /// anything
+    pub fn is_pristine(&self) -> bool {
+        self.path.is_none() && !self.dirty && self.history.undo.is_empty()
+    }
+
+    /// Marks the document as changed since it was last saved (and autosaved).
+    /// Checkpoints already do this, so it's only needed for changes that carry
+    /// on after one (like the rest of a brush stroke)
+    pub fn mark_changed(&mut self) {
+        self.dirty = true;
+        self.autosaved = false;
+        self.revision += 1;
+    }
+
+    /// Copies the parts of the document that editing can change
+    fn snapshot(&self, name: &str) -> Snapshot {
+        Snapshot {
+            name: name.to_string(),
+            image: self.image.clone(),
+            animation: self.animation.clone(),
+            color_profile: self.color_profile.clone(),
+            color_mode: self.color_mode.clone(),
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// Puts the document back the way it was when a snapshot was taken,
+    /// returning a snapshot of how it was before
+    fn restore(&mut self, snapshot: Snapshot) -> Snapshot {
+        let current = self.snapshot(&snapshot.name);
+        self.image = snapshot.image;
+        self.animation = snapshot.animation;
+        self.color_profile = snapshot.color_profile;
+        self.color_mode = snapshot.color_mode;
+        self.metadata = snapshot.metadata;
+        current
+    }
+
+    /// Remembers the current state of the document so the step about to be
+    /// taken can be undone, and marks the document as changed. Must be called
+    /// before every change to the image
+    pub fn checkpoint(&mut self, name: &str) {
+        let snapshot = self.snapshot(name);
+        let history = &mut self.history;
+        history.undo.push(snapshot);
+        if history.undo.len() > MAX_HISTORY {
+            history.undo.remove(0);
+        }
+        history.redo.clear();
+        history.merging = false;
+        self.mark_changed();
+    }
+
+    /// Like `checkpoint`, but repeats of the same step (like dragging a slider)
+    /// are combined into one, so they can be undone all at once. Only repeats
+    /// within one interaction are combined, see `end_merging`
+    pub fn checkpoint_merged(&mut self, name: &str) {
+        let is_repeat = self.history.merging
+            && self.history.redo.is_empty()
+            && self
+                .history
+                .undo
+                .last()
+                .is_some_and(|last| last.name == name);
+
+        if is_repeat {
+            self.mark_changed();
+        } else {
+            self.checkpoint(name);
+            self.history.merging = true;
+        }
+    }
+
+    /// Stops merging repeats into the last step, so the next change is undone
+    /// separately even if it's the same kind of change. Called whenever the
+    /// user starts a new interaction (like grabbing a slider again)
+    pub fn end_merging(&mut self) {
+        self.history.merging = false;
+    }
+
+    /// Undoes the most recent step, if there is one
+    pub fn undo(&mut self) {
+        if let Some(snapshot) = self.history.undo.pop() {
+            let current = self.restore(snapshot);
+            self.history.redo.push(current);
+            self.history.merging = false;
+            self.mark_changed();
+        }
+    }
+
+    /// Redoes the most recently undone step, if there is one
+    pub fn redo(&mut self) {
+        if let Some(snapshot) = self.history.redo.pop() {
+            let current = self.restore(snapshot);
+            self.history.undo.push(current);
+            self.history.merging = false;
+            self.mark_changed();
+        }
+    }
+}
+
+/// Every open document, in tab order
+///
+/// Like frames of an animation, the active document lives in
+/// `TrametesApp::document` (so everything that edits images doesn't need to
+/// know about tabs), and its entry in `documents` is an empty placeholder until
+/// another tab is selected. Methods that need the active document take it as a
+/// parameter
+#[derive(Debug)]
+pub struct Tabs {
+    /// The open documents, in tab order. There is always at least one
+    pub documents: Vec<Document>,
+
+    /// The index of the document being edited
+    pub active: usize,
+
+    /// The document shown next to the active one while tiled, if any
+    pub tiled_with: Option<usize>,
+}
+
+impl Default for Tabs {
+    fn default() -> Self {
+        Self {
+            documents: vec![Document::default()],
+            active: 0,
+            tiled_with: None,
+        }
+    }
+}
+
+impl Tabs {
+    /// A document, given the active document
+    pub fn document<'a>(&'a self, index: usize, active: &'a Document) -> &'a Document {
+        if index == self.active {
+            active
+        } else {
+            &self.documents[index]
+        }
+    }
+
+    /// Switches to editing another document, storing the active one away and
+    /// swapping the other one in
+    pub fn select(&mut self, active: &mut Document, index: usize) {
+        if index == self.active || index >= self.documents.len() {
+            return;
+        }
+
+        self.documents[self.active] = std::mem::take(active);
+        *active = std::mem::take(&mut self.documents[index]);
+
+        // Keep showing the same two documents side by side
+        if self.tiled_with == Some(index) {
+            self.tiled_with = Some(self.active);
+        }
+        self.active = index;
+    }
+
+    /// Adds a document in a new tab after the active one, and switches to it.
+    /// If the active document is blank and untouched, it's replaced instead
+    pub fn open(&mut self, active: &mut Document, document: Document) {
+        if active.is_pristine() {
+            *active = document;
+            return;
+        }
+
+        let index = self.active + 1;
+        self.documents.insert(index, Document::default());
+        if let Some(tiled_with) = &mut self.tiled_with {
+            if *tiled_with >= index {
+                *tiled_with += 1;
+            }
+        }
+
+        self.documents[self.active] = std::mem::take(active);
+        *active = document;
+        self.active = index;
+    }
+
+    /// Closes a document. Closing the last document leaves a new blank one in
+    /// its place
+    pub fn close(&mut self, active: &mut Document, index: usize) {
+        if self.documents.len() == 1 {
+            *active = Document::default();
+            return;
+        }
+
+        if index == self.active {
+            // Switch to the tab after it (or before it, if it was the last)
+            let next = if index + 1 < self.documents.len() {
+                index + 1
+            } else {
+                index - 1
+            };
+            self.select(active, next);
+        }
+
+        self.documents.remove(index);
+        if self.active > index {
+            self.active -= 1;
+        }
+        self.tiled_with = self
+            .tiled_with
+            .filter(|tiled_with| *tiled_with != index)
+            .map(|tiled_with| {
+                if tiled_with > index {
+                    tiled_with - 1
+                } else {
+                    tiled_with
+                }
+            })
+            .filter(|tiled_with| *tiled_with != self.active);
+    }
+
+    /// Starts or stops showing another document next to the active one
+    pub fn set_tiled(&mut self, tiled: bool) {
+        self.tiled_with = if tiled && self.documents.len() > 1 {
+            // The next tab (wrapping around) is a sensible partner
+            Some((self.active + 1) % self.documents.len())
+        } else {
+            None
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel_buffer::PixelBuffer;
+
+    /// A 1x1 image of a single color
+    fn solid(color: [u8; 4]) -> LayeredImage {
+        LayeredImage::from_flat(PixelBuffer {
+            pixels: color.to_vec(),
+            width: 1,
+            height: 1,
+        })
+    }
+
+    #[test]
+    fn only_repeats_within_one_interaction_are_merged() {
+        let mut document = Document::new(solid([255, 0, 0, 255]), Animation::default());
+        for opacity in [0.9, 0.8, 0.7] {
+            document.checkpoint_merged("Layer Opacity");
+            document.image.active_mut().opacity = opacity;
+        }
+        assert_eq!(document.history.undo.len(), 1);
+
+        // Grabbing the slider again (even for another layer) is a new step
+        document.end_merging();
+        document.checkpoint_merged("Layer Opacity");
+        document.image.active_mut().opacity = 0.5;
+        assert_eq!(document.history.undo.len(), 2);
+
+        document.undo();
+        assert_eq!(document.image.active().opacity, 0.7);
+        document.undo();
+        assert_eq!(document.image.active().opacity, 1.0);
+    }
+
+    #[test]
+    fn reloading_throws_away_changes_and_can_be_undone() {
+        let mut document = Document::new(solid([255, 0, 0, 255]), Animation::default());
+        document.checkpoint("Paint");
+        document.image = solid([0, 255, 0, 255]);
+
+        let loaded = LoadedImage {
+            image: solid([0, 0, 255, 255]),
+            animation: Animation::default(),
+            color_profile: None,
+            color_mode: ColorMode::default(),
+            metadata: Metadata::default(),
+        };
+        document.reload(loaded, None);
+        assert_eq!(document.image.flatten().pixels, [0, 0, 255, 255]);
+        assert!(!document.dirty);
+
+        document.undo();
+        assert_eq!(document.image.flatten().pixels, [0, 255, 0, 255]);
+        assert!(document.dirty);
+    }
+}
@@ -0,0 +1,507 @@
+//! Changes to documents (converting colors, adding layers, flipping, etc.),
+//! independent of any UI. Every change is recorded as a step that can be
+//! undone, and changes that can't be done return an error without changing
+//! anything
+
+use crate::{
+    adjustments::Adjustment,
+    animation::Animation,
+    color_management::{self, ColorProfile},
+    color_mode::{ColorMode, MAX_PALETTE_SIZE},
+    document::Document,
+    error::Error,
+    layers::{Layer, LayeredImage},
+    metadata::Metadata,
+    pixel_buffer::PixelBuffer,
+    quantize::{self, Dithering, QuantizationMethod},
+};
+
+/// Every frame's image, for operations that affect the whole animation
+fn all_images_mut(document: &mut Document) -> impl Iterator<Item = &mut LayeredImage> {
+    std::iter::once(&mut document.image).chain(document.animation.other_images_mut())
+}
+
+/// Every pixel of every layer of every frame, for operations that affect the
+/// whole image (like color conversions)
+fn all_pixel_buffers_mut(document: &mut Document) -> impl Iterator<Item = &mut PixelBuffer> {
+    all_images_mut(document).flat_map(LayeredImage::iter_pixel_buffers_mut)
+}
+
+/// Copies of every frame of a document, with their colors converted from one
+/// color profile to another (so the document is left alone if converting
+/// fails partway through)
+fn converted_frames(
+    document: &Document,
+    from: &ColorProfile,
+    to: &ColorProfile,
+) -> Result<(LayeredImage, Animation), Error> {
+    let mut image = document.image.clone();
+    let mut animation = document.animation.clone();
+
+    for buffer in std::iter::once(&mut image)
+        .chain(animation.other_images_mut())
+        .flat_map(LayeredImage::iter_pixel_buffers_mut)
+    {
+        color_management::convert_pixels(&mut buffer.pixels, from, to)?;
+    }
+
+    Ok((image, animation))
+}
+
+/// Tags the image with a color profile without changing its pixels (so the
+/// colors will look different, but the pixel values stay the same). Passing
+/// None removes the profile, leaving the image untagged (assumed to be sRGB)
+pub fn assign_profile(document: &mut Document, profile: Option<ColorProfile>) {
+    document.checkpoint("Assign Profile");
+    document.color_profile = profile;
+}
+
+/// Converts the pixels of the image from its current color profile to another
+/// one (so the colors look the same, but the pixel values change), then tags
+/// the image with the new profile
+pub fn convert_to_profile(document: &mut Document, profile: ColorProfile) -> Result<(), Error> {
+    let current_profile = document
+        .color_profile
+        .clone()
+        .unwrap_or_else(ColorProfile::srgb);
+    let (image, animation) = converted_frames(document, &current_profile, &profile)?;
+
+    document.checkpoint("Convert to Profile");
+    document.image = image;
+    document.animation = animation;
+    document.color_profile = Some(profile);
+    Ok(())
+}
+
+/// Switches the image to full RGB color (the pixels don't need to change, since
+/// grayscale and palette colors are all valid RGB colors)
+pub fn convert_to_rgb(document: &mut Document) {
+    document.checkpoint("Convert to RGB");
+    document.color_mode = ColorMode::Rgba;
+}
+
+/// Converts the image to grayscale
+pub fn convert_to_grayscale(document: &mut Document) -> Result<(), Error> {
+    // Grayscale images can't have RGB color profiles, so bring the colors into
+    // sRGB (what untagged images are assumed to be) first
+    let converted = match &document.color_profile {
+        Some(profile) => Some(converted_frames(document, profile, &ColorProfile::srgb())?),
+        None => None,
+    };
+
+    document.checkpoint("Convert to Grayscale");
+    if let Some((image, animation)) = converted {
+        document.image = image;
+        document.animation = animation;
+    }
+    document.color_profile = None;
+    document.color_mode = ColorMode::Grayscale;
+    for buffer in all_pixel_buffers_mut(document) {
+        for pixel in buffer.pixels.chunks_exact_mut(4) {
+            let gray = ColorMode::Grayscale.constrain([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            pixel.copy_from_slice(&gray);
+        }
+    }
+    Ok(())
+}
+
+/// Converts the image to indexed color, picking a palette of at most
+/// `max_colors` colors with the given method
+pub fn convert_to_indexed(
+    document: &mut Document,
+    max_colors: usize,
+    method: QuantizationMethod,
+    dithering: Dithering,
+) {
+    document.checkpoint("Convert to Indexed");
+    // Every layer (of every frame) shares the same palette, so pick it based
+    // on all of them
+    let all_pixels: Vec<u8> = std::iter::once(&document.image)
+        .chain(document.animation.other_images())
+        .flat_map(|image| &image.layers)
+        .flat_map(|layer| layer.pixels.pixels.iter().copied())
+        .collect();
+    let palette = quantize::quantize(&all_pixels, max_colors, method);
+
+    for buffer in all_pixel_buffers_mut(document) {
+        quantize::remap(&mut buffer.pixels, buffer.width, &palette, dithering);
+    }
+    document.color_mode = ColorMode::Indexed(palette);
+}
+
+/// Changes a color in the palette of an indexed image, recoloring every pixel
+/// that used it. Repeated changes (like dragging around a color picker) are
+/// undone all at once
+pub fn set_palette_color(
+    document: &mut Document,
+    index: usize,
+    color: [u8; 4],
+) -> Result<(), Error> {
+    let ColorMode::Indexed(palette) = &document.color_mode else {
+        return Err(Error::NotIndexed);
+    };
+    if index >= palette.colors.len() {
+        return Err(Error::OutOfRange(index));
+    }
+
+    document.checkpoint_merged("Edit Palette Color");
+    let ColorMode::Indexed(palette) = &mut document.color_mode else {
+        unreachable!("the image was just checked to be indexed");
+    };
+    let old_color = std::mem::replace(&mut palette.colors[index], color);
+    for buffer in all_pixel_buffers_mut(document) {
+        for pixel in buffer.pixels.chunks_exact_mut(4) {
+            if pixel == old_color {
+                pixel.copy_from_slice(&color);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds a color to the palette of an indexed image
+pub fn add_palette_color(document: &mut Document, color: [u8; 4]) -> Result<(), Error> {
+    let ColorMode::Indexed(palette) = &document.color_mode else {
+        return Err(Error::NotIndexed);
+    };
+    if palette.colors.len() >= MAX_PALETTE_SIZE {
+        return Err(Error::PaletteFull);
+    }
+
+    document.checkpoint("Add Palette Color");
+    if let ColorMode::Indexed(palette) = &mut document.color_mode {
+        palette.colors.push(color);
+    }
+    Ok(())
+}
+
+/// Removes a color from the palette of an indexed image, switching every pixel
+/// that used it to the nearest remaining color
+pub fn remove_palette_color(document: &mut Document, index: usize) -> Result<(), Error> {
+    let ColorMode::Indexed(palette) = &document.color_mode else {
+        return Err(Error::NotIndexed);
+    };
+    if index >= palette.colors.len() {
+        return Err(Error::OutOfRange(index));
+    }
+    // Palettes can't be empty
+    if palette.colors.len() <= 1 {
+        return Err(Error::LastPaletteColor);
+    }
+
+    document.checkpoint("Remove Palette Color");
+    let ColorMode::Indexed(palette) = &mut document.color_mode else {
+        unreachable!("the image was just checked to be indexed");
+    };
+    palette.colors.remove(index);
+    let palette = palette.clone();
+    for buffer in all_pixel_buffers_mut(document) {
+        quantize::remap(&mut buffer.pixels, buffer.width, &palette, Dithering::None);
+    }
+    Ok(())
+}
+
+/// Adjusts the colors of the active layer (of the current frame), keeping them
+/// to colors the image's color mode can represent
+pub fn adjust(document: &mut Document, adjustment: &Adjustment) {
+    document.checkpoint(&adjustment.kind().to_string());
+    adjustment.apply(
+        &mut document.image.active_mut().pixels,
+        &document.color_mode,
+    );
+}
+
+/// Adds a new, empty (transparent) layer above the active layer
+pub fn new_layer(document: &mut Document) {
+    document.checkpoint("New Layer");
+    let image = &mut document.image;
+    let pixels = PixelBuffer {
+        pixels: vec![0; image.width * image.height * 4],
+        width: image.width,
+        height: image.height,
+    };
+    let name = format!("Layer {}", image.layers.len() + 1);
+
+    image.active_layer += 1;
+    image
+        .layers
+        .insert(image.active_layer, Layer::new(name, pixels));
+}
+
+/// Adds a copy of the active layer above it
+pub fn duplicate_layer(document: &mut Document) {
+    document.checkpoint("Duplicate Layer");
+    let image = &mut document.image;
+    let mut layer = image.active().clone();
+    layer.name = format!("{} copy", layer.name);
+
+    image.active_layer += 1;
+    image.layers.insert(image.active_layer, layer);
+}
+
+/// Adds a layer (from another image) above the active layer, converting its
+/// colors from its color profile (None meaning sRGB) to fit the image. The
+/// step is named `step_name` in the undo history
+pub fn insert_layer(
+    document: &mut Document,
+    step_name: &str,
+    mut layer: Layer,
+    color_profile: Option<&ColorProfile>,
+) -> Result<(), Error> {
+    // (Untagged images are assumed to be sRGB)
+    let srgb = ColorProfile::srgb();
+    let from = color_profile.unwrap_or(&srgb);
+    let to = document.color_profile.as_ref().unwrap_or(&srgb);
+    if !(from.is_srgb() && to.is_srgb()) {
+        color_management::convert_pixels(&mut layer.pixels.pixels, from, to)?;
+    }
+    for pixel in layer.pixels.pixels.chunks_exact_mut(4) {
+        let constrained = document
+            .color_mode
+            .constrain(pixel.try_into().expect("pixels are 4 bytes"));
+        pixel.copy_from_slice(&constrained);
+    }
+
+    document.checkpoint(step_name);
+    let image = &mut document.image;
+    image.active_layer += 1;
+    image.layers.insert(image.active_layer, layer);
+    Ok(())
+}
+
+/// Deletes the active layer (unless it's the only one)
+pub fn delete_layer(document: &mut Document) -> Result<(), Error> {
+    if document.image.layers.len() <= 1 {
+        return Err(Error::LastLayer);
+    }
+
+    document.checkpoint("Delete Layer");
+    let image = &mut document.image;
+    image.layers.remove(image.active_layer);
+    image.active_layer = image.active_layer.saturating_sub(1);
+    Ok(())
+}
+
+/// Moves the active layer one step up (towards the top of the stack), if it
+/// isn't already at the top
+pub fn move_layer_up(document: &mut Document) {
+    let index = document.image.active_layer;
+    if index + 1 < document.image.layers.len() {
+        document.checkpoint("Move Layer Up");
+        document.image.layers.swap(index, index + 1);
+        document.image.active_layer += 1;
+    }
+}
+
+/// Moves the active layer one step down (towards the bottom of the stack), if
+/// it isn't already at the bottom
+pub fn move_layer_down(document: &mut Document) {
+    let index = document.image.active_layer;
+    if index > 0 {
+        document.checkpoint("Move Layer Down");
+        document.image.layers.swap(index, index - 1);
+        document.image.active_layer -= 1;
+    }
+}
+
+/// Mirrors every frame of the image left to right
+pub fn flip_horizontal(document: &mut Document) {
+    document.checkpoint("Flip Horizontal");
+    all_images_mut(document).for_each(LayeredImage::flip_horizontal);
+}
+
+/// Mirrors every frame of the image top to bottom
+pub fn flip_vertical(document: &mut Document) {
+    document.checkpoint("Flip Vertical");
+    all_images_mut(document).for_each(LayeredImage::flip_vertical);
+}
+
+/// Combines all the visible layers into a single layer
+pub fn flatten_image(document: &mut Document) {
+    document.checkpoint("Flatten Image");
+    document.image = LayeredImage::from_flat(document.image.flatten());
+}
+
+/// Adds a blank frame after the current one
+pub fn add_frame(document: &mut Document) {
+    document.checkpoint("Add Frame");
+    document.animation.add_frame(&mut document.image, false);
+}
+
+/// Adds a copy of the current frame after it
+pub fn duplicate_frame(document: &mut Document) {
+    document.checkpoint("Duplicate Frame");
+    document.animation.add_frame(&mut document.image, true);
+}
+
+/// Deletes the current frame (unless it's the only one)
+pub fn delete_frame(document: &mut Document) -> Result<(), Error> {
+    if !document.animation.is_animated() {
+        return Err(Error::LastFrame);
+    }
+
+    document.checkpoint("Delete Frame");
+    document.animation.delete_frame(&mut document.image);
+    Ok(())
+}
+
+/// Removes the GPS location from the image's metadata (it will still be in the
+/// original file until the image is saved)
+pub fn remove_gps_metadata(document: &mut Document) {
+    document.checkpoint("Remove GPS Location");
+    document.metadata.remove_gps();
+}
+
+/// Removes all of the image's metadata (it will still be in the original file
+/// until the image is saved)
+pub fn remove_all_metadata(document: &mut Document) {
+    document.checkpoint("Remove Metadata");
+    document.metadata = Metadata::default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adjustments::BrightnessContrast;
+    use crate::tools::{PointerEvent, Tool, ToolState};
+
+    /// A 2x1 image with a red pixel on the left and a blue one on the right
+    fn red_blue_document() -> Document {
+        let pixels = PixelBuffer {
+            pixels: vec![255, 0, 0, 255, 0, 0, 255, 255],
+            width: 2,
+            height: 1,
+        };
+        Document::new(LayeredImage::from_flat(pixels), Animation::default())
+    }
+
+    #[test]
+    fn flipping_can_be_undone() {
+        let mut document = red_blue_document();
+
+        flip_horizontal(&mut document);
+        assert_eq!(
+            document.image.layers[0].pixels.pixels,
+            [0, 0, 255, 255, 255, 0, 0, 255]
+        );
+        assert!(document.dirty);
+
+        document.undo();
+        assert_eq!(
+            document.image.layers[0].pixels.pixels,
+            [255, 0, 0, 255, 0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn adjustments_change_only_the_active_layer_as_previewed() {
+        let mut document = red_blue_document();
+        duplicate_layer(&mut document);
+        let darken = Adjustment::BrightnessContrast(BrightnessContrast {
+            brightness: -1.0,
+            contrast: 0.0,
+        });
+
+        let mut preview = document.image.active().pixels.clone();
+        darken.apply(&mut preview, &document.color_mode);
+        let previewed = document.image.flatten_with_active(&preview);
+
+        adjust(&mut document, &darken);
+        assert_eq!(
+            document.image.active().pixels.pixels,
+            [0, 0, 0, 255, 0, 0, 0, 255]
+        );
+        assert_eq!(
+            document.image.layers[0].pixels.pixels,
+            [255, 0, 0, 255, 0, 0, 255, 255]
+        );
+        assert_eq!(document.image.flatten().pixels, previewed.pixels);
+
+        document.undo();
+        assert_eq!(
+            document.image.active().pixels.pixels,
+            [255, 0, 0, 255, 0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn indexed_palettes_only_use_colors_from_the_frames() {
+        let mut document = red_blue_document();
+        duplicate_frame(&mut document);
+
+        convert_to_indexed(
+            &mut document,
+            4,
+            QuantizationMethod::default(),
+            Dithering::None,
+        );
+        let ColorMode::Indexed(palette) = &document.color_mode else {
+            panic!("the image should be indexed");
+        };
+        assert!(!palette.colors.contains(&[255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn deleting_the_last_layer_fails_without_changing_anything() {
+        let mut document = red_blue_document();
+
+        assert!(matches!(delete_layer(&mut document), Err(Error::LastLayer)));
+        assert_eq!(document.image.layers.len(), 1);
+        assert!(document.history.undo.is_empty());
+        assert!(!document.dirty);
+    }
+
+    #[test]
+    fn palette_operations_need_an_indexed_image() {
+        let mut document = red_blue_document();
+        assert!(matches!(
+            add_palette_color(&mut document, [0, 0, 0, 255]),
+            Err(Error::NotIndexed)
+        ));
+
+        convert_to_indexed(
+            &mut document,
+            2,
+            QuantizationMethod::default(),
+            Dithering::None,
+        );
+        add_palette_color(&mut document, [0, 0, 0, 255]).unwrap();
+        let ColorMode::Indexed(palette) = &document.color_mode else {
+            panic!("the image should be indexed");
+        };
+        assert_eq!(palette.colors.len(), 3);
+    }
+
+    #[test]
+    fn brush_strokes_are_undone_one_at_a_time() {
+        let mut document = red_blue_document();
+        let tools = ToolState {
+            current_tool: Tool::Brush,
+            ..Default::default()
+        };
+        let white = [255, 255, 255, 255];
+
+        for pos in [(0.5, 0.5), (1.5, 0.5)] {
+            tools.handle_event(PointerEvent::Press { pos }, &mut document, white, false);
+            tools.handle_event(
+                PointerEvent::Drag {
+                    pos,
+                    screen_delta: (0.0, 0.0),
+                },
+                &mut document,
+                white,
+                false,
+            );
+            tools.handle_event(PointerEvent::Release, &mut document, white, false);
+        }
+        assert_eq!(document.image.layers[0].pixels.pixels, [255; 8]);
+        assert_eq!(document.history.undo.len(), 2);
+
+        document.undo();
+        document.undo();
+        assert_eq!(
+            document.image.layers[0].pixels.pixels,
+            [255, 0, 0, 255, 0, 0, 255, 255]
+        );
+    }
+}
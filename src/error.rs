@@ -0,0 +1,167 @@
+//! The errors that editing and file operations can fail with
+
+use std::{fmt::Display, io::ErrorKind};
+
+use image::error::{ImageError, LimitErrorKind};
+
+/// Why an operation on an image couldn't be done
+#[derive(Debug)]
+pub enum Error {
+    /// The file isn't in a format that can be read (or the image can't be
+    /// saved in the format asked for), with what wasn't supported
+    UnsupportedFormat(String),
+
+    /// The file is damaged, or isn't what it claims to be, with what went
+    /// wrong while decoding it
+    Decode(String),
+
+    /// The image couldn't be encoded in the format asked for, with what went
+    /// wrong
+    Encode(String),
+
+    /// The file doesn't exist
+    NotFound,
+
+    /// The file (or the directory it's in) can't be read or written by us
+    PermissionDenied,
+
+    /// There isn't enough memory to hold the image
+    OutOfMemory,
+
+    /// The image is wider or taller than can be handled
+    DimensionsTooLarge,
+
+    /// Reading or writing a file failed for some other reason
+    Io(std::io::Error),
+
+    /// The file dialog couldn't be shown, with why
+    Dialog(String),
+
+    /// The browser couldn't do something for us (on the web), with what went
+    /// wrong
+    Browser(String),
+
+    /// The pixels couldn't be converted from one color profile to another
+    ColorConversion(moxcms::CmsError),
+
+    /// A palette operation was used on an image that isn't indexed
+    NotIndexed,
+
+    /// A color was added to a palette that already has the most colors it can
+    PaletteFull,
+
+    /// There's no palette color (or layer, or frame) with the given index
+    OutOfRange(usize),
+
+    /// The only color of a palette was removed (palettes can't be empty)
+    LastPaletteColor,
+
+    /// The only layer of an image was deleted (images need at least one)
+    LastLayer,
+
+    /// The only frame of an image was deleted (images need at least one)
+    LastFrame,
+
+    /// The user cancelled opening or saving a file before it finished
+    Cancelled,
+}
+
+impl Error {
+    /// Makes an error from one returned while reading a file, keeping the
+    /// kinds of errors we know about and treating anything else as the file
+    /// being invalid
+    pub(crate) fn decoding(err: Box<dyn std::error::Error>) -> Self {
+        Self::from_boxed(err, Self::Decode)
+    }
+
+    /// Makes an error from one returned while writing a file, keeping the
+    /// kinds of errors we know about and treating anything else as the image
+    /// not being encodable
+    pub(crate) fn encoding(err: Box<dyn std::error::Error>) -> Self {
+        Self::from_boxed(err, Self::Encode)
+    }
+
+    fn from_boxed(err: Box<dyn std::error::Error>, otherwise: fn(String) -> Self) -> Self {
+        let err = match err.downcast::<Self>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<std::io::Error>() {
+            Ok(err) => return (*err).into(),
+            Err(err) => err,
+        };
+        match err.downcast::<ImageError>() {
+            Ok(err) => (*err).into(),
+            Err(err) => otherwise(err.to_string()),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedFormat(details) => write!(f, "Unsupported format: {details}"),
+            Self::Decode(details) => write!(f, "The file is damaged or invalid: {details}"),
+            Self::Encode(details) => write!(f, "The image couldn't be encoded: {details}"),
+            Self::NotFound => write!(f, "The file doesn't exist"),
+            Self::PermissionDenied => write!(f, "Permission denied"),
+            Self::OutOfMemory => write!(f, "There isn't enough memory for the image"),
+            Self::DimensionsTooLarge => write!(f, "The image is too large"),
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Dialog(details) => write!(f, "The file dialog couldn't be shown: {details}"),
+            Self::Browser(details) => write!(f, "The browser couldn't do that: {details}"),
+            Self::ColorConversion(err) => write!(f, "Failed to convert colors: {err:?}"),
+            Self::NotIndexed => write!(f, "The image doesn't have a palette"),
+            Self::PaletteFull => write!(f, "The palette can't have any more colors"),
+            Self::OutOfRange(index) => write!(f, "There is nothing at index {index}"),
+            Self::LastPaletteColor => write!(f, "The palette needs at least one color"),
+            Self::LastLayer => write!(f, "The image needs at least one layer"),
+            Self::LastFrame => write!(f, "The image needs at least one frame"),
+            Self::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::NotFound => Self::NotFound,
+            ErrorKind::PermissionDenied => Self::PermissionDenied,
+            ErrorKind::OutOfMemory => Self::OutOfMemory,
+            ErrorKind::UnexpectedEof => Self::Decode("the file is cut off".to_string()),
+            _ => Self::Io(err),
+        }
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(err: ImageError) -> Self {
+        match err {
+            ImageError::Decoding(err) => Self::Decode(err.to_string()),
+            ImageError::Encoding(err) => Self::Encode(err.to_string()),
+            ImageError::Unsupported(err) => Self::UnsupportedFormat(err.to_string()),
+            ImageError::Limits(err) => match err.kind() {
+                LimitErrorKind::DimensionError => Self::DimensionsTooLarge,
+                LimitErrorKind::InsufficientMemory => Self::OutOfMemory,
+                _ => Self::Decode(err.to_string()),
+            },
+            ImageError::Parameter(err) => Self::Decode(err.to_string()),
+            ImageError::IoError(err) => err.into(),
+        }
+    }
+}
+
+impl From<moxcms::CmsError> for Error {
+    fn from(err: moxcms::CmsError) -> Self {
+        Self::ColorConversion(err)
+    }
+}
@@ -0,0 +1,205 @@
+//! The format-specific options for saving images, and remembering the
+//! last-used ones
+
+use std::fmt::Display;
+
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+
+use crate::fileio;
+
+/// The name of the file the last-used options are stored in (in the app's
+/// data directory)
+const FILE_NAME: &str = "export_options.json";
+
+/// How hard to try to make PNG files small (at the cost of saving speed)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PngCompression {
+    /// Saves quickly, making larger files
+    Fast,
+
+    /// A balance between speed and file size
+    #[default]
+    Default,
+
+    /// Makes the smallest files, saving slowly
+    Best,
+}
+
+impl Display for PngCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fast => write!(f, "Fast"),
+            Self::Default => write!(f, "Default"),
+            Self::Best => write!(f, "Best (slow)"),
+        }
+    }
+}
+
+/// The options for saving PNG files
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PngOptions {
+    /// How hard to try to make files small
+    pub compression: PngCompression,
+}
+
+/// How much color (chroma) detail to throw away in JPEG files, relative to
+/// brightness (luma) detail
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChromaSubsampling {
+    /// No subsampling (the best quality)
+    Full,
+
+    /// Half the horizontal color resolution
+    Half,
+
+    /// Half the horizontal and vertical color resolution (the smallest files,
+    /// and what most software uses)
+    #[default]
+    Quarter,
+}
+
+impl ChromaSubsampling {
+    /// Every option, in the order they should be shown in the UI
+    pub const ALL: [ChromaSubsampling; 3] = [Self::Full, Self::Half, Self::Quarter];
+}
+
+impl Display for ChromaSubsampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => write!(f, "4:4:4 (best quality)"),
+            Self::Half => write!(f, "4:2:2"),
+            Self::Quarter => write!(f, "4:2:0 (smallest)"),
+        }
+    }
+}
+
+/// The options for saving JPEG files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JpegOptions {
+    /// From 1 (smallest) to 100 (best quality)
+    pub quality: u8,
+
+    /// How much color detail to throw away
+    pub chroma_subsampling: ChromaSubsampling,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        Self {
+            quality: 90,
+            chroma_subsampling: ChromaSubsampling::default(),
+        }
+    }
+}
+
+/// The options for saving WebP files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebPOptions {
+    /// Whether to store the exact pixels (making larger files)
+    pub lossless: bool,
+
+    /// From 0 (smallest) to 100 (best quality), ignored for lossless files
+    pub quality: u8,
+}
+
+impl Default for WebPOptions {
+    fn default() -> Self {
+        Self {
+            lossless: false,
+            quality: 80,
+        }
+    }
+}
+
+/// The format-specific options for saving images, one set per format so the
+/// last-used options for each format can be remembered
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    /// The options for PNG files
+    pub png: PngOptions,
+
+    /// The options for JPEG files
+    pub jpeg: JpegOptions,
+
+    /// The options for WebP files
+    pub webp: WebPOptions,
+}
+
+impl ExportOptions {
+    /// Loads the options last used by a previous run, or the defaults if there
+    /// aren't any (or they can't be read)
+    pub fn load() -> Self {
+        let mut options: Self = fileio::load_app_data(FILE_NAME);
+        // (In case the file was edited by hand)
+        options.jpeg.quality = options.jpeg.quality.clamp(1, 100);
+        options.webp.quality = options.webp.quality.min(100);
+        options
+    }
+
+    /// Writes the options out for future runs
+    pub fn save(&self) {
+        fileio::save_app_data(FILE_NAME, self);
+    }
+
+    /// Whether saving in the given format with these options loses detail
+    /// (and so is worth previewing)
+    pub fn is_lossy(&self, format: ImageFormat) -> bool {
+        match format {
+            ImageFormat::Jpeg => true,
+            ImageFormat::WebP => LOSSY_WEBP && !self.webp.lossless,
+            _ => false,
+        }
+    }
+}
+
+/// Whether WebP files can be saved with lossy compression, which needs
+/// libwebp (a C library that isn't built for the web, where WebP files are
+/// always lossless)
+pub const LOSSY_WEBP: bool = cfg!(not(target_arch = "wasm32"));
+
+/// Formats a file size in a human-readable way (like "1.5 MiB")
+pub fn format_file_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["bytes", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} {}", UNITS[0])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_options_use_defaults() {
+        let options: ExportOptions =
+            serde_json::from_str(r#"{ "jpeg": { "quality": 50 }, "png": {} }"#).unwrap();
+
+        assert_eq!(
+            options,
+            ExportOptions {
+                jpeg: JpegOptions {
+                    quality: 50,
+                    ..JpegOptions::default()
+                },
+                ..ExportOptions::default()
+            }
+        );
+    }
+}
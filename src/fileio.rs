@@ -1,72 +1,1707 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-use image::{io::Reader, DynamicImage, GenericImageView, ImageBuffer};
-use native_dialog::{FileDialog, MessageType};
+use flate2::{write::ZlibEncoder, Compression};
+use image::{
+    codecs::{
+        gif::GifDecoder,
+        jpeg::JpegDecoder,
+        png::{CompressionType, FilterType as PngFilterType, PngDecoder, PngEncoder},
+        webp::{WebPDecoder, WebPEncoder},
+    },
+    io::{Limits, Reader},
+    AnimationDecoder, ColorType, DynamicImage, GenericImageView, ImageBuffer, ImageDecoder,
+    ImageEncoder, ImageFormat,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use native_dialog::FileDialog;
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{pixel_buffer::PixelBuffer, ui};
+use crate::{
+    animation::{Animation, AnimationFrame, FlatFrame, LoopCount, DEFAULT_FRAME_DELAY_MS},
+    color_management::ColorProfile,
+    color_mode::{luma, ColorMode, Palette, MAX_PALETTE_SIZE},
+    error::Error,
+    export::{
+        ChromaSubsampling, ExportOptions, JpegOptions, PngCompression, PngOptions, WebPOptions,
+    },
+    jobs::Progress,
+    layers::LayeredImage,
+    metadata::{self, Metadata},
+    openraster,
+    pixel_buffer::PixelBuffer,
+    project, psd,
+    quantize::{self, Dithering},
+};
 
-/// Prompts the user for an image file to open, returning a path (or None if the
-/// user did not provide one)
-pub fn get_image_path_to_open() -> Option<PathBuf> {
-    match FileDialog::new().show_open_single_file() {
-        Ok(path) => path,
+/// An image read from a file, along with any extra information stored in the
+/// file that we care about
+#[derive(Debug)]
+pub struct LoadedImage {
+    /// The pixels of the image (the frame to edit, for animated images: the
+    /// first one, unless a project file says otherwise)
+    pub image: LayeredImage,
+
+    /// The frames of the image (with the current frame's image moved into
+    /// `image`), which is just the one frame for still images
+    pub animation: Animation,
+
+    /// The ICC color profile embedded in the file, if there was one
+    pub color_profile: Option<ColorProfile>,
+
+    /// The color mode the file was stored in (grayscale or palette-based files
+    /// open as grayscale or indexed images)
+    pub color_mode: ColorMode,
+
+    /// The EXIF and XMP metadata embedded in the file
+    pub metadata: Metadata,
+}
+
+/// What a file the user is asked to open is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilePurpose {
+    /// Opening an image in a new tab
+    Open,
+
+    /// Adding an image as a new layer of the current image
+    AddLayer,
+
+    /// Splitting a sprite sheet into layers or frames
+    ImportSpriteSheet,
+
+    /// Tagging the current image with an ICC profile
+    AssignProfile,
+
+    /// Converting the current image to an ICC profile
+    ConvertToProfile,
+
+    /// Reading a document's file again, replacing what's in the document
+    Reload {
+        /// Which document to reload
+        document_id: u64,
+
+        /// For reloads because another program changed the file, the
+        /// document's revision when the change was noticed (if the document
+        /// changes before the file is read, the user is asked instead)
+        revision: Option<u64>,
+    },
+}
+
+impl FilePurpose {
+    /// Whether the file should be an ICC profile (rather than an image)
+    pub fn is_icc_profile(self) -> bool {
+        matches!(self, Self::AssignProfile | Self::ConvertToProfile)
+    }
+}
+
+/// A file the user chose to open, and its contents
+#[derive(Debug)]
+pub struct PickedFile {
+    /// Where the file came from (just its name, on the web)
+    pub path: PathBuf,
+
+    /// The contents of the file
+    pub bytes: Vec<u8>,
+}
+
+/// Prompts the user for a file to open, returning a path (or None if the user
+/// did not provide one)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_path_to_open(purpose: FilePurpose) -> Result<Option<PathBuf>, Error> {
+    let mut dialog = FileDialog::new();
+    if purpose.is_icc_profile() {
+        dialog = dialog.add_filter("ICC Profile", &["icc", "icm"]);
+    }
+    dialog
+        .show_open_single_file()
+        .map_err(|err| Error::Dialog(err.to_string()))
+}
+
+/// Reads the embedded ICC profile (if any) from an image file. Only formats
+/// the `image` crate exposes ICC profiles for are supported
+fn read_icc_profile(bytes: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let reader = Cursor::new(bytes);
+
+    match format {
+        ImageFormat::Png => PngDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Builds a palette from the raw RGB palette bytes and per-entry alpha values
+/// found in PNG (PLTE/tRNS) and GIF files
+fn palette_from_rgb(rgb: &[u8], alpha: impl Fn(usize) -> u8) -> Palette {
+    Palette {
+        colors: rgb
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(index, color)| [color[0], color[1], color[2], alpha(index)])
+            .collect(),
+    }
+}
+
+/// Works out which color mode an image file is stored in. Only PNG and GIF
+/// files can be stored as anything other than RGBA
+fn read_color_mode(bytes: &[u8], format: ImageFormat) -> Option<ColorMode> {
+    let reader = Cursor::new(bytes);
+
+    match format {
+        ImageFormat::Png => {
+            let reader = png::Decoder::new(reader).read_info().ok()?;
+            let info = reader.info();
+
+            match info.color_type {
+                png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                    Some(ColorMode::Grayscale)
+                }
+                png::ColorType::Indexed => {
+                    let trns = info.trns.as_deref().unwrap_or(&[]);
+                    let palette = palette_from_rgb(info.palette.as_deref()?, |index| {
+                        trns.get(index).copied().unwrap_or(255)
+                    });
+                    Some(ColorMode::Indexed(palette))
+                }
+                _ => None,
+            }
+        }
+        ImageFormat::Gif => {
+            let mut decoder = gif::DecodeOptions::new().read_info(reader).ok()?;
+            let global_palette = decoder.global_palette().map(<[u8]>::to_vec);
+
+            // Each frame of an animated GIF can have its own palette, so
+            // combine them all (as long as they fit in a single palette)
+            let mut colors: Vec<[u8; 4]> = Vec::new();
+            while let Some(frame) = decoder.next_frame_info().ok()? {
+                let transparent = frame.transparent.map(|index| index as usize);
+                let palette = palette_from_rgb(
+                    frame.palette.as_deref().or(global_palette.as_deref())?,
+                    |index| if Some(index) == transparent { 0 } else { 255 },
+                );
+
+                for color in palette.colors {
+                    if !colors.contains(&color) {
+                        colors.push(color);
+                    }
+                }
+            }
+
+            if colors.is_empty() || colors.len() > MAX_PALETTE_SIZE {
+                return None;
+            }
+            Some(ColorMode::Indexed(Palette { colors }))
+        }
+        _ => None,
+    }
+}
+
+/// Decodes every frame of an animated GIF, PNG, or WebP file, returning None
+/// for still images (and formats that can't be animated)
+fn read_animation_frames(bytes: &[u8], format: ImageFormat) -> Option<Vec<image::Frame>> {
+    let reader = Cursor::new(bytes);
+
+    let frames = match format {
+        ImageFormat::Gif => GifDecoder::new(reader).ok()?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader).ok()?;
+            if !decoder.is_apng() {
+                return None;
+            }
+            decoder.apng().into_frames()
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader).ok()?;
+            if !decoder.has_animation() {
+                return None;
+            }
+            decoder.into_frames()
+        }
+        _ => return None,
+    };
+
+    match frames.collect_frames() {
+        Ok(frames) if frames.len() > 1 => Some(frames),
+        Ok(_) => None,
         Err(err) => {
-            eprintln!("failed to get an image file path from the user: {err:?}");
-            ui::message_popup("Failed to prompt for a file to open", MessageType::Error);
+            eprintln!("failed to decode animation frames: {err}");
             None
         }
     }
 }
 
+/// The chunks (type and data) of a WebP file, or any other RIFF file
+fn webp_chunks(bytes: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 12;
+    std::iter::from_fn(move || {
+        let chunk_type = bytes.get(pos..pos + 4)?;
+        let len = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().unwrap()) as usize;
+        let data = bytes.get(pos + 8..pos + 8 + len)?;
+
+        // Chunks are padded to an even length
+        pos += 8 + len + len % 2;
+        Some((chunk_type, data))
+    })
+}
+
+/// Skips over a run of GIF sub-blocks (each a length byte followed by that
+/// many bytes, ending with an empty one), returning each one's data and the
+/// position after them all
+fn gif_sub_blocks(bytes: &[u8], mut pos: usize) -> Option<(Vec<&[u8]>, usize)> {
+    let mut sub_blocks = Vec::new();
+    loop {
+        let len = *bytes.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            return Some((sub_blocks, pos));
+        }
+        sub_blocks.push(bytes.get(pos..pos + len)?);
+        pos += len;
+    }
+}
+
+/// Reads how many times a GIF should repeat after its first play from its
+/// NETSCAPE2.0 application extension, by walking the GIF's blocks. None if it
+/// doesn't have one
+///
+/// See https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+fn read_gif_repeats(bytes: &[u8]) -> Option<u16> {
+    // The size of a color table, if the flags in a header say there is one
+    let color_table_len = |flags: u8| {
+        if flags & 0x80 != 0 {
+            3 << ((flags & 0x07) + 1)
+        } else {
+            0
+        }
+    };
+
+    // The header, then the logical screen descriptor and global color table
+    let mut pos = 13 + color_table_len(*bytes.get(10)?);
+    loop {
+        match *bytes.get(pos)? {
+            // An extension, made up of a label and sub-blocks
+            0x21 => {
+                let label = *bytes.get(pos + 1)?;
+                let (sub_blocks, next) = gif_sub_blocks(bytes, pos + 2)?;
+                if let [b"NETSCAPE2.0", [1, low, high], ..] = sub_blocks.as_slice() {
+                    if label == 0xFF {
+                        return Some(u16::from_le_bytes([*low, *high]));
+                    }
+                }
+                pos = next;
+            }
+            // An image, made up of its descriptor, local color table, LZW
+            // code size, and sub-blocks of image data
+            0x2C => {
+                let flags = *bytes.get(pos + 9)?;
+                let data = pos + 10 + color_table_len(flags) + 1;
+                pos = gif_sub_blocks(bytes, data)?.1;
+            }
+            // The trailer (or garbage)
+            _ => return None,
+        }
+    }
+}
+
+/// Reads how many times an animated GIF, PNG, or WebP file should play
+fn read_loop_count(bytes: &[u8], format: ImageFormat) -> LoopCount {
+    match format {
+        ImageFormat::Gif => {
+            // GIFs store how many times to repeat *after* the first play, and
+            // GIFs without a repeat count only play once
+            match read_gif_repeats(bytes) {
+                None => LoopCount::Finite(1),
+                Some(0) => LoopCount::Infinite,
+                Some(repeats) => LoopCount::Finite(repeats.saturating_add(1)),
+            }
+        }
+        ImageFormat::Png => {
+            let plays = png::Decoder::new(bytes)
+                .read_info()
+                .ok()
+                .and_then(|reader| reader.info().animation_control)
+                .map(|control| control.num_plays);
+
+            match plays {
+                None | Some(0) => LoopCount::Infinite,
+                Some(plays) => LoopCount::Finite(plays.min(u16::MAX as u32) as u16),
+            }
+        }
+        ImageFormat::WebP => {
+            let plays = webp_chunks(bytes)
+                .find(|(chunk_type, _)| *chunk_type == b"ANIM")
+                .and_then(|(_, data)| data.get(4..6))
+                .map(|plays| u16::from_le_bytes([plays[0], plays[1]]));
+
+            match plays {
+                None | Some(0) => LoopCount::Infinite,
+                Some(plays) => LoopCount::Finite(plays),
+            }
+        }
+        _ => LoopCount::default(),
+    }
+}
+
+/// The directory Trametes keeps its own files in (like the recent files list
+/// and autosaves), following each platform's convention for configuration
+/// files. None if there's nowhere to keep them
+#[cfg(not(target_arch = "wasm32"))]
+pub fn app_data_dir() -> Option<PathBuf> {
+    let env_path = |name| std::env::var_os(name).map(PathBuf::from);
+
+    let config_dir = if cfg!(target_os = "windows") {
+        env_path("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        env_path("HOME")?.join("Library/Application Support")
+    } else {
+        env_path("XDG_CONFIG_HOME")
+            .filter(|path| path.is_absolute())
+            .or_else(|| Some(env_path("HOME")?.join(".config")))?
+    };
+
+    Some(config_dir.join("trametes"))
+}
+
+/// The directory Trametes keeps its own files in. Browsers don't give us a file
+/// system, so there isn't one on the web (yet)
+#[cfg(target_arch = "wasm32")]
+pub fn app_data_dir() -> Option<PathBuf> {
+    None
+}
+
+/// Loads something a previous run saved with [`save_app_data`] (like the recent
+/// files list), or the default if there's nothing saved (or it can't be read).
+/// Each thing the app remembers between runs is kept in its own small JSON
+/// file in the app's data directory, so a damaged file only loses that one
+/// thing (and on the web, where there's no data directory, nothing is
+/// remembered)
+pub fn load_app_data<T: DeserializeOwned + Default>(file_name: &str) -> T {
+    let Some(path) = app_data_dir().map(|dir| dir.join(file_name)) else {
+        return T::default();
+    };
+
+    match std::fs::read(&path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+            eprintln!("failed to parse {path:?}: {err:?}");
+            T::default()
+        }),
+        // Nothing has been saved yet the first time the app runs
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => T::default(),
+        Err(err) => {
+            eprintln!("failed to read {path:?}: {err:?}");
+            T::default()
+        }
+    }
+}
+
+/// Saves something as JSON in the app's data directory, for future runs.
+/// Failing to save it isn't worth bothering the user about, so errors are only
+/// logged
+pub fn save_app_data<T: Serialize>(file_name: &str, value: &T) {
+    let Some(path) = app_data_dir().map(|dir| dir.join(file_name)) else {
+        return;
+    };
+
+    let result = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| serde_json::to_vec_pretty(value).map_err(Into::into))
+        .and_then(|json| std::fs::write(&path, json));
+    if let Err(err) = result {
+        eprintln!("failed to save {path:?}: {err:?}");
+    }
+}
+
+/// The largest width or height of image that can be opened (much larger images
+/// would need more memory than most computers have)
+pub const MAX_DIMENSION: usize = 1 << 16;
+
+/// The most memory that's allocated for the pixels of an image being read
+/// (enough for the largest image that can be opened, in 8-bit RGBA)
+pub const MAX_ALLOC: u64 = MAX_DIMENSION as u64 * MAX_DIMENSION as u64 * 4;
+
+/// Checks that an image isn't too large to open, before any memory is
+/// allocated for its pixels
+pub fn check_dimensions(width: usize, height: usize) -> Result<(), Error> {
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(Error::DimensionsTooLarge);
+    }
+    Ok(())
+}
+
+/// The limits every image decoded with the `image` crate is read with, so
+/// that a file can't claim huge sizes to make it allocate too much
+fn image_limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION as u32);
+    limits.max_image_height = Some(MAX_DIMENSION as u32);
+    limits.max_alloc = Some(MAX_ALLOC);
+    limits
+}
+
+/// Whether or not a path has the given file extension (ignoring case)
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|path_extension| path_extension.eq_ignore_ascii_case(extension))
+}
+
+/// Whether or not a path is for a project file (which keeps layers and
+/// every frame)
+pub fn is_project(path: &Path) -> bool {
+    has_extension(path, project::EXTENSION)
+}
+
+/// Whether or not a path is for an OpenRaster file (which keeps layers, but
+/// not color profiles or color modes)
+pub fn is_openraster(path: &Path) -> bool {
+    has_extension(path, openraster::EXTENSION)
+}
+
+/// When the file at a path was last modified, or None if that can't be told
+/// (like if the file doesn't exist)
+pub fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
 /// Reads an image from a file path
-pub fn read_image_from_file(path: &Path) -> Option<((u32, u32), Vec<u8>)> {
-    let img = Reader::open(path)
-        .ok()?
+pub fn read_image_from_file(path: &Path) -> Result<LoadedImage, Error> {
+    read_image(path, &std::fs::read(path)?)
+}
+
+/// Reads an image from the contents of a file, with `path` being where the file
+/// came from (only its extension is used, to tell layered formats apart)
+pub fn read_image(path: &Path, bytes: &[u8]) -> Result<LoadedImage, Error> {
+    if is_project(path) {
+        return project::read_project(Cursor::new(bytes)).map_err(Error::decoding);
+    }
+
+    if is_openraster(path) {
+        return openraster::read_openraster(Cursor::new(bytes)).map_err(Error::decoding);
+    }
+
+    if psd::EXTENSIONS
+        .iter()
+        .any(|extension| has_extension(path, extension))
+    {
+        return psd::read_psd(Cursor::new(bytes)).map_err(Error::decoding);
+    }
+
+    let mut reader = Reader::new(Cursor::new(bytes))
         // Guess the encoding format based on the file contents instead of the
         // extension
-        .with_guessed_format()
-        .ok()?
-        .decode()
-        .ok()?;
+        .with_guessed_format()?;
+    reader.limits(image_limits());
+    if reader.format().is_none() {
+        return Err(Error::UnsupportedFormat(
+            "the file isn't an image format we recognize".to_string(),
+        ));
+    }
+
+    let color_profile = reader
+        .format()
+        .and_then(|format| read_icc_profile(bytes, format))
+        .and_then(|icc| {
+            let profile = ColorProfile::from_icc(icc);
+            if profile.is_none() {
+                eprintln!("ignoring unsupported ICC profile embedded in {path:?}");
+            }
+            profile
+        });
+
+    let format = reader.format();
+    let mut color_mode = format
+        .and_then(|format| read_color_mode(bytes, format))
+        .unwrap_or_default();
+
+    let mut metadata = Metadata::read_from_file(bytes);
+
+    // Animated images are decoded frame by frame, and still images in one go
+    let (mut frames, loop_count) =
+        match format.and_then(|format| read_animation_frames(bytes, format)) {
+            Some(frames) => {
+                let frames: Vec<(DynamicImage, u32)> = frames
+                    .into_iter()
+                    .map(|frame| {
+                        let (numerator, denominator) = frame.delay().numer_denom_ms();
+                        let delay_ms = (numerator as f64 / denominator.max(1) as f64).round();
+                        (
+                            DynamicImage::ImageRgba8(frame.into_buffer()),
+                            delay_ms as u32,
+                        )
+                    })
+                    .collect();
+                let format = format.expect("only formats that can be animated have frames");
+                (frames, read_loop_count(bytes, format))
+            }
+            None => (
+                vec![(reader.decode()?, DEFAULT_FRAME_DELAY_MS)],
+                LoopCount::default(),
+            ),
+        };
+
+    // Phone cameras often store photos sideways, and rely on the EXIF
+    // orientation to display them the right way up
+    if let Some(orientation) = metadata
+        .orientation()
+        .filter(|orientation| *orientation != 1)
+    {
+        frames = frames
+            .into_iter()
+            .map(|(img, delay_ms)| (metadata::apply_orientation(img, orientation), delay_ms))
+            .collect();
+        metadata.reset_orientation();
+    }
+
+    let mut frames: Vec<(PixelBuffer, u32)> = frames
+        .into_iter()
+        .map(|(img, delay_ms)| {
+            let (width, height) = img.dimensions();
+            let pixels = img
+                .pixels()
+                .flat_map(|(_, _, pixel)| pixel.0.into_iter())
+                .collect();
+
+            let buffer = PixelBuffer {
+                pixels,
+                width: width as usize,
+                height: height as usize,
+            };
+            (buffer, delay_ms)
+        })
+        .collect();
 
-    let (width, height) = img.dimensions();
+    if let ColorMode::Indexed(palette) = &mut color_mode {
+        // Pixels store colors rather than indices, so duplicate palette
+        // entries are meaningless
+        let mut seen = HashSet::new();
+        palette.colors.retain(|color| seen.insert(*color));
 
-    let pixels = img
-        .pixels()
-        .flat_map(|(_, _, pixel)| pixel.0.into_iter())
+        // Make sure every pixel is actually in the palette (decoders aren't
+        // always consistent about the RGB values of transparent pixels, for
+        // example)
+        for (buffer, _) in &mut frames {
+            quantize::remap(&mut buffer.pixels, buffer.width, palette, Dithering::None);
+        }
+
+        // GIF palettes are padded to a power of two, so drop any unused
+        // padding at the end
+        if format == Some(ImageFormat::Gif) {
+            let used: HashSet<u8> = frames
+                .iter()
+                .flat_map(|(buffer, _)| palette.index_pixels(&buffer.pixels))
+                .collect();
+            while palette.colors.len() > 1 && !used.contains(&((palette.colors.len() - 1) as u8)) {
+                palette.colors.pop();
+            }
+        }
+    }
+
+    let frames = frames
+        .into_iter()
+        .map(|(buffer, delay_ms)| AnimationFrame {
+            image: LayeredImage::from_flat(buffer),
+            delay_ms,
+        })
         .collect();
+    let (image, animation) = Animation::from_frames(frames, loop_count)
+        .ok_or_else(|| Error::Decode("the file has no frames".to_string()))?;
 
-    Some(((width, height), pixels))
+    Ok(LoadedImage {
+        image,
+        animation,
+        color_profile,
+        color_mode,
+        metadata,
+    })
 }
 
 /// Prompts the user for a path to save an image to, returning the path (or None
 /// if the user did not provide one)
-pub fn get_image_path_to_save_as() -> Option<PathBuf> {
-    match FileDialog::new().show_save_single_file() {
-        Ok(path) => path,
-        Err(err) => {
-            eprintln!("failed to get an image file path from the user: {err:?}");
-            ui::message_popup("Failed to prompt for a file name", MessageType::Error);
-            None
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_image_path_to_save_as() -> Result<Option<PathBuf>, Error> {
+    FileDialog::new()
+        .show_save_single_file()
+        .map_err(|err| Error::Dialog(err.to_string()))
+}
+
+/// Asks the user for a file name to save an image as, returning it as a path
+/// (or None if the user did not provide one). Browsers don't let us choose
+/// where files go, so they end up wherever downloads do
+#[cfg(target_arch = "wasm32")]
+pub fn get_image_path_to_save_as() -> Result<Option<PathBuf>, Error> {
+    crate::web::prompt_file_name()
+}
+
+/// Writes the contents of a file. On the web, where there's no file system,
+/// the browser downloads it instead (named after the path's file name)
+pub fn write_file(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    #[cfg(not(target_arch = "wasm32"))]
+    std::fs::write(path, bytes)?;
+
+    #[cfg(target_arch = "wasm32")]
+    crate::web::download(path, bytes)?;
+
+    Ok(())
+}
+
+/// How much of a file is read or written at a time, between checks for
+/// whether the user cancelled
+const CHUNK_SIZE: u64 = 1 << 20;
+
+/// Reads the contents of a file a chunk at a time, reporting how far along it
+/// is (and stopping early if cancelled)
+pub fn read_file(path: &Path, progress: &Progress) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    progress.start_stage("Reading", Some(len));
+
+    let mut bytes = Vec::with_capacity(len as usize);
+    loop {
+        progress.check_cancelled()?;
+        let read = (&mut file).take(CHUNK_SIZE).read_to_end(&mut bytes)?;
+        if read == 0 {
+            return Ok(bytes);
         }
+        progress.advance(read as u64);
     }
 }
 
-/// Saves an image to a file path, displaying an error to the user if saving
-/// fails
-pub fn save_image_to_file(path: &Path, image: &PixelBuffer) {
-    let img = DynamicImage::ImageRgba8(
-        ImageBuffer::from_raw(
-            image.width as u32,
-            image.height as u32,
-            // TODO how can we avoid this clone? It shouldn't be necessary
-            image.pixels.clone(),
-        )
-        .unwrap(),
+/// Like [`write_file`], but a chunk at a time, reporting how far along it is
+/// (and stopping early if cancelled). The file is written under a temporary
+/// name first, so a file that's already there is never replaced with a
+/// half-written one
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_file_with_progress(
+    path: &Path,
+    bytes: &[u8],
+    progress: &Progress,
+) -> Result<(), Error> {
+    progress.start_stage("Writing", Some(bytes.len() as u64));
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let result = write_chunks(&temporary, bytes, progress)
+        .and_then(|()| Ok(std::fs::rename(&temporary, path)?));
+    if result.is_err() {
+        // (There may be nothing to remove, if it couldn't even be created)
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// Like [`write_file`]. Browsers download files all at once, so there's no
+/// progress to report
+#[cfg(target_arch = "wasm32")]
+pub fn write_file_with_progress(
+    path: &Path,
+    bytes: &[u8],
+    progress: &Progress,
+) -> Result<(), Error> {
+    progress.start_stage("Downloading", None);
+    write_file(path, bytes)
+}
+
+/// Writes the contents of a file a chunk at a time, for
+/// [`write_file_with_progress`]
+#[cfg(not(target_arch = "wasm32"))]
+fn write_chunks(path: &Path, bytes: &[u8], progress: &Progress) -> Result<(), Error> {
+    let mut file = File::create(path)?;
+    for chunk in bytes.chunks(CHUNK_SIZE as usize) {
+        progress.check_cancelled()?;
+        file.write_all(chunk)?;
+        progress.advance(chunk.len() as u64);
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Encodes pixels as an RGBA PNG
+pub fn encode_png(image: &PixelBuffer) -> Result<Vec<u8>, image::ImageError> {
+    let mut bytes = Vec::new();
+    to_dynamic_image(image, &ColorMode::Rgba)
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+    Ok(bytes)
+}
+
+/// Decodes an image in any supported format from memory, with the same size
+/// limits as [`read_image`]
+pub fn decode_image(bytes: &[u8]) -> Result<PixelBuffer, image::ImageError> {
+    let mut reader = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(image_limits());
+    let img = reader.decode()?.into_rgba8();
+
+    Ok(PixelBuffer {
+        width: img.width() as usize,
+        height: img.height() as usize,
+        pixels: img.into_raw(),
+    })
+}
+
+/// Builds a PNG chunk (length, type, data, and CRC)
+fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(chunk_type);
+    crc.update(data);
+
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&crc.finalize().to_be_bytes());
+    chunk
+}
+
+/// The offset just after the IHDR chunk of an encoded PNG file (which always
+/// comes first, right after the signature). Chunks that must come before the
+/// image data can be inserted here
+const PNG_IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+
+/// Inserts an iCCP chunk into an encoded PNG file
+fn embed_icc_profile_png(png: &mut Vec<u8>, profile: &ColorProfile) -> std::io::Result<()> {
+    // The profile name must be 1-79 Latin-1 characters, so just play it safe
+    let name: Vec<u8> = profile
+        .name
+        .bytes()
+        .filter(|byte| (0x20..0x7f).contains(byte))
+        .take(79)
+        .collect();
+    let name = if name.is_empty() {
+        b"ICC".to_vec()
+    } else {
+        name
+    };
+
+    let mut data = name;
+    data.push(0); // Null separator
+    data.push(0); // Compression method (zlib, the only option)
+    let mut encoder = ZlibEncoder::new(data, Compression::default());
+    encoder.write_all(&profile.icc)?;
+    let data = encoder.finish()?;
+
+    // The iCCP chunk must come before the image data
+    png.splice(PNG_IHDR_END..PNG_IHDR_END, png_chunk(b"iCCP", &data));
+
+    Ok(())
+}
+
+/// Inserts APP2 ICC_PROFILE segments into an encoded JPEG file
+fn embed_icc_profile_jpeg(jpeg: &mut Vec<u8>, profile: &ColorProfile) {
+    // Each APP2 segment holds a 14 byte header and at most 65519 bytes of
+    // profile data, so large profiles are split across multiple segments
+    const MAX_CHUNK_LEN: usize = 65519;
+    let chunks: Vec<&[u8]> = profile.icc.chunks(MAX_CHUNK_LEN).collect();
+
+    let mut segments = Vec::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        segments.extend_from_slice(&[0xFF, 0xE2]);
+        segments.extend_from_slice(&((chunk.len() + 16) as u16).to_be_bytes());
+        segments.extend_from_slice(b"ICC_PROFILE\0");
+        segments.push(index as u8 + 1);
+        segments.push(chunks.len() as u8);
+        segments.extend_from_slice(chunk);
+    }
+
+    let insert_at = jpeg_segment_insert_position(jpeg);
+    jpeg.splice(insert_at..insert_at, segments);
+}
+
+/// Where to insert APPn segments into an encoded JPEG file: after the SOI
+/// marker, and after the JFIF APP0 segment if there is one (JFIF requires APP0
+/// to come first)
+fn jpeg_segment_insert_position(jpeg: &[u8]) -> usize {
+    let mut insert_at = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0]) {
+        let app0_len = u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        insert_at += 2 + app0_len;
+    }
+    insert_at
+}
+
+/// Inserts eXIf and iTXt (XMP) chunks into an encoded PNG file
+fn embed_metadata_png(png: &mut Vec<u8>, metadata: &Metadata) {
+    let mut chunks = Vec::new();
+    if let Some(exif) = &metadata.exif {
+        chunks.extend(png_chunk(b"eXIf", exif));
+    }
+    if let Some(xmp) = &metadata.xmp {
+        let mut data = metadata::XMP_PNG_KEYWORD.as_bytes().to_vec();
+        // Null separator, not compressed, compression method, and empty
+        // language tag and translated keyword
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(xmp.as_bytes());
+        chunks.extend(png_chunk(b"iTXt", &data));
+    }
+
+    // The eXIf chunk must come before the image data
+    png.splice(PNG_IHDR_END..PNG_IHDR_END, chunks);
+}
+
+/// Inserts APP1 EXIF and XMP segments into an encoded JPEG file
+fn embed_metadata_jpeg(jpeg: &mut Vec<u8>, metadata: &Metadata) {
+    // Segment lengths include the length itself
+    const MAX_SEGMENT_LEN: usize = u16::MAX as usize - 2;
+
+    let mut segments = Vec::new();
+    for (header, data) in [
+        (metadata::EXIF_HEADER, metadata.exif.as_deref()),
+        (
+            metadata::XMP_HEADER,
+            metadata.xmp.as_ref().map(|xmp| xmp.as_bytes()),
+        ),
+    ] {
+        let Some(data) = data else {
+            continue;
+        };
+        let len = header.len() + data.len();
+        if len > MAX_SEGMENT_LEN {
+            // TODO support extended XMP (split across multiple segments)
+            eprintln!("not embedding metadata, it is too large for a JPEG segment");
+            continue;
+        }
+
+        segments.extend_from_slice(&[0xFF, 0xE1]);
+        segments.extend_from_slice(&((len + 2) as u16).to_be_bytes());
+        segments.extend_from_slice(header);
+        segments.extend_from_slice(data);
+    }
+
+    // EXIF is supposed to come before everything else (except JFIF's APP0)
+    let insert_at = jpeg_segment_insert_position(jpeg);
+    jpeg.splice(insert_at..insert_at, segments);
+}
+
+/// The `png` crate's equivalent of a compression setting
+fn png_compression(compression: PngCompression) -> png::Compression {
+    match compression {
+        PngCompression::Fast => png::Compression::Fast,
+        PngCompression::Default => png::Compression::Default,
+        PngCompression::Best => png::Compression::Best,
+    }
+}
+
+/// Encodes an indexed-color image as a palette-based PNG
+fn encode_indexed_png(
+    image: &PixelBuffer,
+    palette: &Palette,
+    options: &PngOptions,
+) -> Result<Vec<u8>, png::EncodingError> {
+    let mut bytes = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut bytes, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png_compression(options.compression));
+    encoder.set_palette(
+        palette
+            .colors
+            .iter()
+            .flat_map(|color| [color[0], color[1], color[2]])
+            .collect::<Vec<u8>>(),
+    );
+
+    // The tRNS chunk can leave off trailing fully opaque entries
+    let alphas: Vec<u8> = palette.colors.iter().map(|color| color[3]).collect();
+    if let Some(last_transparent) = alphas.iter().rposition(|alpha| *alpha != 255) {
+        encoder.set_trns(alphas[..=last_transparent].to_vec());
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&palette.index_pixels(&image.pixels))?;
+    writer.finish()?;
+
+    Ok(bytes)
+}
+
+/// Encodes an indexed-color image as a GIF, using the palette as the global
+/// color table
+fn encode_indexed_gif(
+    image: &PixelBuffer,
+    palette: &Palette,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let width = u16::try_from(image.width)?;
+    let height = u16::try_from(image.height)?;
+
+    let palette_rgb: Vec<u8> = palette
+        .colors
+        .iter()
+        .flat_map(|color| [color[0], color[1], color[2]])
+        .collect();
+
+    // GIFs only support a single, fully transparent, palette entry
+    let transparent = palette.colors.iter().position(|color| color[3] == 0);
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut bytes, width, height, &palette_rgb)?;
+        let frame = gif::Frame::from_indexed_pixels(
+            width,
+            height,
+            &palette.index_pixels(&image.pixels),
+            transparent.map(|index| index as u8),
+        );
+        encoder.write_frame(&frame)?;
+    }
+
+    Ok(bytes)
+}
+
+/// Encodes the frames of an animation as an animated GIF. Indexed images share
+/// their palette between every frame, other images get a palette picked for
+/// each frame
+fn encode_animated_gif(
+    frames: &[FlatFrame],
+    color_mode: &ColorMode,
+    loop_count: LoopCount,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let first = &frames.first().ok_or("animation has no frames")?.pixels;
+    let width = u16::try_from(first.width)?;
+    let height = u16::try_from(first.height)?;
+
+    let (global_palette, transparent) = match color_mode {
+        ColorMode::Indexed(palette) => (
+            palette
+                .colors
+                .iter()
+                .flat_map(|color| [color[0], color[1], color[2]])
+                .collect(),
+            // GIFs only support a single, fully transparent, palette entry
+            palette
+                .colors
+                .iter()
+                .position(|color| color[3] == 0)
+                .map(|index| index as u8),
+        ),
+        _ => (Vec::new(), None),
+    };
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut bytes, width, height, &global_palette)?;
+        match loop_count {
+            LoopCount::Infinite => encoder.set_repeat(gif::Repeat::Infinite)?,
+            // GIFs store how many times to repeat *after* the first play
+            LoopCount::Finite(plays) if plays > 1 => {
+                encoder.set_repeat(gif::Repeat::Finite(plays - 1))?
+            }
+            // GIFs without a repeat count only play once
+            LoopCount::Finite(_) => {}
+        }
+
+        for frame in frames {
+            let mut gif_frame = match color_mode {
+                ColorMode::Indexed(palette) => gif::Frame::from_indexed_pixels(
+                    width,
+                    height,
+                    &palette.index_pixels(&frame.pixels.pixels),
+                    transparent,
+                ),
+                _ => {
+                    gif::Frame::from_rgba_speed(width, height, &mut frame.pixels.pixels.clone(), 10)
+                }
+            };
+
+            // GIF delays are in hundredths of a second
+            gif_frame.delay = ((frame.delay_ms + 5) / 10).min(u16::MAX as u32) as u16;
+            // Every frame is a whole image, so the previous one should be
+            // cleared rather than drawn on top of
+            gif_frame.dispose = gif::DisposalMethod::Background;
+
+            encoder.write_frame(&gif_frame)?;
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Encodes the frames of an animation as an animated PNG (APNG). Frames are
+/// always stored as RGBA
+fn encode_apng(
+    frames: &[FlatFrame],
+    options: &PngOptions,
+    loop_count: LoopCount,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let first = &frames.first().ok_or("animation has no frames")?.pixels;
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, first.width as u32, first.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png_compression(options.compression));
+        encoder.set_animated(
+            frames.len() as u32,
+            match loop_count {
+                LoopCount::Infinite => 0,
+                LoopCount::Finite(plays) => plays as u32,
+            },
+        )?;
+        // Every frame is a whole image, so it replaces the previous one
+        encoder.set_blend_op(png::BlendOp::Source)?;
+        encoder.set_dispose_op(png::DisposeOp::None)?;
+
+        let mut writer = encoder.write_header()?;
+        for frame in frames {
+            // Delays are stored as a fraction of a second
+            writer.set_frame_delay(frame.delay_ms.min(u16::MAX as u32) as u16, 1000)?;
+            writer.write_image_data(&frame.pixels.pixels)?;
+        }
+        writer.finish()?;
+    }
+
+    Ok(bytes)
+}
+
+/// Makes a chunk of a WebP (RIFF) file, padded to an even length
+fn webp_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(8 + data.len() + 1);
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+
+    chunk
+}
+
+/// The 24-bit little-endian integers used in WebP headers
+fn webp_u24(value: usize) -> [u8; 3] {
+    let [a, b, c, _] = (value.min(0xFF_FFFF) as u32).to_le_bytes();
+    [a, b, c]
+}
+
+/// Encodes the frames of an animation as an animated WebP. Each frame is
+/// encoded like a still WebP, then wrapped up in an animation frame (ANMF)
+/// chunk
+///
+/// See https://developers.google.com/speed/webp/docs/riff_container#animation
+fn encode_animated_webp(
+    frames: &[FlatFrame],
+    options: &WebPOptions,
+    loop_count: LoopCount,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let first = &frames.first().ok_or("animation has no frames")?.pixels;
+
+    let mut frame_chunks = Vec::new();
+    let mut has_alpha = false;
+    for frame in frames {
+        has_alpha |= frame
+            .pixels
+            .pixels
+            .chunks_exact(4)
+            .any(|pixel| pixel[3] != 255);
+        let encoded = encode_webp(&frame.pixels, options)?;
+
+        // The frame covers the whole canvas
+        let mut data = vec![0; 6];
+        data.extend_from_slice(&webp_u24(frame.pixels.width - 1));
+        data.extend_from_slice(&webp_u24(frame.pixels.height - 1));
+        data.extend_from_slice(&webp_u24(frame.delay_ms as usize));
+        // Every frame is a whole image, so it isn't blended with (or
+        // disposed of before) the previous one
+        data.push(0b10);
+
+        // Only keep the image data, the still image's header doesn't belong
+        for (chunk_type, chunk_data) in webp_chunks(&encoded) {
+            match chunk_type {
+                b"ALPH" => data.extend(webp_chunk(b"ALPH", chunk_data)),
+                b"VP8 " => data.extend(webp_chunk(b"VP8 ", chunk_data)),
+                b"VP8L" => data.extend(webp_chunk(b"VP8L", chunk_data)),
+                _ => {}
+            }
+        }
+
+        frame_chunks.extend(webp_chunk(b"ANMF", &data));
+    }
+
+    // The animation and (maybe) alpha flags, then the canvas size
+    let mut vp8x = vec![if has_alpha { 0b1_0010 } else { 0b10 }, 0, 0, 0];
+    vp8x.extend_from_slice(&webp_u24(first.width - 1));
+    vp8x.extend_from_slice(&webp_u24(first.height - 1));
+
+    // A transparent background color, then the number of plays
+    let mut anim = vec![0; 4];
+    anim.extend_from_slice(
+        &match loop_count {
+            LoopCount::Infinite => 0,
+            LoopCount::Finite(plays) => plays,
+        }
+        .to_le_bytes(),
+    );
+
+    let mut body = b"WEBP".to_vec();
+    body.extend(webp_chunk(b"VP8X", &vp8x));
+    body.extend(webp_chunk(b"ANIM", &anim));
+    body.extend(frame_chunks);
+
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend(body);
+
+    Ok(bytes)
+}
+
+/// Converts the image to the `image` crate's representation for encoding,
+/// using a grayscale representation for grayscale images
+fn to_dynamic_image(image: &PixelBuffer, color_mode: &ColorMode) -> DynamicImage {
+    let width = image.width as u32;
+    let height = image.height as u32;
+
+    match color_mode {
+        ColorMode::Grayscale => {
+            let has_transparency = image.pixels.chunks_exact(4).any(|pixel| pixel[3] != 255);
+            if has_transparency {
+                let pixels = image
+                    .pixels
+                    .chunks_exact(4)
+                    .flat_map(|pixel| [luma(pixel[0], pixel[1], pixel[2]), pixel[3]])
+                    .collect();
+                DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, pixels).unwrap())
+            } else {
+                let pixels = image
+                    .pixels
+                    .chunks_exact(4)
+                    .map(|pixel| luma(pixel[0], pixel[1], pixel[2]))
+                    .collect();
+                DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, pixels).unwrap())
+            }
+        }
+        ColorMode::Rgba | ColorMode::Indexed(_) => DynamicImage::ImageRgba8(
+            ImageBuffer::from_raw(
+                width,
+                height,
+                // TODO how can we avoid this clone? It shouldn't be necessary
+                image.pixels.clone(),
+            )
+            .unwrap(),
+        ),
+    }
+}
+
+/// Encodes a PNG with the given options
+fn encode_png_with_options(
+    image: &DynamicImage,
+    options: &PngOptions,
+) -> Result<Vec<u8>, image::ImageError> {
+    let mut bytes = Vec::new();
+    let encoder = PngEncoder::new_with_quality(
+        &mut bytes,
+        match options.compression {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        },
+        PngFilterType::Adaptive,
     );
+    encoder.write_image(
+        image.as_bytes(),
+        image.width(),
+        image.height(),
+        image.color(),
+    )?;
+
+    Ok(bytes)
+}
+
+/// Encodes a JPEG with the given options
+fn encode_jpeg(
+    image: &PixelBuffer,
+    color_mode: &ColorMode,
+    options: &JpegOptions,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let width = u16::try_from(image.width)?;
+    let height = u16::try_from(image.height)?;
+
+    let mut bytes = Vec::new();
+    let mut encoder = jpeg_encoder::Encoder::new(&mut bytes, options.quality.clamp(1, 100));
+    encoder.set_sampling_factor(match options.chroma_subsampling {
+        ChromaSubsampling::Full => jpeg_encoder::SamplingFactor::R_4_4_4,
+        ChromaSubsampling::Half => jpeg_encoder::SamplingFactor::R_4_2_2,
+        ChromaSubsampling::Quarter => jpeg_encoder::SamplingFactor::R_4_2_0,
+    });
+
+    // JPEGs can't store transparency, so the alpha channel is ignored (images
+    // should be flattened onto a matte color first)
+    if *color_mode == ColorMode::Grayscale {
+        let pixels: Vec<u8> = image
+            .pixels
+            .chunks_exact(4)
+            .map(|pixel| luma(pixel[0], pixel[1], pixel[2]))
+            .collect();
+        encoder.encode(&pixels, width, height, jpeg_encoder::ColorType::Luma)?;
+    } else {
+        encoder.encode(&image.pixels, width, height, jpeg_encoder::ColorType::Rgba)?;
+    }
+
+    Ok(bytes)
+}
+
+/// Encodes a WebP with the given options (always losslessly on the web, see
+/// [`LOSSY_WEBP`](crate::export::LOSSY_WEBP))
+fn encode_webp(image: &PixelBuffer, options: &WebPOptions) -> Result<Vec<u8>, image::ImageError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if !options.lossless {
+        let encoder =
+            ::webp::Encoder::from_rgba(&image.pixels, image.width as u32, image.height as u32);
+        return encoder
+            .encode_simple(false, options.quality.min(100) as f32)
+            .map(|encoded| encoded.to_vec())
+            .map_err(|err| {
+                image::ImageError::Encoding(image::error::EncodingError::new(
+                    image::error::ImageFormatHint::Exact(ImageFormat::WebP),
+                    format!("{err:?}"),
+                ))
+            });
+    }
+    #[cfg(target_arch = "wasm32")]
+    let _ = options;
+
+    let mut bytes = Vec::new();
+    WebPEncoder::new_lossless(&mut bytes).encode(
+        &image.pixels,
+        image.width as u32,
+        image.height as u32,
+        ColorType::Rgba8,
+    )?;
+
+    Ok(bytes)
+}
+
+/// Whether or not a format can store transparency (BMP technically can, but
+/// hardly anything reads it correctly, so it's treated as if it can't)
+pub fn supports_transparency(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Png
+            | ImageFormat::Gif
+            | ImageFormat::WebP
+            | ImageFormat::Tiff
+            | ImageFormat::Tga
+            | ImageFormat::Ico
+            | ImageFormat::OpenExr
+            | ImageFormat::Farbfeld
+            | ImageFormat::Avif
+            | ImageFormat::Qoi
+    )
+}
+
+/// Whether or not a format can store animations
+pub fn supports_animation(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP
+    )
+}
+
+/// Whether or not saving an image in a format would lose transparency (so it
+/// needs to be flattened onto a matte color first)
+pub fn needs_matte(image: &PixelBuffer, format: ImageFormat) -> bool {
+    !supports_transparency(format) && image.pixels.chunks_exact(4).any(|pixel| pixel[3] != 255)
+}
+
+/// Flattens an image onto a solid matte color, making it fully opaque
+fn flatten_onto_matte(image: &PixelBuffer, matte: [u8; 3]) -> PixelBuffer {
+    let mut flattened = image.clone();
+    for pixel in flattened.pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as f32 / 255.0;
+        for (channel, matte) in pixel.iter_mut().zip(matte) {
+            *channel = (*channel as f32 * alpha + matte as f32 * (1.0 - alpha)).round() as u8;
+        }
+        pixel[3] = 255;
+    }
+
+    flattened
+}
+
+/// Encodes an image in the given format (using the options for that format),
+/// embedding the color profile and metadata if the format supports them.
+/// Transparent images are flattened onto the matte color if the format can't
+/// store transparency
+pub fn encode_image(
+    image: &PixelBuffer,
+    format: ImageFormat,
+    color_profile: Option<&ColorProfile>,
+    color_mode: &ColorMode,
+    metadata: &Metadata,
+    options: &ExportOptions,
+    matte: [u8; 3],
+) -> Result<Vec<u8>, Error> {
+    let flattened;
+    let image = if needs_matte(image, format) {
+        flattened = flatten_onto_matte(image, matte);
+        &flattened
+    } else {
+        image
+    };
+
+    let mut bytes = match (color_mode, format) {
+        (ColorMode::Indexed(palette), ImageFormat::Png) => {
+            encode_indexed_png(image, palette, &options.png)
+                .map_err(|err| Error::Encode(err.to_string()))?
+        }
+        (ColorMode::Indexed(palette), ImageFormat::Gif) => {
+            encode_indexed_gif(image, palette).map_err(Error::encoding)?
+        }
+        (_, ImageFormat::Png) => {
+            encode_png_with_options(&to_dynamic_image(image, color_mode), &options.png)?
+        }
+        (_, ImageFormat::Jpeg) => {
+            encode_jpeg(image, color_mode, &options.jpeg).map_err(Error::encoding)?
+        }
+        (_, ImageFormat::WebP) => encode_webp(image, &options.webp)?,
+        _ => {
+            let mut bytes = Vec::new();
+            to_dynamic_image(image, color_mode).write_to(&mut Cursor::new(&mut bytes), format)?;
+            bytes
+        }
+    };
+
+    embed_color_profile_and_metadata(&mut bytes, format, color_profile, color_mode, metadata)?;
+
+    Ok(bytes)
+}
+
+/// Encodes the frames of an animation in the given format (using the options
+/// for that format), embedding the color profile and metadata if the format
+/// supports them. Every format that can store animations can also store
+/// transparency, so no matte is needed
+pub fn encode_animation(
+    frames: &[FlatFrame],
+    loop_count: LoopCount,
+    format: ImageFormat,
+    color_profile: Option<&ColorProfile>,
+    color_mode: &ColorMode,
+    metadata: &Metadata,
+    options: &ExportOptions,
+) -> Result<Vec<u8>, Error> {
+    let bytes = match format {
+        ImageFormat::Gif => encode_animated_gif(frames, color_mode, loop_count),
+        ImageFormat::Png => encode_apng(frames, &options.png, loop_count),
+        ImageFormat::WebP => encode_animated_webp(frames, &options.webp, loop_count),
+        _ => {
+            return Err(Error::UnsupportedFormat(format!(
+                "{format:?} files can't store animations"
+            )))
+        }
+    };
+    let mut bytes = bytes.map_err(Error::encoding)?;
+
+    embed_color_profile_and_metadata(&mut bytes, format, color_profile, color_mode, metadata)?;
+
+    Ok(bytes)
+}
+
+/// Embeds the color profile and metadata into an encoded image, if the format
+/// supports them
+fn embed_color_profile_and_metadata(
+    bytes: &mut Vec<u8>,
+    format: ImageFormat,
+    color_profile: Option<&ColorProfile>,
+    color_mode: &ColorMode,
+    metadata: &Metadata,
+) -> std::io::Result<()> {
+    match color_profile {
+        // RGB color profiles can't be embedded in grayscale images
+        Some(_) if *color_mode == ColorMode::Grayscale => {
+            eprintln!("not embedding color profile, it is not a grayscale profile");
+        }
+        Some(profile) => match format {
+            ImageFormat::Png => embed_icc_profile_png(bytes, profile)?,
+            ImageFormat::Jpeg => embed_icc_profile_jpeg(bytes, profile),
+            _ => eprintln!("not embedding color profile, unsupported for {format:?}"),
+        },
+        None => {}
+    }
+
+    if !metadata.is_empty() {
+        match format {
+            ImageFormat::Png => embed_metadata_png(bytes, metadata),
+            ImageFormat::Jpeg => embed_metadata_jpeg(bytes, metadata),
+            _ => eprintln!("not embedding metadata, unsupported for {format:?}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes an image in the format implied by a file path's extension,
+/// embedding the given color profile and metadata if there are any (and the
+/// format supports them). Grayscale and indexed images are stored as such when
+/// the format supports it. Layers are only kept in project and OpenRaster
+/// files, other formats get a flattened copy of the image (encoded with the
+/// options for that format, and flattened onto the matte color if the format
+/// can't store transparency)
+pub fn encode_image_for_path(
+    path: &Path,
+    image: &LayeredImage,
+    color_profile: Option<&ColorProfile>,
+    color_mode: &ColorMode,
+    metadata: &Metadata,
+    export_options: &ExportOptions,
+    matte: [u8; 3],
+) -> Result<Vec<u8>, Error> {
+    if is_project(path) {
+        encode_project(
+            image,
+            &Animation::default(),
+            color_profile,
+            color_mode,
+            metadata,
+        )
+    } else if is_openraster(path) {
+        let mut bytes = Cursor::new(Vec::new());
+        openraster::write_openraster(&mut bytes, image).map_err(Error::encoding)?;
+        Ok(bytes.into_inner())
+    } else {
+        encode_image(
+            &image.flatten(),
+            ImageFormat::from_path(path)?,
+            color_profile,
+            color_mode,
+            metadata,
+            export_options,
+            matte,
+        )
+    }
+}
+
+/// Encodes an image as a project file, with every frame of its animation
+/// (`image` being the current frame's)
+pub fn encode_project(
+    image: &LayeredImage,
+    animation: &Animation,
+    color_profile: Option<&ColorProfile>,
+    color_mode: &ColorMode,
+    metadata: &Metadata,
+) -> Result<Vec<u8>, Error> {
+    let mut bytes = Cursor::new(Vec::new());
+    project::write_project(
+        &mut bytes,
+        image,
+        animation,
+        color_profile,
+        color_mode,
+        metadata,
+    )
+    .map_err(Error::encoding)?;
+    Ok(bytes.into_inner())
+}
+
+/// Encodes the frames of an animation in the format implied by a file path's
+/// extension (which must be able to store animations), embedding the given
+/// color profile and metadata if there are any (and the format supports them)
+pub fn encode_animation_for_path(
+    path: &Path,
+    frames: &[FlatFrame],
+    loop_count: LoopCount,
+    color_profile: Option<&ColorProfile>,
+    color_mode: &ColorMode,
+    metadata: &Metadata,
+    export_options: &ExportOptions,
+) -> Result<Vec<u8>, Error> {
+    encode_animation(
+        frames,
+        loop_count,
+        ImageFormat::from_path(path)?,
+        color_profile,
+        color_mode,
+        metadata,
+        export_options,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes an image as an indexed file of the given format, then reads it
+    /// back
+    fn indexed_round_trip(
+        image: &PixelBuffer,
+        palette: &Palette,
+        format: ImageFormat,
+    ) -> LoadedImage {
+        let bytes = encode_image(
+            image,
+            format,
+            None,
+            &ColorMode::Indexed(palette.clone()),
+            &Metadata::default(),
+            &ExportOptions::default(),
+            [255, 255, 255],
+        )
+        .unwrap();
+        let path = PathBuf::from(format!("image.{}", format.extensions_str()[0]));
+        read_image(&path, &bytes).unwrap()
+    }
+
+    /// A 3x2 image using every color of the palette
+    fn indexed_image(palette: &Palette) -> PixelBuffer {
+        PixelBuffer {
+            pixels: palette
+                .colors
+                .iter()
+                .cycle()
+                .take(6)
+                .flatten()
+                .copied()
+                .collect(),
+            width: 3,
+            height: 2,
+        }
+    }
+
+    #[test]
+    fn indexed_png_round_trips() {
+        let palette = Palette {
+            colors: vec![[255, 0, 0, 255], [0, 128, 255, 255], [10, 20, 30, 0]],
+        };
+        let image = indexed_image(&palette);
+
+        let loaded = indexed_round_trip(&image, &palette, ImageFormat::Png);
+
+        assert_eq!(loaded.color_mode, ColorMode::Indexed(palette));
+        assert_eq!(loaded.image.layers[0].pixels, image);
+    }
+
+    #[test]
+    fn indexed_gif_round_trips() {
+        let palette = Palette {
+            colors: vec![[255, 0, 0, 255], [0, 128, 255, 255], [10, 20, 30, 255]],
+        };
+        let image = indexed_image(&palette);
+
+        let loaded = indexed_round_trip(&image, &palette, ImageFormat::Gif);
+
+        assert_eq!(loaded.color_mode, ColorMode::Indexed(palette));
+        assert_eq!(loaded.image.layers[0].pixels, image);
+    }
+
+    #[test]
+    fn metadata_survives_saving_and_loading() {
+        let metadata = Metadata {
+            // A little-endian TIFF with a single IFD, holding a resolution
+            // unit of inches
+            exif: Some(
+                [
+                    &b"II*\0\x08\0\0\0\x01\0"[..],
+                    &[0x28, 0x01, 3, 0, 1, 0, 0, 0, 2, 0, 0, 0],
+                    &[0; 4],
+                ]
+                .concat(),
+            ),
+            xmp: Some(r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"/>"#.to_string()),
+        };
+        let image = PixelBuffer {
+            pixels: vec![255; 4 * 4 * 4],
+            width: 4,
+            height: 4,
+        };
+
+        for format in [ImageFormat::Png, ImageFormat::Jpeg] {
+            let bytes = encode_image(
+                &image,
+                format,
+                None,
+                &ColorMode::Rgba,
+                &metadata,
+                &ExportOptions::default(),
+                [255, 255, 255],
+            )
+            .unwrap();
+            let path = PathBuf::from(format!("image.{}", format.extensions_str()[0]));
+            let loaded = read_image(&path, &bytes).unwrap();
+
+            assert_eq!(loaded.metadata, metadata, "{format:?}");
+        }
+    }
+
+    /// Three frames of different colors, each shown for a different time
+    fn animation_frames() -> Vec<FlatFrame> {
+        [
+            ([255, 0, 0, 255], 100),
+            ([0, 255, 0, 255], 250),
+            ([0, 0, 255, 255], 40),
+        ]
+        .into_iter()
+        .map(|(color, delay_ms)| FlatFrame {
+            pixels: PixelBuffer::filled(16, 16, color),
+            delay_ms,
+        })
+        .collect()
+    }
+
+    fn encode_test_animation(format: ImageFormat, loop_count: LoopCount) -> Vec<u8> {
+        encode_animation(
+            &animation_frames(),
+            loop_count,
+            format,
+            None,
+            &ColorMode::Rgba,
+            &Metadata::default(),
+            &ExportOptions::default(),
+        )
+        .unwrap()
+    }
+
+    fn read_test_animation(format: ImageFormat, bytes: &[u8]) -> LoadedImage {
+        let path = PathBuf::from(format!("animation.{}", format.extensions_str()[0]));
+        read_image(&path, bytes).unwrap()
+    }
+
+    #[test]
+    fn animations_round_trip() {
+        for format in [ImageFormat::Gif, ImageFormat::Png, ImageFormat::WebP] {
+            for loop_count in [
+                LoopCount::Infinite,
+                LoopCount::Finite(1),
+                LoopCount::Finite(3),
+            ] {
+                let bytes = encode_test_animation(format, loop_count);
+                let loaded = read_test_animation(format, &bytes);
+
+                let delays: Vec<u32> = loaded
+                    .animation
+                    .frames
+                    .iter()
+                    .map(|frame| frame.delay_ms)
+                    .collect();
+                assert_eq!(delays, [100, 250, 40], "{format:?}");
+                assert_eq!(loaded.animation.loop_count, loop_count, "{format:?}");
+                // WebP frames are lossy by default
+                if format != ImageFormat::WebP {
+                    assert_eq!(
+                        loaded.image.layers[0].pixels,
+                        animation_frames()[0].pixels,
+                        "{format:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gif_loop_counts_are_only_read_from_the_netscape_extension() {
+        let mut bytes = encode_test_animation(ImageFormat::Gif, LoopCount::Finite(3));
+
+        // A comment that just happens to look like a (different) loop count,
+        // right after the global color table
+        let flags = bytes[10];
+        let after_color_table = 13 + (3 << ((flags & 0x07) + 1));
+        assert_ne!(flags & 0x80, 0);
+        let comment = [&[0x21, 0xFE, 15][..], b"NETSCAPE2.0", &[3, 1, 9, 0], &[0]].concat();
+        bytes.splice(after_color_table..after_color_table, comment);
 
-    if let Err(err) = img.save(path) {
-        eprintln!("failed to save image to file: {err:?}");
-        ui::message_popup("Failed to save file", MessageType::Error);
+        let loaded = read_test_animation(ImageFormat::Gif, &bytes);
+        assert_eq!(loaded.animation.frames.len(), 3);
+        assert_eq!(loaded.animation.loop_count, LoopCount::Finite(3));
     }
 }
@@ -0,0 +1,398 @@
+//! Opening and saving files in the background, so large images don't freeze
+//! the window while they're decoded or encoded. Each job reports its progress
+//! and can be cancelled, and its result is picked up by the app once it's done
+
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use crate::{
+    color_management::ColorProfile,
+    document::SaveData,
+    error::Error,
+    fileio::{self, FilePurpose, LoadedImage},
+    pixel_buffer::PixelBuffer,
+};
+
+/// How far along a job is, shared between the job and the UI
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// What the job is doing right now (like "Decoding")
+    stage: Mutex<&'static str>,
+
+    /// How much of the current stage is done, out of `total`
+    done: AtomicU64,
+
+    /// How much there is to do in the current stage, or 0 if there's no way to
+    /// tell (like while decoding)
+    total: AtomicU64,
+
+    /// Whether the user asked for the job to stop
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    /// Moves on to another stage of the job, with how much there is to do in
+    /// it (if that can be known)
+    pub fn start_stage(&self, stage: &'static str, total: Option<u64>) {
+        *self.stage.lock().unwrap_or_else(|err| err.into_inner()) = stage;
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Records that some more of the current stage is done
+    pub fn advance(&self, amount: u64) {
+        self.done.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    /// What the job is doing right now
+    pub fn stage(&self) -> &'static str {
+        *self.stage.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// How much of the current stage is done, from 0 to 1, or None if there's
+    /// no way to tell
+    pub fn fraction(&self) -> Option<f32> {
+        let total = self.total.load(Ordering::Relaxed);
+        let done = self.done.load(Ordering::Relaxed);
+        (total > 0).then(|| (done as f64 / total as f64).min(1.0) as f32)
+    }
+
+    /// Asks the job to stop as soon as it can
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the user asked for the job to stop
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails with [`Error::Cancelled`] if the user asked for the job to stop
+    /// (called between steps, to bail out early)
+    pub fn check_cancelled(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// A job that has finished (successfully or not)
+#[derive(Debug)]
+pub enum Finished {
+    /// A file was read (and decoded)
+    Opened {
+        /// What the file is for
+        purpose: FilePurpose,
+
+        /// Where the file came from
+        path: PathBuf,
+
+        /// When the file was last modified, as of just before it was read
+        modified: Option<SystemTime>,
+
+        /// The image in the file
+        result: Result<LoadedImage, Error>,
+    },
+
+    /// A document was saved
+    Saved {
+        /// Which document was saved
+        document_id: u64,
+
+        /// Where the document was saved to
+        path: PathBuf,
+
+        /// The document's revision when saving started
+        revision: u64,
+
+        /// When the file was last modified, as of just after it was written
+        modified: Option<SystemTime>,
+
+        /// Whether to add the file to the recent files
+        remember: bool,
+
+        /// Warnings about anything the file couldn't store, if saving worked
+        result: Result<Vec<String>, Error>,
+    },
+
+    /// A thumbnail was made for the Open Recent menu
+    RecentThumbnail {
+        /// The file the thumbnail is of
+        path: PathBuf,
+
+        /// The thumbnail, and the color profile of the file
+        result: Result<(PixelBuffer, Option<ColorProfile>), Error>,
+    },
+
+    /// An image was encoded to preview exporting it
+    ExportPreview {
+        /// Which preview this is, out of the ones asked for
+        request: u64,
+
+        /// The encoded image, and what it looks like decoded again (if that's
+        /// worth showing)
+        result: Result<(Vec<u8>, Option<PixelBuffer>), Error>,
+    },
+}
+
+/// A job that's still running
+#[derive(Debug)]
+pub struct Job {
+    /// What the job is doing (like "Opening photo.png")
+    pub description: String,
+
+    /// How far along the job is
+    pub progress: Arc<Progress>,
+
+    /// The document being saved, if the job is saving one
+    pub saving: Option<u64>,
+
+    /// Whether the job is shown in the list of running jobs (quick jobs the
+    /// user didn't directly ask for, like export previews, aren't)
+    pub shown: bool,
+
+    /// Where the result arrives once the job is done
+    receiver: Receiver<Finished>,
+}
+
+/// Every job that's still running
+#[derive(Debug, Default)]
+pub struct Jobs {
+    running: Vec<Job>,
+}
+
+/// The name of the file at a path, for descriptions of jobs
+fn file_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Jobs {
+    /// Starts a job, running `work` on a background thread (or right away on
+    /// the web, which doesn't have threads)
+    fn spawn(
+        &mut self,
+        description: String,
+        saving: Option<u64>,
+        shown: bool,
+        work: impl FnOnce(&Progress) -> Finished + Send + 'static,
+    ) {
+        let progress = Arc::new(Progress::default());
+        let (sender, receiver) = mpsc::channel();
+
+        let job_progress = Arc::clone(&progress);
+        let run = move || {
+            // (If the app is gone there's nobody to tell)
+            let _ = sender.send(work(&job_progress));
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(run);
+        #[cfg(target_arch = "wasm32")]
+        run();
+
+        self.running.push(Job {
+            description,
+            progress,
+            saving,
+            shown,
+            receiver,
+        });
+    }
+
+    /// Reads and decodes an image file in the background
+    pub fn open(&mut self, path: PathBuf, purpose: FilePurpose) {
+        let verb = match purpose {
+            FilePurpose::Reload { .. } => "Reloading",
+            _ => "Opening",
+        };
+        let description = format!("{verb} {}", file_name(&path));
+        self.spawn(description, None, true, move |progress| {
+            let modified = fileio::modified_time(&path);
+            let result = fileio::read_file(&path, progress)
+                .and_then(|bytes| {
+                    progress.start_stage("Decoding", None);
+                    fileio::read_image(&path, &bytes)
+                })
+                .and_then(|loaded| {
+                    // Decoding can't be stopped partway, but its result can
+                    // still be thrown away
+                    progress.check_cancelled()?;
+                    Ok(loaded)
+                });
+            Finished::Opened {
+                purpose,
+                path,
+                modified,
+                result,
+            }
+        });
+    }
+
+    /// Encodes and writes a document in the background. The document can keep
+    /// changing in the meantime, since `data` is a copy of it
+    pub fn save(&mut self, document_id: u64, data: SaveData, remember: bool) {
+        let description = format!("Saving {}", file_name(&data.path));
+        self.spawn(description, Some(document_id), true, move |progress| {
+            let result = data.write(progress);
+            Finished::Saved {
+                document_id,
+                modified: fileio::modified_time(&data.path),
+                path: data.path,
+                revision: data.revision,
+                remember,
+                result,
+            }
+        });
+    }
+
+    /// Reads an image file in the background and makes a thumbnail of it no
+    /// larger than `max_size` for the Open Recent menu
+    pub fn make_recent_thumbnail(&mut self, path: PathBuf, max_size: u32, linear_light: bool) {
+        let description = format!("Making a thumbnail of {}", file_name(&path));
+        self.spawn(description, None, false, move |_| {
+            let result = fileio::read_image_from_file(&path).map(|loaded| {
+                let thumbnail = loaded.image.flatten().thumbnail(max_size, linear_light);
+                (thumbnail, loaded.color_profile)
+            });
+            Finished::RecentThumbnail { path, result }
+        });
+    }
+
+    /// Encodes an image in the background to preview exporting it, with
+    /// `encode` returning the encoded image and what it looks like decoded
+    /// again (if that's worth showing)
+    pub fn encode_export_preview(
+        &mut self,
+        request: u64,
+        encode: impl FnOnce() -> Result<(Vec<u8>, Option<PixelBuffer>), Error> + Send + 'static,
+    ) {
+        let description = "Estimating the exported file size".to_string();
+        self.spawn(description, None, false, move |_| Finished::ExportPreview {
+            request,
+            result: encode(),
+        });
+    }
+
+    /// The jobs that are still running, oldest first
+    pub fn running(&self) -> &[Job] {
+        &self.running
+    }
+
+    /// Whether a document is being saved
+    pub fn is_saving(&self, document_id: u64) -> bool {
+        self.running
+            .iter()
+            .any(|job| job.saving == Some(document_id))
+    }
+
+    /// Whether any document is being saved
+    pub fn is_saving_any(&self) -> bool {
+        self.running.iter().any(|job| job.saving.is_some())
+    }
+
+    /// Takes the results of the jobs that have finished since the last time
+    /// this was called
+    pub fn take_finished(&mut self) -> Vec<Finished> {
+        let mut finished = Vec::new();
+        self.running.retain(|job| match job.receiver.try_recv() {
+            Ok(result) => {
+                finished.push(result);
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => {
+                // The job panicked, and there's no result coming
+                eprintln!("{} stopped unexpectedly", job.description);
+                false
+            }
+        });
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        animation::Animation, document::Document, export::ExportOptions, layers::LayeredImage,
+        pixel_buffer::PixelBuffer,
+    };
+
+    /// A 1x1 red image
+    fn red_document() -> Document {
+        let pixels = PixelBuffer {
+            pixels: vec![255, 0, 0, 255],
+            width: 1,
+            height: 1,
+        };
+        Document::new(LayeredImage::from_flat(pixels), Animation::default())
+    }
+
+    /// A file path in a fresh temporary directory, for tests that save files
+    fn temporary_path(test_name: &str, file_name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("trametes-{test_name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(file_name)
+    }
+
+    #[test]
+    fn progress_is_reported_for_each_stage() {
+        let progress = Progress::default();
+
+        progress.start_stage("Reading", Some(4));
+        progress.advance(1);
+        assert_eq!(progress.stage(), "Reading");
+        assert_eq!(progress.fraction(), Some(0.25));
+
+        progress.start_stage("Decoding", None);
+        assert_eq!(progress.fraction(), None);
+
+        assert!(progress.check_cancelled().is_ok());
+        progress.cancel();
+        assert!(matches!(progress.check_cancelled(), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn documents_changed_while_being_saved_stay_unsaved() {
+        let path = temporary_path("changed-while-saving", "red.png");
+        let mut document = red_document();
+        document.checkpoint("Paint");
+
+        let data = document.save_data(&path, &ExportOptions::default(), [255; 3]);
+        document.checkpoint("Paint Some More");
+        data.write(&Progress::default()).unwrap();
+
+        assert!(!document.finish_save(data.path, data.revision, None));
+        assert!(document.dirty);
+        assert_eq!(document.path.as_deref(), Some(path.as_path()));
+
+        // What was saved is how the document was when saving started
+        let saved = Document::open(&path).unwrap();
+        assert_eq!(saved.image.flatten().pixels, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn cancelled_saves_leave_the_file_alone() {
+        let path = temporary_path("cancelled-save", "red.png");
+        std::fs::write(&path, b"not replaced").unwrap();
+
+        let document = red_document();
+        let data = document.save_data(&path, &ExportOptions::default(), [255; 3]);
+        let progress = Progress::default();
+        progress.cancel();
+
+        assert!(matches!(data.write(&progress), Err(Error::Cancelled)));
+        assert_eq!(std::fs::read(&path).unwrap(), b"not replaced");
+        assert!(!path.with_extension("png.tmp").exists());
+    }
+}
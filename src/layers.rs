@@ -0,0 +1,338 @@
+//! Images made of stacks of layers, and how layers are combined
+
+use std::fmt::Display;
+
+use crate::pixel_buffer::PixelBuffer;
+
+/// How a layer's colors are combined with the colors of the layers below it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Covers the layers below (only letting them show through transparency)
+    #[default]
+    Normal,
+
+    /// Darkens by multiplying colors together
+    Multiply,
+
+    /// Lightens by multiplying the inverted colors together
+    Screen,
+
+    /// Multiplies dark areas and screens light areas of the layers below
+    Overlay,
+
+    /// Keeps the darker of the two colors
+    Darken,
+
+    /// Keeps the lighter of the two colors
+    Lighten,
+
+    /// Brightens the layers below to reflect the layer's color
+    ColorDodge,
+
+    /// Darkens the layers below to reflect the layer's color
+    ColorBurn,
+
+    /// Multiplies or screens, depending on the layer's color
+    HardLight,
+
+    /// A gentler version of hard light
+    SoftLight,
+
+    /// Subtracts the darker color from the lighter one
+    Difference,
+
+    /// Like difference, but with lower contrast
+    Exclusion,
+}
+
+impl BlendMode {
+    /// Every blend mode, in the order they should be shown in the UI
+    pub const ALL: [BlendMode; 12] = [
+        Self::Normal,
+        Self::Multiply,
+        Self::Screen,
+        Self::Overlay,
+        Self::Darken,
+        Self::Lighten,
+        Self::ColorDodge,
+        Self::ColorBurn,
+        Self::HardLight,
+        Self::SoftLight,
+        Self::Difference,
+        Self::Exclusion,
+    ];
+
+    /// Blends a single channel of a layer (`source`) onto the layers below it
+    /// (`backdrop`), ignoring transparency. Values range from 0.0 to 1.0
+    ///
+    /// See https://www.w3.org/TR/compositing-1/#blending
+    fn blend(&self, backdrop: f32, source: f32) -> f32 {
+        match self {
+            Self::Normal => source,
+            Self::Multiply => backdrop * source,
+            Self::Screen => backdrop + source - backdrop * source,
+            Self::Overlay => Self::HardLight.blend(source, backdrop),
+            Self::Darken => backdrop.min(source),
+            Self::Lighten => backdrop.max(source),
+            Self::ColorDodge => {
+                if backdrop == 0.0 {
+                    0.0
+                } else if source == 1.0 {
+                    1.0
+                } else {
+                    (backdrop / (1.0 - source)).min(1.0)
+                }
+            }
+            Self::ColorBurn => {
+                if backdrop == 1.0 {
+                    1.0
+                } else if source == 0.0 {
+                    0.0
+                } else {
+                    1.0 - ((1.0 - backdrop) / source).min(1.0)
+                }
+            }
+            Self::HardLight => {
+                if source <= 0.5 {
+                    Self::Multiply.blend(backdrop, 2.0 * source)
+                } else {
+                    Self::Screen.blend(backdrop, 2.0 * source - 1.0)
+                }
+            }
+            Self::SoftLight => {
+                if source <= 0.5 {
+                    backdrop - (1.0 - 2.0 * source) * backdrop * (1.0 - backdrop)
+                } else {
+                    let d = if backdrop <= 0.25 {
+                        ((16.0 * backdrop - 12.0) * backdrop + 4.0) * backdrop
+                    } else {
+                        backdrop.sqrt()
+                    };
+                    backdrop + (2.0 * source - 1.0) * (d - backdrop)
+                }
+            }
+            Self::Difference => (backdrop - source).abs(),
+            Self::Exclusion => backdrop + source - 2.0 * backdrop * source,
+        }
+    }
+}
+
+impl Display for BlendMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Normal => "Normal",
+                Self::Multiply => "Multiply",
+                Self::Screen => "Screen",
+                Self::Overlay => "Overlay",
+                Self::Darken => "Darken",
+                Self::Lighten => "Lighten",
+                Self::ColorDodge => "Color Dodge",
+                Self::ColorBurn => "Color Burn",
+                Self::HardLight => "Hard Light",
+                Self::SoftLight => "Soft Light",
+                Self::Difference => "Difference",
+                Self::Exclusion => "Exclusion",
+            }
+        )
+    }
+}
+
+/// A single layer of an image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    /// The name of the layer, as shown to the user
+    pub name: String,
+
+    /// The pixels of the layer (which may be a different size than the image)
+    pub pixels: PixelBuffer,
+
+    /// The horizontal position of the layer's top-left corner in the image
+    pub x: i32,
+
+    /// The vertical position of the layer's top-left corner in the image
+    pub y: i32,
+
+    /// How opaque the layer is, from 0.0 (invisible) to 1.0 (fully opaque)
+    pub opacity: f32,
+
+    /// Whether or not the layer is shown (hidden layers don't contribute to
+    /// the image at all)
+    pub visible: bool,
+
+    /// How the layer's colors are combined with the layers below it
+    pub blend_mode: BlendMode,
+}
+
+impl Layer {
+    /// Makes a new, visible, fully opaque layer at the top-left of the image
+    pub fn new(name: impl Into<String>, pixels: PixelBuffer) -> Self {
+        Self {
+            name: name.into(),
+            pixels,
+            x: 0,
+            y: 0,
+            opacity: 1.0,
+            visible: true,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+}
+
+/// An image made up of a stack of layers
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayeredImage {
+    /// The width of the image (the canvas), in pixels
+    pub width: usize,
+
+    /// The height of the image (the canvas), in pixels
+    pub height: usize,
+
+    /// The layers of the image, from bottom to top. There is always at least
+    /// one layer
+    pub layers: Vec<Layer>,
+
+    /// The index of the layer currently being edited
+    pub active_layer: usize,
+}
+
+impl Default for LayeredImage {
+    fn default() -> Self {
+        Self::from_flat(PixelBuffer::default())
+    }
+}
+
+impl LayeredImage {
+    /// Makes an image with a single layer containing the given pixels
+    pub fn from_flat(pixels: PixelBuffer) -> Self {
+        Self {
+            width: pixels.width,
+            height: pixels.height,
+            layers: vec![Layer::new("Background", pixels)],
+            active_layer: 0,
+        }
+    }
+
+    /// The layer currently being edited
+    pub fn active(&self) -> &Layer {
+        &self.layers[self.active_layer]
+    }
+
+    /// The layer currently being edited
+    pub fn active_mut(&mut self) -> &mut Layer {
+        &mut self.layers[self.active_layer]
+    }
+
+    /// Every pixel of every layer, for operations that affect the whole image
+    /// (like color conversions)
+    pub fn iter_pixel_buffers_mut(&mut self) -> impl Iterator<Item = &mut PixelBuffer> {
+        self.layers.iter_mut().map(|layer| &mut layer.pixels)
+    }
+
+    /// Mirrors the whole image (every layer, and where each layer is) left to
+    /// right
+    pub fn flip_horizontal(&mut self) {
+        for layer in &mut self.layers {
+            layer.pixels.flip_horizontal();
+            layer.x = self.width as i32 - layer.x - layer.pixels.width as i32;
+        }
+    }
+
+    /// Mirrors the whole image (every layer, and where each layer is) top to
+    /// bottom
+    pub fn flip_vertical(&mut self) {
+        for layer in &mut self.layers {
+            layer.pixels.flip_vertical();
+            layer.y = self.height as i32 - layer.y - layer.pixels.height as i32;
+        }
+    }
+
+    /// Combines all the visible layers into a single flat image
+    pub fn flatten(&self) -> PixelBuffer {
+        let mut flat = PixelBuffer {
+            pixels: vec![0; self.width * self.height * 4],
+            width: self.width,
+            height: self.height,
+        };
+
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            composite_layer(&mut flat, layer, &layer.pixels);
+        }
+
+        flat
+    }
+
+    /// Like `flatten`, but with other pixels in place of the active layer's
+    /// (to preview a change to it without making it)
+    pub fn flatten_with_active(&self, pixels: &PixelBuffer) -> PixelBuffer {
+        let mut flat = PixelBuffer {
+            pixels: vec![0; self.width * self.height * 4],
+            width: self.width,
+            height: self.height,
+        };
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            if layer.visible {
+                let pixels = if index == self.active_layer {
+                    pixels
+                } else {
+                    &layer.pixels
+                };
+                composite_layer(&mut flat, layer, pixels);
+            }
+        }
+
+        flat
+    }
+}
+
+/// Draws a layer's pixels on top of an image, respecting the layer's position,
+/// opacity, and blend mode
+fn composite_layer(backdrop: &mut PixelBuffer, layer: &Layer, pixels: &PixelBuffer) {
+    let opacity = layer.opacity.clamp(0.0, 1.0);
+    if opacity == 0.0 {
+        return;
+    }
+
+    for layer_row in 0..pixels.height {
+        let row = layer_row as i64 + layer.y as i64;
+        if row < 0 || row >= backdrop.height as i64 {
+            continue;
+        }
+
+        for layer_col in 0..pixels.width {
+            let col = layer_col as i64 + layer.x as i64;
+            if col < 0 || col >= backdrop.width as i64 {
+                continue;
+            }
+
+            let source_index = (layer_row * pixels.width + layer_col) * 4;
+            let source = &pixels.pixels[source_index..source_index + 4];
+            let backdrop_index = (row as usize * backdrop.width + col as usize) * 4;
+            let target = &mut backdrop.pixels[backdrop_index..backdrop_index + 4];
+
+            let source_alpha = source[3] as f32 / 255.0 * opacity;
+            if source_alpha == 0.0 {
+                continue;
+            }
+            let backdrop_alpha = target[3] as f32 / 255.0;
+            let result_alpha = source_alpha + backdrop_alpha * (1.0 - source_alpha);
+
+            // See https://www.w3.org/TR/compositing-1/#generalformula
+            for channel in 0..3 {
+                let source_color = source[channel] as f32 / 255.0;
+                let backdrop_color = target[channel] as f32 / 255.0;
+                let blended = layer.blend_mode.blend(backdrop_color, source_color);
+
+                let color = source_alpha * (1.0 - backdrop_alpha) * source_color
+                    + source_alpha * backdrop_alpha * blended
+                    + (1.0 - source_alpha) * backdrop_alpha * backdrop_color;
+
+                target[channel] = (color / result_alpha * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+            target[3] = (result_alpha * 255.0).round() as u8;
+        }
+    }
+}
@@ -1,4 +1,20 @@
 //! A free and open source raster graphics editor written in Rust
+//!
+//! Besides the editor itself ([`TrametesApp`]), the editing core doesn't
+//! depend on the GUI, so images can be opened, edited, and saved from other
+//! Rust code:
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use trametes::{document::Document, edit, export::ExportOptions};
+//!
+//! let mut document = Document::open(Path::new("photo.png"))?;
+//! edit::flip_horizontal(&mut document);
+//! edit::convert_to_grayscale(&mut document)?;
+//! document.save(Path::new("flipped.png"), &ExportOptions::default(), [255; 3])?;
+//! # Ok::<(), trametes::error::Error>(())
+//! ```
 
 #![warn(missing_docs, missing_debug_implementations, unused_unsafe)]
 #![deny(unsafe_code)] // This can be revisited, but for now we don't need unsafe
@@ -11,11 +27,35 @@
 // TODO do we *really* need a lib, and if we do, do we really need a separate
 // `app` mod? This feels overcomplicated
 
+pub mod adjustments;
+pub mod animation;
 mod app;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod batch;
+pub mod color_management;
+pub mod color_mode;
 mod commands;
+pub mod document;
+pub mod edit;
+pub mod error;
+pub mod export;
 mod fileio;
+mod jobs;
+pub mod layers;
 mod math;
-mod pixel_buffer;
-mod tools;
+pub mod metadata;
+mod notifications;
+mod openraster;
+pub mod pixel_buffer;
+mod presets;
+mod project;
+mod psd;
+pub mod quantize;
+mod recent_files;
+mod recovery;
+mod sprite_sheet;
+pub mod tools;
 mod ui;
+#[cfg(target_arch = "wasm32")]
+mod web;
 pub use app::TrametesApp;
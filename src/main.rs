@@ -1,7 +1,12 @@
 #![warn(clippy::all, rust_2018_idioms)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+#[cfg(not(target_arch = "wasm32"))]
 use eframe::{NativeOptions, Theme};
+#[cfg(not(target_arch = "wasm32"))]
 use egui::Vec2;
 use trametes::TrametesApp;
 
@@ -11,6 +16,15 @@ fn main() -> eframe::Result<()> {
     // Log to stdout (if you run with `RUST_LOG=debug`).
     tracing_subscriber::fmt::init();
 
+    // `trametes batch ...` processes images without opening a window
+    let mut args = std::env::args_os().skip(1).peekable();
+    if args.peek().is_some_and(|arg| arg == "batch") {
+        #[cfg(windows)]
+        attach_to_parent_console();
+        std::process::exit(trametes::batch::run(args.skip(1)));
+    }
+    let files = args.map(PathBuf::from).collect();
+
     eframe::run_native(
         "Trametes",
         NativeOptions {
@@ -28,13 +42,34 @@ fn main() -> eframe::Result<()> {
             centered: true,
             ..Default::default()
         },
-        Box::new(|cc| Box::new(TrametesApp::new(cc))),
+        // Open any files passed on the command line (like `trametes photo.png`)
+        Box::new(|cc| Box::new(TrametesApp::new(cc).with_files_to_open(files))),
     )
 }
 
-/// Compile to the web using trunk
+/// Attaches to the console of whatever started us (like a command prompt), so
+/// batch mode's output shows up even though release builds don't get a
+/// console of their own on Windows
+#[cfg(windows)]
+fn attach_to_parent_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+
+    // SAFETY: AttachConsole has no preconditions. If it fails (like when we
+    // weren't started from a console, or already have one), output just goes
+    // where it would have anyway
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// Compile to the web using trunk (`trunk serve`, which uses `index.html`)
 #[cfg(target_arch = "wasm32")]
-fn main_web() -> eframe::Result<()> {
+fn main() {
     // Make sure panics are logged using `console.error`.
     console_error_panic_hook::set_once();
 
@@ -44,12 +79,13 @@ fn main_web() -> eframe::Result<()> {
     let web_options = eframe::WebOptions::default();
 
     wasm_bindgen_futures::spawn_local(async {
-        eframe::start_web(
-            "the_canvas_id", // hardcode it
-            web_options,
-            Box::new(|cc| Box::new(TrametesApp::new(cc))),
-        )
-        .await
-        .expect("failed to start eframe");
+        eframe::WebRunner::new()
+            .start(
+                "the_canvas_id", // hardcode it
+                web_options,
+                Box::new(|cc| Box::new(TrametesApp::new(cc))),
+            )
+            .await
+            .expect("failed to start eframe");
     });
 }
@@ -1,3 +1,5 @@
+use std::sync::OnceLock;
+
 /// Linearly interpolates from a to b
 ///
 /// Examples:
@@ -11,6 +13,65 @@ pub fn lerp(fraction: f32, a: f32, b: f32) -> f32 {
     (1.0 - fraction) * a + fraction * b
 }
 
+/// The number of entries in the linear light to sRGB lookup table. This needs to
+/// be much larger than 256, since sRGB has much more precision near black
+const LINEAR_TO_SRGB_LUT_SIZE: usize = 4096;
+
+/// Converts an sRGB-encoded value (0.0 to 1.0) to linear light (0.0 to 1.0)
+fn srgb_decode(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear light value (0.0 to 1.0) to sRGB-encoded (0.0 to 1.0)
+fn srgb_encode(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts an sRGB-encoded channel value to linear light (0.0 to 1.0), using
+/// a lookup table
+pub fn srgb_to_linear(value: u8) -> f32 {
+    static LUT: OnceLock<[f32; 256]> = OnceLock::new();
+    let lut = LUT.get_or_init(|| std::array::from_fn(|value| srgb_decode(value as f32 / 255.0)));
+
+    lut[value as usize]
+}
+
+/// Converts a linear light value (0.0 to 1.0, clamped) to an sRGB-encoded
+/// channel value, using a lookup table
+pub fn linear_to_srgb(value: f32) -> u8 {
+    static LUT: OnceLock<Vec<u8>> = OnceLock::new();
+    let lut = LUT.get_or_init(|| {
+        (0..LINEAR_TO_SRGB_LUT_SIZE)
+            .map(|index| {
+                let value = index as f32 / (LINEAR_TO_SRGB_LUT_SIZE - 1) as f32;
+                (srgb_encode(value) * 255.0).round() as u8
+            })
+            .collect()
+    });
+
+    let index = (value.clamp(0.0, 1.0) * (LINEAR_TO_SRGB_LUT_SIZE - 1) as f32).round() as usize;
+    lut[index]
+}
+
+/// Linearly interpolates from one sRGB-encoded channel value to another,
+/// either directly on the encoded values, or in linear light (which avoids
+/// dark fringes between very different colors)
+pub fn lerp_srgb(fraction: f32, a: u8, b: u8, linear_light: bool) -> u8 {
+    if linear_light {
+        linear_to_srgb(lerp(fraction, srgb_to_linear(a), srgb_to_linear(b)))
+    } else {
+        lerp(fraction, a as f32, b as f32).round() as u8
+    }
+}
+
 fn dist_sq(x1: f32, y1: f32, x2: f32, y2: f32) -> f32 {
     (x1 - x2).powi(2) + (y1 - y2).powi(2)
 }
@@ -97,3 +158,62 @@ pub fn square_circle_intersection(
         square_center.1 + square_side_len / 2.0,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips_through_linear_light() {
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+
+    #[test]
+    fn srgb_to_linear_matches_known_values() {
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert_eq!(srgb_to_linear(255), 1.0);
+        assert!((srgb_to_linear(128) - 0.2158605).abs() < 1e-5);
+        assert!((srgb_to_linear(188) - 0.5028865).abs() < 1e-5);
+    }
+
+    #[test]
+    fn linear_to_srgb_clamps_out_of_range_values() {
+        assert_eq!(linear_to_srgb(-1.0), 0);
+        assert_eq!(linear_to_srgb(2.0), 255);
+    }
+
+    #[test]
+    fn lerp_srgb_blends_black_and_white() {
+        // Half black and half white is 50% linear light, which is much brighter
+        // than the halfway point between the encoded values
+        assert_eq!(lerp_srgb(0.5, 0, 255, false), 128);
+        assert_eq!(lerp_srgb(0.5, 0, 255, true), 188);
+    }
+
+    #[test]
+    fn lerp_srgb_blends_complementary_colors_without_darkening() {
+        let red = [255, 0, 0];
+        let green = [0, 255, 0];
+
+        let blend = |linear_light| -> [u8; 3] {
+            std::array::from_fn(|channel| {
+                lerp_srgb(0.5, red[channel], green[channel], linear_light)
+            })
+        };
+
+        assert_eq!(blend(false), [128, 128, 0]);
+        assert_eq!(blend(true), [188, 188, 0]);
+    }
+
+    #[test]
+    fn lerp_srgb_endpoints_are_exact() {
+        for linear_light in [false, true] {
+            for (a, b) in [(0, 255), (12, 200), (255, 1)] {
+                assert_eq!(lerp_srgb(0.0, a, b, linear_light), a);
+                assert_eq!(lerp_srgb(1.0, a, b, linear_light), b);
+            }
+        }
+    }
+}
@@ -0,0 +1,807 @@
+//! EXIF and XMP metadata (camera settings, GPS location, copyright, etc.)
+//!
+//! Metadata is kept as the raw bytes it was read as, so it can be written back
+//! out unchanged (including anything we don't understand, like maker notes).
+//! The few edits we need (resetting the orientation and removing the GPS
+//! location) are made in place
+
+use std::io::Read;
+
+use exif::{Context, In, Tag};
+use flate2::read::ZlibDecoder;
+use image::DynamicImage;
+use quick_xml::{
+    events::{BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+
+/// The header at the start of EXIF data in JPEG files (and sometimes
+/// elsewhere)
+pub const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// The header at the start of XMP data in JPEG files
+pub const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// The PNG iTXt keyword XMP data is stored under
+pub const XMP_PNG_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// EXIF tags we need to find ourselves
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_GPS_IFD: u16 = 0x8825;
+
+/// The metadata embedded in an image file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Raw EXIF data (a TIFF structure, without the `Exif\0\0` header)
+    pub exif: Option<Vec<u8>>,
+
+    /// Raw XMP data (an XML document)
+    pub xmp: Option<String>,
+}
+
+/// A single, human-readable, piece of EXIF metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataField {
+    /// Which group the field is in ("Image", "Camera", "GPS", etc.)
+    pub group: &'static str,
+
+    /// What the field is (like "Camera Model")
+    pub name: String,
+
+    /// The field's value, formatted for display
+    pub value: String,
+}
+
+/// The EXIF fields of some metadata, and whether it has a GPS location,
+/// worked out once (parsing the EXIF data every frame would be wasteful)
+#[derive(Debug, Clone)]
+pub struct MetadataSummary {
+    /// The metadata this summarizes
+    pub metadata: Metadata,
+
+    /// The metadata's EXIF fields, in a human-readable form
+    pub fields: Vec<MetadataField>,
+
+    /// Whether or not the metadata includes a GPS location
+    pub has_gps: bool,
+}
+
+impl MetadataSummary {
+    /// Summarizes some metadata
+    pub fn new(metadata: &Metadata) -> Self {
+        Self {
+            metadata: metadata.clone(),
+            fields: metadata.fields(),
+            has_gps: metadata.has_gps(),
+        }
+    }
+}
+
+impl Metadata {
+    /// Whether or not there is any metadata at all
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.xmp.is_none()
+    }
+
+    /// Reads the metadata from an encoded image file (JPEG, PNG, or WebP, other
+    /// formats have no metadata as far as we're concerned)
+    pub fn read_from_file(bytes: &[u8]) -> Self {
+        let mut metadata = if bytes.starts_with(&[0xFF, 0xD8]) {
+            read_jpeg(bytes)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            read_png(bytes)
+        } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+            read_webp(bytes)
+        } else {
+            Self::default()
+        };
+
+        // Some writers include the JPEG header even where it doesn't belong
+        if let Some(exif) = &mut metadata.exif {
+            if exif.starts_with(EXIF_HEADER) {
+                exif.drain(..EXIF_HEADER.len());
+            }
+        }
+        metadata.exif = metadata.exif.filter(|exif| !exif.is_empty());
+
+        metadata
+    }
+
+    /// Parses the EXIF data, if there is any (and it's valid)
+    fn parse_exif(&self) -> Option<exif::Exif> {
+        let exif = self.exif.as_ref()?;
+        match exif::Reader::new().read_raw(exif.clone()) {
+            Ok(exif) => Some(exif),
+            Err(err) => {
+                eprintln!("failed to parse EXIF data: {err}");
+                None
+            }
+        }
+    }
+
+    /// The EXIF orientation (1 through 8) of the image, describing how it
+    /// should be rotated and flipped for display
+    pub fn orientation(&self) -> Option<u32> {
+        self.parse_exif()?
+            .get_field(Tag::Orientation, In::PRIMARY)?
+            .value
+            .get_uint(0)
+            .filter(|orientation| (1..=8).contains(orientation))
+    }
+
+    /// Marks the image as not needing any rotation or flipping, for once the
+    /// orientation has been applied to the pixels themselves
+    pub fn reset_orientation(&mut self) {
+        if let Some(exif) = &mut self.exif {
+            set_tiff_orientation(exif);
+        }
+        if let Some(xmp) = &self.xmp {
+            match rewrite_xmp(xmp, false) {
+                Some(rewritten) => self.xmp = Some(rewritten),
+                None => eprintln!("failed to reset the orientation in XMP data"),
+            }
+        }
+    }
+
+    /// Whether or not the metadata includes a GPS location
+    pub fn has_gps(&self) -> bool {
+        let exif_has_gps = self.parse_exif().is_some_and(|exif| {
+            exif.fields()
+                .any(|field| field.tag.context() == Context::Gps)
+        });
+        let xmp_has_gps = self
+            .xmp
+            .as_ref()
+            .is_some_and(|xmp| xmp.contains("exif:GPS"));
+
+        exif_has_gps || xmp_has_gps
+    }
+
+    /// Removes the GPS location (and everything else GPS-related)
+    pub fn remove_gps(&mut self) {
+        if let Some(exif) = &mut self.exif {
+            if remove_tiff_gps(exif).is_none() {
+                // Better safe than sorry
+                eprintln!("failed to remove GPS data from EXIF data, removing it all");
+                self.exif = None;
+            }
+        }
+        if let Some(xmp) = &self.xmp {
+            self.xmp = rewrite_xmp(xmp, true);
+            if self.xmp.is_none() {
+                eprintln!("failed to remove GPS data from XMP data, removing it all");
+            }
+        }
+    }
+
+    /// The EXIF fields, in a human-readable form
+    pub fn fields(&self) -> Vec<MetadataField> {
+        let Some(exif) = self.parse_exif() else {
+            return Vec::new();
+        };
+
+        exif.fields()
+            // The thumbnail's fields are just noise
+            .filter(|field| field.ifd_num == In::PRIMARY)
+            // Not human-readable anyway
+            .filter(|field| field.tag != Tag::MakerNote)
+            .map(|field| {
+                let mut value = field.display_value().with_unit(&exif).to_string();
+                if value.chars().count() > 64 {
+                    value = value.chars().take(64).chain(['…']).collect();
+                }
+
+                MetadataField {
+                    group: match field.tag.context() {
+                        Context::Tiff => "Image",
+                        Context::Exif => "Camera",
+                        Context::Gps => "GPS",
+                        Context::Interop => "Interoperability",
+                        _ => "Other",
+                    },
+                    name: field.tag.to_string(),
+                    value,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Rotates and flips an image according to its EXIF orientation, so it looks
+/// the way it's supposed to
+pub fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn read_jpeg(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker = bytes[pos + 1];
+        // Start of scan (image data follows) or end of image
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let len = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let Some(segment) = bytes.get(pos + 4..pos + 2 + len) else {
+            break;
+        };
+
+        if marker == 0xE1 {
+            if let Some(exif) = segment.strip_prefix(EXIF_HEADER) {
+                metadata.exif = Some(exif.to_vec());
+            } else if let Some(xmp) = segment.strip_prefix(XMP_HEADER) {
+                metadata.xmp = Some(String::from_utf8_lossy(xmp).into_owned());
+            }
+        }
+
+        pos += 2 + len;
+    }
+
+    metadata
+}
+
+/// Splits off everything up to the next null byte
+fn split_null_terminated(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let null = bytes.iter().position(|byte| *byte == 0)?;
+    Some((&bytes[..null], &bytes[null + 1..]))
+}
+
+/// Reads the XMP data out of a PNG iTXt chunk, if it has any
+fn read_png_xmp(chunk: &[u8]) -> Option<String> {
+    let (keyword, rest) = split_null_terminated(chunk)?;
+    if keyword != XMP_PNG_KEYWORD.as_bytes() {
+        return None;
+    }
+    let (&compressed, rest) = rest.split_first()?;
+    let (_language, rest) = split_null_terminated(rest.get(1..)?)?;
+    let (_translated_keyword, text) = split_null_terminated(rest)?;
+
+    if compressed == 1 {
+        let mut xmp = String::new();
+        ZlibDecoder::new(text).read_to_string(&mut xmp).ok()?;
+        Some(xmp)
+    } else {
+        Some(String::from_utf8_lossy(text).into_owned())
+    }
+}
+
+fn read_png(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+
+    let mut pos = 8;
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let chunk_type = &bytes[pos + 4..pos + 8];
+        let Some(data) = bytes.get(pos + 8..pos + 8 + len) else {
+            break;
+        };
+
+        match chunk_type {
+            b"eXIf" => metadata.exif = Some(data.to_vec()),
+            b"iTXt" => {
+                if let Some(xmp) = read_png_xmp(data) {
+                    metadata.xmp = Some(xmp);
+                }
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+
+        // Length, type, data, and CRC
+        pos += 12 + len;
+    }
+
+    metadata
+}
+
+fn read_webp(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let chunk_type = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let Some(data) = bytes.get(pos + 8..pos + 8 + len) else {
+            break;
+        };
+
+        match chunk_type {
+            b"EXIF" => metadata.exif = Some(data.to_vec()),
+            b"XMP " => metadata.xmp = Some(String::from_utf8_lossy(data).into_owned()),
+            _ => {}
+        }
+
+        // Chunks are padded to an even length
+        pos += 8 + len + len % 2;
+    }
+
+    metadata
+}
+
+/// Just enough of a TIFF (the structure EXIF data uses) reader/writer to make
+/// small edits in place
+struct Tiff<'a> {
+    data: &'a mut [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a mut [u8]) -> Option<Self> {
+        let big_endian = match data.get(..2)? {
+            b"MM" => true,
+            b"II" => false,
+            _ => return None,
+        };
+        Some(Self { data, big_endian })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().unwrap();
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().unwrap();
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) -> Option<()> {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.data
+            .get_mut(offset..offset + 2)?
+            .copy_from_slice(&bytes);
+        Some(())
+    }
+
+    fn zero(&mut self, offset: usize, len: usize) -> Option<()> {
+        self.data.get_mut(offset..offset.checked_add(len)?)?.fill(0);
+        Some(())
+    }
+
+    /// The offset of the first IFD (which describes the main image)
+    fn ifd0(&self) -> Option<usize> {
+        Some(self.u32(4)? as usize)
+    }
+
+    /// The offset of the entry for a tag in an IFD
+    fn find_entry(&self, ifd: usize, tag: u16) -> Option<usize> {
+        let count = self.u16(ifd)? as usize;
+        (0..count)
+            .map(|index| ifd + 2 + index * 12)
+            .find(|entry| self.u16(*entry) == Some(tag))
+    }
+}
+
+/// The size of a single value of a TIFF field type
+fn tiff_type_size(field_type: u16) -> usize {
+    match field_type {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+/// Sets the orientation in raw EXIF data to 1 (no rotation or flipping)
+fn set_tiff_orientation(exif: &mut [u8]) -> Option<()> {
+    let mut tiff = Tiff::new(exif)?;
+    let entry = tiff.find_entry(tiff.ifd0()?, TAG_ORIENTATION)?;
+    // A single SHORT, stored in the entry itself
+    tiff.set_u16(entry + 8, 1)
+}
+
+/// Removes the GPS IFD from raw EXIF data, zeroing out everything in it (so the
+/// location can't be recovered)
+fn remove_tiff_gps(exif: &mut [u8]) -> Option<()> {
+    let mut tiff = Tiff::new(exif)?;
+    let ifd0 = tiff.ifd0()?;
+    let Some(pointer_entry) = tiff.find_entry(ifd0, TAG_GPS_IFD) else {
+        // No GPS data to begin with
+        return Some(());
+    };
+
+    let gps_ifd = tiff.u32(pointer_entry + 8)? as usize;
+    let gps_count = tiff.u16(gps_ifd)? as usize;
+    for index in 0..gps_count {
+        let entry = gps_ifd + 2 + index * 12;
+        let size = tiff_type_size(tiff.u16(entry + 2)?) * tiff.u32(entry + 4)? as usize;
+        // Values that don't fit in the entry itself are stored elsewhere
+        if size > 4 {
+            let offset = tiff.u32(entry + 8)? as usize;
+            tiff.zero(offset, size)?;
+        }
+    }
+    tiff.zero(gps_ifd, 2 + gps_count * 12 + 4)?;
+
+    // Remove the pointer to the GPS IFD by shifting the following entries
+    // (and the next IFD offset) back over it
+    let ifd0_count = tiff.u16(ifd0)? as usize;
+    let ifd0_end = ifd0 + 2 + ifd0_count * 12 + 4;
+    tiff.data
+        .get_mut(pointer_entry..ifd0_end)?
+        .copy_within(12.., 0);
+    tiff.zero(ifd0_end - 12, 12)?;
+    tiff.set_u16(ifd0, ifd0_count as u16 - 1)
+}
+
+/// Whether an XMP property name is GPS-related
+fn is_gps_property(name: &[u8]) -> bool {
+    // NOTE: this assumes the conventional "exif" namespace prefix
+    name.starts_with(b"exif:GPS")
+}
+
+/// Rewrites XMP data, either removing all GPS-related properties, or resetting
+/// the orientation to 1 (no rotation or flipping)
+fn rewrite_xmp(xmp: &str, remove_gps: bool) -> Option<String> {
+    let mut reader = Reader::from_str(xmp);
+    let mut writer = Writer::new(Vec::new());
+    let mut in_orientation = false;
+
+    // Copies an element, dropping GPS attributes or resetting the orientation
+    let rewrite_element = |element: &BytesStart| -> Option<BytesStart<'static>> {
+        let mut rewritten =
+            BytesStart::new(String::from_utf8(element.name().as_ref().to_vec()).ok()?);
+        for attribute in element.attributes() {
+            let mut attribute = attribute.ok()?;
+            if remove_gps && is_gps_property(attribute.key.as_ref()) {
+                continue;
+            }
+            if !remove_gps && attribute.key.as_ref() == b"tiff:Orientation" {
+                attribute.value = b"1".to_vec().into();
+            }
+            rewritten.push_attribute(attribute);
+        }
+        Some(rewritten)
+    };
+
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(element) => {
+                if remove_gps && is_gps_property(element.name().as_ref()) {
+                    reader.read_to_end(element.name()).ok()?;
+                    continue;
+                }
+                in_orientation = element.name().as_ref() == b"tiff:Orientation";
+                writer
+                    .write_event(Event::Start(rewrite_element(&element)?))
+                    .ok()?;
+            }
+            Event::Empty(element) => {
+                if remove_gps && is_gps_property(element.name().as_ref()) {
+                    continue;
+                }
+                writer
+                    .write_event(Event::Empty(rewrite_element(&element)?))
+                    .ok()?;
+            }
+            Event::Text(_) if in_orientation && !remove_gps => {
+                writer.write_event(Event::Text(BytesText::new("1"))).ok()?;
+            }
+            Event::End(element) => {
+                in_orientation = false;
+                writer.write_event(Event::End(element)).ok()?;
+            }
+            Event::Eof => break,
+            event => writer.write_event(event).ok()?,
+        }
+    }
+
+    String::from_utf8(writer.into_inner()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use exif::{experimental::Writer, Field, Rational, Value};
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    /// A recognizable latitude, to check that it's really gone once removed
+    const LATITUDE: u32 = 0x1234_5678;
+
+    /// Encodes EXIF data with a camera model, orientation, and (optionally) GPS
+    /// location
+    fn exif_bytes(little_endian: bool, with_gps: bool) -> Vec<u8> {
+        let mut fields = vec![
+            Field {
+                tag: Tag::Model,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"Trametes Cam".to_vec()]),
+            },
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![6]),
+            },
+        ];
+        if with_gps {
+            fields.push(Field {
+                tag: Tag::GPSLatitudeRef,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"N".to_vec()]),
+            });
+            fields.push(Field {
+                tag: Tag::GPSLatitude,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![Rational::from((LATITUDE, 1)); 3]),
+            });
+        }
+
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut bytes = Cursor::new(Vec::new());
+        writer.write(&mut bytes, little_endian).unwrap();
+        bytes.into_inner()
+    }
+
+    fn exif_metadata(little_endian: bool, with_gps: bool) -> Metadata {
+        Metadata {
+            exif: Some(exif_bytes(little_endian, with_gps)),
+            xmp: None,
+        }
+    }
+
+    fn field_value(metadata: &Metadata, name: &str) -> Option<String> {
+        metadata
+            .fields()
+            .into_iter()
+            .find(|field| field.name == name)
+            .map(|field| field.value)
+    }
+
+    const XMP: &str = concat!(
+        r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">"#,
+        r#"<rdf:Description tiff:Orientation="6" exif:GPSLatitude="51,30N" xmp:Rating="3"/>"#,
+        r#"<rdf:Description><tiff:Orientation>8</tiff:Orientation>"#,
+        r#"<exif:GPSLongitude>0,7W</exif:GPSLongitude><dc:creator>Someone</dc:creator>"#,
+        r#"</rdf:Description></rdf:RDF></x:xmpmeta>"#,
+    );
+
+    #[test]
+    fn orientations_are_applied() {
+        // A 3x2 image where each pixel is a different color
+        let image = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        // The size of the oriented image, and where the first two pixels of
+        // the top row end up
+        let expected = [
+            (1, (3, 2), (0, 0), (1, 0)),
+            (2, (3, 2), (2, 0), (1, 0)),
+            (3, (3, 2), (2, 1), (1, 1)),
+            (4, (3, 2), (0, 1), (1, 1)),
+            (5, (2, 3), (0, 0), (0, 1)),
+            (6, (2, 3), (1, 0), (1, 1)),
+            (7, (2, 3), (1, 2), (1, 1)),
+            (8, (2, 3), (0, 2), (0, 1)),
+        ];
+        for (orientation, size, first, second) in expected {
+            let oriented =
+                apply_orientation(DynamicImage::ImageRgba8(image.clone()), orientation).to_rgba8();
+
+            assert_eq!(oriented.dimensions(), size, "orientation {orientation}");
+            assert_eq!(
+                *oriented.get_pixel(first.0, first.1),
+                Rgba([0, 0, 0, 255]),
+                "orientation {orientation}"
+            );
+            assert_eq!(
+                *oriented.get_pixel(second.0, second.1),
+                Rgba([1, 0, 0, 255]),
+                "orientation {orientation}"
+            );
+        }
+    }
+
+    #[test]
+    fn orientation_is_reset_in_exif() {
+        for little_endian in [true, false] {
+            let mut metadata = exif_metadata(little_endian, true);
+            assert_eq!(metadata.orientation(), Some(6));
+
+            metadata.reset_orientation();
+
+            assert_eq!(metadata.orientation(), Some(1));
+            assert_eq!(
+                field_value(&metadata, "Model").as_deref(),
+                Some("\"Trametes Cam\"")
+            );
+            assert!(metadata.has_gps());
+        }
+    }
+
+    #[test]
+    fn gps_is_removed_from_exif() {
+        for little_endian in [true, false] {
+            let mut metadata = exif_metadata(little_endian, true);
+            assert!(metadata.has_gps());
+
+            metadata.remove_gps();
+
+            assert!(!metadata.has_gps());
+            assert_eq!(metadata.orientation(), Some(6));
+            assert!(field_value(&metadata, "Model").is_some());
+
+            // The location itself is zeroed out, not just unreachable
+            let exif = metadata.exif.unwrap();
+            let latitude = if little_endian {
+                LATITUDE.to_le_bytes()
+            } else {
+                LATITUDE.to_be_bytes()
+            };
+            assert!(!exif.windows(4).any(|window| window == latitude));
+        }
+    }
+
+    #[test]
+    fn removing_gps_leaves_exif_without_it_alone() {
+        let mut metadata = exif_metadata(true, false);
+        metadata.remove_gps();
+        assert_eq!(metadata, exif_metadata(true, false));
+    }
+
+    #[test]
+    fn xmp_is_rewritten() {
+        let reset = rewrite_xmp(XMP, false).unwrap();
+        assert!(reset.contains(r#"tiff:Orientation="1""#));
+        assert!(reset.contains("<tiff:Orientation>1</tiff:Orientation>"));
+        assert!(reset.contains("exif:GPSLatitude"));
+        assert!(reset.contains("<dc:creator>Someone</dc:creator>"));
+
+        let without_gps = rewrite_xmp(XMP, true).unwrap();
+        assert!(!without_gps.contains("GPS"));
+        assert!(without_gps.contains(r#"tiff:Orientation="6""#));
+        assert!(without_gps.contains(r#"xmp:Rating="3""#));
+        assert!(without_gps.contains("<dc:creator>Someone</dc:creator>"));
+    }
+
+    /// The metadata that every reader test expects to find
+    fn expected() -> Metadata {
+        Metadata {
+            exif: Some(exif_bytes(true, true)),
+            xmp: Some(XMP.to_string()),
+        }
+    }
+
+    #[test]
+    fn jpeg_metadata_is_read() {
+        let segment = |marker: u8, header: &[u8], data: &[u8]| {
+            let len = (2 + header.len() + data.len()) as u16;
+            [&[0xFF, marker][..], &len.to_be_bytes(), header, data].concat()
+        };
+        let exif = expected().exif.unwrap();
+        let bytes = [
+            &[0xFF, 0xD8][..],
+            &segment(0xE0, b"JFIF\0", &[1, 2, 0, 0, 1, 0, 1, 0, 0]),
+            &segment(0xE1, EXIF_HEADER, &exif),
+            &segment(0xE1, XMP_HEADER, XMP.as_bytes()),
+            &segment(0xDA, &[], &[]),
+            &[0xFF, 0xD9],
+        ]
+        .concat();
+
+        assert_eq!(Metadata::read_from_file(&bytes), expected());
+    }
+
+    #[test]
+    fn png_metadata_is_read() {
+        // CRCs aren't checked, so they're left as zeroes
+        let chunk = |chunk_type: &[u8], data: &[u8]| {
+            [
+                &(data.len() as u32).to_be_bytes()[..],
+                chunk_type,
+                data,
+                &[0; 4],
+            ]
+            .concat()
+        };
+        let itxt = |compressed: bool| {
+            let text = if compressed {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                std::io::Write::write_all(&mut encoder, XMP.as_bytes()).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                XMP.as_bytes().to_vec()
+            };
+            [
+                XMP_PNG_KEYWORD.as_bytes(),
+                &[0, compressed as u8, 0, 0, 0],
+                &text,
+            ]
+            .concat()
+        };
+
+        for compressed in [false, true] {
+            let bytes = [
+                &b"\x89PNG\r\n\x1a\n"[..],
+                &chunk(b"IHDR", &[0; 13]),
+                &chunk(b"eXIf", &expected().exif.unwrap()),
+                &chunk(b"iTXt", &itxt(compressed)),
+                &chunk(b"IEND", &[]),
+            ]
+            .concat();
+
+            assert_eq!(Metadata::read_from_file(&bytes), expected());
+        }
+    }
+
+    #[test]
+    fn webp_metadata_is_read() {
+        let chunk = |chunk_type: &[u8], data: &[u8]| {
+            let padding: &[u8] = if data.len() % 2 == 1 { &[0] } else { &[] };
+            [
+                chunk_type,
+                &(data.len() as u32).to_le_bytes(),
+                data,
+                padding,
+            ]
+            .concat()
+        };
+        // An odd length, to check the padding is skipped
+        let xmp = format!("{XMP} ");
+        let xmp = if xmp.len() % 2 == 1 {
+            xmp
+        } else {
+            format!("{xmp} ")
+        };
+        let chunks = [
+            chunk(b"VP8X", &[0; 10]),
+            chunk(b"XMP ", xmp.as_bytes()),
+            chunk(b"EXIF", &expected().exif.unwrap()),
+        ]
+        .concat();
+        let bytes = [
+            &b"RIFF"[..],
+            &(chunks.len() as u32 + 4).to_le_bytes(),
+            b"WEBP",
+            &chunks,
+        ]
+        .concat();
+
+        let metadata = Metadata::read_from_file(&bytes);
+        assert_eq!(metadata.exif, expected().exif);
+        assert_eq!(metadata.xmp, Some(xmp));
+    }
+
+    #[test]
+    fn exif_headers_are_stripped() {
+        let exif = expected().exif.unwrap();
+        let bytes = [
+            &b"RIFF\0\0\0\0WEBP"[..],
+            b"EXIF",
+            &(EXIF_HEADER.len() as u32 + exif.len() as u32).to_le_bytes(),
+            EXIF_HEADER,
+            &exif,
+        ]
+        .concat();
+
+        assert_eq!(Metadata::read_from_file(&bytes).exif, Some(exif));
+    }
+}
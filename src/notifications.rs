@@ -0,0 +1,206 @@
+//! Messages for the user (like why something couldn't be done), kept until
+//! the UI shows them: info and warnings as toasts that go away on their own,
+//! and errors one at a time until the user acknowledges them
+
+use std::collections::VecDeque;
+
+use crate::error::Error;
+
+/// How long toasts stay on screen, in seconds
+pub const TOAST_DURATION: f64 = 5.0;
+
+/// The most notifications kept in the log (older ones are forgotten)
+const MAX_LOG_LEN: usize = 200;
+
+/// How important a notification is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Something the user might like to know
+    Info,
+
+    /// Something was done, but not quite how the user might expect
+    Warning,
+
+    /// Something couldn't be done
+    Error,
+}
+
+/// A message for the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// How important the message is
+    pub severity: Severity,
+
+    /// The message itself
+    pub message: String,
+}
+
+/// A notification shown as a toast
+#[derive(Debug, Clone)]
+pub struct Toast {
+    /// What the toast says
+    pub notification: Notification,
+
+    /// When (on egui's clock, in seconds) the toast was first shown, if it has
+    /// been yet
+    shown_at: Option<f64>,
+}
+
+impl Toast {
+    /// How much of the toast's time on screen is left, from 1 (just shown) to 0
+    pub fn remaining(&self, now: f64) -> f64 {
+        let elapsed = self.shown_at.map_or(0.0, |shown_at| now - shown_at);
+        (1.0 - elapsed / TOAST_DURATION).clamp(0.0, 1.0)
+    }
+}
+
+/// Every notification, and which ones are still waiting to be seen
+#[derive(Debug, Default)]
+pub struct Notifications {
+    /// Every notification so far, oldest first
+    log: VecDeque<Notification>,
+
+    /// Info and warnings currently shown as toasts, oldest first
+    toasts: Vec<Toast>,
+
+    /// Errors waiting for the user to acknowledge them, oldest first
+    errors: VecDeque<String>,
+}
+
+impl Notifications {
+    /// Adds a notification to the log, and queues it to be shown
+    pub fn push(&mut self, severity: Severity, message: impl Into<String>) {
+        let notification = Notification {
+            severity,
+            message: message.into(),
+        };
+
+        match severity {
+            Severity::Info | Severity::Warning => self.toasts.push(Toast {
+                notification: notification.clone(),
+                shown_at: None,
+            }),
+            Severity::Error => self.errors.push_back(notification.message.clone()),
+        }
+
+        if self.log.len() == MAX_LOG_LEN {
+            self.log.pop_front();
+        }
+        self.log.push_back(notification);
+    }
+
+    /// Tells the user something they might like to know
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(Severity::Info, message);
+    }
+
+    /// Warns the user that something wasn't done quite how they might expect
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.push(Severity::Warning, message);
+    }
+
+    /// Tells the user that something couldn't be done
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.push(Severity::Error, message);
+    }
+
+    /// Tells the user why something couldn't be done
+    pub fn report_error(&mut self, err: &Error) {
+        eprintln!("{err:?}");
+        self.error(err.to_string());
+    }
+
+    /// Tells the user if something couldn't be done
+    pub fn report(&mut self, result: Result<(), Error>) {
+        if let Err(err) = result {
+            self.report_error(&err);
+        }
+    }
+
+    /// Every notification so far (or at least the most recent ones), oldest
+    /// first
+    pub fn log(&self) -> &VecDeque<Notification> {
+        &self.log
+    }
+
+    /// Forgets every notification in the log
+    pub fn clear_log(&mut self) {
+        self.log.clear();
+    }
+
+    /// The toasts that should be on screen at the given time (on egui's clock,
+    /// in seconds), forgetting any that have been shown for long enough
+    pub fn toasts(&mut self, now: f64) -> &[Toast] {
+        for toast in &mut self.toasts {
+            toast.shown_at.get_or_insert(now);
+        }
+        self.toasts.retain(|toast| toast.remaining(now) > 0.0);
+        &self.toasts
+    }
+
+    /// Closes a toast before its time is up
+    pub fn dismiss_toast(&mut self, index: usize) {
+        if index < self.toasts.len() {
+            self.toasts.remove(index);
+        }
+    }
+
+    /// The oldest error the user hasn't acknowledged yet
+    pub fn current_error(&self) -> Option<&str> {
+        self.errors.front().map(String::as_str)
+    }
+
+    /// Marks the current error as acknowledged, moving on to the next one
+    pub fn acknowledge_error(&mut self) {
+        self.errors.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toasts_disappear_after_being_shown_for_long_enough() {
+        let mut notifications = Notifications::default();
+        notifications.info("first");
+
+        // Toasts aren't timed until they're first shown
+        assert_eq!(notifications.toasts(100.0).len(), 1);
+        notifications.warning("second");
+        assert_eq!(notifications.toasts(102.0).len(), 2);
+
+        let toasts = notifications.toasts(100.0 + TOAST_DURATION);
+        assert_eq!(toasts.len(), 1);
+        assert_eq!(toasts[0].notification.message, "second");
+        assert!(notifications.toasts(102.0 + TOAST_DURATION).is_empty());
+
+        // They're still in the log, though
+        assert_eq!(notifications.log().len(), 2);
+    }
+
+    #[test]
+    fn errors_wait_to_be_acknowledged_in_order() {
+        let mut notifications = Notifications::default();
+        notifications.error("first");
+        notifications.error("second");
+
+        assert!(notifications.toasts(0.0).is_empty());
+        assert_eq!(notifications.current_error(), Some("first"));
+        notifications.acknowledge_error();
+        assert_eq!(notifications.current_error(), Some("second"));
+        notifications.acknowledge_error();
+        assert_eq!(notifications.current_error(), None);
+    }
+
+    #[test]
+    fn the_log_forgets_the_oldest_notifications() {
+        let mut notifications = Notifications::default();
+        for i in 0..MAX_LOG_LEN + 1 {
+            notifications.info(i.to_string());
+        }
+
+        assert_eq!(notifications.log().len(), MAX_LOG_LEN);
+        assert_eq!(notifications.log()[0].message, "1");
+    }
+}
@@ -0,0 +1,311 @@
+//! OpenRaster (`.ora`) support, for exchanging layered images with other
+//! editors like Krita, GIMP, and MyPaint
+//!
+//! See https://www.openraster.org/ for the specification
+
+use std::{
+    error::Error,
+    fmt::Write as _,
+    io::{Read, Seek, Write},
+};
+
+use quick_xml::{escape::escape, events::Event, Reader};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    animation::Animation,
+    color_mode::ColorMode,
+    fileio::{self, LoadedImage},
+    layers::{BlendMode, Layer, LayeredImage},
+    metadata::Metadata,
+};
+
+/// The file extension for OpenRaster files
+pub const EXTENSION: &str = "ora";
+
+/// The MIME type stored in the `mimetype` entry of OpenRaster files
+pub const MIME_TYPE: &str = "image/openraster";
+
+/// The version of the spec we write
+const SPEC_VERSION: &str = "0.0.6";
+
+/// The largest width or height of the thumbnail
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// The OpenRaster name for a blend mode (its `composite-op`)
+fn composite_op(blend_mode: BlendMode) -> &'static str {
+    match blend_mode {
+        BlendMode::Normal => "svg:src-over",
+        BlendMode::Multiply => "svg:multiply",
+        BlendMode::Screen => "svg:screen",
+        BlendMode::Overlay => "svg:overlay",
+        BlendMode::Darken => "svg:darken",
+        BlendMode::Lighten => "svg:lighten",
+        BlendMode::ColorDodge => "svg:color-dodge",
+        BlendMode::ColorBurn => "svg:color-burn",
+        BlendMode::HardLight => "svg:hard-light",
+        BlendMode::SoftLight => "svg:soft-light",
+        BlendMode::Difference => "svg:difference",
+        BlendMode::Exclusion => "svg:exclusion",
+    }
+}
+
+/// The blend mode for an OpenRaster `composite-op`, falling back to normal for
+/// ones we don't support
+fn blend_mode(composite_op: &str) -> BlendMode {
+    BlendMode::ALL
+        .into_iter()
+        .find(|blend_mode| self::composite_op(*blend_mode) == composite_op)
+        .unwrap_or_else(|| {
+            eprintln!("unsupported OpenRaster composite-op {composite_op:?}, using normal");
+            BlendMode::Normal
+        })
+}
+
+/// Writes an image as an OpenRaster file
+pub fn write_openraster<W: Write + Seek>(
+    writer: W,
+    image: &LayeredImage,
+) -> Result<(), Box<dyn Error>> {
+    let mut zip = ZipWriter::new(writer);
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // The mimetype must be first, and uncompressed
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(MIME_TYPE.as_bytes())?;
+
+    let mut stack_xml = String::new();
+    writeln!(stack_xml, "<?xml version='1.0' encoding='UTF-8'?>")?;
+    writeln!(
+        stack_xml,
+        r#"<image version="{SPEC_VERSION}" w="{}" h="{}">"#,
+        image.width, image.height
+    )?;
+    writeln!(stack_xml, "  <stack>")?;
+
+    // The stack lists layers from top to bottom
+    for (index, layer) in image.layers.iter().enumerate().rev() {
+        let src = format!("data/layer{index}.png");
+
+        zip.start_file(&src, stored)?;
+        zip.write_all(&fileio::encode_png(&layer.pixels)?)?;
+
+        writeln!(
+            stack_xml,
+            r#"    <layer name="{}" src="{src}" x="{}" y="{}" opacity="{}" visibility="{}" composite-op="{}" />"#,
+            escape(&layer.name),
+            layer.x,
+            layer.y,
+            layer.opacity,
+            if layer.visible { "visible" } else { "hidden" },
+            composite_op(layer.blend_mode),
+        )?;
+    }
+
+    writeln!(stack_xml, "  </stack>")?;
+    writeln!(stack_xml, "</image>")?;
+
+    zip.start_file("stack.xml", deflated)?;
+    zip.write_all(stack_xml.as_bytes())?;
+
+    let merged = image.flatten();
+    zip.start_file("mergedimage.png", stored)?;
+    zip.write_all(&fileio::encode_png(&merged)?)?;
+
+    // Other programs only show the thumbnail as a rough preview, so it isn't
+    // worth following the linear light setting for
+    zip.start_file("Thumbnails/thumbnail.png", stored)?;
+    zip.write_all(&fileio::encode_png(
+        &merged.thumbnail(THUMBNAIL_SIZE, false),
+    )?)?;
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// Reads the full contents of a file in a zip archive
+fn read_entry<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    zip.by_name(name)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// A layer as described in `stack.xml`
+struct StackLayer {
+    name: String,
+    src: String,
+    x: i32,
+    y: i32,
+    opacity: f32,
+    visible: bool,
+    blend_mode: BlendMode,
+}
+
+/// The contents of `stack.xml`
+struct Stack {
+    width: usize,
+    height: usize,
+
+    /// The layers of the image, from top to bottom
+    layers: Vec<StackLayer>,
+}
+
+/// Reads the image size and layers from `stack.xml`. Nested stacks (layer
+/// groups) aren't supported, so their layers are read as if they weren't
+/// grouped (hidden groups hide all their layers, though)
+fn parse_stack_xml(xml: &str) -> Result<Stack, Box<dyn Error>> {
+    let mut reader = Reader::from_str(xml);
+
+    let mut size = None;
+    let mut layers = Vec::new();
+    // Whether each stack we're inside of is visible
+    let mut stack_visibility: Vec<bool> = Vec::new();
+
+    loop {
+        let (element, is_empty) = match reader.read_event()? {
+            Event::Start(element) => (element, false),
+            Event::Empty(element) => (element, true),
+            Event::End(element) => {
+                if element.name().as_ref() == b"stack" {
+                    stack_visibility.pop();
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let attribute = |name: &str| -> Result<Option<String>, Box<dyn Error>> {
+            Ok(match element.try_get_attribute(name)? {
+                Some(attribute) => Some(attribute.unescape_value()?.into_owned()),
+                None => None,
+            })
+        };
+        let visible = attribute("visibility")?.as_deref() != Some("hidden");
+
+        match element.name().as_ref() {
+            b"image" => {
+                let width = attribute("w")?.ok_or("image has no width")?.parse()?;
+                let height = attribute("h")?.ok_or("image has no height")?.parse()?;
+                size = Some((width, height));
+            }
+            // Empty stacks don't have a matching end event
+            b"stack" if !is_empty => stack_visibility.push(visible),
+            b"layer" => {
+                let Some(src) = attribute("src")? else {
+                    // Layers without a source are allowed, but useless
+                    continue;
+                };
+
+                layers.push(StackLayer {
+                    name: attribute("name")?.unwrap_or_default(),
+                    src,
+                    x: attribute("x")?.map(|x| x.parse()).transpose()?.unwrap_or(0),
+                    y: attribute("y")?.map(|y| y.parse()).transpose()?.unwrap_or(0),
+                    opacity: attribute("opacity")?
+                        .map(|opacity| opacity.parse())
+                        .transpose()?
+                        .unwrap_or(1.0),
+                    visible: visible && stack_visibility.iter().all(|visible| *visible),
+                    blend_mode: attribute("composite-op")?
+                        .map(|op| blend_mode(&op))
+                        .unwrap_or_default(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let (width, height) = size.ok_or("stack.xml has no image element")?;
+    Ok(Stack {
+        width,
+        height,
+        layers,
+    })
+}
+
+/// Reads an image from an OpenRaster file
+pub fn read_openraster<R: Read + Seek>(reader: R) -> Result<LoadedImage, Box<dyn Error>> {
+    let mut zip = ZipArchive::new(reader)?;
+
+    let stack_xml = String::from_utf8(read_entry(&mut zip, "stack.xml")?)?;
+    let stack = parse_stack_xml(&stack_xml)?;
+    fileio::check_dimensions(stack.width, stack.height)?;
+
+    let mut layers = Vec::new();
+    // The stack is top to bottom, but our layers are bottom to top
+    for stack_layer in stack.layers.into_iter().rev() {
+        let pixels = fileio::decode_image(&read_entry(&mut zip, &stack_layer.src)?)?;
+
+        layers.push(Layer {
+            name: stack_layer.name,
+            pixels,
+            x: stack_layer.x,
+            y: stack_layer.y,
+            opacity: stack_layer.opacity.clamp(0.0, 1.0),
+            visible: stack_layer.visible,
+            blend_mode: stack_layer.blend_mode,
+        });
+    }
+
+    // Fall back on the merged image if there aren't any usable layers
+    if layers.is_empty() {
+        let merged = fileio::decode_image(&read_entry(&mut zip, "mergedimage.png")?)?;
+        layers.push(Layer::new("Background", merged));
+    }
+
+    Ok(LoadedImage {
+        image: LayeredImage {
+            width: stack.width,
+            height: stack.height,
+            active_layer: layers.len() - 1,
+            layers,
+        },
+        animation: Animation::default(),
+        color_profile: None,
+        color_mode: ColorMode::Rgba,
+        metadata: Metadata::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::pixel_buffer::PixelBuffer;
+
+    #[test]
+    fn layers_round_trip() {
+        let mut top = Layer::new(
+            "Ink & <Shading>",
+            PixelBuffer::filled(2, 3, [10, 20, 30, 128]),
+        );
+        top.x = -2;
+        top.y = 5;
+        top.opacity = 0.25;
+        top.visible = false;
+        top.blend_mode = BlendMode::Multiply;
+
+        let image = LayeredImage {
+            width: 4,
+            height: 6,
+            layers: vec![
+                Layer::new("\"Paper\"", PixelBuffer::filled(4, 6, [255, 255, 255, 255])),
+                top,
+            ],
+            active_layer: 1,
+        };
+
+        let mut bytes = Vec::new();
+        write_openraster(Cursor::new(&mut bytes), &image).unwrap();
+        let loaded = read_openraster(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(loaded.image, image);
+    }
+}
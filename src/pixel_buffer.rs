@@ -1,31 +1,171 @@
-#[derive(Debug)]
+//! Flat RGBA images, and operations on them (resizing, flipping, etc.)
+
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, ImageBuffer, Rgba32FImage, RgbaImage,
+};
+
+use crate::math::{linear_to_srgb, srgb_to_linear};
+
+/// A flat image, stored as 8-bit RGBA pixels
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PixelBuffer {
+    /// The red, green, blue, and alpha values of each pixel (4 bytes per
+    /// pixel), row by row from the top-left
     pub pixels: Vec<u8>,
+
+    /// The width of the image, in pixels
     pub width: usize,
+
+    /// The height of the image, in pixels
     pub height: usize,
 }
 
 impl Default for PixelBuffer {
     fn default() -> Self {
-        let width = 800;
-        let height = 600;
+        Self::filled(800, 600, [255, 255, 255, 255])
+    }
+}
+
+impl PixelBuffer {
+    /// An image where every pixel is the same color
+    pub fn filled(width: usize, height: usize, color: [u8; 4]) -> Self {
+        Self {
+            pixels: std::iter::repeat_n(color, width * height)
+                .flatten()
+                .collect(),
+            width,
+            height,
+        }
+    }
 
-        const WHITE: [u8; 4] = [255, 255, 255, 255];
+    /// Wraps the pixels for use with the `image` crate's operations
+    fn to_rgba_image(&self) -> RgbaImage {
+        ImageBuffer::from_raw(self.width as u32, self.height as u32, self.pixels.clone()).unwrap()
+    }
+
+    fn from_rgba_image(image: RgbaImage) -> PixelBuffer {
+        PixelBuffer {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels: image.into_raw(),
+        }
+    }
 
-        let pixels: Vec<u8> = std::iter::repeat(WHITE)
-            .take(width * height)
-            .flatten()
+    /// Converts the pixels to floating point for the `image` crate's
+    /// operations, with the colors decoded to linear light
+    fn to_linear_image(&self) -> Rgba32FImage {
+        let pixels = self
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                [
+                    srgb_to_linear(pixel[0]),
+                    srgb_to_linear(pixel[1]),
+                    srgb_to_linear(pixel[2]),
+                    pixel[3] as f32 / 255.0,
+                ]
+            })
             .collect();
+        ImageBuffer::from_raw(self.width as u32, self.height as u32, pixels).unwrap()
+    }
 
-        Self {
-            pixels,
+    fn from_linear_image(image: Rgba32FImage) -> PixelBuffer {
+        PixelBuffer {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels: image
+                .pixels()
+                .flat_map(|pixel| {
+                    [
+                        linear_to_srgb(pixel[0]),
+                        linear_to_srgb(pixel[1]),
+                        linear_to_srgb(pixel[2]),
+                        (pixel[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+                    ]
+                })
+                .collect(),
+        }
+    }
+
+    /// Makes a scaled-down copy that fits within `max_size` x `max_size`,
+    /// averaging colors either directly on the sRGB-encoded values or in
+    /// linear light
+    pub fn thumbnail(&self, max_size: u32, linear_light: bool) -> PixelBuffer {
+        if linear_light {
+            let thumbnail = DynamicImage::ImageRgba32F(self.to_linear_image())
+                .resize(max_size, max_size, FilterType::Triangle)
+                .into_rgba32f();
+            return Self::from_linear_image(thumbnail);
+        }
+
+        let thumbnail = DynamicImage::ImageRgba8(self.to_rgba_image())
+            .resize(max_size, max_size, FilterType::Triangle)
+            .into_rgba8();
+
+        Self::from_rgba_image(thumbnail)
+    }
+
+    /// Makes a copy scaled to exactly `width` x `height` (which can change the
+    /// aspect ratio), resampling either directly on the sRGB-encoded values or
+    /// in linear light (which keeps fine detail from getting darker)
+    pub fn resize(&self, width: usize, height: usize, linear_light: bool) -> PixelBuffer {
+        let (width, height) = (width.max(1) as u32, height.max(1) as u32);
+        if linear_light {
+            return Self::from_linear_image(imageops::resize(
+                &self.to_linear_image(),
+                width,
+                height,
+                FilterType::CatmullRom,
+            ));
+        }
+
+        Self::from_rgba_image(imageops::resize(
+            &self.to_rgba_image(),
             width,
             height,
+            FilterType::CatmullRom,
+        ))
+    }
+
+    /// Mirrors the pixels left to right
+    pub fn flip_horizontal(&mut self) {
+        for row in self.pixels.chunks_exact_mut(self.width * 4) {
+            let (mut left, mut right) = (0, self.width.saturating_sub(1));
+            while left < right {
+                for channel in 0..4 {
+                    row.swap(left * 4 + channel, right * 4 + channel);
+                }
+                left += 1;
+                right -= 1;
+            }
         }
     }
-}
 
-impl PixelBuffer {
+    /// Mirrors the pixels top to bottom
+    pub fn flip_vertical(&mut self) {
+        let row_len = self.width * 4;
+        for row in 0..self.height / 2 {
+            let (top, bottom) = self.pixels.split_at_mut((self.height - row - 1) * row_len);
+            top[row * row_len..(row + 1) * row_len].swap_with_slice(&mut bottom[..row_len]);
+        }
+    }
+
+    /// Makes a copy rotated 90 degrees clockwise
+    pub fn rotate_90(&self) -> PixelBuffer {
+        Self::from_rgba_image(imageops::rotate90(&self.to_rgba_image()))
+    }
+
+    /// Makes a copy rotated 180 degrees
+    pub fn rotate_180(&self) -> PixelBuffer {
+        Self::from_rgba_image(imageops::rotate180(&self.to_rgba_image()))
+    }
+
+    /// Makes a copy rotated 270 degrees clockwise (90 degrees counterclockwise)
+    pub fn rotate_270(&self) -> PixelBuffer {
+        Self::from_rgba_image(imageops::rotate270(&self.to_rgba_image()))
+    }
+
     /// Returns an iterator over the pixels in a rectangular subsection of this
     /// PixelBuffer. The iterator yields ((r, g, b, a), (col, row))
     // TODO can these lifetime annotations be inferred?
@@ -66,3 +206,94 @@ impl PixelBuffer {
             })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An image where each pixel's red value is its index, so tests can tell
+    /// where pixels ended up
+    fn numbered(width: usize, height: usize) -> PixelBuffer {
+        PixelBuffer {
+            pixels: (0..(width * height) as u8)
+                .flat_map(|index| [index, 0, 0, 255])
+                .collect(),
+            width,
+            height,
+        }
+    }
+
+    /// The red value of each pixel, row by row
+    fn reds(image: &PixelBuffer) -> Vec<u8> {
+        image.pixels.chunks_exact(4).map(|pixel| pixel[0]).collect()
+    }
+
+    /// A black and white checkerboard, with 1 pixel squares
+    fn checkerboard(size: usize) -> PixelBuffer {
+        PixelBuffer {
+            pixels: (0..size * size)
+                .flat_map(|index| {
+                    let value = if (index % size + index / size).is_multiple_of(2) {
+                        0
+                    } else {
+                        255
+                    };
+                    [value, value, value, 255]
+                })
+                .collect(),
+            width: size,
+            height: size,
+        }
+    }
+
+    #[test]
+    fn resizing_in_linear_light_keeps_the_brightness() {
+        // Half black and half white is 50% linear light, which is encoded as
+        // 188 (not 128)
+        for (linear_light, expected) in [(false, 128), (true, 188)] {
+            // (Allowing for some ringing at the corners)
+            let resized = checkerboard(16).resize(8, 8, linear_light);
+            assert_eq!((resized.width, resized.height), (8, 8));
+            for pixel in resized.pixels.chunks_exact(4) {
+                assert!(pixel[0].abs_diff(expected) <= 4, "{pixel:?} ~ {expected}");
+                assert_eq!(pixel[3], 255);
+            }
+
+            let thumbnail = checkerboard(16).thumbnail(8, linear_light);
+            assert_eq!((thumbnail.width, thumbnail.height), (8, 8));
+            for pixel in thumbnail.pixels.chunks_exact(4) {
+                assert!(pixel[0].abs_diff(expected) <= 4, "{pixel:?} ~ {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn flipping_odd_sizes_leaves_the_middle_in_place() {
+        let mut image = numbered(3, 3);
+        image.flip_horizontal();
+        assert_eq!(reds(&image), [2, 1, 0, 5, 4, 3, 8, 7, 6]);
+
+        let mut image = numbered(3, 3);
+        image.flip_vertical();
+        assert_eq!(reds(&image), [6, 7, 8, 3, 4, 5, 0, 1, 2]);
+    }
+
+    #[test]
+    fn rotating_swaps_the_dimensions() {
+        // 0 1 2
+        // 3 4 5
+        let image = numbered(3, 2);
+
+        let rotated = image.rotate_90();
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        assert_eq!(reds(&rotated), [3, 0, 4, 1, 5, 2]);
+
+        let rotated = image.rotate_180();
+        assert_eq!((rotated.width, rotated.height), (3, 2));
+        assert_eq!(reds(&rotated), [5, 4, 3, 2, 1, 0]);
+
+        let rotated = image.rotate_270();
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        assert_eq!(reds(&rotated), [2, 5, 1, 4, 0, 3]);
+    }
+}
@@ -0,0 +1,66 @@
+//! Saved settings for adjustments (like a favorite curve)
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adjustments::{Adjustment, AdjustmentKind},
+    fileio,
+};
+
+/// The name of the file the presets are stored in (in the app's data
+/// directory)
+const FILE_NAME: &str = "adjustment_presets.json";
+
+/// Named settings for an adjustment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    /// What the user called the preset
+    pub name: String,
+
+    /// The adjustment, with its settings
+    pub adjustment: Adjustment,
+}
+
+/// Every saved preset, oldest first
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Presets {
+    presets: Vec<Preset>,
+}
+
+impl Presets {
+    /// Loads the presets saved by a previous run, or none if there aren't any
+    /// (or they can't be read)
+    pub fn load() -> Self {
+        fileio::load_app_data(FILE_NAME)
+    }
+
+    /// Writes the presets out for future runs
+    fn save(&self) {
+        fileio::save_app_data(FILE_NAME, self);
+    }
+
+    /// The presets for a kind of adjustment, oldest first
+    pub fn of_kind(&self, kind: AdjustmentKind) -> impl Iterator<Item = &Preset> {
+        self.presets
+            .iter()
+            .filter(move |preset| preset.adjustment.kind() == kind)
+    }
+
+    /// Saves settings for an adjustment under a name, replacing any preset
+    /// for the same kind of adjustment with that name
+    pub fn add(&mut self, name: &str, adjustment: Adjustment) {
+        self.remove(adjustment.kind(), name);
+        self.presets.push(Preset {
+            name: name.to_string(),
+            adjustment,
+        });
+        self.save();
+    }
+
+    /// Forgets a preset
+    pub fn remove(&mut self, kind: AdjustmentKind, name: &str) {
+        self.presets
+            .retain(|preset| preset.adjustment.kind() != kind || preset.name != name);
+        self.save();
+    }
+}
@@ -0,0 +1,666 @@
+//! The native Trametes project format (`.trametes`)
+//!
+//! Unlike flat image formats, a project file keeps everything needed to pick
+//! up editing where you left off. It is a zip archive containing:
+//! - `mimetype`: the text `application/x-trametes`, stored uncompressed as the
+//!   first entry so the file type can be sniffed without unzipping
+//! - `manifest.json`: a description of the image (see [`Manifest`])
+//! - `frames/<f>/<n>.png`: the pixels of each layer of each frame, as RGBA
+//!   PNGs (version 1 projects only have one frame, with its layers in
+//!   `layers/<n>.png`)
+//! - `profile.icc`: the image's ICC color profile (only if it has one)
+//! - `metadata/exif.bin` and `metadata/xmp.xml`: the image's raw EXIF and XMP
+//!   metadata (only if it has any)
+//! - `merged.png`: the flattened image (of the frame being edited), so other
+//!   programs can show a preview
+//!
+//! The undo history isn't saved (only the image as it is now), so reopening a
+//! project starts with nothing to undo.
+//!
+//! The manifest has a `format_version`, which is bumped whenever the format
+//! changes in a way older versions of Trametes couldn't understand. New fields
+//! should be given defaults (via `#[serde(default)]`) so older project files
+//! keep loading without needing a version bump.
+
+use std::{
+    error::Error,
+    io::{Read, Seek, Write},
+};
+
+use serde::{Deserialize, Serialize};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    animation::{Animation, AnimationFrame, LoopCount, DEFAULT_FRAME_DELAY_MS},
+    color_management::ColorProfile,
+    color_mode::{ColorMode, Palette, MAX_PALETTE_SIZE},
+    error::Error::UnsupportedFormat,
+    fileio::{self, LoadedImage},
+    layers::{BlendMode, Layer, LayeredImage},
+    metadata::Metadata,
+};
+
+/// The file extension for project files
+pub const EXTENSION: &str = "trametes";
+
+/// The MIME type stored in the `mimetype` entry of project files
+const MIME_TYPE: &str = "application/x-trametes";
+
+/// The newest version of the format this version of Trametes understands
+const FORMAT_VERSION: u32 = 2;
+
+/// The contents of `manifest.json`
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    /// The version of the format the project was saved with
+    format_version: u32,
+
+    /// The width of the image, in pixels
+    width: usize,
+
+    /// The height of the image, in pixels
+    height: usize,
+
+    /// The layers of the image, from bottom to top (only in version 1, which
+    /// doesn't have `frames`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    layers: Vec<ManifestLayer>,
+
+    /// The index of the layer being edited (only in version 1)
+    #[serde(default, skip_serializing)]
+    active_layer: usize,
+
+    /// The frames of the image, in order (just the one for still images)
+    #[serde(default)]
+    frames: Vec<ManifestFrame>,
+
+    /// The index of the frame being edited
+    #[serde(default)]
+    current_frame: usize,
+
+    /// How many times the animation plays in total, or None if it plays
+    /// forever
+    #[serde(default)]
+    loop_count: Option<u16>,
+
+    /// How the pixels of the image are represented
+    #[serde(default)]
+    color_mode: ManifestColorMode,
+
+    /// The path (within the archive) of the ICC color profile, if there is one
+    #[serde(default)]
+    color_profile: Option<String>,
+
+    /// The path (within the archive) of the raw EXIF data, if there is any
+    #[serde(default)]
+    exif: Option<String>,
+
+    /// The path (within the archive) of the raw XMP data, if there is any
+    #[serde(default)]
+    xmp: Option<String>,
+}
+
+/// A single frame in `manifest.json`
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestFrame {
+    /// The layers of the frame, from bottom to top
+    layers: Vec<ManifestLayer>,
+
+    /// The index of the layer being edited
+    #[serde(default)]
+    active_layer: usize,
+
+    /// How long the frame is shown for, in milliseconds
+    #[serde(default = "default_delay_ms")]
+    delay_ms: u32,
+}
+
+fn default_delay_ms() -> u32 {
+    DEFAULT_FRAME_DELAY_MS
+}
+
+/// A single layer in `manifest.json`
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestLayer {
+    /// The name of the layer
+    name: String,
+
+    /// The path (within the archive) of the PNG with the layer's pixels
+    file: String,
+
+    /// The horizontal position of the layer's top-left corner
+    #[serde(default)]
+    x: i32,
+
+    /// The vertical position of the layer's top-left corner
+    #[serde(default)]
+    y: i32,
+
+    /// How opaque the layer is, from 0.0 to 1.0
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+
+    /// Whether or not the layer is shown
+    #[serde(default = "default_visible")]
+    visible: bool,
+
+    /// How the layer is combined with the layers below it
+    #[serde(default)]
+    blend_mode: ManifestBlendMode,
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+/// A color mode in `manifest.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ManifestColorMode {
+    #[default]
+    Rgba,
+    Grayscale,
+    Indexed {
+        palette: Vec<[u8; 4]>,
+    },
+}
+
+/// A blend mode in `manifest.json` (kept separate from [`BlendMode`] so the
+/// names in the file don't change if the code does)
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ManifestBlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+}
+
+impl From<BlendMode> for ManifestBlendMode {
+    fn from(blend_mode: BlendMode) -> Self {
+        match blend_mode {
+            BlendMode::Normal => Self::Normal,
+            BlendMode::Multiply => Self::Multiply,
+            BlendMode::Screen => Self::Screen,
+            BlendMode::Overlay => Self::Overlay,
+            BlendMode::Darken => Self::Darken,
+            BlendMode::Lighten => Self::Lighten,
+            BlendMode::ColorDodge => Self::ColorDodge,
+            BlendMode::ColorBurn => Self::ColorBurn,
+            BlendMode::HardLight => Self::HardLight,
+            BlendMode::SoftLight => Self::SoftLight,
+            BlendMode::Difference => Self::Difference,
+            BlendMode::Exclusion => Self::Exclusion,
+        }
+    }
+}
+
+impl From<ManifestBlendMode> for BlendMode {
+    fn from(blend_mode: ManifestBlendMode) -> Self {
+        match blend_mode {
+            ManifestBlendMode::Normal => Self::Normal,
+            ManifestBlendMode::Multiply => Self::Multiply,
+            ManifestBlendMode::Screen => Self::Screen,
+            ManifestBlendMode::Overlay => Self::Overlay,
+            ManifestBlendMode::Darken => Self::Darken,
+            ManifestBlendMode::Lighten => Self::Lighten,
+            ManifestBlendMode::ColorDodge => Self::ColorDodge,
+            ManifestBlendMode::ColorBurn => Self::ColorBurn,
+            ManifestBlendMode::HardLight => Self::HardLight,
+            ManifestBlendMode::SoftLight => Self::SoftLight,
+            ManifestBlendMode::Difference => Self::Difference,
+            ManifestBlendMode::Exclusion => Self::Exclusion,
+        }
+    }
+}
+
+/// Writes an image as a project file, with every frame of its animation
+/// (`image` being the current frame's)
+pub fn write_project<W: Write + Seek>(
+    writer: W,
+    image: &LayeredImage,
+    animation: &Animation,
+    color_profile: Option<&ColorProfile>,
+    color_mode: &ColorMode,
+    metadata: &Metadata,
+) -> Result<(), Box<dyn Error>> {
+    let mut zip = ZipWriter::new(writer);
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(MIME_TYPE.as_bytes())?;
+
+    let mut manifest_frames = Vec::new();
+    for (frame_index, frame) in animation.frames.iter().enumerate() {
+        let frame_image = animation.frame_image(frame_index, image);
+
+        let mut manifest_layers = Vec::new();
+        for (index, layer) in frame_image.layers.iter().enumerate() {
+            let file = format!("frames/{frame_index}/{index}.png");
+
+            // PNGs are already compressed, no need to do it twice
+            zip.start_file(&file, stored)?;
+            zip.write_all(&fileio::encode_png(&layer.pixels)?)?;
+
+            manifest_layers.push(ManifestLayer {
+                name: layer.name.clone(),
+                file,
+                x: layer.x,
+                y: layer.y,
+                opacity: layer.opacity,
+                visible: layer.visible,
+                blend_mode: layer.blend_mode.into(),
+            });
+        }
+
+        manifest_frames.push(ManifestFrame {
+            layers: manifest_layers,
+            active_layer: frame_image.active_layer,
+            delay_ms: frame.delay_ms,
+        });
+    }
+
+    let color_profile = match color_profile {
+        Some(profile) => {
+            let file = "profile.icc".to_string();
+            zip.start_file(&file, deflated)?;
+            zip.write_all(&profile.icc)?;
+            Some(file)
+        }
+        None => None,
+    };
+
+    let exif = match &metadata.exif {
+        Some(exif) => {
+            let file = "metadata/exif.bin".to_string();
+            zip.start_file(&file, deflated)?;
+            zip.write_all(exif)?;
+            Some(file)
+        }
+        None => None,
+    };
+
+    let xmp = match &metadata.xmp {
+        Some(xmp) => {
+            let file = "metadata/xmp.xml".to_string();
+            zip.start_file(&file, deflated)?;
+            zip.write_all(xmp.as_bytes())?;
+            Some(file)
+        }
+        None => None,
+    };
+
+    zip.start_file("merged.png", stored)?;
+    zip.write_all(&fileio::encode_png(&image.flatten())?)?;
+
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        width: image.width,
+        height: image.height,
+        layers: Vec::new(),
+        active_layer: 0,
+        frames: manifest_frames,
+        current_frame: animation.current_frame,
+        loop_count: match animation.loop_count {
+            LoopCount::Infinite => None,
+            LoopCount::Finite(plays) => Some(plays),
+        },
+        color_mode: match color_mode {
+            ColorMode::Rgba => ManifestColorMode::Rgba,
+            ColorMode::Grayscale => ManifestColorMode::Grayscale,
+            ColorMode::Indexed(palette) => ManifestColorMode::Indexed {
+                palette: palette.colors.clone(),
+            },
+        },
+        color_profile,
+        exif,
+        xmp,
+    };
+    zip.start_file("manifest.json", deflated)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)?;
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// Reads the full contents of a file in a zip archive
+fn read_entry<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    zip.by_name(name)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reads an image from a project file
+pub fn read_project<R: Read + Seek>(reader: R) -> Result<LoadedImage, Box<dyn Error>> {
+    let mut zip = ZipArchive::new(reader)?;
+
+    let manifest: Manifest = serde_json::from_slice(&read_entry(&mut zip, "manifest.json")?)?;
+    if manifest.format_version > FORMAT_VERSION {
+        return Err(UnsupportedFormat(format!(
+            "project was saved with a newer version of Trametes (format version {}, but only \
+             up to {FORMAT_VERSION} is supported)",
+            manifest.format_version
+        ))
+        .into());
+    }
+    fileio::check_dimensions(manifest.width, manifest.height)?;
+
+    // Version 1 projects have a single frame, described at the top level
+    let manifest_frames = if manifest.frames.is_empty() {
+        vec![ManifestFrame {
+            layers: manifest.layers,
+            active_layer: manifest.active_layer,
+            delay_ms: DEFAULT_FRAME_DELAY_MS,
+        }]
+    } else {
+        manifest.frames
+    };
+
+    let mut frames = Vec::new();
+    for manifest_frame in manifest_frames {
+        if manifest_frame.layers.is_empty() {
+            return Err("project has no layers".into());
+        }
+
+        let mut layers = Vec::new();
+        for manifest_layer in manifest_frame.layers {
+            let pixels = fileio::decode_image(&read_entry(&mut zip, &manifest_layer.file)?)?;
+
+            layers.push(Layer {
+                name: manifest_layer.name,
+                pixels,
+                x: manifest_layer.x,
+                y: manifest_layer.y,
+                opacity: manifest_layer.opacity.clamp(0.0, 1.0),
+                visible: manifest_layer.visible,
+                blend_mode: manifest_layer.blend_mode.into(),
+            });
+        }
+
+        frames.push(AnimationFrame {
+            image: LayeredImage {
+                width: manifest.width,
+                height: manifest.height,
+                active_layer: manifest_frame.active_layer.min(layers.len() - 1),
+                layers,
+            },
+            delay_ms: manifest_frame.delay_ms,
+        });
+    }
+
+    let loop_count = match manifest.loop_count {
+        Some(plays) => LoopCount::Finite(plays.max(1)),
+        None => LoopCount::Infinite,
+    };
+    let (mut image, mut animation) =
+        Animation::from_frames(frames, loop_count).ok_or("project has no frames")?;
+    animation.select_frame(&mut image, manifest.current_frame);
+
+    let color_profile = match manifest.color_profile {
+        Some(file) => Some(
+            ColorProfile::from_icc(read_entry(&mut zip, &file)?)
+                .ok_or("project has an invalid color profile")?,
+        ),
+        None => None,
+    };
+
+    let color_mode = match manifest.color_mode {
+        ManifestColorMode::Rgba => ColorMode::Rgba,
+        ManifestColorMode::Grayscale => ColorMode::Grayscale,
+        ManifestColorMode::Indexed { palette } if palette.is_empty() => {
+            return Err("project has an empty palette".into())
+        }
+        ManifestColorMode::Indexed { palette } if palette.len() > MAX_PALETTE_SIZE => {
+            return Err("project has too many colors in its palette".into())
+        }
+        ManifestColorMode::Indexed { palette } => ColorMode::Indexed(Palette { colors: palette }),
+    };
+
+    let metadata = Metadata {
+        exif: match manifest.exif {
+            Some(file) => Some(read_entry(&mut zip, &file)?),
+            None => None,
+        },
+        xmp: match manifest.xmp {
+            Some(file) => Some(String::from_utf8(read_entry(&mut zip, &file)?)?),
+            None => None,
+        },
+    };
+
+    Ok(LoadedImage {
+        image,
+        animation,
+        color_profile,
+        color_mode,
+        metadata,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::pixel_buffer::PixelBuffer;
+
+    fn round_trip(
+        image: &LayeredImage,
+        color_profile: Option<&ColorProfile>,
+        color_mode: &ColorMode,
+        metadata: &Metadata,
+    ) -> LoadedImage {
+        let mut bytes = Vec::new();
+        write_project(
+            Cursor::new(&mut bytes),
+            image,
+            &Animation::default(),
+            color_profile,
+            color_mode,
+            metadata,
+        )
+        .unwrap();
+        read_project(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn layers_round_trip() {
+        let mut top = Layer::new("Top", PixelBuffer::filled(3, 2, [10, 20, 30, 128]));
+        top.x = -1;
+        top.y = 4;
+        top.opacity = 0.25;
+        top.visible = false;
+        top.blend_mode = BlendMode::Multiply;
+
+        let image = LayeredImage {
+            width: 5,
+            height: 6,
+            layers: vec![
+                Layer::new(
+                    "Background",
+                    PixelBuffer::filled(5, 6, [255, 255, 255, 255]),
+                ),
+                top,
+            ],
+            active_layer: 1,
+        };
+
+        let loaded = round_trip(&image, None, &ColorMode::Rgba, &Metadata::default());
+
+        assert_eq!(loaded.image, image);
+        assert!(loaded.color_profile.is_none());
+        assert_eq!(loaded.color_mode, ColorMode::Rgba);
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let frames = (0..3)
+            .map(|index| AnimationFrame {
+                image: LayeredImage::from_flat(PixelBuffer::filled(2, 3, [index * 50, 0, 0, 255])),
+                delay_ms: 40 + u32::from(index),
+            })
+            .collect();
+        let (mut image, mut animation) =
+            Animation::from_frames(frames, LoopCount::Finite(3)).unwrap();
+        animation.select_frame(&mut image, 1);
+
+        let mut bytes = Vec::new();
+        write_project(
+            Cursor::new(&mut bytes),
+            &image,
+            &animation,
+            None,
+            &ColorMode::Rgba,
+            &Metadata::default(),
+        )
+        .unwrap();
+        let loaded = read_project(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(loaded.image, image);
+        assert_eq!(loaded.animation, animation);
+    }
+
+    #[test]
+    fn color_profile_and_mode_round_trip() {
+        let image = LayeredImage::from_flat(PixelBuffer::filled(4, 4, [1, 2, 3, 255]));
+        let profile = ColorProfile::display_p3();
+        let color_mode = ColorMode::Indexed(Palette {
+            colors: vec![[1, 2, 3, 255], [0, 0, 0, 0]],
+        });
+
+        let loaded = round_trip(&image, Some(&profile), &color_mode, &Metadata::default());
+
+        assert_eq!(loaded.image, image);
+        assert_eq!(loaded.color_profile.unwrap().icc, profile.icc);
+        assert_eq!(loaded.color_mode, color_mode);
+    }
+
+    #[test]
+    fn metadata_round_trips() {
+        let image = LayeredImage::from_flat(PixelBuffer::filled(1, 1, [0, 0, 0, 255]));
+        let metadata = Metadata {
+            exif: Some(b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\0".to_vec()),
+            xmp: Some("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_string()),
+        };
+
+        let loaded = round_trip(&image, None, &ColorMode::Rgba, &metadata);
+
+        assert_eq!(loaded.metadata, metadata);
+    }
+
+    #[test]
+    fn starts_with_uncompressed_mimetype() {
+        let mut bytes = Vec::new();
+        let image = LayeredImage::from_flat(PixelBuffer::filled(1, 1, [0, 0, 0, 255]));
+        write_project(
+            Cursor::new(&mut bytes),
+            &image,
+            &Animation::default(),
+            None,
+            &ColorMode::Rgba,
+            &Metadata::default(),
+        )
+        .unwrap();
+
+        // The name and contents of the first entry come right after its 30 byte
+        // local file header
+        assert_eq!(&bytes[30..38], b"mimetype");
+        assert_eq!(&bytes[38..38 + MIME_TYPE.len()], MIME_TYPE.as_bytes());
+    }
+
+    /// Builds a project file by hand, with the given manifest and layer PNGs
+    fn project_with_manifest(manifest: &str, layers: &[(&str, &PixelBuffer)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut zip = ZipWriter::new(Cursor::new(&mut bytes));
+        zip.start_file("manifest.json", FileOptions::default())
+            .unwrap();
+        zip.write_all(manifest.as_bytes()).unwrap();
+        for (name, pixels) in layers {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(&fileio::encode_png(pixels).unwrap()).unwrap();
+        }
+        zip.finish().unwrap();
+        drop(zip);
+
+        bytes
+    }
+
+    #[test]
+    fn minimal_manifest_uses_defaults() {
+        let pixels = PixelBuffer::filled(2, 2, [9, 8, 7, 255]);
+        let bytes = project_with_manifest(
+            r#"{
+                "format_version": 1,
+                "width": 2,
+                "height": 2,
+                "layers": [{ "name": "Only", "file": "layers/0.png" }]
+            }"#,
+            &[("layers/0.png", &pixels)],
+        );
+
+        let loaded = read_project(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(
+            loaded.image,
+            LayeredImage {
+                width: 2,
+                height: 2,
+                layers: vec![Layer::new("Only", pixels)],
+                active_layer: 0,
+            }
+        );
+        assert_eq!(loaded.animation, Animation::default());
+        assert!(loaded.color_profile.is_none());
+        assert_eq!(loaded.color_mode, ColorMode::Rgba);
+    }
+
+    #[test]
+    fn newer_format_versions_are_rejected() {
+        let pixels = PixelBuffer::filled(1, 1, [0, 0, 0, 255]);
+        let bytes = project_with_manifest(
+            r#"{
+                "format_version": 999,
+                "width": 1,
+                "height": 1,
+                "layers": [{ "name": "Only", "file": "layers/0.png" }]
+            }"#,
+            &[("layers/0.png", &pixels)],
+        );
+
+        assert!(read_project(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn oversized_palettes_are_rejected() {
+        let pixels = PixelBuffer::filled(1, 1, [0, 0, 0, 255]);
+        let palette = vec![[0, 0, 0, 255]; MAX_PALETTE_SIZE + 1];
+        let manifest = serde_json::json!({
+            "format_version": 1,
+            "width": 1,
+            "height": 1,
+            "layers": [{ "name": "Only", "file": "layers/0.png" }],
+            "color_mode": { "type": "indexed", "palette": palette },
+        });
+        let bytes = project_with_manifest(&manifest.to_string(), &[("layers/0.png", &pixels)]);
+
+        let err = read_project(Cursor::new(bytes)).unwrap_err();
+        assert!(err.to_string().contains("too many colors"), "{err}");
+    }
+}
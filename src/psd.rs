@@ -0,0 +1,1290 @@
+//! Photoshop (`.psd` and `.psb`) import
+//!
+//! Only raster layers are read (along with their names, offsets, opacity,
+//! visibility, and blend modes). Files using features we can't represent as
+//! layers (adjustment layers, masks, clipping, layer effects, ...) open as the
+//! merged composite Photoshop stores alongside the layers instead, so they at
+//! least look right
+//!
+//! See https://www.adobe.com/devnet-apps/photoshop/fileformatashtml/ for the
+//! specification
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    io::{Read, Seek},
+};
+
+use flate2::read::ZlibDecoder;
+
+use crate::{
+    animation::Animation,
+    color_management::ColorProfile,
+    color_mode::{ColorMode, Palette},
+    error::Error::{OutOfMemory, UnsupportedFormat},
+    fileio::{self, LoadedImage},
+    layers::{BlendMode, Layer, LayeredImage},
+    metadata::Metadata,
+    pixel_buffer::PixelBuffer,
+};
+
+/// The file extensions for Photoshop files (PSB is the "large document"
+/// variant, which is nearly identical)
+pub const EXTENSIONS: [&str; 2] = ["psd", "psb"];
+
+/// Image resource IDs we care about
+const RESOURCE_ICC_PROFILE: u16 = 1039;
+const RESOURCE_INDEXED_COLOR_COUNT: u16 = 1046;
+const RESOURCE_TRANSPARENCY_INDEX: u16 = 1047;
+const RESOURCE_EXIF: u16 = 1058;
+const RESOURCE_XMP: u16 = 1060;
+
+/// Additional layer information keys for adjustment and fill layers, which
+/// don't have (meaningful) pixels of their own
+const ADJUSTMENT_LAYER_KEYS: [&[u8; 4]; 20] = [
+    b"SoCo", b"GdFl", b"PtFl", b"brit", b"levl", b"curv", b"expA", b"vibA", b"hue ", b"hue2",
+    b"blnc", b"blwh", b"phfl", b"mixr", b"clrL", b"nvrt", b"post", b"thrs", b"grdm", b"selc",
+];
+
+/// Additional layer information keys for layer effects (drop shadows, strokes,
+/// etc.), which are only rendered in the merged composite
+const LAYER_EFFECT_KEYS: [&[u8; 4]; 4] = [b"lrFX", b"lfx2", b"lfxs", b"lmfx"];
+
+/// Additional layer information keys for vector masks
+const VECTOR_MASK_KEYS: [&[u8; 4]; 2] = [b"vmsk", b"vsms"];
+
+/// Reads big-endian values from a byte slice, failing (instead of panicking)
+/// if the data ends early
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Whether this is a PSB file (which uses 8-byte lengths in some places)
+    is_psb: bool,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8], is_psb: bool) -> Self {
+        Self {
+            data,
+            pos: 0,
+            is_psb,
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if len > self.remaining() {
+            return Err("unexpected end of PSD data".into());
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), Box<dyn Error>> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Box<dyn Error>> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn i16(&mut self) -> Result<i16, Box<dyn Error>> {
+        Ok(i16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, Box<dyn Error>> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    /// Reads a length that is 4 bytes in PSD files, but 8 bytes in PSB files
+    fn length(&mut self) -> Result<usize, Box<dyn Error>> {
+        Ok(if self.is_psb {
+            self.u64()? as usize
+        } else {
+            self.u32()? as usize
+        })
+    }
+
+    /// Reads a section prefixed by its length
+    fn section(&mut self, len: usize) -> Result<ByteReader<'a>, Box<dyn Error>> {
+        Ok(ByteReader::new(self.bytes(len)?, self.is_psb))
+    }
+
+    /// Reads a Pascal string (a length byte followed by that many bytes),
+    /// padded so its total size is a multiple of `padding`
+    fn pascal_string(&mut self, padding: usize) -> Result<String, Box<dyn Error>> {
+        let len = self.u8()? as usize;
+        let name = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.skip((padding - (len + 1) % padding) % padding)?;
+        Ok(name)
+    }
+}
+
+/// The color modes Photoshop files can be in (that we support)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PsdColorMode {
+    Bitmap,
+    Grayscale,
+    Indexed,
+    Rgb,
+    Cmyk,
+    Duotone,
+}
+
+impl PsdColorMode {
+    fn from_u16(mode: u16) -> Result<Self, Box<dyn Error>> {
+        Ok(match mode {
+            0 => Self::Bitmap,
+            1 => Self::Grayscale,
+            2 => Self::Indexed,
+            3 => Self::Rgb,
+            4 => Self::Cmyk,
+            // Duotone images are stored as grayscale, with the inks described
+            // elsewhere
+            8 => Self::Duotone,
+            _ => return Err(UnsupportedFormat(format!("Photoshop color mode {mode}")).into()),
+        })
+    }
+
+    /// The number of channels making up the color (not counting transparency)
+    fn color_channels(&self) -> usize {
+        match self {
+            Self::Bitmap | Self::Grayscale | Self::Indexed | Self::Duotone => 1,
+            Self::Rgb => 3,
+            Self::Cmyk => 4,
+        }
+    }
+}
+
+/// The information at the start of every Photoshop file
+#[derive(Debug)]
+struct Header {
+    is_psb: bool,
+    channels: usize,
+    width: usize,
+    height: usize,
+    depth: u16,
+    color_mode: PsdColorMode,
+}
+
+fn read_header(reader: &mut ByteReader) -> Result<Header, Box<dyn Error>> {
+    if reader.bytes(4)? != b"8BPS" {
+        return Err("not a Photoshop file".into());
+    }
+    let is_psb = match reader.u16()? {
+        1 => false,
+        2 => true,
+        version => {
+            return Err(UnsupportedFormat(format!("Photoshop file version {version}")).into())
+        }
+    };
+    reader.is_psb = is_psb;
+    reader.skip(6)?;
+
+    let channels = reader.u16()? as usize;
+    let height = reader.u32()? as usize;
+    let width = reader.u32()? as usize;
+    let depth = reader.u16()?;
+    let color_mode = PsdColorMode::from_u16(reader.u16()?)?;
+
+    if !matches!(depth, 1 | 8 | 16) {
+        return Err(UnsupportedFormat(format!("{depth}-bit Photoshop files")).into());
+    }
+    fileio::check_dimensions(width, height)?;
+    if (depth == 1) != (color_mode == PsdColorMode::Bitmap) {
+        return Err("PSD bit depth doesn't match its color mode".into());
+    }
+    if channels < color_mode.color_channels() {
+        return Err("PSD has too few channels for its color mode".into());
+    }
+
+    Ok(Header {
+        is_psb,
+        channels,
+        width,
+        height,
+        depth,
+        color_mode,
+    })
+}
+
+/// The image resources we care about
+#[derive(Debug, Default)]
+struct Resources {
+    icc_profile: Option<Vec<u8>>,
+    indexed_color_count: Option<usize>,
+    transparency_index: Option<usize>,
+    metadata: Metadata,
+}
+
+fn read_resources(mut reader: ByteReader) -> Result<Resources, Box<dyn Error>> {
+    let mut resources = Resources::default();
+
+    while reader.remaining() >= 4 {
+        // Usually "8BIM", but a few other signatures exist too
+        reader.skip(4)?;
+        let id = reader.u16()?;
+        reader.pascal_string(2)?;
+        let len = reader.u32()? as usize;
+        let mut data = reader.section(len)?;
+        reader.skip(len % 2)?;
+
+        match id {
+            RESOURCE_ICC_PROFILE => resources.icc_profile = Some(data.data.to_vec()),
+            RESOURCE_INDEXED_COLOR_COUNT => {
+                resources.indexed_color_count = Some(data.u16()? as usize);
+            }
+            RESOURCE_TRANSPARENCY_INDEX => {
+                resources.transparency_index = Some(data.u16()? as usize);
+            }
+            RESOURCE_EXIF => resources.metadata.exif = Some(data.data.to_vec()),
+            RESOURCE_XMP => {
+                resources.metadata.xmp = Some(String::from_utf8_lossy(data.data).into_owned());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(resources)
+}
+
+/// Decodes PackBits run-length encoded data
+fn unpack_bits(mut packed: &[u8], unpacked: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    while let Some((&header, rest)) = packed.split_first() {
+        let header = header as i8;
+        packed = rest;
+
+        if header >= 0 {
+            let len = header as usize + 1;
+            if len > packed.len() {
+                return Err("PackBits literal run goes past the end of the data".into());
+            }
+            unpacked.extend_from_slice(&packed[..len]);
+            packed = &packed[len..];
+        } else if header != -128 {
+            let Some((&byte, rest)) = packed.split_first() else {
+                return Err("PackBits repeat run goes past the end of the data".into());
+            };
+            unpacked.extend(std::iter::repeat_n(byte, (1 - header as isize) as usize));
+            packed = rest;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decompresses some rows of channel data (in whatever compression `reader`
+/// says is used), returning the raw bytes
+fn decompress_rows(
+    reader: &mut ByteReader,
+    rows: usize,
+    row_len: usize,
+    depth: u16,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let compression = reader.u16()?;
+    let len = rows
+        .checked_mul(row_len)
+        .ok_or("PSD channel is too large")?;
+
+    // Nothing is allocated up front from these sizes, since they haven't been
+    // checked against how much data there actually is yet (callers check the
+    // buffers they make from them against an `AllocationBudget`)
+
+    let mut data = match compression {
+        // Raw
+        0 => reader.bytes(len)?.to_vec(),
+        // PackBits, with the compressed size of every row first
+        1 => {
+            let mut row_sizes = Vec::new();
+            for _ in 0..rows {
+                row_sizes.push(if reader.is_psb {
+                    reader.u32()? as usize
+                } else {
+                    reader.u16()? as usize
+                });
+            }
+
+            let mut data = Vec::new();
+            for row_size in row_sizes {
+                unpack_bits(reader.bytes(row_size)?, &mut data)?;
+            }
+            data
+        }
+        // Zip, with or without prediction (delta encoding)
+        2 | 3 => {
+            // (Only as much as the channel needs is decompressed, so a small
+            // file can't inflate to gigabytes)
+            let mut data = Vec::new();
+            ZlibDecoder::new(&reader.data[reader.pos..])
+                .take(len as u64)
+                .read_to_end(&mut data)?;
+            reader.pos = reader.data.len();
+            if data.len() < len {
+                return Err("PSD channel data is too short".into());
+            }
+
+            if compression == 3 {
+                for row in data.chunks_mut(row_len) {
+                    if depth == 16 {
+                        let mut previous = 0u16;
+                        for value in row.chunks_exact_mut(2) {
+                            previous =
+                                previous.wrapping_add(u16::from_be_bytes([value[0], value[1]]));
+                            value.copy_from_slice(&previous.to_be_bytes());
+                        }
+                    } else {
+                        for index in 1..row.len() {
+                            row[index] = row[index].wrapping_add(row[index - 1]);
+                        }
+                    }
+                }
+            }
+            data
+        }
+        _ => return Err(format!("unsupported PSD compression {compression}").into()),
+    };
+
+    if data.len() < len {
+        return Err("PSD channel data is too short".into());
+    }
+    data.truncate(len);
+
+    Ok(data)
+}
+
+/// Keeps track of how much memory the pixels read from a file take up, so a
+/// small (or damaged) file can't make us allocate more than
+/// [`fileio::MAX_ALLOC`] just by claiming large sizes
+#[derive(Debug)]
+struct AllocationBudget {
+    remaining: u64,
+}
+
+impl AllocationBudget {
+    fn new() -> Self {
+        Self {
+            remaining: fileio::MAX_ALLOC,
+        }
+    }
+
+    /// Fails if there isn't room for a buffer that's only needed for a moment
+    /// (like the channels of a layer while they're combined)
+    fn check(&self, bytes: u64) -> Result<(), crate::error::Error> {
+        if bytes > self.remaining {
+            return Err(OutOfMemory);
+        }
+        Ok(())
+    }
+
+    /// Counts a buffer that's kept (like the pixels of a layer), failing if
+    /// there isn't room for it
+    fn spend(&mut self, bytes: u64) -> Result<(), crate::error::Error> {
+        self.check(bytes)?;
+        self.remaining -= bytes;
+        Ok(())
+    }
+}
+
+/// The number of bytes in a row of a channel
+fn row_len(width: usize, depth: u16) -> usize {
+    match depth {
+        1 => width.div_ceil(8),
+        16 => width * 2,
+        _ => width,
+    }
+}
+
+/// Converts raw channel data to 8 bits per pixel
+fn to_8_bit(data: Vec<u8>, width: usize, depth: u16) -> Vec<u8> {
+    match depth {
+        // Bitmap images use 1 for black
+        1 => data
+            .chunks(row_len(width, depth).max(1))
+            .flat_map(|row| {
+                (0..width).map(|col| {
+                    if row[col / 8] & (0x80 >> (col % 8)) != 0 {
+                        0
+                    } else {
+                        255
+                    }
+                })
+            })
+            .collect(),
+        // Big-endian, so the first byte is the most significant one
+        16 => data.chunks_exact(2).map(|value| value[0]).collect(),
+        _ => data,
+    }
+}
+
+/// Reads a single channel of a layer
+fn read_channel(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    header: &Header,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut reader = ByteReader::new(data, header.is_psb);
+    let raw = decompress_rows(
+        &mut reader,
+        height,
+        row_len(width, header.depth),
+        header.depth,
+    )?;
+    Ok(to_8_bit(raw, width, header.depth))
+}
+
+/// Combines separate channels into RGBA pixels
+fn to_rgba(
+    color_mode: PsdColorMode,
+    channels: &[Vec<u8>],
+    alpha: Option<&[u8]>,
+    palette: Option<&Palette>,
+) -> Vec<u8> {
+    let pixel_count = channels[0].len();
+    let mut pixels = Vec::with_capacity(pixel_count * 4);
+
+    for index in 0..pixel_count {
+        let mut pixel = match color_mode {
+            PsdColorMode::Bitmap | PsdColorMode::Grayscale | PsdColorMode::Duotone => {
+                let gray = channels[0][index];
+                [gray, gray, gray, 255]
+            }
+            PsdColorMode::Indexed => palette
+                .and_then(|palette| palette.colors.get(channels[0][index] as usize).copied())
+                .unwrap_or([0, 0, 0, 255]),
+            PsdColorMode::Rgb => [
+                channels[0][index],
+                channels[1][index],
+                channels[2][index],
+                255,
+            ],
+            // CMYK values are stored inverted (255 is no ink)
+            // TODO: use the embedded CMYK profile instead of this naive conversion
+            PsdColorMode::Cmyk => {
+                let k = channels[3][index] as u32;
+                let channel = |value: u8| (value as u32 * k / 255) as u8;
+                [
+                    channel(channels[0][index]),
+                    channel(channels[1][index]),
+                    channel(channels[2][index]),
+                    255,
+                ]
+            }
+        };
+        if let Some(alpha) = alpha {
+            pixel[3] = alpha[index];
+        }
+        pixels.extend_from_slice(&pixel);
+    }
+
+    pixels
+}
+
+/// The blend mode for a Photoshop blend mode key, if we support it
+fn blend_mode(key: &[u8; 4]) -> Option<BlendMode> {
+    Some(match key {
+        b"norm" => BlendMode::Normal,
+        b"mul " => BlendMode::Multiply,
+        b"scrn" => BlendMode::Screen,
+        b"over" => BlendMode::Overlay,
+        b"dark" => BlendMode::Darken,
+        b"lite" => BlendMode::Lighten,
+        b"div " => BlendMode::ColorDodge,
+        b"idiv" => BlendMode::ColorBurn,
+        b"hLit" => BlendMode::HardLight,
+        b"sLit" => BlendMode::SoftLight,
+        b"diff" => BlendMode::Difference,
+        b"smud" => BlendMode::Exclusion,
+        _ => return None,
+    })
+}
+
+/// What kind of layer group marker a layer is, if any
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    /// The top of a group (this layer holds the group's settings)
+    GroupStart,
+
+    /// The bottom of a group (a hidden layer with no contents)
+    GroupEnd,
+}
+
+/// A layer record, describing a layer (its pixels are stored separately)
+#[derive(Debug)]
+struct LayerRecord {
+    name: String,
+    top: i32,
+    left: i32,
+    bottom: i32,
+    right: i32,
+    /// The ID and data length of each channel
+    channels: Vec<(i16, usize)>,
+    blend_key: [u8; 4],
+    opacity: u8,
+    visible: bool,
+    section: Option<Section>,
+    /// Why the layer can't be represented as one of our layers, if it can't
+    unsupported: Option<String>,
+}
+
+/// Iterates over tagged blocks ("additional layer information"), calling `f`
+/// with the key and data of each one
+fn read_tagged_blocks<'a>(
+    reader: &mut ByteReader<'a>,
+    mut f: impl FnMut(&[u8; 4], ByteReader<'a>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    while reader.remaining() >= 12 {
+        let signature: [u8; 4] = reader.array()?;
+        if &signature != b"8BIM" && &signature != b"8B64" {
+            break;
+        }
+        let key: [u8; 4] = reader.array()?;
+
+        // A few blocks have 8-byte lengths in PSB files
+        let has_long_length = reader.is_psb
+            && matches!(
+                &key,
+                b"LMsk"
+                    | b"Lr16"
+                    | b"Lr32"
+                    | b"Layr"
+                    | b"Mt16"
+                    | b"Mt32"
+                    | b"Mtrn"
+                    | b"Alph"
+                    | b"FMsk"
+                    | b"lnk2"
+                    | b"FEid"
+                    | b"FXid"
+                    | b"PxSD"
+            );
+        let len = if has_long_length {
+            reader.u64()? as usize
+        } else {
+            reader.u32()? as usize
+        };
+        let data = reader.section(len.min(reader.remaining()))?;
+        f(&key, data)?;
+
+        // Blocks are supposed to be padded to an even length (and often are
+        // to a multiple of 4), but not every writer includes that in the
+        // length
+        while reader.remaining() > 0
+            && !reader.data[reader.pos..].starts_with(b"8BIM")
+            && !reader.data[reader.pos..].starts_with(b"8B64")
+            && !reader.pos.is_multiple_of(4)
+        {
+            reader.skip(1)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_layer_record(reader: &mut ByteReader) -> Result<LayerRecord, Box<dyn Error>> {
+    let top = reader.i32()?;
+    let left = reader.i32()?;
+    let bottom = reader.i32()?;
+    let right = reader.i32()?;
+
+    let channel_count = reader.u16()? as usize;
+    let mut channels = Vec::with_capacity(channel_count);
+    for _ in 0..channel_count {
+        channels.push((reader.i16()?, reader.length()?));
+    }
+
+    if reader.bytes(4)? != b"8BIM" {
+        return Err("invalid PSD blend mode signature".into());
+    }
+    let blend_key: [u8; 4] = reader.array()?;
+    let opacity = reader.u8()?;
+    let clipping = reader.u8()?;
+    let flags = reader.u8()?;
+    reader.skip(1)?;
+
+    let mut unsupported = None;
+    if clipping != 0 {
+        unsupported = Some("clipping masks".to_string());
+    }
+
+    let extra_len = reader.u32()? as usize;
+    let mut extra = reader.section(extra_len)?;
+
+    let mask_len = extra.u32()? as usize;
+    let mut mask = extra.section(mask_len)?;
+    if mask_len >= 18 {
+        mask.skip(17)?;
+        // Bit 1 means the mask is disabled
+        if mask.u8()? & 0x02 == 0 {
+            unsupported = Some("layer masks".to_string());
+        }
+    }
+
+    let blending_ranges_len = extra.u32()? as usize;
+    extra.skip(blending_ranges_len)?;
+
+    let mut name = extra.pascal_string(4)?;
+    let mut section = None;
+
+    read_tagged_blocks(&mut extra, |key, mut data| {
+        match key {
+            // The Unicode version of the name
+            b"luni" => {
+                let len = data.u32()? as usize;
+                let units: Vec<u16> = (0..len).map(|_| data.u16()).collect::<Result<_, _>>()?;
+                name = String::from_utf16_lossy(&units)
+                    .trim_end_matches('\0')
+                    .to_string();
+            }
+            b"lsct" | b"lsdk" => {
+                section = match data.u32()? {
+                    1 | 2 => Some(Section::GroupStart),
+                    3 => Some(Section::GroupEnd),
+                    _ => None,
+                };
+            }
+            // Fill opacity (like opacity, but not applied to layer effects)
+            b"iOpa" if data.u8()? != 255 => {
+                unsupported = Some("fill opacity".to_string());
+            }
+            key if ADJUSTMENT_LAYER_KEYS.contains(&key) => {
+                unsupported = Some("adjustment and fill layers".to_string());
+            }
+            key if LAYER_EFFECT_KEYS.contains(&key) => {
+                unsupported = Some("layer effects".to_string());
+            }
+            key if VECTOR_MASK_KEYS.contains(&key) => {
+                unsupported = Some("vector masks".to_string());
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    if section.is_none() && blend_mode(&blend_key).is_none() {
+        unsupported = Some(format!(
+            "the {:?} blend mode",
+            String::from_utf8_lossy(&blend_key)
+        ));
+    }
+
+    Ok(LayerRecord {
+        name,
+        top,
+        left,
+        bottom,
+        right,
+        channels,
+        blend_key,
+        opacity,
+        // Bit 1 means the layer is hidden
+        visible: flags & 0x02 == 0,
+        section,
+        unsupported,
+    })
+}
+
+/// Reads the layers from the layer info section, returning them from bottom to
+/// top. Fails with the reason if the layers use features we don't support
+fn read_layers(
+    mut reader: ByteReader,
+    header: &Header,
+) -> Result<Result<Vec<Layer>, String>, Box<dyn Error>> {
+    let layer_count = reader.i16()?.unsigned_abs() as usize;
+
+    let mut records = Vec::with_capacity(layer_count);
+    for _ in 0..layer_count {
+        records.push(read_layer_record(&mut reader)?);
+    }
+
+    if let Some(reason) = records.iter().find_map(|record| record.unsupported.clone()) {
+        return Ok(Err(reason));
+    }
+
+    // Read the pixels of each layer (channel data follows all the records)
+    let mut budget = AllocationBudget::new();
+    let mut layer_pixels = Vec::with_capacity(records.len());
+    for record in &records {
+        let width = record.right.saturating_sub(record.left).max(0) as usize;
+        let height = record.bottom.saturating_sub(record.top).max(0) as usize;
+        fileio::check_dimensions(width, height)?;
+
+        let pixel_count = if width == 0 || height == 0 {
+            // Empty layers get a blank canvas (group markers don't get
+            // anything)
+            if record.section.is_none() {
+                budget.spend(header.width as u64 * header.height as u64 * 4)?;
+            }
+            0
+        } else {
+            width as u64 * height as u64
+        };
+        // The layer's pixels are kept, and its channels (of up to 2 bytes a
+        // sample) only while they're combined into them
+        budget.spend(pixel_count * 4)?;
+        budget.check(pixel_count * 2 * (header.color_mode.color_channels() as u64 + 1))?;
+
+        let mut color = vec![None; header.color_mode.color_channels()];
+        let mut alpha = None;
+        for (id, len) in &record.channels {
+            let data = reader.bytes(*len)?;
+            if width == 0 || height == 0 {
+                continue;
+            }
+
+            match *id {
+                -1 => alpha = Some(read_channel(data, width, height, header)?),
+                id if id >= 0 && (id as usize) < color.len() => {
+                    color[id as usize] = Some(read_channel(data, width, height, header)?);
+                }
+                // Layer masks (which aren't supported) and spot colors
+                _ => {}
+            }
+        }
+
+        let pixels = if width == 0 || height == 0 {
+            None
+        } else {
+            let color = color
+                .into_iter()
+                .map(|channel| channel.unwrap_or_else(|| vec![0; width * height]))
+                .collect::<Vec<_>>();
+            Some(PixelBuffer {
+                width,
+                height,
+                pixels: to_rgba(header.color_mode, &color, alpha.as_deref(), None),
+            })
+        };
+        layer_pixels.push(pixels);
+    }
+
+    // Groups are flattened, since we don't support them. Going from top to
+    // bottom means we know whether each group is visible before reaching its
+    // layers
+    let mut layers = Vec::new();
+    let mut group_visibility: Vec<bool> = Vec::new();
+    for (record, pixels) in records.into_iter().zip(layer_pixels).rev() {
+        match record.section {
+            Some(Section::GroupStart) => {
+                // Pass-through groups just contain layers, anything else
+                // composites the group separately first (normal groups only
+                // differ when they contain other blend modes, so close enough)
+                if record.opacity != 255 || !matches!(&record.blend_key, b"pass" | b"norm") {
+                    return Ok(Err("layer group blending".to_string()));
+                }
+                group_visibility.push(record.visible);
+            }
+            Some(Section::GroupEnd) => {
+                group_visibility.pop();
+            }
+            None => {
+                let (pixels, x, y) = match pixels {
+                    Some(pixels) => (pixels, record.left, record.top),
+                    // Empty layers get a blank canvas to paint on
+                    None => (
+                        PixelBuffer {
+                            width: header.width,
+                            height: header.height,
+                            pixels: vec![0; header.width * header.height * 4],
+                        },
+                        0,
+                        0,
+                    ),
+                };
+
+                layers.push(Layer {
+                    name: record.name,
+                    pixels,
+                    x,
+                    y,
+                    opacity: record.opacity as f32 / 255.0,
+                    visible: record.visible && group_visibility.iter().all(|visible| *visible),
+                    blend_mode: blend_mode(&record.blend_key).unwrap_or_default(),
+                });
+            }
+        }
+    }
+    layers.reverse();
+
+    Ok(Ok(layers))
+}
+
+/// Finds the layer info in the layer and mask information section. 16-bit
+/// files store it in a tagged block at the end instead of the usual place.
+/// Also returns whether the first extra channel of the merged image is its
+/// transparency
+fn find_layer_info<'a>(
+    mut reader: ByteReader<'a>,
+) -> Result<(Option<ByteReader<'a>>, bool), Box<dyn Error>> {
+    if reader.remaining() == 0 {
+        return Ok((None, false));
+    }
+
+    let layer_info_len = reader.length()?;
+    let mut layer_info = Some(reader.section(layer_info_len)?).filter(|_| layer_info_len > 0);
+
+    let global_mask_len = reader.u32()? as usize;
+    reader.skip(global_mask_len)?;
+
+    read_tagged_blocks(&mut reader, |key, data| {
+        if layer_info.is_none() && matches!(key, b"Lr16" | b"Layr") {
+            layer_info = Some(data);
+        }
+        Ok(())
+    })?;
+
+    // A negative layer count means the merged image has transparency
+    let has_merged_alpha = match &layer_info {
+        Some(layer_info) if layer_info.remaining() >= 2 => {
+            i16::from_be_bytes([layer_info.data[0], layer_info.data[1]]) < 0
+        }
+        _ => false,
+    };
+
+    Ok((layer_info, has_merged_alpha))
+}
+
+/// Reads the merged composite image stored at the end of the file
+fn read_merged_image(
+    reader: &mut ByteReader,
+    header: &Header,
+    has_alpha: bool,
+    palette: Option<&Palette>,
+) -> Result<PixelBuffer, Box<dyn Error>> {
+    let color_channels = header.color_mode.color_channels();
+    let channels = if has_alpha && header.channels > color_channels {
+        color_channels + 1
+    } else {
+        color_channels
+    };
+
+    // The image's pixels are kept, and every channel only while they're
+    // combined into them
+    let pixel_count = header.width as u64 * header.height as u64;
+    let mut budget = AllocationBudget::new();
+    budget.spend(pixel_count * 4)?;
+    budget.check(
+        header.height as u64 * header.channels as u64 * row_len(header.width, header.depth) as u64,
+    )?;
+
+    // Every channel is stored one after another, so treat them as one tall
+    // image (but only read the channels we need)
+    let raw = decompress_rows(
+        reader,
+        header.height * header.channels,
+        row_len(header.width, header.depth),
+        header.depth,
+    )?;
+    let plane_len = header.height * row_len(header.width, header.depth);
+    let planes: Vec<Vec<u8>> = raw
+        .chunks(plane_len.max(1))
+        .take(channels)
+        .map(|plane| to_8_bit(plane.to_vec(), header.width, header.depth))
+        .collect();
+
+    let (color, alpha) = planes.split_at(color_channels);
+    Ok(PixelBuffer {
+        width: header.width,
+        height: header.height,
+        pixels: to_rgba(
+            header.color_mode,
+            color,
+            alpha.first().map(Vec::as_slice),
+            palette,
+        ),
+    })
+}
+
+/// Reads the palette of an indexed image from the color mode data
+fn read_palette(color_data: &[u8], resources: &Resources) -> Result<Palette, Box<dyn Error>> {
+    if color_data.len() < 768 {
+        return Err("indexed PSD is missing its palette".into());
+    }
+
+    let count = resources.indexed_color_count.unwrap_or(256).clamp(1, 256);
+    let colors = (0..count)
+        .map(|index| {
+            let alpha = if resources.transparency_index == Some(index) {
+                0
+            } else {
+                255
+            };
+            [
+                color_data[index],
+                color_data[256 + index],
+                color_data[512 + index],
+                alpha,
+            ]
+        })
+        .collect();
+
+    Ok(Palette { colors })
+}
+
+/// Reads an image from a Photoshop file
+pub fn read_psd<R: Read + Seek>(mut reader: R) -> Result<LoadedImage, Box<dyn Error>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut reader = ByteReader::new(&data, false);
+
+    let header = read_header(&mut reader)?;
+
+    let color_data_len = reader.u32()? as usize;
+    let color_data = reader.bytes(color_data_len)?;
+
+    let resources_len = reader.u32()? as usize;
+    let resources = read_resources(reader.section(resources_len)?)?;
+
+    let layer_and_mask_len = reader.length()?;
+    let (layer_info, has_merged_alpha) = find_layer_info(reader.section(layer_and_mask_len)?)?;
+
+    let palette = match header.color_mode {
+        PsdColorMode::Indexed => Some(read_palette(color_data, &resources)?),
+        _ => None,
+    };
+
+    let layers = match layer_info {
+        Some(layer_info) => match read_layers(layer_info, &header)? {
+            Ok(layers) if !layers.is_empty() => Some(layers),
+            Ok(_) => None,
+            Err(reason) => {
+                eprintln!("PSD uses unsupported features ({reason}), using the merged image");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let layers = match layers {
+        Some(layers) => layers,
+        None => {
+            let merged =
+                read_merged_image(&mut reader, &header, has_merged_alpha, palette.as_ref())?;
+            vec![Layer::new("Background", merged)]
+        }
+    };
+
+    let color_mode = match (header.color_mode, palette) {
+        (PsdColorMode::Indexed, Some(mut palette)) => {
+            // Pixels store colors rather than indices, so duplicate palette
+            // entries are meaningless
+            let mut seen = HashSet::new();
+            palette.colors.retain(|color| seen.insert(*color));
+            ColorMode::Indexed(palette)
+        }
+        (PsdColorMode::Bitmap | PsdColorMode::Grayscale | PsdColorMode::Duotone, _) => {
+            ColorMode::Grayscale
+        }
+        _ => ColorMode::Rgba,
+    };
+
+    // CMYK profiles don't describe the (converted) pixels we end up with
+    let color_profile = resources
+        .icc_profile
+        .filter(|_| header.color_mode != PsdColorMode::Cmyk)
+        .and_then(|icc| {
+            let profile = ColorProfile::from_icc(icc);
+            if profile.is_none() {
+                eprintln!("ignoring unsupported ICC profile embedded in PSD");
+            }
+            profile
+        });
+
+    Ok(LoadedImage {
+        image: LayeredImage {
+            width: header.width,
+            height: header.height,
+            active_layer: layers.len() - 1,
+            layers,
+        },
+        animation: Animation::default(),
+        color_profile,
+        color_mode,
+        metadata: resources.metadata,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // The fixtures are generated by tests/fixtures/psd/generate.py, which
+    // describes what each one contains
+    const LAYERS_PSD: &[u8] = include_bytes!("../tests/fixtures/psd/layers.psd");
+    const FALLBACK_PSD: &[u8] = include_bytes!("../tests/fixtures/psd/fallback.psd");
+    const GRAY16_PSB: &[u8] = include_bytes!("../tests/fixtures/psd/gray16.psb");
+    const INDEXED_PSD: &[u8] = include_bytes!("../tests/fixtures/psd/indexed.psd");
+
+    fn read(bytes: &[u8]) -> LoadedImage {
+        read_psd(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn reads_layers() {
+        let loaded = read(LAYERS_PSD);
+        let image = &loaded.image;
+
+        assert_eq!((image.width, image.height), (4, 3));
+        assert_eq!(loaded.color_mode, ColorMode::Rgba);
+        assert!(loaded.color_profile.is_none());
+
+        let names: Vec<&str> = image
+            .layers
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Background",
+                "Red square ✓",
+                "Inside hidden group",
+                "Hidden layer",
+                "Empty"
+            ]
+        );
+        assert_eq!(image.active_layer, 4);
+
+        let background = &image.layers[0];
+        assert_eq!((background.pixels.width, background.pixels.height), (4, 3));
+        assert_eq!(background.pixels.pixels[..4], [0, 0, 100, 255]);
+        // (1, 2)
+        assert_eq!(background.pixels.pixels[36..40], [60, 160, 100, 255]);
+
+        let square = &image.layers[1];
+        assert_eq!((square.x, square.y), (1, 1));
+        assert_eq!(square.opacity, 128.0 / 255.0);
+        assert_eq!(square.blend_mode, BlendMode::Multiply);
+        assert!(square.visible);
+        assert_eq!(
+            square.pixels.pixels,
+            [255, 0, 0, 255, 255, 0, 0, 128, 255, 0, 0, 255, 255, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn hidden_groups_hide_their_layers() {
+        let loaded = read(LAYERS_PSD);
+
+        let grouped = &loaded.image.layers[2];
+        assert!(!grouped.visible);
+        assert_eq!((grouped.x, grouped.y), (3, 2));
+        assert_eq!(grouped.pixels.pixels, [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn reads_hidden_and_zip_compressed_layers() {
+        let loaded = read(LAYERS_PSD);
+
+        let hidden = &loaded.image.layers[3];
+        assert!(!hidden.visible);
+        assert_eq!(hidden.blend_mode, BlendMode::Screen);
+        assert_eq!((hidden.x, hidden.y), (-2, -1));
+        assert_eq!(hidden.pixels.pixels, [200, 200, 200, 255]);
+    }
+
+    #[test]
+    fn empty_layers_cover_the_canvas() {
+        let loaded = read(LAYERS_PSD);
+
+        let empty = &loaded.image.layers[4];
+        assert_eq!((empty.x, empty.y), (0, 0));
+        assert_eq!((empty.pixels.width, empty.pixels.height), (4, 3));
+        assert!(empty.pixels.pixels.iter().all(|value| *value == 0));
+    }
+
+    #[test]
+    fn unsupported_features_fall_back_to_merged_image() {
+        let loaded = read(FALLBACK_PSD);
+
+        assert_eq!(loaded.image.layers.len(), 1);
+        let layer = &loaded.image.layers[0];
+        assert_eq!(layer.blend_mode, BlendMode::Normal);
+        assert_eq!(
+            layer.pixels.pixels,
+            [10, 50, 90, 255, 20, 60, 100, 255, 30, 70, 110, 255, 40, 80, 120, 255]
+        );
+    }
+
+    #[test]
+    fn reads_16_bit_grayscale_psb() {
+        let loaded = read(GRAY16_PSB);
+
+        assert_eq!(loaded.color_mode, ColorMode::Grayscale);
+        let pixels = &loaded.image.layers[0].pixels;
+        assert_eq!((pixels.width, pixels.height), (3, 2));
+        let grays: Vec<u8> = pixels
+            .pixels
+            .chunks_exact(4)
+            .map(|pixel| pixel[0])
+            .collect();
+        assert_eq!(grays, [0x00, 0x12, 0xff, 0x80, 0x7f, 0x41]);
+    }
+
+    #[test]
+    fn reads_indexed_palette_and_transparency() {
+        let loaded = read(INDEXED_PSD);
+
+        assert_eq!(
+            loaded.color_mode,
+            ColorMode::Indexed(Palette {
+                colors: vec![
+                    [255, 0, 0, 255],
+                    [0, 255, 0, 255],
+                    [0, 0, 255, 255],
+                    [9, 9, 9, 0]
+                ]
+            })
+        );
+        assert_eq!(
+            loaded.image.layers[0].pixels.pixels,
+            [255, 0, 0, 255, 0, 255, 0, 255, 9, 9, 9, 0]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(read_psd(Cursor::new(b"not a psd")).is_err());
+
+        // Cutting a file short shouldn't panic (it may still succeed if only
+        // the unused merged image is cut off)
+        for len in 0..LAYERS_PSD.len() {
+            let _ = read_psd(Cursor::new(&LAYERS_PSD[..len]));
+        }
+        assert!(read_psd(Cursor::new(&LAYERS_PSD[..100])).is_err());
+    }
+
+    /// A 1x1 RGB PSD with a single layer whose rectangle is huge (but whose
+    /// channel data is tiny), like a corrupt or malicious file might have
+    /// A PSD with a square canvas and some copies of a layer with the given
+    /// edges (top, left, bottom, right), each with one empty channel
+    fn layers_psd(canvas_size: u32, edges: [i32; 4], layer_count: i16) -> Vec<u8> {
+        let mut record = Vec::new();
+        for edge in edges {
+            record.extend_from_slice(&i32::to_be_bytes(edge));
+        }
+        record.extend_from_slice(&1u16.to_be_bytes()); // Channels
+        record.extend_from_slice(&0i16.to_be_bytes()); // Red
+        record.extend_from_slice(&2u32.to_be_bytes()); // Channel data length
+        record.extend_from_slice(b"8BIMnorm");
+        record.extend_from_slice(&[255, 0, 0, 0]); // Opacity, clipping, flags
+        record.extend_from_slice(&12u32.to_be_bytes()); // Extra data length
+        record.extend_from_slice(&0u32.to_be_bytes()); // Mask
+        record.extend_from_slice(&0u32.to_be_bytes()); // Blending ranges
+        record.extend_from_slice(&[0; 4]); // Name (padded)
+
+        let mut layer_info = layer_count.to_be_bytes().to_vec();
+        for _ in 0..layer_count {
+            layer_info.extend_from_slice(&record);
+        }
+        for _ in 0..layer_count {
+            layer_info.extend_from_slice(&1u16.to_be_bytes()); // PackBits
+        }
+
+        let mut layer_and_mask = (layer_info.len() as u32).to_be_bytes().to_vec();
+        layer_and_mask.extend_from_slice(&layer_info);
+        layer_and_mask.extend_from_slice(&0u32.to_be_bytes()); // Global mask
+
+        let mut psd = b"8BPS".to_vec();
+        psd.extend_from_slice(&1u16.to_be_bytes()); // Version
+        psd.extend_from_slice(&[0; 6]);
+        psd.extend_from_slice(&3u16.to_be_bytes()); // Channels
+        psd.extend_from_slice(&canvas_size.to_be_bytes()); // Height
+        psd.extend_from_slice(&canvas_size.to_be_bytes()); // Width
+        psd.extend_from_slice(&8u16.to_be_bytes()); // Depth
+        psd.extend_from_slice(&3u16.to_be_bytes()); // RGB
+        psd.extend_from_slice(&0u32.to_be_bytes()); // Color data
+        psd.extend_from_slice(&0u32.to_be_bytes()); // Resources
+        psd.extend_from_slice(&(layer_and_mask.len() as u32).to_be_bytes());
+        psd.extend_from_slice(&layer_and_mask);
+        psd
+    }
+
+    #[test]
+    fn rejects_huge_layers_without_allocating_them() {
+        let psd = layers_psd(1, [0, 0, i32::MAX, i32::MAX], 1);
+        let err = read_psd(Cursor::new(psd)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(crate::error::Error::DimensionsTooLarge)
+        ));
+    }
+
+    #[test]
+    fn rejects_many_empty_layers_without_allocating_them() {
+        // Each empty layer would get a 16 GiB canvas
+        let size = fileio::MAX_DIMENSION as u32;
+        let psd = layers_psd(size, [0; 4], 1000);
+        let err = read_psd(Cursor::new(psd)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(crate::error::Error::OutOfMemory)
+        ));
+
+        // Likewise for layers with channels that aren't in the file
+        let edges = [0, 0, size as i32, size as i32];
+        let psd = layers_psd(size, edges, 1000);
+        let err = read_psd(Cursor::new(psd)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::Error>(),
+            Some(crate::error::Error::OutOfMemory)
+        ));
+    }
+
+    #[test]
+    fn short_channel_data_fails_instead_of_allocating() {
+        // 60000 rows of PackBits data with no row sizes after them
+        let mut reader = ByteReader::new(&[0, 1], false);
+        assert!(decompress_rows(&mut reader, 60_000, 60_000, 8).is_err());
+
+        let mut reader = ByteReader::new(&[0, 0], false);
+        assert!(decompress_rows(&mut reader, usize::MAX, 2, 8).is_err());
+    }
+
+    #[test]
+    fn zip_channels_only_decompress_what_they_need() {
+        use std::io::Write;
+
+        // 64 MiB of zeros compresses to well under a megabyte
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        for _ in 0..64 {
+            encoder.write_all(&[0; 1 << 20]).unwrap();
+        }
+        let mut data = 2u16.to_be_bytes().to_vec();
+        data.extend_from_slice(&encoder.finish().unwrap());
+
+        let mut reader = ByteReader::new(&data, false);
+        assert_eq!(decompress_rows(&mut reader, 2, 3, 8).unwrap(), vec![0; 6]);
+
+        // Too little data still fails
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&[0; 5]).unwrap();
+        let mut data = 2u16.to_be_bytes().to_vec();
+        data.extend_from_slice(&encoder.finish().unwrap());
+
+        let mut reader = ByteReader::new(&data, false);
+        assert!(decompress_rows(&mut reader, 2, 3, 8).is_err());
+    }
+}
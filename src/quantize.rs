@@ -0,0 +1,410 @@
+//! Reducing images to a limited palette of colors (for indexed color)
+
+use std::{collections::HashMap, fmt::Display};
+
+use crate::color_mode::{Palette, MAX_PALETTE_SIZE};
+
+/// An algorithm for picking a limited palette to represent an image with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QuantizationMethod {
+    /// Repeatedly split the box of colors with the largest range at its median
+    #[default]
+    MedianCut,
+
+    /// Build an octree of colors, then merge the least common leaves
+    Octree,
+}
+
+impl Display for QuantizationMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MedianCut => write!(f, "Median Cut"),
+            Self::Octree => write!(f, "Octree"),
+        }
+    }
+}
+
+/// How to hide the banding caused by mapping an image to a limited palette
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dithering {
+    /// Just use the nearest palette color for each pixel
+    None,
+
+    /// Floyd–Steinberg error diffusion
+    #[default]
+    FloydSteinberg,
+
+    /// Ordered dithering with an 8x8 Bayer matrix
+    Ordered,
+}
+
+impl Display for Dithering {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            Self::FloydSteinberg => write!(f, "Floyd–Steinberg"),
+            Self::Ordered => write!(f, "Ordered"),
+        }
+    }
+}
+
+/// Counts how many times each color appears in an RGBA image
+fn histogram(pixels: &[u8]) -> Vec<([u8; 4], u64)> {
+    let mut counts: HashMap<[u8; 4], u64> = HashMap::new();
+    for pixel in pixels.chunks_exact(4) {
+        *counts
+            .entry([pixel[0], pixel[1], pixel[2], pixel[3]])
+            .or_default() += 1;
+    }
+
+    counts.into_iter().collect()
+}
+
+/// The count-weighted average of some colors
+fn average_color(colors: &[([u8; 4], u64)]) -> [u8; 4] {
+    let mut sums = [0u64; 4];
+    let mut total = 0;
+    for (color, count) in colors {
+        for channel in 0..4 {
+            sums[channel] += color[channel] as u64 * count;
+        }
+        total += count;
+    }
+
+    sums.map(|sum| ((sum as f64 / total.max(1) as f64).round()) as u8)
+}
+
+fn median_cut(mut colors: Vec<([u8; 4], u64)>, max_colors: usize) -> Vec<[u8; 4]> {
+    /// The channel with the widest range of values in a box, and that range
+    fn widest_channel(colors: &[([u8; 4], u64)]) -> (usize, u8) {
+        (0..4)
+            .map(|channel| {
+                let min = colors.iter().map(|(c, _)| c[channel]).min().unwrap_or(0);
+                let max = colors.iter().map(|(c, _)| c[channel]).max().unwrap_or(0);
+                (channel, max - min)
+            })
+            .max_by_key(|(_, range)| *range)
+            .unwrap()
+    }
+
+    let mut boxes = vec![std::mem::take(&mut colors)];
+    while boxes.len() < max_colors {
+        // Split the box with the widest range (if any can still be split)
+        let Some((box_index, (channel, _))) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(index, colors)| (index, widest_channel(colors)))
+            .max_by_key(|(_, (_, range))| *range)
+        else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(box_index);
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+
+        // Split at the (pixel count weighted) median
+        let total: u64 = colors.iter().map(|(_, count)| count).sum();
+        let mut running_total = 0;
+        let mut split_at = colors.len() - 1;
+        for (index, (_, count)) in colors.iter().enumerate() {
+            running_total += count;
+            if running_total * 2 >= total {
+                split_at = index + 1;
+                break;
+            }
+        }
+        let split_at = split_at.clamp(1, colors.len() - 1);
+
+        let upper = colors.split_off(split_at);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|colors| average_color(colors)).collect()
+}
+
+#[derive(Debug, Default)]
+struct OctreeNode {
+    sums: [u64; 4],
+    count: u64,
+    children: [Option<usize>; 8],
+    is_leaf: bool,
+}
+
+fn octree(colors: Vec<([u8; 4], u64)>, max_colors: usize) -> Vec<[u8; 4]> {
+    const DEPTH: usize = 8;
+
+    let mut nodes = vec![OctreeNode::default()];
+    // The (non-leaf) nodes at each level, so we can reduce from the bottom up
+    let mut levels: Vec<Vec<usize>> = vec![vec![0]; 1];
+    levels.resize(DEPTH, Vec::new());
+    let mut leaf_count = 0;
+
+    for (color, count) in colors {
+        let mut node = 0;
+        for level in 0..DEPTH {
+            let shift = 7 - level;
+            let child_index = (((color[0] >> shift) & 1) << 2
+                | ((color[1] >> shift) & 1) << 1
+                | ((color[2] >> shift) & 1)) as usize;
+
+            node = match nodes[node].children[child_index] {
+                Some(child) => child,
+                None => {
+                    let child = nodes.len();
+                    nodes.push(OctreeNode::default());
+                    nodes[node].children[child_index] = Some(child);
+                    if level + 1 < DEPTH {
+                        levels[level + 1].push(child);
+                    } else {
+                        nodes[child].is_leaf = true;
+                        leaf_count += 1;
+                    }
+                    child
+                }
+            };
+        }
+
+        for (channel, sum) in nodes[node].sums.iter_mut().enumerate() {
+            *sum += color[channel] as u64 * count;
+        }
+        nodes[node].count += count;
+    }
+
+    // Merge the least common nodes into their parents, deepest first, until
+    // there are few enough leaves
+    for level in (0..DEPTH).rev() {
+        // Every node below this level is a leaf by now, so counts are just
+        // the sums of the children's counts
+        let subtree_count = |nodes: &[OctreeNode], node: usize| -> u64 {
+            nodes[node]
+                .children
+                .iter()
+                .flatten()
+                .map(|child| nodes[*child].count)
+                .sum()
+        };
+        let mut level_nodes = std::mem::take(&mut levels[level]);
+        level_nodes.sort_unstable_by_key(|node| std::cmp::Reverse(subtree_count(&nodes, *node)));
+
+        while leaf_count > max_colors {
+            let Some(node) = level_nodes.pop() else {
+                break;
+            };
+
+            let mut merged_children = 0;
+            for child in nodes[node].children.into_iter().flatten() {
+                let child_sums = nodes[child].sums;
+                let child_count = nodes[child].count;
+                for (sum, child_sum) in nodes[node].sums.iter_mut().zip(child_sums) {
+                    *sum += child_sum;
+                }
+                nodes[node].count += child_count;
+                nodes[child].is_leaf = false;
+                merged_children += 1;
+            }
+            nodes[node].children = [None; 8];
+            nodes[node].is_leaf = true;
+            leaf_count = leaf_count + 1 - merged_children;
+        }
+    }
+
+    nodes
+        .iter()
+        .filter(|node| node.is_leaf && node.count > 0)
+        .map(|node| {
+            node.sums
+                .map(|sum| (sum as f64 / node.count as f64).round() as u8)
+        })
+        .collect()
+}
+
+/// Picks a palette of at most `max_colors` colors to represent an RGBA image
+pub fn quantize(pixels: &[u8], max_colors: usize, method: QuantizationMethod) -> Palette {
+    let max_colors = max_colors.clamp(1, MAX_PALETTE_SIZE);
+
+    let colors = histogram(pixels);
+
+    // No need to do anything clever if the image already has few enough colors
+    let mut palette_colors = if colors.len() <= max_colors {
+        colors.into_iter().map(|(color, _)| color).collect()
+    } else {
+        match method {
+            QuantizationMethod::MedianCut => median_cut(colors, max_colors),
+            QuantizationMethod::Octree => octree(colors, max_colors),
+        }
+    };
+
+    // Keep the palette in a stable, predictable order
+    palette_colors.sort_unstable();
+    palette_colors.dedup();
+    if palette_colors.is_empty() {
+        palette_colors.push([0, 0, 0, 255]);
+    }
+
+    Palette {
+        colors: palette_colors,
+    }
+}
+
+/// The classic 8x8 Bayer matrix used for ordered dithering
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Replaces every pixel of an RGBA image with a color from the palette, using
+/// the given dithering method
+pub fn remap(pixels: &mut [u8], width: usize, palette: &Palette, dithering: Dithering) {
+    // Dithering only nudges colors a little, so the same few keep coming up.
+    // Remember the ones we've already looked up (like `Palette::index_pixels`)
+    let mut cache: HashMap<[u8; 4], [u8; 4]> = HashMap::new();
+    let mut nearest =
+        |color: [u8; 4]| *cache.entry(color).or_insert_with(|| palette.nearest(color));
+
+    match dithering {
+        Dithering::None => {
+            let indices = palette.index_pixels(pixels);
+            for (pixel, index) in pixels.chunks_exact_mut(4).zip(indices) {
+                pixel.copy_from_slice(&palette.colors[index as usize]);
+            }
+        }
+        Dithering::FloydSteinberg => {
+            // The accumulated error for each channel of each pixel
+            let mut errors = vec![[0.0f32; 4]; pixels.len() / 4];
+            let height = pixels.len() / 4 / width.max(1);
+
+            for row in 0..height {
+                for col in 0..width {
+                    let index = row * width + col;
+                    let pixel = &mut pixels[index * 4..index * 4 + 4];
+
+                    let mut wanted = [0.0f32; 4];
+                    let mut color = [0u8; 4];
+                    for channel in 0..4 {
+                        wanted[channel] = pixel[channel] as f32 + errors[index][channel];
+                        color[channel] = wanted[channel].round().clamp(0.0, 255.0) as u8;
+                    }
+
+                    let chosen = nearest(color);
+                    pixel.copy_from_slice(&chosen);
+
+                    let error = std::array::from_fn::<f32, 4, _>(|channel| {
+                        wanted[channel] - chosen[channel] as f32
+                    });
+                    let mut spread = |col: usize, row: usize, weight: f32| {
+                        if col < width && row < height {
+                            for (channel, error) in error.iter().enumerate() {
+                                errors[row * width + col][channel] += error * weight;
+                            }
+                        }
+                    };
+                    spread(col + 1, row, 7.0 / 16.0);
+                    if col > 0 {
+                        spread(col - 1, row + 1, 3.0 / 16.0);
+                    }
+                    spread(col, row + 1, 5.0 / 16.0);
+                    spread(col + 1, row + 1, 1.0 / 16.0);
+                }
+            }
+        }
+        Dithering::Ordered => {
+            // Roughly the distance between neighboring palette colors, if they
+            // were spread evenly across the color cube
+            let spread = 255.0 / (palette.colors.len() as f32).cbrt();
+
+            for (index, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+                let (col, row) = (index % width.max(1), index / width.max(1));
+                let threshold = (BAYER_8X8[row % 8][col % 8] as f32 + 0.5) / 64.0 - 0.5;
+
+                let mut color = [0u8; 4];
+                for channel in 0..3 {
+                    color[channel] =
+                        (pixel[channel] as f32 + threshold * spread).clamp(0.0, 255.0) as u8;
+                }
+                color[3] = pixel[3];
+
+                pixel.copy_from_slice(&nearest(color));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 16x16 RGBA image with a different color in every pixel
+    fn gradient() -> Vec<u8> {
+        (0..16u8)
+            .flat_map(|y| (0..16u8).flat_map(move |x| [x * 16, y * 16, 128, 255]))
+            .collect()
+    }
+
+    const METHODS: [QuantizationMethod; 2] =
+        [QuantizationMethod::MedianCut, QuantizationMethod::Octree];
+
+    #[test]
+    fn palettes_are_no_larger_than_asked() {
+        let pixels = gradient();
+        for method in METHODS {
+            for max_colors in [1, 2, 7, 16, 100] {
+                let palette = quantize(&pixels, max_colors, method);
+                assert!(!palette.colors.is_empty());
+                assert!(
+                    palette.colors.len() <= max_colors,
+                    "{method} made {} colors, wanted at most {max_colors}",
+                    palette.colors.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn few_enough_colors_are_kept_exactly() {
+        let colors = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 128],
+            [0, 0, 0, 0],
+        ];
+        let pixels: Vec<u8> = colors.iter().cycle().take(40).flatten().copied().collect();
+
+        for method in METHODS {
+            let mut palette = quantize(&pixels, 4, method).colors;
+            palette.sort_unstable();
+            let mut expected = colors.to_vec();
+            expected.sort_unstable();
+            assert_eq!(palette, expected, "{method}");
+        }
+    }
+
+    #[test]
+    fn dithering_only_uses_palette_colors() {
+        let palette = quantize(&gradient(), 8, QuantizationMethod::MedianCut);
+
+        for dithering in [
+            Dithering::None,
+            Dithering::FloydSteinberg,
+            Dithering::Ordered,
+        ] {
+            let mut pixels = gradient();
+            remap(&mut pixels, 16, &palette, dithering);
+            for pixel in pixels.chunks_exact(4) {
+                assert!(
+                    palette
+                        .colors
+                        .contains(&[pixel[0], pixel[1], pixel[2], pixel[3]]),
+                    "{dithering} used {pixel:?}, which isn't in the palette"
+                );
+            }
+        }
+    }
+}
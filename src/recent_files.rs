@@ -0,0 +1,62 @@
+//! The list of recently opened (and saved) files, for the Open Recent menu
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fileio;
+
+/// The most files the list remembers
+pub const MAX_RECENT_FILES: usize = 10;
+
+/// The name of the file the list is stored in (in the app's data directory)
+const FILE_NAME: &str = "recent_files.json";
+
+/// Recently opened and saved files, most recent first
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RecentFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentFiles {
+    /// Loads the list saved by a previous run, or an empty list if there isn't
+    /// one (or it can't be read)
+    pub fn load() -> Self {
+        fileio::load_app_data(FILE_NAME)
+    }
+
+    /// Writes the list out for future runs
+    fn save(&self) {
+        fileio::save_app_data(FILE_NAME, self);
+    }
+
+    /// The recent files, most recent first
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Moves a file to the top of the list (adding it if it isn't already
+    /// there), forgetting the oldest file if the list is full
+    pub fn add(&mut self, path: &Path) {
+        // The same file can be reached through different paths, so store the
+        // canonical one to avoid duplicates
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        self.paths.retain(|existing| *existing != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT_FILES);
+        self.save();
+    }
+
+    /// Removes a file from the list (like one that no longer exists)
+    pub fn remove(&mut self, path: &Path) {
+        self.paths.retain(|existing| existing != path);
+        self.save();
+    }
+
+    /// Forgets every file
+    pub fn clear(&mut self) {
+        self.paths.clear();
+        self.save();
+    }
+}
@@ -0,0 +1,385 @@
+//! Autosaving unsaved documents (and saving them in an emergency if the app
+//! crashes), so they can be recovered the next time the app starts
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, TryLockError},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    thread::JoinHandle,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation::Animation, color_management::ColorProfile, color_mode::ColorMode,
+    document::Document, fileio, layers::LayeredImage, metadata::Metadata, project,
+};
+
+/// How often unsaved documents are autosaved, in seconds
+pub const AUTOSAVE_INTERVAL_SECS: f64 = 60.0;
+
+/// The autosaves being written in the background, by document ID
+static IN_PROGRESS: Mutex<BTreeMap<u64, JoinHandle<()>>> = Mutex::new(BTreeMap::new());
+
+/// Counts up to give each autosave's temporary file a different name
+static NEXT_TEMPORARY: AtomicU64 = AtomicU64::new(0);
+
+/// Where a recovered document came from, stored next to its autosave
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryInfo {
+    /// The file the document was opened from (or last saved to), if any
+    pub path: Option<PathBuf>,
+
+    /// The name the document's tab had
+    pub title: String,
+}
+
+/// An autosaved document left behind by a previous run that didn't quit
+/// normally
+#[derive(Debug, Clone)]
+pub struct RecoveredDocument {
+    pub info: RecoveryInfo,
+
+    /// The autosaved image (a project file)
+    pub file: PathBuf,
+}
+
+/// Everything needed to write an autosave, copied out of a document so it can
+/// be written in the background
+struct Autosave {
+    image: LayeredImage,
+    animation: Animation,
+    color_profile: Option<ColorProfile>,
+    color_mode: ColorMode,
+    metadata: Metadata,
+    info: RecoveryInfo,
+}
+
+impl Autosave {
+    fn new(document: &Document) -> Self {
+        Self {
+            image: document.image.clone(),
+            animation: document.animation.clone(),
+            color_profile: document.color_profile.clone(),
+            color_mode: document.color_mode.clone(),
+            metadata: document.metadata.clone(),
+            info: RecoveryInfo {
+                path: document.path.clone(),
+                title: document.title(),
+            },
+        }
+    }
+
+    /// Writes the autosave to disk. The image is written to a temporary file
+    /// first (a different one each time, in case an emergency save overlaps a
+    /// background one), so an older autosave is never replaced with a
+    /// half-written one
+    fn write(&self, file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let number = NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed);
+        let temporary = file.with_extension(format!("{number}.tmp"));
+        project::write_project(
+            BufWriter::new(File::create(&temporary)?),
+            &self.image,
+            &self.animation,
+            self.color_profile.as_ref(),
+            &self.color_mode,
+            &self.metadata,
+        )?;
+        std::fs::rename(&temporary, file)?;
+
+        std::fs::write(info_path(file), serde_json::to_vec_pretty(&self.info)?)?;
+        Ok(())
+    }
+}
+
+/// The directory autosaves are kept in, or None if there's nowhere to keep
+/// them (like on the web)
+fn recovery_dir() -> Option<PathBuf> {
+    Some(fileio::app_data_dir()?.join("recovery"))
+}
+
+/// A name for this run of the app, which a later run won't have even if it
+/// gets the same process ID (which happens a lot in containers and sandboxes)
+fn session_id() -> &'static str {
+    static SESSION_ID: OnceLock<String> = OnceLock::new();
+
+    SESSION_ID.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos());
+        format!("{}_{started}", std::process::id())
+    })
+}
+
+/// The lock file held by a run of the app for as long as it's running, so
+/// other copies of the app can tell its autosaves aren't left over from a
+/// crash
+fn lock_path(dir: &Path, session: &str) -> PathBuf {
+    dir.join(session).with_extension("lock")
+}
+
+/// Whether or not the run of the app with the given session ID is still
+/// running (and holding its lock file)
+fn is_running(dir: &Path, session: &str) -> bool {
+    let Ok(file) = File::open(lock_path(dir, session)) else {
+        // Runs always lock their file before autosaving anything
+        return false;
+    };
+
+    match file.try_lock() {
+        Ok(()) => false,
+        Err(TryLockError::WouldBlock) => true,
+        // Better to offer autosaves twice than never
+        Err(TryLockError::Error(err)) => {
+            eprintln!("failed to check whether session {session} is running: {err:?}");
+            false
+        }
+    }
+}
+
+/// The session ID an autosave was written by
+fn autosave_session(file: &Path) -> String {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    name.split('-').next().unwrap_or_default().to_string()
+}
+
+/// Whether a file in the recovery directory is an autosave
+fn is_autosave(file: &Path) -> bool {
+    file.extension()
+        .is_some_and(|extension| extension == project::EXTENSION)
+}
+
+/// Deletes the lock file of a run of the app that's no longer running, once
+/// none of its autosaves are left to recover
+fn remove_stale_lock(dir: &Path, session: &str) {
+    if session == session_id() || is_running(dir, session) {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let has_autosaves = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .any(|file| is_autosave(&file) && autosave_session(&file) == session);
+    if has_autosaves {
+        return;
+    }
+
+    let path = lock_path(dir, session);
+    if let Err(err) = std::fs::remove_file(&path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("failed to remove stale lock {path:?}: {err:?}");
+        }
+    }
+}
+
+/// Locks this run's lock file (the first time it's called), returning the
+/// directory autosaves are kept in
+fn lock_this_process() -> Option<PathBuf> {
+    static LOCK: OnceLock<Option<File>> = OnceLock::new();
+
+    let dir = recovery_dir()?;
+    LOCK.get_or_init(|| {
+        let path = lock_path(&dir, session_id());
+        let result = std::fs::create_dir_all(&dir)
+            .and_then(|()| File::create(&path))
+            .and_then(|file| file.lock().map(|()| file));
+        result
+            .inspect_err(|err| eprintln!("failed to lock {path:?}: {err:?}"))
+            .ok()
+    });
+    Some(dir)
+}
+
+/// The autosave file for a document. Autosaves are named after the run of the
+/// app, so multiple copies of the app (running at once, or one after another)
+/// don't overwrite each other's
+fn autosave_path(document_id: u64) -> Option<PathBuf> {
+    let dir = lock_this_process()?;
+    let name = format!("{}-{document_id}", session_id());
+    Some(dir.join(name).with_extension(project::EXTENSION))
+}
+
+/// The file next to an autosave saying where it came from
+fn info_path(autosave: &Path) -> PathBuf {
+    autosave.with_extension("json")
+}
+
+/// Autosaves a document in the background. Returns false if the document's
+/// previous autosave is still being written, so it should be tried again later
+pub fn autosave(document: &Document) -> bool {
+    let Some(file) = autosave_path(document.id) else {
+        return true;
+    };
+
+    let mut in_progress = IN_PROGRESS.lock().unwrap_or_else(|err| err.into_inner());
+    if in_progress
+        .get(&document.id)
+        .is_some_and(|thread| !thread.is_finished())
+    {
+        return false;
+    }
+
+    let autosave = Autosave::new(document);
+    let thread = std::thread::spawn(move || {
+        if let Err(err) = autosave.write(&file) {
+            eprintln!("failed to autosave to {file:?}: {err:?}");
+        }
+    });
+    in_progress.insert(document.id, thread);
+    true
+}
+
+/// Waits for a document's background autosave to finish, if there is one
+fn wait_for_autosave(document_id: u64) {
+    let thread = IN_PROGRESS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .remove(&document_id);
+    if let Some(thread) = thread {
+        // The thread already logged why it failed, if it did
+        let _ = thread.join();
+    }
+}
+
+/// Autosaves documents right away (for when the app is about to crash, so
+/// there's no time to do it in the background)
+pub fn emergency_save<'a>(documents: impl IntoIterator<Item = &'a Document>) {
+    for document in documents {
+        let Some(file) = autosave_path(document.id) else {
+            return;
+        };
+
+        // An older background autosave finishing afterwards would replace
+        // this one
+        wait_for_autosave(document.id);
+        match Autosave::new(document).write(&file) {
+            Ok(()) => eprintln!("saved unsaved changes to {file:?}"),
+            Err(err) => eprintln!("failed to save unsaved changes to {file:?}: {err:?}"),
+        }
+    }
+}
+
+/// Deletes a document's autosave (once it's been saved for real, or its
+/// changes have been thrown away), first waiting for it to finish being
+/// written if it's still in progress (so it isn't written back afterwards)
+pub fn remove_autosave(document_id: u64) {
+    wait_for_autosave(document_id);
+    if let Some(file) = autosave_path(document_id) {
+        remove_recovered(&file);
+    }
+}
+
+/// Deletes an autosave and the file saying where it came from (and the lock
+/// file of the run that wrote it, if it was the last one left over from a
+/// crash)
+pub fn remove_recovered(file: &Path) {
+    for path in [file.to_path_buf(), info_path(file)] {
+        if let Err(err) = std::fs::remove_file(&path) {
+            // There's no autosave if the document hasn't changed since it was
+            // opened (or last autosaved)
+            if err.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("failed to remove autosave {path:?}: {err:?}");
+            }
+        }
+    }
+
+    if let Some(dir) = file.parent() {
+        remove_stale_lock(dir, &autosave_session(file));
+    }
+}
+
+/// Finds autosaves left behind by previous runs of the app (skipping those of
+/// other copies of the app that are still running)
+pub fn find_recovered() -> Vec<RecoveredDocument> {
+    let Some(dir) = lock_this_process() else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        // There's no recovery directory until something is autosaved
+        return Vec::new();
+    };
+
+    let files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+
+    // Runs that quit normally leave their (unlocked) lock files behind
+    for file in &files {
+        if file
+            .extension()
+            .is_some_and(|extension| extension == "lock")
+        {
+            let session = file.file_stem().unwrap_or_default().to_string_lossy();
+            remove_stale_lock(&dir, &session);
+        }
+    }
+
+    files
+        .into_iter()
+        .filter(|file| is_autosave(file))
+        .filter(|file| !is_running(&dir, &autosave_session(file)))
+        .map(|file| {
+            let info = std::fs::read(info_path(&file))
+                .ok()
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+                .unwrap_or_else(|| RecoveryInfo {
+                    path: None,
+                    title: "Untitled".to_string(),
+                });
+            RecoveredDocument { info, file }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn processes_holding_their_lock_are_running() {
+        let dir = std::env::temp_dir().join(format!("trametes-recovery-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert!(!is_running(&dir, "123"));
+
+        let lock = File::create(lock_path(&dir, "123")).unwrap();
+        lock.lock().unwrap();
+        assert!(is_running(&dir, "123"));
+
+        // Crashing releases the lock, but leaves the file behind
+        drop(lock);
+        assert!(!is_running(&dir, "123"));
+    }
+
+    #[test]
+    fn stale_locks_are_removed_once_their_autosaves_are() {
+        let dir = std::env::temp_dir().join(format!("trametes-stale-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let session = "123_456";
+        File::create(lock_path(&dir, session)).unwrap();
+        let autosave = dir.join(format!("{session}-1.{}", project::EXTENSION));
+        std::fs::write(&autosave, b"").unwrap();
+        assert_eq!(autosave_session(&autosave), session);
+
+        // (Kept until the autosaves it goes with are dealt with)
+        remove_stale_lock(&dir, session);
+        assert!(lock_path(&dir, session).exists());
+
+        remove_recovered(&autosave);
+        assert!(!lock_path(&dir, session).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
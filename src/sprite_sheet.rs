@@ -0,0 +1,689 @@
+//! Splitting sprite sheets up into separate sprites, and packing sprites back
+//! into sprite sheets (described by a TexturePacker-compatible JSON file, which
+//! most game engines can read)
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::{
+    animation::Animation,
+    layers::{Layer, LayeredImage},
+    pixel_buffer::PixelBuffer,
+};
+
+/// A rectangle within an image, in pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SpriteRect {
+    /// The horizontal position of the rectangle's left edge
+    pub x: usize,
+
+    /// The vertical position of the rectangle's top edge
+    pub y: usize,
+
+    /// The width of the rectangle
+    #[serde(rename = "w")]
+    pub width: usize,
+
+    /// The height of the rectangle
+    #[serde(rename = "h")]
+    pub height: usize,
+}
+
+/// How to divide a sprite sheet up into cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grid {
+    /// Cells of a fixed size, in pixels
+    CellSize { width: usize, height: usize },
+
+    /// A fixed number of columns and rows
+    Count { columns: usize, rows: usize },
+}
+
+/// Whether sprites are an image's frames or its layers
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SpriteSource {
+    #[default]
+    Frames,
+    Layers,
+}
+
+impl Display for SpriteSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Frames => write!(f, "Frames"),
+            Self::Layers => write!(f, "Layers"),
+        }
+    }
+}
+
+/// The options for importing a sprite sheet
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportSettings {
+    /// How the sheet is divided up into cells (one sprite per cell)
+    pub grid: Grid,
+
+    /// The empty space around the edges of the sheet, in pixels
+    pub margin: usize,
+
+    /// The empty space between cells, in pixels
+    pub spacing: usize,
+
+    /// What each sprite becomes
+    pub import_as: SpriteSource,
+
+    /// Whether to leave out fully transparent cells (like unused space at the
+    /// end of the sheet)
+    pub skip_empty: bool,
+}
+
+impl Default for ImportSettings {
+    fn default() -> Self {
+        Self {
+            grid: Grid::CellSize {
+                width: 32,
+                height: 32,
+            },
+            margin: 0,
+            spacing: 0,
+            import_as: SpriteSource::default(),
+            skip_empty: true,
+        }
+    }
+}
+
+impl ImportSettings {
+    /// The cells of a sprite sheet of the given size, from left to right, then
+    /// top to bottom. Partial cells at the edges are left out
+    pub fn cells(&self, sheet_width: usize, sheet_height: usize) -> Vec<SpriteRect> {
+        let usable_width = sheet_width.saturating_sub(2 * self.margin);
+        let usable_height = sheet_height.saturating_sub(2 * self.margin);
+
+        // (The spacing only goes between cells, not after the last one)
+        let (width, height, columns, rows) = match self.grid {
+            Grid::CellSize { width, height } => {
+                let width = width.max(1);
+                let height = height.max(1);
+                (
+                    width,
+                    height,
+                    (usable_width + self.spacing) / (width + self.spacing),
+                    (usable_height + self.spacing) / (height + self.spacing),
+                )
+            }
+            Grid::Count { columns, rows } => {
+                let columns = columns.max(1);
+                let rows = rows.max(1);
+                (
+                    usable_width.saturating_sub((columns - 1) * self.spacing) / columns,
+                    usable_height.saturating_sub((rows - 1) * self.spacing) / rows,
+                    columns,
+                    rows,
+                )
+            }
+        };
+
+        if width == 0 || height == 0 {
+            return Vec::new();
+        }
+
+        (0..rows)
+            .flat_map(|row| {
+                (0..columns).map(move |column| SpriteRect {
+                    x: self.margin + column * (width + self.spacing),
+                    y: self.margin + row * (height + self.spacing),
+                    width,
+                    height,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Copies a rectangle out of an image (the rectangle must be inside the
+/// image)
+fn crop(image: &PixelBuffer, rect: SpriteRect) -> PixelBuffer {
+    let mut pixels = Vec::with_capacity(rect.width * rect.height * 4);
+    for row in rect.y..rect.y + rect.height {
+        let start = (row * image.width + rect.x) * 4;
+        pixels.extend_from_slice(&image.pixels[start..start + rect.width * 4]);
+    }
+
+    PixelBuffer {
+        pixels,
+        width: rect.width,
+        height: rect.height,
+    }
+}
+
+/// Copies an image into a (larger) image at the given position
+fn paste(target: &mut PixelBuffer, image: &PixelBuffer, x: usize, y: usize) {
+    for row in 0..image.height {
+        let source = row * image.width * 4;
+        let destination = ((y + row) * target.width + x) * 4;
+        target.pixels[destination..destination + image.width * 4]
+            .copy_from_slice(&image.pixels[source..source + image.width * 4]);
+    }
+}
+
+/// Whether or not every pixel of an image is fully transparent
+fn is_empty(image: &PixelBuffer) -> bool {
+    image.pixels.chunks_exact(4).all(|pixel| pixel[3] == 0)
+}
+
+/// Splits a sprite sheet up into sprites
+pub fn split(sheet: &PixelBuffer, settings: &ImportSettings) -> Vec<PixelBuffer> {
+    settings
+        .cells(sheet.width, sheet.height)
+        .into_iter()
+        .map(|cell| crop(sheet, cell))
+        .filter(|sprite| !settings.skip_empty || !is_empty(sprite))
+        .collect()
+}
+
+/// How sprites are arranged in an exported sprite sheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Evenly sized cells (the size of the largest sprite), in rows of the
+    /// given number of columns
+    Grid { columns: usize },
+
+    /// Sprites trimmed of transparent edges and packed as tightly as possible
+    /// (in rows), keeping the sheet at most the given width
+    Atlas { max_width: usize },
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Grid { .. } => write!(f, "Grid"),
+            Self::Atlas { .. } => write!(f, "Tight atlas"),
+        }
+    }
+}
+
+/// The options for exporting a sprite sheet
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportSettings {
+    /// What becomes the sprites
+    pub source: SpriteSource,
+
+    /// How the sprites are arranged in the sheet
+    pub layout: Layout,
+
+    /// The empty space between sprites, in pixels
+    pub padding: usize,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            source: SpriteSource::default(),
+            layout: Layout::Grid { columns: 8 },
+            padding: 0,
+        }
+    }
+}
+
+/// A sprite to be packed into a sprite sheet
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    /// The name of the sprite in the JSON description (like "Walk 3")
+    pub name: String,
+
+    /// The sprite's image
+    pub pixels: PixelBuffer,
+
+    /// How long the sprite is shown for, if it's a frame of an animation
+    pub duration_ms: Option<u32>,
+}
+
+/// The frames of an animation, as sprites
+pub fn frame_sprites(animation: &Animation, current: &LayeredImage, name: &str) -> Vec<Sprite> {
+    animation
+        .flatten(current)
+        .into_iter()
+        .enumerate()
+        .map(|(index, frame)| Sprite {
+            name: format!("{name} {index}"),
+            pixels: frame.pixels,
+            duration_ms: Some(frame.delay_ms),
+        })
+        .collect()
+}
+
+/// The layers of an image, as (canvas-sized) sprites. Hidden layers are
+/// included too, since they're often hidden just to keep the canvas tidy
+pub fn layer_sprites(image: &LayeredImage) -> Vec<Sprite> {
+    image
+        .layers
+        .iter()
+        .map(|layer| {
+            let alone = LayeredImage {
+                width: image.width,
+                height: image.height,
+                layers: vec![Layer {
+                    visible: true,
+                    ..layer.clone()
+                }],
+                active_layer: 0,
+            };
+
+            Sprite {
+                name: layer.name.clone(),
+                pixels: alone.flatten(),
+                duration_ms: None,
+            }
+        })
+        .collect()
+}
+
+/// Where a sprite ended up in a sprite sheet
+#[derive(Debug, Clone)]
+pub struct PackedSprite {
+    /// The name of the sprite in the JSON description
+    pub name: String,
+
+    /// Where the sprite is in the sheet
+    pub frame: SpriteRect,
+
+    /// Where the packed part of the sprite was in the original sprite (which
+    /// is only part of it if transparent edges were trimmed off)
+    pub source_rect: SpriteRect,
+
+    /// The width of the original sprite
+    pub source_width: usize,
+
+    /// The height of the original sprite
+    pub source_height: usize,
+
+    /// How long the sprite is shown for, if it's a frame of an animation
+    pub duration_ms: Option<u32>,
+}
+
+/// A packed sprite sheet, and where each sprite is in it
+#[derive(Debug, Clone)]
+pub struct SpriteSheet {
+    /// The sheet's image, with every sprite in it
+    pub image: PixelBuffer,
+
+    /// Where each sprite is in the sheet, in the order they were given
+    pub sprites: Vec<PackedSprite>,
+}
+
+/// The smallest rectangle containing every non-transparent pixel of an image
+/// (or a single pixel, for fully transparent images)
+fn opaque_bounds(image: &PixelBuffer) -> SpriteRect {
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (index, pixel) in image.pixels.chunks_exact(4).enumerate() {
+        if pixel[3] == 0 {
+            continue;
+        }
+
+        let (x, y) = (index % image.width, index / image.width);
+        bounds = Some(match bounds {
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }
+            None => (x, y, x, y),
+        });
+    }
+
+    match bounds {
+        Some((left, top, right, bottom)) => SpriteRect {
+            x: left,
+            y: top,
+            width: right - left + 1,
+            height: bottom - top + 1,
+        },
+        None => SpriteRect {
+            x: 0,
+            y: 0,
+            width: 1.min(image.width),
+            height: 1.min(image.height),
+        },
+    }
+}
+
+/// Packs sprites into a sprite sheet
+pub fn pack(sprites: &[Sprite], settings: &ExportSettings) -> SpriteSheet {
+    let padding = settings.padding;
+
+    // Work out which part of each sprite to pack, and where it goes
+    let (source_rects, positions): (Vec<SpriteRect>, Vec<(usize, usize)>) = match settings.layout {
+        Layout::Grid { columns } => {
+            let columns = columns.max(1);
+            let cell_width = sprites.iter().map(|s| s.pixels.width).max().unwrap_or(0);
+            let cell_height = sprites.iter().map(|s| s.pixels.height).max().unwrap_or(0);
+
+            sprites
+                .iter()
+                .enumerate()
+                .map(|(index, sprite)| {
+                    let rect = SpriteRect {
+                        x: 0,
+                        y: 0,
+                        width: sprite.pixels.width,
+                        height: sprite.pixels.height,
+                    };
+                    let position = (
+                        (index % columns) * (cell_width + padding),
+                        (index / columns) * (cell_height + padding),
+                    );
+                    (rect, position)
+                })
+                .unzip()
+        }
+        Layout::Atlas { max_width } => {
+            let source_rects: Vec<SpriteRect> =
+                sprites.iter().map(|s| opaque_bounds(&s.pixels)).collect();
+            let max_width = max_width.max(source_rects.iter().map(|r| r.width).max().unwrap_or(0));
+
+            // Shelf packing: place the tallest sprites first, left to right,
+            // starting a new row (shelf) whenever one doesn't fit
+            let mut order: Vec<usize> = (0..sprites.len()).collect();
+            order.sort_by_key(|index| std::cmp::Reverse(source_rects[*index].height));
+
+            let mut positions = vec![(0, 0); sprites.len()];
+            let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+            for index in order {
+                let rect = source_rects[index];
+                if x > 0 && x + rect.width > max_width {
+                    x = 0;
+                    y += shelf_height + padding;
+                    shelf_height = 0;
+                }
+
+                positions[index] = (x, y);
+                x += rect.width + padding;
+                shelf_height = shelf_height.max(rect.height);
+            }
+
+            (source_rects, positions)
+        }
+    };
+
+    let width = source_rects
+        .iter()
+        .zip(&positions)
+        .map(|(rect, (x, _))| x + rect.width)
+        .max()
+        .unwrap_or(0);
+    let height = source_rects
+        .iter()
+        .zip(&positions)
+        .map(|(rect, (_, y))| y + rect.height)
+        .max()
+        .unwrap_or(0);
+    let mut image = PixelBuffer {
+        pixels: vec![0; width * height * 4],
+        width,
+        height,
+    };
+
+    let packed = sprites
+        .iter()
+        .zip(source_rects)
+        .zip(positions)
+        .map(|((sprite, source_rect), (x, y))| {
+            paste(&mut image, &crop(&sprite.pixels, source_rect), x, y);
+
+            PackedSprite {
+                name: sprite.name.clone(),
+                frame: SpriteRect {
+                    x,
+                    y,
+                    ..source_rect
+                },
+                source_rect,
+                source_width: sprite.pixels.width,
+                source_height: sprite.pixels.height,
+                duration_ms: sprite.duration_ms,
+            }
+        })
+        .collect();
+
+    SpriteSheet {
+        image,
+        sprites: packed,
+    }
+}
+
+/// A size in the JSON description
+#[derive(Serialize)]
+struct JsonSize {
+    w: usize,
+    h: usize,
+}
+
+/// A sprite in the JSON description
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonFrame<'a> {
+    filename: &'a str,
+    frame: SpriteRect,
+    rotated: bool,
+    trimmed: bool,
+    sprite_source_size: SpriteRect,
+    source_size: JsonSize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration: Option<u32>,
+}
+
+/// Information about the sprite sheet itself in the JSON description
+#[derive(Serialize)]
+struct JsonMeta<'a> {
+    app: &'static str,
+    version: &'static str,
+    image: &'a str,
+    format: &'static str,
+    size: JsonSize,
+    scale: &'static str,
+}
+
+/// The JSON description of a sprite sheet
+#[derive(Serialize)]
+struct JsonSheet<'a> {
+    frames: Vec<JsonFrame<'a>>,
+    meta: JsonMeta<'a>,
+}
+
+impl SpriteSheet {
+    /// Describes the sprite sheet in TexturePacker's "JSON (Array)" format
+    /// (which Aseprite also uses), given the file name of the sheet's image
+    pub fn to_json(&self, image_file_name: &str) -> serde_json::Result<String> {
+        let sheet = JsonSheet {
+            frames: self
+                .sprites
+                .iter()
+                .map(|sprite| JsonFrame {
+                    filename: &sprite.name,
+                    frame: sprite.frame,
+                    rotated: false,
+                    trimmed: sprite.source_rect.width != sprite.source_width
+                        || sprite.source_rect.height != sprite.source_height,
+                    sprite_source_size: sprite.source_rect,
+                    source_size: JsonSize {
+                        w: sprite.source_width,
+                        h: sprite.source_height,
+                    },
+                    duration: sprite.duration_ms,
+                })
+                .collect(),
+            meta: JsonMeta {
+                app: "Trametes",
+                version: env!("CARGO_PKG_VERSION"),
+                image: image_file_name,
+                format: "RGBA8888",
+                size: JsonSize {
+                    w: self.image.width,
+                    h: self.image.height,
+                },
+                scale: "1",
+            },
+        };
+
+        serde_json::to_string_pretty(&sheet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: usize, y: usize, width: usize, height: usize) -> SpriteRect {
+        SpriteRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// A sprite that's transparent except for an opaque rectangle
+    fn sprite(name: &str, width: usize, height: usize, opaque: SpriteRect) -> Sprite {
+        let mut pixels = PixelBuffer {
+            pixels: vec![0; width * height * 4],
+            width,
+            height,
+        };
+        for y in opaque.y..opaque.y + opaque.height {
+            for x in opaque.x..opaque.x + opaque.width {
+                pixels.pixels[(y * width + x) * 4..][..4].copy_from_slice(&[255, 0, 0, 255]);
+            }
+        }
+
+        Sprite {
+            name: name.to_string(),
+            pixels,
+            duration_ms: None,
+        }
+    }
+
+    #[test]
+    fn cells_skip_the_margin_and_spacing() {
+        let settings = ImportSettings {
+            grid: Grid::CellSize {
+                width: 4,
+                height: 3,
+            },
+            margin: 1,
+            spacing: 2,
+            ..ImportSettings::default()
+        };
+
+        // 1 + 4 + 2 + 4 + 1 = 12 wide (with 1 pixel left over), and
+        // 1 + 3 + 2 + 3 + 1 = 10 tall
+        assert_eq!(
+            settings.cells(13, 10),
+            [
+                rect(1, 1, 4, 3),
+                rect(7, 1, 4, 3),
+                rect(1, 6, 4, 3),
+                rect(7, 6, 4, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn cell_counts_divide_up_the_space_between_the_spacing() {
+        let settings = ImportSettings {
+            grid: Grid::Count {
+                columns: 3,
+                rows: 1,
+            },
+            margin: 2,
+            spacing: 1,
+            ..ImportSettings::default()
+        };
+
+        // 20 - 2 * 2 margin - 2 * 1 spacing leaves 14 pixels, 4 per cell
+        let cells = settings.cells(20, 8);
+        assert_eq!(
+            cells,
+            [rect(2, 2, 4, 4), rect(7, 2, 4, 4), rect(12, 2, 4, 4)]
+        );
+    }
+
+    #[test]
+    fn atlas_sprites_dont_overlap() {
+        let sprites = [
+            sprite("a", 10, 10, rect(2, 3, 5, 4)),
+            sprite("b", 8, 8, rect(0, 0, 8, 8)),
+            sprite("c", 6, 12, rect(1, 1, 3, 10)),
+            sprite("d", 4, 4, rect(0, 0, 0, 0)),
+            sprite("e", 9, 2, rect(0, 0, 9, 2)),
+        ];
+        let settings = ExportSettings {
+            layout: Layout::Atlas { max_width: 16 },
+            padding: 1,
+            ..ExportSettings::default()
+        };
+
+        let sheet = pack(&sprites, &settings);
+
+        assert!(sheet.image.width <= 16);
+        for (index, a) in sheet.sprites.iter().enumerate() {
+            let frame = a.frame;
+            assert!(frame.x + frame.width <= sheet.image.width);
+            assert!(frame.y + frame.height <= sheet.image.height);
+            for b in &sheet.sprites[index + 1..] {
+                let other = b.frame;
+                let apart = frame.x + frame.width <= other.x
+                    || other.x + other.width <= frame.x
+                    || frame.y + frame.height <= other.y
+                    || other.y + other.height <= frame.y;
+                assert!(apart, "{} and {} overlap", a.name, b.name);
+            }
+        }
+
+        // Transparent edges are trimmed off
+        assert_eq!(sheet.sprites[0].source_rect, rect(2, 3, 5, 4));
+        assert_eq!(sheet.sprites[0].frame.width, 5);
+    }
+
+    #[test]
+    fn json_matches_texture_packer() {
+        let sprites = [
+            sprite("trimmed", 4, 4, rect(1, 1, 2, 3)),
+            Sprite {
+                duration_ms: Some(50),
+                ..sprite("full", 2, 2, rect(0, 0, 2, 2))
+            },
+        ];
+        let settings = ExportSettings {
+            layout: Layout::Atlas { max_width: 100 },
+            ..ExportSettings::default()
+        };
+        let sheet = pack(&sprites, &settings);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&sheet.to_json("sheet.png").unwrap()).unwrap();
+
+        assert_eq!(
+            json["frames"][0],
+            serde_json::json!({
+                "filename": "trimmed",
+                "frame": { "x": 0, "y": 0, "w": 2, "h": 3 },
+                "rotated": false,
+                "trimmed": true,
+                "spriteSourceSize": { "x": 1, "y": 1, "w": 2, "h": 3 },
+                "sourceSize": { "w": 4, "h": 4 },
+            })
+        );
+        assert_eq!(
+            json["frames"][1],
+            serde_json::json!({
+                "filename": "full",
+                "frame": { "x": 2, "y": 0, "w": 2, "h": 2 },
+                "rotated": false,
+                "trimmed": false,
+                "spriteSourceSize": { "x": 0, "y": 0, "w": 2, "h": 2 },
+                "sourceSize": { "w": 2, "h": 2 },
+                "duration": 50,
+            })
+        );
+        assert_eq!(json["meta"]["image"], "sheet.png");
+        assert_eq!(json["meta"]["size"], serde_json::json!({ "w": 4, "h": 3 }));
+    }
+}
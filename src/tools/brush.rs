@@ -1,8 +1,16 @@
-use egui::{Context, InputState};
+use crate::{color_mode::ColorMode, document::Document, math, pixel_buffer::PixelBuffer};
 
-use crate::{math, pixel_buffer::PixelBuffer, ui::screen_to_image_coords, TrametesApp};
+use super::PointerEvent;
 
-fn apply_brush(pixels: &mut PixelBuffer, brush: &BrushSettings, pos: (f32, f32), color: &[u8; 4]) {
+fn apply_brush(
+    pixels: &mut PixelBuffer,
+    brush: &BrushSettings,
+    pos: (f32, f32),
+    color: &[u8; 4],
+    color_mode: &ColorMode,
+    linear_light: bool,
+) {
+    let color = color_mode.constrain(*color);
     let x1 = (pos.0 - brush.diameter / 2.0)
         .clamp(0.0, pixels.width as f32 - 1.0)
         .floor() as usize;
@@ -17,7 +25,7 @@ fn apply_brush(pixels: &mut PixelBuffer, brush: &BrushSettings, pos: (f32, f32),
         .ceil() as usize;
     let width = x2 - x1 + 1;
     let height = y2 - y1 + 1;
-    for ((r, g, b, _a), (col, row)) in pixels.iter_block_mut(x1, y1, width, height) {
+    for ((r, g, b, a), (col, row)) in pixels.iter_block_mut(x1, y1, width, height) {
         // The pixel is a 1x1 rectangle, so the percentage of it that is covered
         // by a circle is just the area of intersection divided by 1 pixel
         // squared
@@ -31,32 +39,53 @@ fn apply_brush(pixels: &mut PixelBuffer, brush: &BrushSettings, pos: (f32, f32),
         // TODO account for alpha
         assert_eq!(color[3], 255);
 
-        *r = math::lerp(percent_of_pixel_covered, *r as f32, color[0] as f32).round() as u8;
-        *g = math::lerp(percent_of_pixel_covered, *g as f32, color[1] as f32).round() as u8;
-        *b = math::lerp(percent_of_pixel_covered, *b as f32, color[2] as f32).round() as u8;
+        *r = math::lerp_srgb(percent_of_pixel_covered, *r, color[0], linear_light);
+        *g = math::lerp_srgb(percent_of_pixel_covered, *g, color[1], linear_light);
+        *b = math::lerp_srgb(percent_of_pixel_covered, *b, color[2], linear_light);
+
+        // Make sure the blended color can still be represented (for example, in
+        // an indexed image it needs to be a palette color)
+        [*r, *g, *b, *a] = color_mode.constrain([*r, *g, *b, *a]);
     }
 }
 
-pub fn handle_input(input: &InputState, app: &mut TrametesApp, ctx: &Context) {
-    if let Some(pos) = input.pointer.interact_pos() {
-        if input.pointer.any_down() {
-            let pixel_pos = screen_to_image_coords(
-                pos,
-                &app.image_relative_pos,
-                (app.image.width as f32, app.image.height as f32),
-                ctx.available_rect(),
-            );
-
-            apply_brush(
-                &mut app.image,
-                &app.tools.brush,
-                pixel_pos.into(),
-                &app.colors.primary,
-            )
+/// Paints wherever the pointer is held down, with each stroke (from press to
+/// release) undoable on its own
+pub fn handle_event(
+    event: PointerEvent,
+    document: &mut Document,
+    brush: &BrushSettings,
+    color: [u8; 4],
+    linear_light: bool,
+) {
+    let pixel_pos = match event {
+        PointerEvent::Press { pos } => {
+            // Each stroke can be undone separately
+            document.checkpoint("Brush Stroke");
+            pos
         }
-    }
+        PointerEvent::Drag { pos, .. } => {
+            document.mark_changed();
+            pos
+        }
+        PointerEvent::Release => return,
+    };
+
+    // Layers can be offset from the top-left of the image
+    let layer = document.image.active_mut();
+    let layer_pos = (pixel_pos.0 - layer.x as f32, pixel_pos.1 - layer.y as f32);
+
+    apply_brush(
+        &mut layer.pixels,
+        brush,
+        layer_pos,
+        &color,
+        &document.color_mode,
+        linear_light,
+    )
 }
 
+/// The settings for the brush tool
 #[derive(Debug)]
 pub struct BrushSettings {
     /// The diameter of the brush, in pixels
@@ -1,13 +1,15 @@
+//! The tools used to edit images (brush, pan, etc.), driven by pointer events
+//! rather than any particular UI's input, so they can be used (and tested)
+//! without a window
+
 mod brush;
 mod pan;
 
 use std::fmt::Display;
 
-use egui::{Context, InputState};
-
-use crate::TrametesApp;
+use crate::document::Document;
 
-use self::{brush::BrushSettings, pan::PanSettings};
+pub use self::{brush::BrushSettings, pan::PanSettings};
 
 /// A "tool" usable in the editor (brush, eraser, pan, shape)
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -33,20 +35,60 @@ impl Display for Tool {
     }
 }
 
-impl Tool {
-    /// Handles an input event (usually doing whatever the tool is meant to do)
-    pub fn handle_input(&self, input: &InputState, app: &mut TrametesApp, ctx: &Context) {
-        use Tool::*;
-        match self {
-            Pan => pan::handle_input(input, app, ctx),
-            Brush => brush::handle_input(input, app, ctx),
-        }
-    }
+/// Something the pointer (mouse, pen, finger) did over an image. Positions are
+/// in image pixel coordinates, with (0, 0) at the top-left corner of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointerEvent {
+    /// The pointer was pressed down
+    Press {
+        /// Where the pointer was pressed
+        pos: (f32, f32),
+    },
+
+    /// The pointer is still held down (sent repeatedly, even if it hasn't
+    /// moved)
+    Drag {
+        /// Where the pointer is
+        pos: (f32, f32),
+
+        /// How far the pointer moved since the last event, in screen points
+        /// (for tools that move the view rather than the pixels)
+        screen_delta: (f32, f32),
+    },
+
+    /// The pointer was let go
+    Release,
 }
 
+/// The current tool and the settings for every tool
 #[derive(Debug, Default)]
 pub struct ToolState {
+    /// The tool that pointer events go to
     pub current_tool: Tool,
+
+    /// The settings for the pan tool
+    #[allow(dead_code)] // The pan tool doesn't have any settings (yet)
     pub pan: PanSettings,
+
+    /// The settings for the brush tool
     pub brush: BrushSettings,
 }
+
+impl ToolState {
+    /// Lets the current tool handle a pointer event (usually doing whatever the
+    /// tool is meant to do to the document), drawing with the given color
+    pub fn handle_event(
+        &self,
+        event: PointerEvent,
+        document: &mut Document,
+        color: [u8; 4],
+        blend_in_linear_light: bool,
+    ) {
+        match self.current_tool {
+            Tool::Pan => pan::handle_event(event, document),
+            Tool::Brush => {
+                brush::handle_event(event, document, &self.brush, color, blend_in_linear_light)
+            }
+        }
+    }
+}
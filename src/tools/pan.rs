@@ -1,15 +1,15 @@
-use egui::{Context, InputState};
+use crate::document::Document;
 
-use crate::TrametesApp;
+use super::PointerEvent;
 
-pub fn handle_input(input: &InputState, app: &mut TrametesApp, _ctx: &Context) {
-    // TODO not do this janky dt hack to get around
-    // is_decidedly_dragging() not handling file -> open well
-    if input.pointer.is_decidedly_dragging() && input.unstable_dt < 1.0 {
-        app.image_relative_pos.x_translation += input.pointer.delta().x;
-        app.image_relative_pos.y_translation += input.pointer.delta().y;
-    };
+/// Moves the view of the image along with the pointer while it's dragged
+pub fn handle_event(event: PointerEvent, document: &mut Document) {
+    if let PointerEvent::Drag { screen_delta, .. } = event {
+        document.view.x_translation += screen_delta.0;
+        document.view.y_translation += screen_delta.1;
+    }
 }
 
+/// The settings for the pan tool
 #[derive(Debug, Default)]
 pub struct PanSettings {}
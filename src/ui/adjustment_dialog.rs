@@ -0,0 +1,585 @@
+use egui::{
+    pos2, vec2, Button, Color32, ComboBox, Context, Pos2, Rect, Sense, Shape, Slider, Stroke,
+    TextEdit, Ui, Window,
+};
+
+use crate::{
+    adjustments::{
+        Adjustment, AdjustmentKind, BrightnessContrast, Channel, ChannelLevels, ChannelMixer,
+        ColorBalance, Curve, Curves, DesaturateMethod, Histogram, HueRange, HueSaturation, Levels,
+        ToneRange,
+    },
+    commands,
+    document::Document,
+    pixel_buffer::PixelBuffer,
+    presets::Presets,
+    TrametesApp,
+};
+
+/// The width of the histogram and curve editor, in points (one per value)
+const GRAPH_WIDTH: f32 = 256.0;
+
+/// The height of the histogram in the Levels dialog, in points
+const HISTOGRAM_HEIGHT: f32 = 100.0;
+
+/// How close (in points) the pointer has to be to a point on a curve to grab
+/// it
+const CURVE_POINT_GRAB_RADIUS: f32 = 8.0;
+
+/// The active layer's pixels with an adjustment applied, along with
+/// everything they depend on
+#[derive(Debug)]
+struct Preview {
+    adjustment: Adjustment,
+    revision: u64,
+    frame: usize,
+    layer: usize,
+    pixels: PixelBuffer,
+}
+
+/// The state of an adjustment's dialog (like Levels)
+pub struct AdjustmentDialog {
+    /// The document being adjusted
+    document_id: u64,
+
+    /// The adjustment being made, with the settings chosen so far
+    adjustment: Adjustment,
+
+    /// The channel whose levels, curve, or mix is being edited
+    channel: Channel,
+
+    /// The range of hues whose hue, saturation, and lightness are being edited
+    hue_range: HueRange,
+
+    /// The range of tones whose color balance is being edited
+    tone_range: ToneRange,
+
+    /// How many pixels of the layer being adjusted have each value
+    histogram: Histogram,
+
+    /// The name to save the settings under as a preset
+    preset_name: String,
+
+    /// The point on the curve being dragged, if any
+    dragged_point: Option<usize>,
+
+    /// The last preview shown, so it's only worked out again when the
+    /// settings (or the layer being adjusted) change
+    preview: Option<Preview>,
+}
+
+impl std::fmt::Debug for AdjustmentDialog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Histograms are not very interesting to look at
+        f.debug_struct("AdjustmentDialog")
+            .field("document_id", &self.document_id)
+            .field("adjustment", &self.adjustment)
+            .field("channel", &self.channel)
+            .field("preset_name", &self.preset_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AdjustmentDialog {
+    /// Starts adjusting the active layer of a document, from the
+    /// adjustment's default settings
+    pub fn new(document: &Document, kind: AdjustmentKind) -> Self {
+        // Channels can only be mixed one at a time
+        let channel = match kind {
+            AdjustmentKind::ChannelMixer => Channel::Red,
+            _ => Channel::default(),
+        };
+
+        Self {
+            document_id: document.id,
+            adjustment: kind.defaults(),
+            channel,
+            hue_range: HueRange::default(),
+            tone_range: ToneRange::default(),
+            histogram: Histogram::new(&document.image.active().pixels),
+            preset_name: String::new(),
+            dragged_point: None,
+            preview: None,
+        }
+    }
+
+    /// The active layer's pixels with the adjustment applied, to preview on a
+    /// document, if this is the document being adjusted
+    pub fn preview_for(&mut self, document: &Document) -> Option<&PixelBuffer> {
+        if document.id != self.document_id {
+            return None;
+        }
+
+        let is_stale = self.preview.as_ref().is_none_or(|preview| {
+            preview.adjustment != self.adjustment
+                || preview.revision != document.revision
+                || preview.frame != document.animation.current_frame
+                || preview.layer != document.image.active_layer
+        });
+        if is_stale {
+            let mut pixels = document.image.active().pixels.clone();
+            self.adjustment.apply(&mut pixels, &document.color_mode);
+            self.preview = Some(Preview {
+                adjustment: self.adjustment.clone(),
+                revision: document.revision,
+                frame: document.animation.current_frame,
+                layer: document.image.active_layer,
+                pixels,
+            });
+        }
+
+        self.preview.as_ref().map(|preview| &preview.pixels)
+    }
+}
+
+/// Shows the dialog for the adjustment being made, if there is one. Until
+/// it's confirmed, the adjustment is only previewed on the canvas
+pub fn make_adjustment_dialog(app: &mut TrametesApp, ctx: &Context) {
+    let Some(dialog) = &mut app.adjustment else {
+        return;
+    };
+
+    // (The document could have been closed in the meantime)
+    let is_open = std::iter::once(&app.document)
+        .chain(&app.tabs.documents)
+        .any(|document| document.id == dialog.document_id);
+    if !is_open {
+        app.adjustment = None;
+        return;
+    }
+
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    Window::new(dialog.adjustment.kind().to_string())
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            make_presets_row(ui, dialog, &mut app.adjustment_presets);
+            ui.separator();
+
+            match &mut dialog.adjustment {
+                Adjustment::BrightnessContrast(settings) => {
+                    edit_brightness_contrast(ui, settings);
+                }
+                Adjustment::Levels(levels) => {
+                    edit_levels(ui, levels, &mut dialog.channel, &dialog.histogram);
+                }
+                Adjustment::Curves(curves) => edit_curves(
+                    ui,
+                    curves,
+                    &mut dialog.channel,
+                    &dialog.histogram,
+                    &mut dialog.dragged_point,
+                ),
+                Adjustment::HueSaturation(settings) => {
+                    edit_hue_saturation(ui, settings, &mut dialog.hue_range);
+                }
+                Adjustment::ColorBalance(settings) => {
+                    edit_color_balance(ui, settings, &mut dialog.tone_range);
+                }
+                Adjustment::ChannelMixer(mixer) => {
+                    edit_channel_mixer(ui, mixer, &mut dialog.channel);
+                }
+                Adjustment::Vibrance(amount) => {
+                    ui.add(Slider::new(amount, -1.0..=1.0).text("Vibrance"));
+                }
+                Adjustment::Invert => {
+                    ui.label("Swaps every color for its opposite");
+                }
+                Adjustment::Desaturate(method) => {
+                    for option in DesaturateMethod::ALL {
+                        ui.radio_value(method, option, option.to_string());
+                    }
+                }
+                Adjustment::Sepia(amount) => {
+                    ui.add(Slider::new(amount, 0.0..=1.0).text("Amount"));
+                }
+                Adjustment::Posterize(levels) => {
+                    ui.add(Slider::new(levels, 2..=64).text("Levels"));
+                }
+                Adjustment::Threshold(level) => {
+                    make_histogram(
+                        ui,
+                        dialog.histogram.counts(Channel::Rgb),
+                        channel_color(Channel::Rgb),
+                        &[*level],
+                    );
+                    ui.add(Slider::new(level, 0..=255).text("Level"));
+                }
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                confirmed = ui.button("OK").clicked();
+                cancelled = ui.button("Cancel").clicked();
+                if ui.button("Reset").clicked() {
+                    dialog.adjustment = dialog.adjustment.kind().defaults();
+                }
+            });
+        });
+
+    if confirmed {
+        if let Some(dialog) = app.adjustment.take() {
+            commands::adjust(app, dialog.document_id, &dialog.adjustment);
+        }
+    } else if cancelled {
+        app.adjustment = None;
+    }
+}
+
+/// Makes the controls for choosing, saving, and deleting presets
+fn make_presets_row(ui: &mut Ui, dialog: &mut AdjustmentDialog, presets: &mut Presets) {
+    let kind = dialog.adjustment.kind();
+
+    ComboBox::from_label("Preset")
+        .selected_text(if dialog.preset_name.is_empty() {
+            "Custom"
+        } else {
+            &dialog.preset_name
+        })
+        .show_ui(ui, |ui| {
+            for preset in presets.of_kind(kind) {
+                if ui
+                    .selectable_label(dialog.preset_name == preset.name, &preset.name)
+                    .clicked()
+                {
+                    dialog.adjustment = preset.adjustment.clone();
+                    dialog.preset_name = preset.name.clone();
+                }
+            }
+        });
+
+    ui.horizontal(|ui| {
+        ui.add(
+            TextEdit::singleline(&mut dialog.preset_name)
+                .hint_text("Preset name")
+                .desired_width(120.0),
+        );
+
+        let name = dialog.preset_name.trim().to_string();
+        if ui
+            .add_enabled(!name.is_empty(), Button::new("Save Preset"))
+            .clicked()
+        {
+            presets.add(&name, dialog.adjustment.clone());
+            dialog.preset_name = name.clone();
+        }
+
+        let exists = presets.of_kind(kind).any(|preset| preset.name == name);
+        if ui
+            .add_enabled(exists, Button::new("Delete Preset"))
+            .clicked()
+        {
+            presets.remove(kind, &name);
+            dialog.preset_name.clear();
+        }
+    });
+}
+
+/// Makes the controls for choosing which channel's levels, curve, or mix to
+/// edit
+fn pick_channel(ui: &mut Ui, label: &str, channel: &mut Channel, options: &[Channel]) {
+    ComboBox::from_label(label)
+        .selected_text(channel.to_string())
+        .show_ui(ui, |ui| {
+            for &option in options {
+                ui.selectable_value(channel, option, option.to_string());
+            }
+        });
+}
+
+/// The color to draw a channel's histogram and curve in
+fn channel_color(channel: Channel) -> Color32 {
+    match channel {
+        Channel::Rgb => Color32::LIGHT_GRAY,
+        Channel::Red => Color32::from_rgb(230, 80, 80),
+        Channel::Green => Color32::from_rgb(80, 200, 80),
+        Channel::Blue => Color32::from_rgb(90, 130, 240),
+    }
+}
+
+/// Draws a histogram of a channel's values filling a rect, scaled so the most
+/// common value reaches the top
+fn draw_histogram(ui: &Ui, rect: Rect, counts: &[u32; 256], color: Color32) {
+    let painter = ui.painter_at(rect);
+    let most = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let column_width = rect.width() / counts.len() as f32;
+    for (value, &count) in counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let x = rect.left() + (value as f32 + 0.5) * column_width;
+        let height = count as f32 / most * rect.height();
+        painter.line_segment(
+            [pos2(x, rect.bottom()), pos2(x, rect.bottom() - height)],
+            Stroke::new(column_width, color),
+        );
+    }
+}
+
+/// Shows a histogram of a channel's values, with lines marking some of them
+/// (like the black and white points)
+fn make_histogram(ui: &mut Ui, counts: &[u32; 256], color: Color32, markers: &[u8]) {
+    let (rect, _) = ui.allocate_exact_size(vec2(GRAPH_WIDTH, HISTOGRAM_HEIGHT), Sense::hover());
+    ui.painter()
+        .rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+    draw_histogram(ui, rect, counts, color);
+
+    for &value in markers {
+        let x = rect.left() + (value as f32 + 0.5) / 256.0 * rect.width();
+        ui.painter().vline(
+            x,
+            rect.y_range(),
+            Stroke::new(1.0, ui.visuals().strong_text_color()),
+        );
+    }
+}
+
+/// Makes the controls of the Brightness/Contrast dialog
+fn edit_brightness_contrast(ui: &mut Ui, settings: &mut BrightnessContrast) {
+    ui.add(Slider::new(&mut settings.brightness, -1.0..=1.0).text("Brightness"));
+    ui.add(Slider::new(&mut settings.contrast, -1.0..=1.0).text("Contrast"));
+}
+
+/// Makes the controls of the Levels dialog, for one channel at a time
+fn edit_levels(ui: &mut Ui, levels: &mut Levels, channel: &mut Channel, histogram: &Histogram) {
+    pick_channel(ui, "Channel", channel, &Channel::ALL);
+
+    let levels = levels.get_mut(*channel);
+    make_histogram(
+        ui,
+        histogram.counts(*channel),
+        channel_color(*channel),
+        &[levels.input_black, levels.input_white],
+    );
+    edit_channel_levels(ui, levels);
+}
+
+/// Makes the sliders for the levels of a single channel
+fn edit_channel_levels(ui: &mut Ui, levels: &mut ChannelLevels) {
+    ui.label("Input");
+    ui.add(Slider::new(&mut levels.input_black, 0..=255).text("Black"));
+    ui.add(
+        Slider::new(&mut levels.gamma, ChannelLevels::GAMMA_RANGE)
+            .logarithmic(true)
+            .text("Gamma"),
+    );
+    ui.add(Slider::new(&mut levels.input_white, 0..=255).text("White"));
+
+    // Keep the black point below the white point
+    levels.input_white = levels.input_white.max(levels.input_black.saturating_add(1));
+    levels.input_black = levels.input_black.min(levels.input_white - 1);
+
+    ui.label("Output");
+    ui.add(Slider::new(&mut levels.output_black, 0..=255).text("Black"));
+    ui.add(Slider::new(&mut levels.output_white, 0..=255).text("White"));
+}
+
+/// Makes the controls of the Curves dialog, for one channel at a time
+fn edit_curves(
+    ui: &mut Ui,
+    curves: &mut Curves,
+    channel: &mut Channel,
+    histogram: &Histogram,
+    dragged_point: &mut Option<usize>,
+) {
+    ui.horizontal(|ui| {
+        pick_channel(ui, "Channel", channel, &Channel::ALL);
+        if ui.button("Reset Channel").clicked() {
+            *curves.get_mut(*channel) = Curve::default();
+        }
+    });
+
+    let (rect, response) =
+        ui.allocate_exact_size(vec2(GRAPH_WIDTH, GRAPH_WIDTH), Sense::click_and_drag());
+    ui.painter()
+        .rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+    draw_histogram(
+        ui,
+        rect,
+        histogram.counts(*channel),
+        ui.visuals().faint_bg_color,
+    );
+
+    let curve = curves.get_mut(*channel);
+    edit_curve_points(&response, rect, curve, dragged_point);
+    draw_curve(ui, rect, curve, channel_color(*channel));
+
+    ui.weak("Click to add a point, drag to move it, and right-click to remove it");
+}
+
+/// Makes the controls of the Hue/Saturation dialog, for one range of hues at a
+/// time
+fn edit_hue_saturation(ui: &mut Ui, settings: &mut HueSaturation, range: &mut HueRange) {
+    ComboBox::from_label("Range")
+        .selected_text(range.to_string())
+        .show_ui(ui, |ui| {
+            for option in HueRange::ALL {
+                ui.selectable_value(range, option, option.to_string());
+            }
+        });
+
+    let shift = settings.get_mut(*range);
+    ui.add(
+        Slider::new(&mut shift.hue, -180.0..=180.0)
+            .suffix("°")
+            .text("Hue"),
+    );
+    ui.add(Slider::new(&mut shift.saturation, -1.0..=1.0).text("Saturation"));
+    ui.add(Slider::new(&mut shift.lightness, -1.0..=1.0).text("Lightness"));
+}
+
+/// Makes the controls of the Color Balance dialog, for one range of tones at a
+/// time
+fn edit_color_balance(ui: &mut Ui, settings: &mut ColorBalance, range: &mut ToneRange) {
+    ui.horizontal(|ui| {
+        for option in ToneRange::ALL {
+            ui.radio_value(range, option, option.to_string());
+        }
+    });
+
+    let shift = settings.get_mut(*range);
+    for (amount, name) in shift
+        .iter_mut()
+        .zip(["Cyan / Red", "Magenta / Green", "Yellow / Blue"])
+    {
+        ui.add(Slider::new(amount, -1.0..=1.0).text(name));
+    }
+
+    ui.checkbox(&mut settings.preserve_luminosity, "Preserve luminosity");
+}
+
+/// Makes the controls of the Channel Mixer dialog, for one output channel at a
+/// time
+fn edit_channel_mixer(ui: &mut Ui, mixer: &mut ChannelMixer, channel: &mut Channel) {
+    let outputs = [Channel::Red, Channel::Green, Channel::Blue];
+    pick_channel(ui, "Output channel", channel, &outputs);
+
+    for (amount, source) in mixer.get_mut(*channel).iter_mut().zip(outputs) {
+        ui.add(Slider::new(amount, -2.0..=2.0).text(source.to_string()));
+    }
+}
+
+/// Where a point on a curve (input value, output value) is drawn in the curve
+/// editor
+fn curve_to_screen(rect: Rect, [input, output]: [u8; 2]) -> Pos2 {
+    pos2(
+        rect.left() + input as f32 / 255.0 * rect.width(),
+        rect.bottom() - output as f32 / 255.0 * rect.height(),
+    )
+}
+
+/// The point on a curve (input value, output value) under a position in the
+/// curve editor
+fn screen_to_curve(rect: Rect, pos: Pos2) -> [u8; 2] {
+    let input = (pos.x - rect.left()) / rect.width();
+    let output = (rect.bottom() - pos.y) / rect.height();
+    [input, output].map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// The index of the point on a curve closest to a position in the curve
+/// editor, if one is close enough to grab
+fn grabbed_point(rect: Rect, curve: &Curve, pos: Pos2) -> Option<usize> {
+    curve
+        .points
+        .iter()
+        .enumerate()
+        .map(|(index, &point)| (index, curve_to_screen(rect, point).distance(pos)))
+        .filter(|&(_, distance)| distance <= CURVE_POINT_GRAB_RADIUS)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index)
+}
+
+/// Adds a point to a curve (keeping the points in order), unless there's
+/// already one with the same input value. Returns the index of the point
+fn add_curve_point(curve: &mut Curve, point: [u8; 2]) -> Option<usize> {
+    let index = curve
+        .points
+        .partition_point(|existing| existing[0] < point[0]);
+    if curve
+        .points
+        .get(index)
+        .is_some_and(|existing| existing[0] == point[0])
+    {
+        return None;
+    }
+    curve.points.insert(index, point);
+    Some(index)
+}
+
+/// Lets the user add, drag, and remove the points of a curve
+fn edit_curve_points(
+    response: &egui::Response,
+    rect: Rect,
+    curve: &mut Curve,
+    dragged_point: &mut Option<usize>,
+) {
+    let Some(pos) = response.interact_pointer_pos() else {
+        return;
+    };
+
+    if response.secondary_clicked() {
+        // Curves need at least two points
+        if let Some(index) = grabbed_point(rect, curve, pos) {
+            if curve.points.len() > 2 {
+                curve.points.remove(index);
+            }
+        }
+    } else if response.drag_started() {
+        *dragged_point = grabbed_point(rect, curve, pos)
+            .or_else(|| add_curve_point(curve, screen_to_curve(rect, pos)));
+    } else if response.clicked() && grabbed_point(rect, curve, pos).is_none() {
+        add_curve_point(curve, screen_to_curve(rect, pos));
+    }
+
+    if response.dragged() {
+        if let Some(index) = dragged_point.filter(|&index| index < curve.points.len()) {
+            // Points can't be dragged past their neighbors
+            let [input, output] = screen_to_curve(rect, pos);
+            let lowest = match index {
+                0 => 0,
+                _ => curve.points[index - 1][0].saturating_add(1),
+            };
+            let highest = match curve.points.get(index + 1) {
+                Some(next) => next[0].saturating_sub(1),
+                None => 255,
+            };
+            curve.points[index] = [input.clamp(lowest, highest), output];
+        }
+    }
+    if response.drag_released() {
+        *dragged_point = None;
+    }
+}
+
+/// Draws a curve and its points in the curve editor
+fn draw_curve(ui: &Ui, rect: Rect, curve: &Curve, color: Color32) {
+    let painter = ui.painter_at(rect);
+
+    // A diagonal line for reference, and a grid of quarters
+    let grid = Stroke::new(1.0, ui.visuals().widgets.noninteractive.bg_stroke.color);
+    for quarter in 1..4 {
+        let offset = quarter as f32 / 4.0;
+        painter.vline(rect.left() + rect.width() * offset, rect.y_range(), grid);
+        painter.hline(rect.x_range(), rect.top() + rect.height() * offset, grid);
+    }
+    painter.line_segment([rect.left_bottom(), rect.right_top()], grid);
+
+    let points = curve
+        .values()
+        .iter()
+        .enumerate()
+        .map(|(input, &output)| {
+            pos2(
+                rect.left() + input as f32 / 255.0 * rect.width(),
+                rect.bottom() - output * rect.height(),
+            )
+        })
+        .collect();
+    painter.add(Shape::line(points, Stroke::new(2.0, color)));
+
+    for &point in &curve.points {
+        painter.circle_filled(curve_to_screen(rect, point), 4.0, color);
+    }
+}
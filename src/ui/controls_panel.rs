@@ -1,4 +1,3 @@
-use eframe::Frame;
 use egui::{Context, DragValue, TopBottomPanel, Ui};
 
 use crate::{tools::Tool, TrametesApp};
@@ -16,10 +15,10 @@ fn brush_controls(ui: &mut Ui, app: &mut TrametesApp) {
 }
 
 /// Make the controls panel (across the top, just below the menu bar)
-pub fn make_controls_panel(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame) {
-    let top_controls_panel_min_height = frame.info().window_info.size.y * 0.05;
-    let top_controls_panel_max_height = frame.info().window_info.size.y * 0.50;
-    let top_controls_panel_default_height = frame.info().window_info.size.y * 0.08;
+pub fn make_controls_panel(app: &mut TrametesApp, ctx: &Context) {
+    let top_controls_panel_min_height = ctx.screen_rect().height() * 0.05;
+    let top_controls_panel_max_height = ctx.screen_rect().height() * 0.50;
+    let top_controls_panel_default_height = ctx.screen_rect().height() * 0.08;
     TopBottomPanel::top("top_controls_panel")
         .resizable(true)
         .min_height(top_controls_panel_min_height)
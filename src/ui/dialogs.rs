@@ -0,0 +1,1058 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use eframe::Frame;
+use egui::{
+    Align2, ComboBox, Context, DragValue, Image, ProgressBar, Slider, Spinner, TextureHandle,
+    TextureOptions, Ui, Window,
+};
+use image::ImageFormat;
+
+use crate::{
+    animation::{FlatFrame, LoopCount},
+    app::PendingClose,
+    color_mode::MAX_PALETTE_SIZE,
+    commands,
+    error::Error,
+    export::{self, ChromaSubsampling, ExportOptions, PngCompression},
+    fileio::{self, LoadedImage},
+    pixel_buffer::PixelBuffer,
+    quantize::{Dithering, QuantizationMethod},
+    recovery,
+    sprite_sheet::{self, Grid, Layout, SpriteSheet, SpriteSource},
+    TrametesApp,
+};
+
+use super::{adjustment_dialog, edit_loop_count, to_display_image};
+
+/// The largest width or height of the before/after previews in the Export
+/// dialog
+const EXPORT_PREVIEW_SIZE: f32 = 256.0;
+
+/// How long the export options have to stay the same before the image is
+/// encoded again to estimate its size, in seconds
+const EXPORT_PREVIEW_DELAY: f64 = 0.3;
+
+/// The number given to the next export preview, so that results for previews
+/// that are no longer wanted can be told apart
+static NEXT_EXPORT_PREVIEW: AtomicU64 = AtomicU64::new(0);
+
+/// Everything chosen in the Export dialog that affects the encoded image
+#[derive(Debug, Clone, PartialEq)]
+struct ExportChoices {
+    /// The options for the format
+    options: ExportOptions,
+
+    /// The color to fill transparent areas with
+    matte: [u8; 3],
+
+    /// How many times the animation plays
+    loop_count: LoopCount,
+}
+
+/// The result of encoding the image with a particular set of choices
+struct ExportPreview {
+    /// What the image was encoded with
+    choices: ExportChoices,
+
+    /// The encoded image, or why it couldn't be encoded
+    encoded: Result<Vec<u8>, String>,
+
+    /// What the encoded image looks like, for lossy formats (until it's been
+    /// turned into a texture)
+    decoded: Option<PixelBuffer>,
+
+    /// What the encoded image looks like, for lossy formats
+    after: Option<TextureHandle>,
+}
+
+/// The state of the Export dialog
+pub struct ExportDialog {
+    /// Where the image will be exported to
+    path: PathBuf,
+
+    /// The format the image will be exported in
+    format: ImageFormat,
+
+    /// The options being chosen (starting from the last-used options)
+    options: ExportOptions,
+
+    /// The (flattened) frames of the image being exported (just the one for
+    /// still images)
+    frames: Arc<Vec<FlatFrame>>,
+
+    /// The frame being edited, which is the one exported to formats that
+    /// can't store animations
+    current_frame: usize,
+
+    /// How many times the animation plays, for animated formats
+    loop_count: LoopCount,
+
+    /// The color to fill transparent areas with, if the format can't store
+    /// transparency
+    matte: [u8; 3],
+
+    /// What the image looks like before exporting, for lossy formats
+    before: Option<TextureHandle>,
+
+    /// The image encoded with recently chosen options (which may not be the
+    /// current ones yet)
+    preview: Option<ExportPreview>,
+
+    /// The preview being encoded in the background, if any, and what it's
+    /// being encoded with
+    encoding: Option<(u64, ExportChoices)>,
+
+    /// The choices as of the last frame, and when they were last changed
+    changed: (ExportChoices, f64),
+}
+
+impl std::fmt::Debug for ExportDialog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Textures and encoded images are not very interesting to look at
+        f.debug_struct("ExportDialog")
+            .field("path", &self.path)
+            .field("format", &self.format)
+            .field("options", &self.options)
+            .field("matte", &self.matte)
+            .field("loop_count", &self.loop_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ExportDialog {
+    pub fn new(
+        path: PathBuf,
+        format: ImageFormat,
+        frames: Vec<FlatFrame>,
+        current_frame: usize,
+        loop_count: LoopCount,
+        options: ExportOptions,
+        matte: [u8; 3],
+    ) -> Self {
+        let choices = ExportChoices {
+            options: options.clone(),
+            matte,
+            loop_count,
+        };
+
+        Self {
+            path,
+            format,
+            options,
+            frames: Arc::new(frames),
+            current_frame,
+            loop_count,
+            matte,
+            before: None,
+            preview: None,
+            encoding: None,
+            // Estimate the size of the first choices right away
+            changed: (choices, f64::NEG_INFINITY),
+        }
+    }
+
+    /// Everything currently chosen that affects the encoded image
+    fn choices(&self) -> ExportChoices {
+        ExportChoices {
+            options: self.options.clone(),
+            matte: self.matte,
+            loop_count: self.loop_count,
+        }
+    }
+
+    /// Shows the result of encoding a preview in the background, unless it's
+    /// no longer wanted
+    pub fn finish_preview(
+        &mut self,
+        request: u64,
+        result: Result<(Vec<u8>, Option<PixelBuffer>), Error>,
+    ) {
+        let Some((_, choices)) = self.encoding.take_if(|(encoding, _)| *encoding == request) else {
+            return;
+        };
+
+        let (encoded, decoded) = match result {
+            Ok((bytes, decoded)) => (Ok(bytes), decoded),
+            Err(err) => (Err(err.to_string()), None),
+        };
+        self.preview = Some(ExportPreview {
+            choices,
+            encoded,
+            decoded,
+            after: None,
+        });
+    }
+
+    /// Whether every frame will be exported (rather than just the current one)
+    fn is_animated(&self) -> bool {
+        self.frames.len() > 1 && fileio::supports_animation(self.format)
+    }
+
+    /// The frame that's previewed (and the only one exported, if the format
+    /// can't store animations)
+    fn image(&self) -> &PixelBuffer {
+        &self.frames[self.image_index()].pixels
+    }
+
+    /// The index of the frame that's previewed
+    fn image_index(&self) -> usize {
+        // Decoding an animation only gives back its first frame, so that's
+        // the one to compare against
+        if self.is_animated() {
+            0
+        } else {
+            self.current_frame
+        }
+    }
+}
+
+/// Shows the options for the format being exported to
+fn make_export_options(ui: &mut Ui, format: ImageFormat, options: &mut ExportOptions) {
+    match format {
+        ImageFormat::Png => {
+            ComboBox::from_label("Compression")
+                .selected_text(options.png.compression.to_string())
+                .show_ui(ui, |ui| {
+                    for compression in [
+                        PngCompression::Fast,
+                        PngCompression::Default,
+                        PngCompression::Best,
+                    ] {
+                        ui.selectable_value(
+                            &mut options.png.compression,
+                            compression,
+                            compression.to_string(),
+                        );
+                    }
+                });
+        }
+        ImageFormat::Jpeg => {
+            ui.add(Slider::new(&mut options.jpeg.quality, 1..=100).text("Quality"));
+
+            ComboBox::from_label("Chroma subsampling")
+                .selected_text(options.jpeg.chroma_subsampling.to_string())
+                .show_ui(ui, |ui| {
+                    for subsampling in ChromaSubsampling::ALL {
+                        ui.selectable_value(
+                            &mut options.jpeg.chroma_subsampling,
+                            subsampling,
+                            subsampling.to_string(),
+                        );
+                    }
+                });
+        }
+        ImageFormat::WebP if !export::LOSSY_WEBP => {
+            ui.label("WebP files are always lossless on the web");
+        }
+        ImageFormat::WebP => {
+            ui.checkbox(&mut options.webp.lossless, "Lossless");
+            ui.add_enabled(
+                !options.webp.lossless,
+                Slider::new(&mut options.webp.quality, 0..=100).text("Quality"),
+            );
+        }
+        _ => {
+            ui.label("This format has no options");
+        }
+    }
+}
+
+/// Shows an image scaled down to fit in the preview area
+fn make_preview_image(ui: &mut Ui, label: &str, texture: &TextureHandle) {
+    let size = texture.size_vec2();
+    let scale = (EXPORT_PREVIEW_SIZE / size.max_elem()).min(1.0);
+
+    ui.vertical(|ui| {
+        ui.label(label);
+        ui.add(Image::new(texture, size * scale));
+    });
+}
+
+/// Makes the "Export" dialog, if it's open
+fn make_export_dialog(app: &mut TrametesApp, ctx: &Context) {
+    let Some(mut dialog) = app.export.take() else {
+        return;
+    };
+
+    // Re-encode (in the background) once the choices stop changing, to keep
+    // the size estimate (and preview) up to date
+    let choices = dialog.choices();
+    let now = ctx.input(|input| input.time);
+    if dialog.changed.0 != choices {
+        dialog.changed = (choices.clone(), now);
+    }
+
+    let up_to_date = dialog
+        .preview
+        .as_ref()
+        .is_some_and(|preview| preview.choices == choices);
+    if !up_to_date && dialog.encoding.is_none() {
+        let remaining = dialog.changed.1 + EXPORT_PREVIEW_DELAY - now;
+        if remaining <= 0.0 {
+            start_export_preview(app, &mut dialog, choices.clone());
+        } else {
+            ctx.request_repaint_after(Duration::from_secs_f64(remaining));
+        }
+    }
+
+    if let Some(preview) = &mut dialog.preview {
+        if let Some(decoded) = preview.decoded.take() {
+            preview.after = Some(ctx.load_texture(
+                "export preview after",
+                to_display_image(&decoded, app.document.color_profile.as_ref()),
+                TextureOptions::default(),
+            ));
+        }
+    }
+
+    if dialog.before.is_none() && dialog.options.is_lossy(dialog.format) {
+        dialog.before = Some(ctx.load_texture(
+            "export preview before",
+            to_display_image(dialog.image(), app.document.color_profile.as_ref()),
+            TextureOptions::default(),
+        ));
+    }
+
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    Window::new("Export")
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let file_name = dialog
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            ui.label(format!("Exporting to {file_name} ({:?})", dialog.format));
+
+            ui.separator();
+            make_export_options(ui, dialog.format, &mut dialog.options);
+
+            if dialog.is_animated() {
+                ui.label(format!(
+                    "All {} frames will be exported",
+                    dialog.frames.len()
+                ));
+                edit_loop_count(ui, &mut dialog.loop_count);
+            } else if dialog.frames.len() > 1 {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!(
+                        "{:?} files can't store animations, so only the current frame will be \
+                        exported",
+                        dialog.format
+                    ),
+                );
+            }
+
+            if fileio::needs_matte(dialog.image(), dialog.format) {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!(
+                        "{:?} files can't store transparency, so transparent areas will be \
+                        filled with the matte color",
+                        dialog.format
+                    ),
+                );
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_srgb(&mut dialog.matte);
+                    ui.label("Matte color");
+                });
+            }
+
+            ui.separator();
+
+            let preview = dialog.preview.as_ref();
+            ui.horizontal(|ui| {
+                match preview.map(|preview| &preview.encoded) {
+                    Some(Ok(bytes)) => {
+                        ui.label(format!(
+                            "Estimated file size: {}",
+                            export::format_file_size(bytes.len())
+                        ));
+                    }
+                    Some(Err(err)) => {
+                        ui.colored_label(
+                            ui.visuals().error_fg_color,
+                            format!("Can't export: {err}"),
+                        );
+                    }
+                    None => {
+                        ui.label("Estimating file size…");
+                    }
+                }
+                if !up_to_date {
+                    ui.add(Spinner::new());
+                }
+            });
+
+            let after = preview.and_then(|preview| preview.after.as_ref());
+            if let (Some(before), Some(after)) = (&dialog.before, after) {
+                ui.horizontal(|ui| {
+                    make_preview_image(ui, "Before", before);
+                    make_preview_image(ui, "After", after);
+                });
+            }
+
+            // Only export what was actually previewed (and successfully
+            // encoded) with the current choices
+            let can_export = up_to_date && preview.is_some_and(|preview| preview.encoded.is_ok());
+            ui.horizontal(|ui| {
+                confirmed = ui
+                    .add_enabled(can_export, egui::Button::new("Export"))
+                    .clicked();
+                cancelled = ui.button("Cancel").clicked();
+            });
+        });
+
+    if confirmed {
+        let animated = dialog.is_animated();
+        if let Some(ExportPreview {
+            choices,
+            encoded: Ok(bytes),
+            ..
+        }) = dialog.preview
+        {
+            if animated && app.document.animation.loop_count != choices.loop_count {
+                app.document.checkpoint("Loop Count");
+                app.document.animation.loop_count = choices.loop_count;
+            }
+
+            commands::finish_export(app, &dialog.path, &bytes, choices.options);
+        }
+    } else if !cancelled {
+        app.export = Some(dialog);
+    }
+}
+
+/// Starts encoding the image in the background with the given choices, to
+/// estimate the exported file size (and show what lossy formats look like)
+fn start_export_preview(app: &mut TrametesApp, dialog: &mut ExportDialog, choices: ExportChoices) {
+    let request = NEXT_EXPORT_PREVIEW.fetch_add(1, Ordering::Relaxed);
+    dialog.encoding = Some((request, choices.clone()));
+
+    let frames = Arc::clone(&dialog.frames);
+    let index = dialog.image_index();
+    let animated = dialog.is_animated();
+    let format = dialog.format;
+    let color_profile = app.document.color_profile.clone();
+    let color_mode = app.document.color_mode.clone();
+    let metadata = app.document.metadata.clone();
+
+    app.jobs.encode_export_preview(request, move || {
+        let bytes = if animated {
+            fileio::encode_animation(
+                &frames,
+                choices.loop_count,
+                format,
+                color_profile.as_ref(),
+                &color_mode,
+                &metadata,
+                &choices.options,
+            )
+        } else {
+            fileio::encode_image(
+                &frames[index].pixels,
+                format,
+                color_profile.as_ref(),
+                &color_mode,
+                &metadata,
+                &choices.options,
+                choices.matte,
+            )
+        }?;
+
+        let decoded = if choices.options.is_lossy(format) {
+            fileio::decode_image(&bytes)
+                .inspect_err(|err| eprintln!("failed to decode export preview: {err:?}"))
+                .ok()
+        } else {
+            None
+        };
+
+        Ok((bytes, decoded))
+    });
+}
+
+/// Makes the "Convert to Indexed" dialog, if it's open
+fn make_indexed_conversion_dialog(app: &mut TrametesApp, ctx: &Context) {
+    let Some(settings) = &mut app.indexed_conversion else {
+        return;
+    };
+
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    Window::new("Convert to Indexed")
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.add(
+                DragValue::new(&mut settings.max_colors)
+                    .clamp_range(2..=MAX_PALETTE_SIZE)
+                    .prefix("Colors: "),
+            );
+
+            ComboBox::from_label("Palette")
+                .selected_text(settings.method.to_string())
+                .show_ui(ui, |ui| {
+                    for method in [QuantizationMethod::MedianCut, QuantizationMethod::Octree] {
+                        ui.selectable_value(&mut settings.method, method, method.to_string());
+                    }
+                });
+
+            ComboBox::from_label("Dithering")
+                .selected_text(settings.dithering.to_string())
+                .show_ui(ui, |ui| {
+                    for dithering in [
+                        Dithering::None,
+                        Dithering::FloydSteinberg,
+                        Dithering::Ordered,
+                    ] {
+                        ui.selectable_value(
+                            &mut settings.dithering,
+                            dithering,
+                            dithering.to_string(),
+                        );
+                    }
+                });
+
+            ui.horizontal(|ui| {
+                confirmed = ui.button("OK").clicked();
+                cancelled = ui.button("Cancel").clicked();
+            });
+        });
+
+    if confirmed {
+        if let Some(settings) = app.indexed_conversion.take() {
+            commands::convert_to_indexed(app, &settings);
+        }
+    } else if cancelled {
+        app.indexed_conversion = None;
+    }
+}
+
+/// The state of the Import Sprite Sheet dialog
+#[derive(Debug)]
+pub struct SpriteSheetImportDialog {
+    /// The sprite sheet being split up
+    sheet: LoadedImage,
+
+    /// The options being chosen
+    settings: sprite_sheet::ImportSettings,
+}
+
+impl SpriteSheetImportDialog {
+    pub fn new(sheet: LoadedImage) -> Self {
+        Self {
+            sheet,
+            settings: sprite_sheet::ImportSettings::default(),
+        }
+    }
+}
+
+/// Makes the "Import Sprite Sheet" dialog, if it's open
+fn make_sprite_sheet_import_dialog(app: &mut TrametesApp, ctx: &Context) {
+    let Some(dialog) = &mut app.sprite_sheet_import else {
+        return;
+    };
+    let settings = &mut dialog.settings;
+
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    Window::new("Import Sprite Sheet")
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let mut by_size = matches!(settings.grid, Grid::CellSize { .. });
+                if ui.radio_value(&mut by_size, true, "Cell size").changed() {
+                    settings.grid = Grid::CellSize {
+                        width: 32,
+                        height: 32,
+                    };
+                }
+                if ui
+                    .radio_value(&mut by_size, false, "Columns and rows")
+                    .changed()
+                {
+                    settings.grid = Grid::Count {
+                        columns: 4,
+                        rows: 4,
+                    };
+                }
+            });
+
+            ui.horizontal(|ui| match &mut settings.grid {
+                Grid::CellSize { width, height } => {
+                    ui.add(
+                        DragValue::new(width)
+                            .clamp_range(1..=4096)
+                            .prefix("Width: "),
+                    );
+                    ui.add(
+                        DragValue::new(height)
+                            .clamp_range(1..=4096)
+                            .prefix("Height: "),
+                    );
+                }
+                Grid::Count { columns, rows } => {
+                    ui.add(
+                        DragValue::new(columns)
+                            .clamp_range(1..=1024)
+                            .prefix("Columns: "),
+                    );
+                    ui.add(DragValue::new(rows).clamp_range(1..=1024).prefix("Rows: "));
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.add(DragValue::new(&mut settings.margin).prefix("Margin: "));
+                ui.add(DragValue::new(&mut settings.spacing).prefix("Spacing: "));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Import as");
+                for source in [SpriteSource::Frames, SpriteSource::Layers] {
+                    ui.radio_value(&mut settings.import_as, source, source.to_string());
+                }
+            });
+            ui.checkbox(&mut settings.skip_empty, "Skip empty cells");
+
+            ui.separator();
+
+            let cells = settings.cells(dialog.sheet.image.width, dialog.sheet.image.height);
+            match cells.first() {
+                Some(cell) => ui.label(format!(
+                    "{} cells of {} x {} pixels",
+                    cells.len(),
+                    cell.width,
+                    cell.height
+                )),
+                None => ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    "The cells don't fit in the sprite sheet",
+                ),
+            };
+
+            ui.horizontal(|ui| {
+                confirmed = ui
+                    .add_enabled(!cells.is_empty(), egui::Button::new("OK"))
+                    .clicked();
+                cancelled = ui.button("Cancel").clicked();
+            });
+        });
+
+    if confirmed {
+        if let Some(dialog) = app.sprite_sheet_import.take() {
+            commands::finish_sprite_sheet_import(app, dialog.sheet, &dialog.settings);
+        }
+    } else if cancelled {
+        app.sprite_sheet_import = None;
+    }
+}
+
+/// The state of the Export Sprite Sheet dialog
+#[derive(Debug)]
+pub struct SpriteSheetExportDialog {
+    /// Where the sprite sheet will be exported to
+    path: PathBuf,
+
+    /// The format the sprite sheet's image will be exported in
+    format: ImageFormat,
+
+    /// The options being chosen (starting from the last-used options)
+    settings: sprite_sheet::ExportSettings,
+
+    /// The sprite sheet packed with the most recently chosen options
+    packed: Option<(sprite_sheet::ExportSettings, SpriteSheet)>,
+}
+
+impl SpriteSheetExportDialog {
+    pub fn new(path: PathBuf, format: ImageFormat, settings: sprite_sheet::ExportSettings) -> Self {
+        Self {
+            path,
+            format,
+            settings,
+            packed: None,
+        }
+    }
+}
+
+/// Makes the "Export Sprite Sheet" dialog, if it's open
+fn make_sprite_sheet_export_dialog(app: &mut TrametesApp, ctx: &Context) {
+    let Some(mut dialog) = app.sprite_sheet_export.take() else {
+        return;
+    };
+
+    // Re-pack whenever the options change, to keep the sheet size up to date
+    if dialog
+        .packed
+        .as_ref()
+        .is_none_or(|(settings, _)| *settings != dialog.settings)
+    {
+        let name = dialog
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let sprites = match dialog.settings.source {
+            SpriteSource::Frames => {
+                sprite_sheet::frame_sprites(&app.document.animation, &app.document.image, &name)
+            }
+            SpriteSource::Layers => sprite_sheet::layer_sprites(&app.document.image),
+        };
+        let sheet = sprite_sheet::pack(&sprites, &dialog.settings);
+        dialog.packed = Some((dialog.settings.clone(), sheet));
+    }
+
+    let mut confirmed = false;
+    let mut cancelled = false;
+
+    Window::new("Export Sprite Sheet")
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let settings = &mut dialog.settings;
+
+            ui.horizontal(|ui| {
+                ui.label("Sprites from");
+                for source in [SpriteSource::Frames, SpriteSource::Layers] {
+                    ui.radio_value(&mut settings.source, source, source.to_string());
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let mut is_grid = matches!(settings.layout, Layout::Grid { .. });
+                if ui
+                    .radio_value(&mut is_grid, true, Layout::Grid { columns: 0 }.to_string())
+                    .changed()
+                {
+                    settings.layout = Layout::Grid { columns: 8 };
+                }
+                if ui
+                    .radio_value(
+                        &mut is_grid,
+                        false,
+                        Layout::Atlas { max_width: 0 }.to_string(),
+                    )
+                    .changed()
+                {
+                    settings.layout = Layout::Atlas { max_width: 1024 };
+                }
+
+                match &mut settings.layout {
+                    Layout::Grid { columns } => ui.add(
+                        DragValue::new(columns)
+                            .clamp_range(1..=1024)
+                            .prefix("Columns: "),
+                    ),
+                    Layout::Atlas { max_width } => ui.add(
+                        DragValue::new(max_width)
+                            .clamp_range(1..=16384)
+                            .prefix("Max width: "),
+                    ),
+                };
+            });
+
+            ui.add(DragValue::new(&mut settings.padding).prefix("Padding: "));
+
+            ui.separator();
+
+            let (_, sheet) = dialog.packed.as_ref().expect("the sheet was just packed");
+            ui.label(format!(
+                "{} sprites, {} x {} pixels",
+                sheet.sprites.len(),
+                sheet.image.width,
+                sheet.image.height
+            ));
+            if let Some(json_name) = dialog.path.with_extension("json").file_name() {
+                ui.label(format!(
+                    "Sprite positions will be saved to {}",
+                    json_name.to_string_lossy()
+                ));
+            }
+
+            ui.horizontal(|ui| {
+                confirmed = ui.button("Export").clicked();
+                cancelled = ui.button("Cancel").clicked();
+            });
+        });
+
+    if confirmed {
+        if let Some((settings, sheet)) = dialog.packed {
+            commands::finish_sprite_sheet_export(
+                app,
+                &dialog.path,
+                dialog.format,
+                &sheet,
+                settings,
+            );
+        }
+    } else if !cancelled {
+        app.sprite_sheet_export = Some(dialog);
+    }
+}
+
+/// Makes the "Save changes?" prompt, if the user is closing a tab (or
+/// quitting) with unsaved changes
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))] // (No quitting on the web)
+fn make_save_changes_dialog(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame) {
+    let Some(pending) = app.pending_close else {
+        return;
+    };
+
+    // Quitting goes through every unsaved document in turn
+    let index = match pending {
+        PendingClose::Tab(index) => Some(index).filter(|index| *index < app.tabs.documents.len()),
+        PendingClose::Quit => (0..app.tabs.documents.len())
+            .find(|index| app.tabs.document(*index, &app.document).dirty),
+    };
+    let Some(index) = index else {
+        if pending == PendingClose::Quit && app.jobs.is_saving_any() {
+            // Quitting partway through saving would leave files half-written
+            return;
+        }
+        app.pending_close = None;
+        if pending == PendingClose::Quit {
+            app.quit_confirmed = true;
+            #[cfg(not(target_arch = "wasm32"))]
+            frame.close();
+        }
+        return;
+    };
+
+    let document = app.tabs.document(index, &app.document);
+    if app.jobs.is_saving(document.id) {
+        // Wait to see whether saving works (the progress window is showing)
+        return;
+    }
+    if !document.dirty {
+        // It was saved (or the changes were undone) in the meantime
+        app.pending_close = None;
+        commands::discard_tab(app, index);
+        return;
+    }
+    let title = document.title();
+
+    let mut save = false;
+    let mut discard = false;
+    let mut cancelled = false;
+
+    Window::new("Save changes?")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "{title} has unsaved changes. Do you want to save them before closing it?"
+            ));
+
+            ui.horizontal(|ui| {
+                save = ui.button("Save").clicked();
+                discard = ui.button("Don't Save").clicked();
+                cancelled = ui.button("Cancel").clicked();
+            });
+        });
+
+    // Once a tab is closed there's nothing left to ask about, but quitting
+    // moves on to the next unsaved document (next frame)
+    let closing_tab = matches!(pending, PendingClose::Tab(_));
+    if save {
+        // Saving works on the document being edited. It happens in the
+        // background, and once it's done the tab is closed (above). If the
+        // user backed out of saving (or it failed), they're asked again
+        commands::select_tab(app, index);
+        commands::save(app);
+    } else if discard {
+        commands::discard_tab(app, index);
+        if closing_tab {
+            app.pending_close = None;
+        }
+    } else if cancelled {
+        app.pending_close = None;
+    }
+}
+
+/// Makes the window showing files being opened and saved in the background,
+/// with how far along each one is and a button to cancel it
+fn make_jobs_dialog(app: &mut TrametesApp, ctx: &Context) {
+    if app.jobs.running().is_empty() {
+        return;
+    }
+
+    // Keep the progress moving (and pick up results) even if nothing else
+    // happens
+    ctx.request_repaint_after(Duration::from_millis(100));
+    if !app.jobs.running().iter().any(|job| job.shown) {
+        return;
+    }
+
+    Window::new("Working")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_BOTTOM, [0.0, -40.0])
+        .show(ctx, |ui| {
+            for job in app.jobs.running().iter().filter(|job| job.shown) {
+                ui.label(&job.description);
+                ui.horizontal(|ui| {
+                    let progress = &job.progress;
+                    if progress.is_cancelled() {
+                        ui.spinner();
+                        ui.label("Cancelling…");
+                        return;
+                    }
+
+                    match progress.fraction() {
+                        Some(fraction) => {
+                            ui.add(
+                                ProgressBar::new(fraction)
+                                    .desired_width(200.0)
+                                    .text(format!(
+                                        "{} ({:.0}%)",
+                                        progress.stage(),
+                                        fraction * 100.0
+                                    )),
+                            );
+                        }
+                        None => {
+                            ui.spinner();
+                            ui.label(progress.stage());
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        progress.cancel();
+                    }
+                });
+            }
+        });
+}
+
+/// Makes the dialog asking whether to reload an image with unsaved changes
+/// after another program changed its file
+fn make_changed_on_disk_dialog(app: &mut TrametesApp, ctx: &Context) {
+    let Some(&document_id) = app.changed_on_disk.first() else {
+        return;
+    };
+    let document = std::iter::once(&app.document)
+        .chain(&app.tabs.documents)
+        .find(|document| document.id == document_id);
+    let Some(document) = document.filter(|document| document.dirty) else {
+        // It was closed (or saved, or its changes were undone) in the meantime
+        app.changed_on_disk.remove(0);
+        return;
+    };
+    let title = document.title();
+
+    let mut reload = false;
+    let mut keep = false;
+
+    Window::new("File changed")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "{title} was changed by another program. Do you want to reload it, throwing \
+                away your unsaved changes? (Reloading can be undone)"
+            ));
+
+            ui.horizontal(|ui| {
+                reload = ui.button("Reload").clicked();
+                keep = ui.button("Keep My Changes").clicked();
+            });
+        });
+
+    if reload {
+        commands::reload(app, document_id);
+    }
+    if reload || keep {
+        app.changed_on_disk.remove(0);
+    }
+}
+
+/// Makes the "Recover Unsaved Changes" dialog, if a previous run left autosaves
+/// behind (because it crashed)
+fn make_recovery_dialog(app: &mut TrametesApp, ctx: &Context) {
+    if app.recovered.is_empty() {
+        return;
+    }
+
+    let mut restore = Vec::new();
+    let mut discard = Vec::new();
+
+    Window::new("Recover Unsaved Changes")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label("Trametes didn't close properly last time. These images had unsaved changes:");
+
+            ui.separator();
+
+            egui::Grid::new("recovered_documents").show(ui, |ui| {
+                for (index, recovered) in app.recovered.iter().enumerate() {
+                    let label = ui.label(&recovered.info.title);
+                    if let Some(path) = &recovered.info.path {
+                        label.on_hover_text(path.display().to_string());
+                    }
+                    if ui.button("Restore").clicked() {
+                        restore.push(index);
+                    }
+                    if ui.button("Discard").clicked() {
+                        discard.push(index);
+                    }
+                    ui.end_row();
+                }
+            });
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("Restore All").clicked() {
+                    restore.extend(0..app.recovered.len());
+                }
+                if ui.button("Discard All").clicked() {
+                    discard.extend(0..app.recovered.len());
+                }
+            });
+        });
+
+    for index in &restore {
+        let recovered = app.recovered[*index].clone();
+        commands::restore_recovered(app, &recovered);
+    }
+    for index in &discard {
+        recovery::remove_recovered(&app.recovered[*index].file);
+    }
+
+    let mut index = 0;
+    app.recovered.retain(|_| {
+        let handled = restore.contains(&index) || discard.contains(&index);
+        index += 1;
+        !handled
+    });
+}
+
+/// Makes any dialogs the user currently has open
+pub fn make_dialogs(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame) {
+    make_save_changes_dialog(app, ctx, frame);
+    make_jobs_dialog(app, ctx);
+    make_changed_on_disk_dialog(app, ctx);
+    make_recovery_dialog(app, ctx);
+    make_indexed_conversion_dialog(app, ctx);
+    adjustment_dialog::make_adjustment_dialog(app, ctx);
+    make_export_dialog(app, ctx);
+    make_sprite_sheet_import_dialog(app, ctx);
+    make_sprite_sheet_export_dialog(app, ctx);
+}
@@ -1,28 +1,44 @@
-use eframe::Frame;
+use std::path::PathBuf;
+
 use egui::{
-    pos2, warn_if_debug_build, CentralPanel, Color32, ColorImage, Context, ImageData, Pos2, Rect,
-    TextureFilter, TextureOptions, Ui,
+    pos2, warn_if_debug_build, Align2, CentralPanel, Color32, Context, ImageData, InputState,
+    LayerId, Order, Pos2, Rect, Sense, TextStyle, TextureFilter, TextureOptions, Ui,
+};
+
+use crate::{
+    commands,
+    document::{Document, ImageTransformations},
+    fileio::{FilePurpose, PickedFile},
+    pixel_buffer::PixelBuffer,
+    tools::PointerEvent,
+    TrametesApp,
 };
 
-use crate::{app::ImageTransformations, TrametesApp};
+/// The tint of onion skins of frames before the current one
+const ONION_SKIN_BEFORE_TINT: Color32 = Color32::from_rgb(255, 96, 96);
+
+/// The tint of onion skins of frames after the current one
+const ONION_SKIN_AFTER_TINT: Color32 = Color32::from_rgb(96, 160, 255);
 
-use super::{image_to_screen_coords, screen_to_image_coords, windows};
+use super::{image_to_screen_coords, screen_to_image_coords, to_display_image, windows};
 
-fn zoom_image(zoom_delta: f32, zoom_origin: Pos2, app: &mut TrametesApp, panel_rect: Rect) {
+fn zoom_image(zoom_delta: f32, zoom_origin: Pos2, document: &mut Document, panel_rect: Rect) {
     // TODO do we want to do an epsilon comparison here? I feel like
     // it's reasonable to expect *exactly* 1.0, but maybe not. Idk
     if zoom_delta != 1.0 {
-        let original_scale = app.image_relative_pos.scale;
+        let image_size = (document.image.width as f32, document.image.height as f32);
+        let view = &mut document.view;
+        let original_scale = view.scale;
 
         // Adjust the scale
-        app.image_relative_pos.scale *= zoom_delta;
+        view.scale *= zoom_delta;
         let min_scale = 0.5
             * f32::min(
-                panel_rect.width() / app.image.width as f32,
-                panel_rect.height() / app.image.height as f32,
+                panel_rect.width() / image_size.0,
+                panel_rect.height() / image_size.1,
             );
         let max_scale = f32::min(panel_rect.width() / 2.0, panel_rect.height() / 2.0);
-        app.image_relative_pos.scale = app.image_relative_pos.scale.clamp(min_scale, max_scale);
+        view.scale = view.scale.clamp(min_scale, max_scale);
 
         // Adjust the x and y translation so the cursor's location
         // relative to the image is unchanged
@@ -30,47 +46,56 @@ fn zoom_image(zoom_delta: f32, zoom_origin: Pos2, app: &mut TrametesApp, panel_r
             zoom_origin,
             &ImageTransformations {
                 scale: original_scale,
-                ..app.image_relative_pos
+                ..*view
             },
-            (app.image.width as f32, app.image.height as f32),
-            panel_rect,
-        );
-        let new_image_pos = screen_to_image_coords(
-            zoom_origin,
-            &app.image_relative_pos,
-            (app.image.width as f32, app.image.height as f32),
+            image_size,
             panel_rect,
         );
+        let new_image_pos = screen_to_image_coords(zoom_origin, view, image_size, panel_rect);
         let image_delta_x = original_image_pos.x - new_image_pos.x;
         let image_delta_y = original_image_pos.y - new_image_pos.y;
-        let screen_delta_x = image_delta_x * app.image_relative_pos.scale;
-        let screen_delta_y = image_delta_y * app.image_relative_pos.scale;
-        app.image_relative_pos.x_translation -= screen_delta_x;
-        app.image_relative_pos.y_translation -= screen_delta_y;
+        let screen_delta_x = image_delta_x * view.scale;
+        let screen_delta_y = image_delta_y * view.scale;
+        view.x_translation -= screen_delta_x;
+        view.y_translation -= screen_delta_y;
     }
 }
 
-fn clamp_image_to_bounds(app: &mut TrametesApp, panel_rect: Rect) {
-    let width = app.image.width as f32 * app.image_relative_pos.scale;
-    let height = app.image.height as f32 * app.image_relative_pos.scale;
+fn clamp_image_to_bounds(document: &mut Document, panel_rect: Rect) {
+    let view = &mut document.view;
+    let width = document.image.width as f32 * view.scale;
+    let height = document.image.height as f32 * view.scale;
     let margin = 0.25;
     let min_x = panel_rect.width() * margin - (panel_rect.width() + width) / 2.0;
     let max_x = panel_rect.width() * (1.0 - margin) - (panel_rect.width() - width) / 2.0;
     let min_y = panel_rect.height() * margin - (panel_rect.height() + height) / 2.0;
     let max_y = panel_rect.height() * (1.0 - margin) - (panel_rect.height() - height) / 2.0;
-    app.image_relative_pos.x_translation = app.image_relative_pos.x_translation.clamp(min_x, max_x);
-    app.image_relative_pos.y_translation = app.image_relative_pos.y_translation.clamp(min_y, max_y);
+    view.x_translation = view.x_translation.clamp(min_x, max_x);
+    view.y_translation = view.y_translation.clamp(min_y, max_y);
 }
 
-/// Makes the actual image itself
-fn make_image(app: &mut TrametesApp, ui: &mut Ui) {
+/// Draws a document's image in part of the canvas, returning the screen rect
+/// the image was drawn in
+fn make_image(
+    ui: &mut Ui,
+    document: &Document,
+    preview: Option<&PixelBuffer>,
+    panel_rect: Rect,
+    texture_name: &str,
+) -> Rect {
+    // Show the adjustment being chosen (if any) without actually making it
+    let flat = match preview {
+        Some(pixels) => document.image.flatten_with_active(pixels),
+        None => document.image.flatten(),
+    };
+
     // Create a texture for the image
     // TODO do we *really* need to recreate a new texture each time?
-    let image =
-        ColorImage::from_rgba_unmultiplied([app.image.width, app.image.height], &app.image.pixels);
+    // TODO cache the flattened image, and only re-flatten what has changed
+    let image = to_display_image(&flat, document.color_profile.as_ref());
     let image_data = ImageData::Color(image);
     let texture = ui.ctx().load_texture(
-        "main image",
+        texture_name,
         image_data,
         TextureOptions {
             magnification: TextureFilter::Nearest,
@@ -79,33 +104,171 @@ fn make_image(app: &mut TrametesApp, ui: &mut Ui) {
     );
 
     // Calculate the transformed screen rect to draw the image in
+    let image_size = (document.image.width as f32, document.image.height as f32);
     let pos = Rect::from_min_max(
+        image_to_screen_coords(pos2(0.0, 0.0), &document.view, image_size, panel_rect),
         image_to_screen_coords(
-            pos2(0.0, 0.0),
-            &app.image_relative_pos,
-            (app.image.width as f32, app.image.height as f32),
-            ui.ctx().available_rect(),
-        ),
-        image_to_screen_coords(
-            pos2(app.image.width as f32, app.image.height as f32),
-            &app.image_relative_pos,
-            (app.image.width as f32, app.image.height as f32),
-            ui.ctx().available_rect(),
+            pos2(image_size.0, image_size.1),
+            &document.view,
+            image_size,
+            panel_rect,
         ),
     );
 
-    // Draw the image (clipped so it doesn't cover the UI)
-    let painter = ui.painter_at(ui.available_rect_before_wrap());
+    // Draw the image (clipped so it doesn't cover the UI, or the other image
+    // while tiled)
+    let painter = ui.painter_at(panel_rect.intersect(ui.available_rect_before_wrap()));
     painter.image(
         (&texture).into(),
         pos,
         Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)), // No transform
         Color32::WHITE,                                     // No tint
     );
+
+    pos
+}
+
+/// Shows the frames around the current one faintly on top of it (onion
+/// skinning), except while playing
+fn make_onion_skins(app: &TrametesApp, ui: &mut Ui, panel_rect: Rect, pos: Rect) {
+    let onion_skin = &app.timeline.onion_skin;
+    let animation = &app.document.animation;
+    if !onion_skin.enabled || !animation.is_animated() || app.timeline.frame_shown_at.is_some() {
+        return;
+    }
+
+    let painter = ui.painter_at(panel_rect.intersect(ui.available_rect_before_wrap()));
+    let current = animation.current_frame;
+    let frame_count = animation.frames.len();
+    let before = (1..=onion_skin.frames_before).filter_map(|distance| {
+        let index = current.checked_sub(distance)?;
+        Some((index, distance, ONION_SKIN_BEFORE_TINT))
+    });
+    let after = (1..=onion_skin.frames_after)
+        .map(|distance| (current + distance, distance, ONION_SKIN_AFTER_TINT))
+        .filter(|(index, _, _)| *index < frame_count);
+
+    for (index, distance, tint) in before.chain(after) {
+        // TODO cache these too, they only change when switching frames
+        let image = to_display_image(
+            &animation.frames[index].image.flatten(),
+            app.document.color_profile.as_ref(),
+        );
+        let texture = ui.ctx().load_texture(
+            format!("onion skin {index}"),
+            ImageData::Color(image),
+            TextureOptions {
+                magnification: TextureFilter::Nearest,
+                minification: TextureFilter::Linear,
+            },
+        );
+
+        // Farther frames fade out
+        let opacity = onion_skin.opacity / distance as f32;
+        painter.image(
+            (&texture).into(),
+            pos,
+            Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+            tint.linear_multiply(opacity),
+        );
+    }
 }
 
-/// Makes the "main panel" (the large central area with the editable image)
-pub fn make_main_panel(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame) {
+/// Opens files dropped onto the window, each in a new tab (or, while holding
+/// Shift, as new layers of the current image)
+fn handle_dropped_files(app: &mut TrametesApp, ctx: &Context) {
+    let (dropped_files, as_layers) =
+        ctx.input(|input| (input.raw.dropped_files.clone(), input.modifiers.shift));
+
+    for file in dropped_files {
+        // Files dropped on the web only have names and contents, not paths
+        match (file.path, file.bytes) {
+            (Some(path), _) if as_layers => commands::add_layer_from_path(app, path),
+            (Some(path), _) => commands::open_path(app, path),
+            (None, Some(bytes)) => {
+                let purpose = if as_layers {
+                    FilePurpose::AddLayer
+                } else {
+                    FilePurpose::Open
+                };
+                let file = PickedFile {
+                    path: PathBuf::from(file.name),
+                    bytes: bytes.to_vec(),
+                };
+                commands::open_picked_file(app, purpose, file);
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+/// Darkens the canvas while files are being dragged over the window, explaining
+/// what dropping them will do
+fn make_drop_overlay(ctx: &Context, canvas_rect: Rect) {
+    let (file_count, as_layers) =
+        ctx.input(|input| (input.raw.hovered_files.len(), input.modifiers.shift));
+    if file_count == 0 {
+        return;
+    }
+
+    let text = match (file_count, as_layers) {
+        (1, false) => "Drop to open the image in a new tab\n(hold Shift to add it as a layer)",
+        (1, true) => "Drop to add the image as a layer",
+        (_, false) => "Drop to open the images in new tabs\n(hold Shift to add them as layers)",
+        (_, true) => "Drop to add the images as layers",
+    };
+
+    let painter = ctx.layer_painter(LayerId::new(Order::Foreground, "drop_overlay".into()));
+    painter.rect_filled(canvas_rect, 0.0, Color32::from_black_alpha(192));
+    painter.text(
+        canvas_rect.center(),
+        Align2::CENTER_CENTER,
+        text,
+        TextStyle::Heading.resolve(&ctx.style()),
+        Color32::WHITE,
+    );
+}
+
+/// Translates egui's pointer input into an event for the tools, in the image's
+/// pixel coordinates
+fn pointer_event(
+    input: &InputState,
+    document: &Document,
+    panel_rect: Rect,
+) -> Option<PointerEvent> {
+    let pointer = &input.pointer;
+    if pointer.any_released() {
+        return Some(PointerEvent::Release);
+    }
+
+    let screen_pos = pointer.interact_pos()?;
+    let image_pos = screen_to_image_coords(
+        screen_pos,
+        &document.view,
+        (document.image.width as f32, document.image.height as f32),
+        panel_rect,
+    );
+    let pos = (image_pos.x, image_pos.y);
+
+    if pointer.any_pressed() {
+        Some(PointerEvent::Press { pos })
+    } else if pointer.any_down() {
+        // TODO not do this janky dt hack to get around
+        // is_decidedly_dragging() not handling file -> open well
+        let screen_delta = if pointer.is_decidedly_dragging() && input.unstable_dt < 1.0 {
+            (pointer.delta().x, pointer.delta().y)
+        } else {
+            (0.0, 0.0)
+        };
+        Some(PointerEvent::Drag { pos, screen_delta })
+    } else {
+        None
+    }
+}
+
+/// Makes the "main panel" (the large central area with the editable image,
+/// and the image it's tiled with, if any)
+pub fn make_main_panel(app: &mut TrametesApp, ctx: &Context) {
     // The panel frame is used for adjusting the style of the panel
     let panel_frame = egui::containers::Frame {
         fill: Color32::from_rgb(50, 50, 50),
@@ -113,31 +276,95 @@ pub fn make_main_panel(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame)
     };
 
     CentralPanel::default().frame(panel_frame).show(ctx, |ui| {
+        // While tiled, the active image is on the left and the other image is
+        // on the right
+        let canvas_rect = ui.ctx().available_rect();
+        let (panel_rect, tiled_rect) = match app.tabs.tiled_with {
+            Some(_) => {
+                let (left, right) = canvas_rect.split_left_right_at_fraction(0.5);
+                (left, Some(right))
+            }
+            None => (canvas_rect, None),
+        };
+
         // Handle user inputs
         // TODO separate this out - I don't think it belongs in UI code
         ui.input(|input| {
-            let panel_rect = ui.ctx().available_rect();
+            let hover_pos = input.pointer.interact_pos();
 
-            // Handle zooming
-            let zoom_origin = input
-                .pointer
-                .interact_pos()
-                .filter(|pos| panel_rect.contains(*pos))
-                .unwrap_or(panel_rect.center());
-            zoom_image(input.zoom_delta(), zoom_origin, app, panel_rect);
+            // Handle zooming (of whichever image is under the cursor)
+            let zoom_delta = input.zoom_delta();
+            if let (Some(index), Some(rect)) = (app.tabs.tiled_with, tiled_rect) {
+                if let Some(pos) = hover_pos.filter(|pos| rect.contains(*pos)) {
+                    let document = &mut app.tabs.documents[index];
+                    zoom_image(zoom_delta, pos, document, rect);
+                    clamp_image_to_bounds(document, rect);
+                }
+            }
+            if tiled_rect.is_none() || hover_pos.is_some_and(|pos| panel_rect.contains(pos)) {
+                let zoom_origin = hover_pos
+                    .filter(|pos| panel_rect.contains(*pos))
+                    .unwrap_or(panel_rect.center());
+                zoom_image(zoom_delta, zoom_origin, &mut app.document, panel_rect);
+            }
 
-            // Let whatever tool is active do its thing
-            let current_tool = app.tools.current_tool;
-            current_tool.handle_input(input, app, ctx);
+            // Let whatever tool is active do its thing (unless the user is
+            // dragging on the other image, or has an error to acknowledge)
+            let pressed_elsewhere = tiled_rect.is_some_and(|rect| {
+                input
+                    .pointer
+                    .press_origin()
+                    .is_some_and(|origin| rect.contains(origin))
+            });
+            let error_shown = app.notifications.current_error().is_some();
+            if !pressed_elsewhere && !error_shown {
+                if let Some(event) = pointer_event(input, &app.document, panel_rect) {
+                    app.tools.handle_event(
+                        event,
+                        &mut app.document,
+                        app.colors.primary,
+                        app.settings.blend_in_linear_light,
+                    );
+                }
+            }
 
             // Ensure the image is in-bounds
-            clamp_image_to_bounds(app, panel_rect);
+            clamp_image_to_bounds(&mut app.document, panel_rect);
         });
 
-        windows::make_draggable_windows(app, ctx, frame);
+        windows::make_draggable_windows(app, ctx);
+
+        let preview = app
+            .adjustment
+            .as_mut()
+            .and_then(|dialog| dialog.preview_for(&app.document));
+        let pos = make_image(ui, &app.document, preview, panel_rect, "main image");
+        make_onion_skins(app, ui, panel_rect, pos);
+
+        if let (Some(index), Some(rect)) = (app.tabs.tiled_with, tiled_rect) {
+            let document = &app.tabs.documents[index];
+            let preview = app
+                .adjustment
+                .as_mut()
+                .and_then(|dialog| dialog.preview_for(document));
+            make_image(ui, document, preview, rect, "tiled image");
+            ui.painter().vline(
+                rect.left(),
+                rect.y_range(),
+                ui.visuals().widgets.noninteractive.bg_stroke,
+            );
 
-        make_image(app, ui);
+            // Clicking the other image switches to editing it
+            let response = ui.interact(rect, ui.id().with("tiled image"), Sense::click());
+            if response.clicked() {
+                commands::select_tab(app, index);
+            }
+        }
 
         warn_if_debug_build(ui);
+
+        make_drop_overlay(ctx, canvas_rect);
     });
+
+    handle_dropped_files(app, ctx);
 }
@@ -1,7 +1,88 @@
 use eframe::Frame;
-use egui::{Context, TopBottomPanel};
+use egui::{Button, Context, RichText, TextureOptions, TopBottomPanel, Ui};
 
-use crate::{commands, TrametesApp};
+use crate::{
+    adjustments::AdjustmentKind,
+    app::{IndexedConversionSettings, RecentThumbnail},
+    color_management::ColorProfile,
+    color_mode::ColorMode,
+    commands, TrametesApp,
+};
+
+use super::{ui_helpers::to_display_image, AdjustmentDialog};
+
+/// The largest width or height of the thumbnails in the Open Recent menu
+const RECENT_THUMBNAIL_SIZE: u32 = 32;
+
+/// Makes the File > Open Recent menu
+fn make_open_recent_menu(app: &mut TrametesApp, ui: &mut Ui) {
+    let paths = app.recent_files.paths().to_vec();
+    if paths.is_empty() {
+        ui.weak("No recent files");
+    }
+
+    let mut chosen = None;
+    for path in &paths {
+        let exists = path.exists();
+        let name = path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+
+        // Reading files can be slow, so thumbnails are made in the background
+        if exists && !app.recent_thumbnails.0.contains_key(path) {
+            app.jobs.make_recent_thumbnail(
+                path.clone(),
+                RECENT_THUMBNAIL_SIZE,
+                app.settings.blend_in_linear_light,
+            );
+            app.recent_thumbnails
+                .0
+                .insert(path.clone(), RecentThumbnail::Loading);
+        }
+        if let Some(thumbnail) = app.recent_thumbnails.0.get_mut(path) {
+            if let RecentThumbnail::Read(pixels, color_profile) = thumbnail {
+                *thumbnail = RecentThumbnail::Loaded(ui.ctx().load_texture(
+                    format!("recent thumbnail {}", path.display()),
+                    to_display_image(pixels, color_profile.as_ref()),
+                    TextureOptions::default(),
+                ));
+            }
+        }
+
+        let button = match app.recent_thumbnails.0.get(path) {
+            Some(RecentThumbnail::Loaded(thumbnail)) if exists => {
+                let size = thumbnail.size_vec2();
+                Button::image_and_text(thumbnail.id(), size, name)
+            }
+            _ if exists => Button::new(name),
+            _ => Button::new(RichText::new(format!("{name} (missing)")).weak()),
+        };
+        let hover_text = if exists {
+            path.display().to_string()
+        } else {
+            format!("{} no longer exists", path.display())
+        };
+
+        if ui.add(button).on_hover_text(hover_text).clicked() {
+            chosen = Some(path);
+        }
+    }
+    ui.separator();
+
+    if ui
+        .add_enabled(!paths.is_empty(), Button::new("Clear Recent"))
+        .clicked()
+    {
+        commands::clear_recent_files(app);
+        ui.close_menu();
+    }
+
+    if let Some(path) = chosen {
+        ui.close_menu();
+        commands::open_recent(app, path);
+    }
+}
 
 /// Makes the top menu bar (File, Edit, View, etc.)
 pub fn make_top_menu_bar(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame) {
@@ -17,12 +98,11 @@ pub fn make_top_menu_bar(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame
                     commands::open(app);
                 }
 
-                ui.menu_button("Open Recent", |ui| {
-                    // TODO
-                    if ui.button("TODO put stuff here lol").clicked() {
-                        commands::todo("open recent");
-                    }
-                });
+                // Files opened on the web can't be reopened without asking the
+                // user for them again
+                if !frame.is_web() {
+                    ui.menu_button("Open Recent", |ui| make_open_recent_menu(app, ui));
+                }
 
                 ui.separator();
 
@@ -34,32 +114,85 @@ pub fn make_top_menu_bar(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame
                     commands::save_as(app);
                 }
 
+                // (Files on the web can't be read again)
+                let can_revert = app.document.path.is_some() && !frame.is_web();
+                if ui
+                    .add_enabled(can_revert, Button::new("Revert"))
+                    .on_hover_text("Reload the image from its file, throwing away unsaved changes")
+                    .clicked()
+                {
+                    commands::revert(app);
+                    ui.close_menu();
+                }
+
+                if ui.button("Export...").clicked() {
+                    commands::export(app);
+                    ui.close_menu();
+                }
+
+                ui.separator();
+
+                if ui.button("Import Sprite Sheet...").clicked() {
+                    commands::import_sprite_sheet(app);
+                    ui.close_menu();
+                }
+
+                if ui.button("Export Sprite Sheet...").clicked() {
+                    commands::export_sprite_sheet(app);
+                    ui.close_menu();
+                }
+
+                ui.separator();
+
+                if ui.button("Image Properties...").clicked() {
+                    app.windows.image_properties = true;
+                    ui.close_menu();
+                }
+
                 ui.separator();
 
                 if ui.button("Settings...").clicked() {
-                    commands::todo("settings");
+                    app.windows.settings = true;
+                    ui.close_menu();
+                }
+
+                if ui.button("Close").clicked() {
+                    commands::close_tab(app, app.tabs.active);
+                    ui.close_menu();
                 }
 
                 // No "Quit" on the web
-                if !frame.is_web() && ui.button("Quit").clicked() {
+                #[cfg(not(target_arch = "wasm32"))]
+                if ui.button("Quit").clicked() {
                     frame.close();
                 }
             });
 
             // Edit
             ui.menu_button("Edit", |ui| {
-                if ui.button("Undo").clicked() {
-                    commands::todo("undo");
+                let history = &app.document.history;
+                let (can_undo, can_redo) = (!history.undo.is_empty(), !history.redo.is_empty());
+                let undo_text = match history.undo.last() {
+                    Some(step) => format!("Undo {}", step.name),
+                    None => "Undo".to_string(),
+                };
+                let redo_text = match history.redo.last() {
+                    Some(step) => format!("Redo {}", step.name),
+                    None => "Redo".to_string(),
+                };
+
+                if ui.add_enabled(can_undo, Button::new(undo_text)).clicked() {
+                    commands::undo(app);
                 }
 
-                if ui.button("Redo").clicked() {
-                    commands::todo("redo");
+                if ui.add_enabled(can_redo, Button::new(redo_text)).clicked() {
+                    commands::redo(app);
                 }
 
                 ui.separator();
 
                 if ui.button("Cut").clicked() {
-                    commands::todo("cut");
+                    commands::todo(app, "cut");
                 }
 
                 if ui.button("Copy").clicked() {
@@ -67,7 +200,7 @@ pub fn make_top_menu_bar(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame
                 }
 
                 if ui.button("Paste").clicked() {
-                    commands::todo("paste");
+                    commands::paste(app);
                 }
 
                 if ui.button("Paste into New Image").clicked() {
@@ -80,25 +213,182 @@ pub fn make_top_menu_bar(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame
                     ui.checkbox(&mut app.windows.colors, "Colors");
                     ui.checkbox(&mut app.windows.history, "History");
                     ui.checkbox(&mut app.windows.layers, "Layers");
+                    ui.checkbox(&mut app.windows.palette, "Palette");
+                    ui.checkbox(&mut app.windows.timeline, "Timeline");
+                    ui.checkbox(&mut app.windows.notifications, "Notifications");
                 });
             });
 
+            // Window
+            ui.menu_button("Window", |ui| {
+                let mut tiled = app.tabs.tiled_with.is_some();
+                if ui
+                    .add_enabled(
+                        app.tabs.documents.len() > 1,
+                        egui::Checkbox::new(&mut tiled, "Tile"),
+                    )
+                    .on_hover_text("Show two images side by side")
+                    .changed()
+                {
+                    app.tabs.set_tiled(tiled);
+                }
+
+                ui.separator();
+
+                let mut selected = None;
+                for index in 0..app.tabs.documents.len() {
+                    let document = app.tabs.document(index, &app.document);
+                    if ui
+                        .radio(index == app.tabs.active, document.title())
+                        .clicked()
+                    {
+                        selected = Some(index);
+                    }
+                }
+                if let Some(index) = selected {
+                    commands::select_tab(app, index);
+                    ui.close_menu();
+                }
+            });
+
             // Image
             ui.menu_button("Image", |ui| {
-                // TODO put stuff here
-                ui.label("TODO put stuff here");
+                ui.menu_button("Mode", |ui| {
+                    if ui
+                        .radio(app.document.color_mode == ColorMode::Rgba, "RGB")
+                        .clicked()
+                    {
+                        commands::convert_to_rgb(app);
+                        ui.close_menu();
+                    }
+
+                    if ui
+                        .radio(app.document.color_mode == ColorMode::Grayscale, "Grayscale")
+                        .clicked()
+                    {
+                        commands::convert_to_grayscale(app);
+                        ui.close_menu();
+                    }
+
+                    let is_indexed = matches!(app.document.color_mode, ColorMode::Indexed(_));
+                    if ui.radio(is_indexed, "Indexed...").clicked() {
+                        app.indexed_conversion = Some(IndexedConversionSettings::default());
+                        ui.close_menu();
+                    }
+                });
+
+                ui.separator();
+
+                if ui.button("Flip Horizontal").clicked() {
+                    commands::flip_horizontal(app);
+                    ui.close_menu();
+                }
+
+                if ui.button("Flip Vertical").clicked() {
+                    commands::flip_vertical(app);
+                    ui.close_menu();
+                }
+                ui.separator();
+
+                let current_profile = match &app.document.color_profile {
+                    Some(profile) => profile.name.as_str(),
+                    None => "Untagged (sRGB)",
+                };
+                ui.label(format!("Color Profile: {current_profile}"));
+
+                ui.menu_button("Assign Profile", |ui| {
+                    for profile in ColorProfile::builtins() {
+                        if ui.button(&profile.name).clicked() {
+                            commands::assign_profile(app, Some(profile));
+                            ui.close_menu();
+                        }
+                    }
+
+                    if ui.button("From File...").clicked() {
+                        commands::assign_profile_from_file(app);
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+
+                    if ui.button("Don't Color Manage").clicked() {
+                        commands::assign_profile(app, None);
+                        ui.close_menu();
+                    }
+                });
+
+                ui.menu_button("Convert to Profile", |ui| {
+                    for profile in ColorProfile::builtins() {
+                        if ui.button(&profile.name).clicked() {
+                            commands::convert_to_profile(app, profile);
+                            ui.close_menu();
+                        }
+                    }
+
+                    if ui.button("From File...").clicked() {
+                        commands::convert_to_profile_from_file(app);
+                        ui.close_menu();
+                    }
+                });
             });
 
             // Layer
             ui.menu_button("Layer", |ui| {
-                // TODO put stuff here
-                ui.label("TODO put stuff here");
+                if ui.button("New Layer").clicked() {
+                    commands::new_layer(app);
+                    ui.close_menu();
+                }
+
+                if ui.button("Duplicate Layer").clicked() {
+                    commands::duplicate_layer(app);
+                    ui.close_menu();
+                }
+
+                if ui
+                    .add_enabled(
+                        app.document.image.layers.len() > 1,
+                        Button::new("Delete Layer"),
+                    )
+                    .clicked()
+                {
+                    commands::delete_layer(app);
+                    ui.close_menu();
+                }
+
+                ui.separator();
+
+                if ui.button("Move Layer Up").clicked() {
+                    commands::move_layer_up(app);
+                    ui.close_menu();
+                }
+
+                if ui.button("Move Layer Down").clicked() {
+                    commands::move_layer_down(app);
+                    ui.close_menu();
+                }
+
+                ui.separator();
+
+                if ui.button("Flatten Image").clicked() {
+                    commands::flatten_image(app);
+                    ui.close_menu();
+                }
             });
 
             // Effects
             ui.menu_button("Effects", |ui| {
-                // TODO put stuff here
-                ui.label("TODO put stuff here");
+                ui.menu_button("Adjustments", |ui| {
+                    for kind in AdjustmentKind::ALL {
+                        // Separate the color adjustments from the tonal ones
+                        if kind == AdjustmentKind::HueSaturation {
+                            ui.separator();
+                        }
+                        if ui.button(format!("{kind}...")).clicked() {
+                            app.adjustment = Some(AdjustmentDialog::new(&app.document, kind));
+                            ui.close_menu();
+                        }
+                    }
+                });
             });
         });
     });
@@ -1,6 +1,11 @@
+mod adjustment_dialog;
 mod controls_panel;
+mod dialogs;
 mod main_canvas;
 mod menu_bar;
+mod notifications;
+mod tab_bar;
+mod timeline;
 mod ui_helpers;
 mod windows;
 
@@ -8,14 +13,19 @@ use crate::TrametesApp;
 use eframe::Frame;
 use egui::{Context, FontFamily, FontId, TextStyle};
 
-pub use ui_helpers::{image_to_screen_coords, message_popup, rect, screen_to_image_coords};
+pub use adjustment_dialog::AdjustmentDialog;
+pub use dialogs::{ExportDialog, SpriteSheetExportDialog, SpriteSheetImportDialog};
+pub use ui_helpers::{
+    edit_loop_count, image_to_screen_coords, interaction_started, rect, screen_to_image_coords,
+    to_display_image,
+};
 
 /// Makes basic and global style changes to the given context
-fn set_style(ctx: &egui::Context, frame: &mut Frame) {
+fn set_style(ctx: &egui::Context) {
     // Set the default text style to be slightly larger
     // TODO is there a cleaner way to do this?
     let mut style = (*ctx.style()).clone();
-    let font_size = frame.info().window_info.size.min_elem() / 50.0;
+    let font_size = ctx.screen_rect().size().min_elem() / 50.0;
     style.text_styles.insert(
         TextStyle::Name("body_large".into()),
         FontId::new(font_size, FontFamily::Proportional),
@@ -26,14 +36,26 @@ fn set_style(ctx: &egui::Context, frame: &mut Frame) {
 
 pub fn draw_ui(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame) {
     // Makes basic and global style changes
-    set_style(ctx, frame);
+    set_style(ctx);
 
     // Top menu bar
     menu_bar::make_top_menu_bar(app, ctx, frame);
 
     // Top controls panel
-    controls_panel::make_controls_panel(app, ctx, frame);
+    controls_panel::make_controls_panel(app, ctx);
+
+    // Tabs for each open image
+    tab_bar::make_tab_bar(app, ctx);
+
+    // Bottom timeline panel (for animations)
+    timeline::make_timeline_panel(app, ctx);
 
     // The main canvas panel
-    main_canvas::make_main_panel(app, ctx, frame);
+    main_canvas::make_main_panel(app, ctx);
+
+    // Any open dialogs
+    dialogs::make_dialogs(app, ctx, frame);
+
+    // Toasts, errors, and the Notifications window
+    notifications::make_notifications(app, ctx);
 }
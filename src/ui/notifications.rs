@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use egui::{
+    Align2, Area, Color32, Context, Frame, Key, Order, Pos2, RichText, ScrollArea, Sense, Ui,
+    Window,
+};
+
+use crate::{
+    notifications::{Notification, Severity, TOAST_DURATION},
+    TrametesApp,
+};
+
+/// The widest toasts and error dialogs get, as a fraction of the window width
+const MAX_WIDTH_FRACTION: f32 = 0.35;
+
+/// Makes the toasts for info and warnings, the dialog for the current error,
+/// and the Notifications window (if it's open)
+pub fn make_notifications(app: &mut TrametesApp, ctx: &Context) {
+    make_toasts(app, ctx);
+    make_error_dialog(app, ctx);
+    make_log_window(app, ctx);
+}
+
+/// Shows a notification's message in the color for how important it is
+fn notification_label(ui: &mut Ui, notification: &Notification) {
+    let (icon, color) = match notification.severity {
+        Severity::Info => ("ℹ", ui.visuals().text_color()),
+        Severity::Warning => ("⚠", ui.visuals().warn_fg_color),
+        Severity::Error => ("⊗", ui.visuals().error_fg_color),
+    };
+    ui.horizontal_top(|ui| {
+        ui.label(RichText::new(icon).color(color));
+        ui.label(&notification.message);
+    });
+}
+
+/// Makes the stack of toasts in the bottom-right corner, which go away on
+/// their own after a few seconds (or when they're clicked)
+fn make_toasts(app: &mut TrametesApp, ctx: &Context) {
+    let now = ctx.input(|input| input.time);
+    let toasts = app.notifications.toasts(now);
+    if toasts.is_empty() {
+        return;
+    }
+
+    // Make sure the toasts disappear even if nothing else happens
+    let soonest = toasts
+        .iter()
+        .map(|toast| toast.remaining(now))
+        .fold(1.0, f64::min);
+    ctx.request_repaint_after(Duration::from_secs_f64(soonest * TOAST_DURATION));
+
+    let max_width = ctx.screen_rect().width() * MAX_WIDTH_FRACTION;
+    let mut dismissed = None;
+    Area::new("toasts")
+        .order(Order::Foreground)
+        .anchor(Align2::RIGHT_BOTTOM, [-10.0, -10.0])
+        .show(ctx, |ui| {
+            ui.set_max_width(max_width);
+            for (index, toast) in toasts.iter().enumerate() {
+                let response = Frame::popup(ui.style())
+                    .show(ui, |ui| notification_label(ui, &toast.notification))
+                    .response
+                    .interact(Sense::click())
+                    .on_hover_text("Click to dismiss");
+                if response.clicked() {
+                    dismissed = Some(index);
+                }
+            }
+        });
+
+    if let Some(index) = dismissed {
+        app.notifications.dismiss_toast(index);
+    }
+}
+
+/// Makes the dialog for the oldest error the user hasn't acknowledged,
+/// blocking the rest of the window until they do
+fn make_error_dialog(app: &mut TrametesApp, ctx: &Context) {
+    let Some(message) = app.notifications.current_error() else {
+        return;
+    };
+
+    // Dim (and swallow clicks on) everything behind the dialog
+    let screen = ctx.screen_rect();
+    Area::new("error_dialog_background")
+        .order(Order::Foreground)
+        .fixed_pos(Pos2::ZERO)
+        .show(ctx, |ui| {
+            ui.painter()
+                .rect_filled(screen, 0.0, Color32::from_black_alpha(128));
+            ui.allocate_rect(screen, Sense::click_and_drag());
+        });
+
+    let mut acknowledged =
+        ctx.input(|input| input.key_pressed(Key::Enter) || input.key_pressed(Key::Escape));
+    let response = Area::new("error_dialog")
+        .order(Order::Foreground)
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            Frame::window(ui.style()).show(ui, |ui| {
+                ui.set_max_width(screen.width() * MAX_WIDTH_FRACTION);
+                ui.heading("Something went wrong");
+                ui.label(RichText::new(message).color(ui.visuals().error_fg_color));
+                ui.separator();
+                if ui.button("OK").clicked() {
+                    acknowledged = true;
+                }
+            });
+        })
+        .response;
+    ctx.move_to_top(response.layer_id);
+
+    if acknowledged {
+        app.notifications.acknowledge_error();
+    }
+}
+
+/// Makes the Notifications window, listing every notification so far (newest
+/// first)
+fn make_log_window(app: &mut TrametesApp, ctx: &Context) {
+    let mut clear = false;
+    Window::new("Notifications")
+        .resizable(true)
+        .default_size([300.0, 200.0])
+        .open(&mut app.windows.notifications)
+        .show(ctx, |ui| {
+            if app.notifications.log().is_empty() {
+                ui.label(RichText::new("Nothing yet").weak());
+            }
+            ScrollArea::vertical().show(ui, |ui| {
+                for notification in app.notifications.log().iter().rev() {
+                    notification_label(ui, notification);
+                    ui.separator();
+                }
+            });
+            clear = ui.button("Clear").clicked();
+        });
+
+    if clear {
+        app.notifications.clear_log();
+    }
+}
@@ -0,0 +1,53 @@
+use egui::{Button, Context, ScrollArea, TopBottomPanel};
+
+use crate::{commands, TrametesApp};
+
+/// Makes the row of tabs (just above the canvas) for switching between open
+/// images
+pub fn make_tab_bar(app: &mut TrametesApp, ctx: &Context) {
+    TopBottomPanel::top("tab_bar_panel").show(ctx, |ui| {
+        let mut selected = None;
+        let mut closed = None;
+
+        ScrollArea::horizontal().show(ui, |ui| {
+            ui.horizontal(|ui| {
+                for index in 0..app.tabs.documents.len() {
+                    let document = app.tabs.document(index, &app.document);
+                    let title = if document.dirty {
+                        format!("{}*", document.title())
+                    } else {
+                        document.title()
+                    };
+                    let hover_text = match &document.path {
+                        Some(path) => path.display().to_string(),
+                        None => "Not saved yet".to_string(),
+                    };
+
+                    if ui
+                        .selectable_label(index == app.tabs.active, title)
+                        .on_hover_text(hover_text)
+                        .clicked()
+                    {
+                        selected = Some(index);
+                    }
+                    if ui
+                        .add(Button::new("×").small().frame(false))
+                        .on_hover_text("Close")
+                        .clicked()
+                    {
+                        closed = Some(index);
+                    }
+
+                    ui.separator();
+                }
+            });
+        });
+
+        if let Some(index) = selected {
+            commands::select_tab(app, index);
+        }
+        if let Some(index) = closed {
+            commands::close_tab(app, index);
+        }
+    });
+}
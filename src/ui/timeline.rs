@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use egui::{Button, Context, DragValue, ScrollArea, Slider, TopBottomPanel, Ui};
+
+use crate::{commands, TrametesApp};
+
+use super::{edit_loop_count, interaction_started};
+
+/// Moves on to the next frame if the animation is playing and the current
+/// frame has been shown for long enough
+fn advance_playback(app: &mut TrametesApp, ctx: &Context) {
+    let Some(shown_at) = app.timeline.frame_shown_at else {
+        return;
+    };
+
+    let now = ctx.input(|input| input.time);
+    let delay = app.document.animation.frames[app.document.animation.current_frame].delay_ms as f64
+        / 1000.0;
+    let remaining = shown_at + delay - now;
+
+    if remaining <= 0.0 {
+        // Always loop while editing, the loop count only matters once saved
+        let next = (app.document.animation.current_frame + 1) % app.document.animation.frames.len();
+        commands::select_frame(app, next);
+        app.timeline.frame_shown_at = Some(now);
+        ctx.request_repaint();
+    } else {
+        ctx.request_repaint_after(Duration::from_secs_f64(remaining));
+    }
+}
+
+/// Makes the buttons for stepping through, playing, and editing frames
+fn make_frame_controls(app: &mut TrametesApp, ui: &mut Ui, now: f64) {
+    let frame_count = app.document.animation.frames.len();
+    let current = app.document.animation.current_frame;
+    let playing = app.timeline.frame_shown_at.is_some();
+
+    ui.horizontal(|ui| {
+        if ui.button("⏮").on_hover_text("First frame").clicked() {
+            commands::select_frame(app, 0);
+        }
+        if ui.button("⏴").on_hover_text("Previous frame").clicked() {
+            commands::select_frame(app, (current + frame_count - 1) % frame_count);
+        }
+        if ui
+            .add_enabled(
+                app.document.animation.is_animated(),
+                Button::new(if playing { "⏸" } else { "▶" }),
+            )
+            .on_hover_text(if playing { "Pause" } else { "Play" })
+            .clicked()
+        {
+            app.timeline.frame_shown_at = if playing { None } else { Some(now) };
+        }
+        if ui.button("⏵").on_hover_text("Next frame").clicked() {
+            commands::select_frame(app, (current + 1) % frame_count);
+        }
+        if ui.button("⏭").on_hover_text("Last frame").clicked() {
+            commands::select_frame(app, frame_count - 1);
+        }
+
+        ui.separator();
+
+        if ui.button("Add Frame").clicked() {
+            commands::add_frame(app);
+        }
+        if ui.button("Duplicate Frame").clicked() {
+            commands::duplicate_frame(app);
+        }
+        if ui
+            .add_enabled(
+                app.document.animation.is_animated(),
+                Button::new("Delete Frame"),
+            )
+            .clicked()
+        {
+            commands::delete_frame(app);
+        }
+
+        ui.separator();
+
+        // (Edited on copies, so the document can be checkpointed before they
+        // change)
+        let current = app.document.animation.current_frame;
+        let mut delay_ms = app.document.animation.frames[current].delay_ms;
+        let response = ui.add(
+            DragValue::new(&mut delay_ms)
+                .clamp_range(0..=60_000)
+                .prefix("Delay: ")
+                .suffix(" ms"),
+        );
+        if interaction_started(&response) {
+            app.document.end_merging();
+        }
+        if response.changed() {
+            app.document.checkpoint_merged("Frame Delay");
+            app.document.animation.frames[current].delay_ms = delay_ms;
+        }
+
+        let mut loop_count = app.document.animation.loop_count;
+        if interaction_started(&edit_loop_count(ui, &mut loop_count)) {
+            app.document.end_merging();
+        }
+        if loop_count != app.document.animation.loop_count {
+            app.document.checkpoint_merged("Loop Count");
+            app.document.animation.loop_count = loop_count;
+        }
+    });
+}
+
+/// Makes the onion skinning settings
+fn make_onion_skin_controls(app: &mut TrametesApp, ui: &mut Ui) {
+    let onion_skin = &mut app.timeline.onion_skin;
+
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut onion_skin.enabled, "Onion skin")
+            .on_hover_text("Show the frames around the current one faintly on top of it");
+
+        ui.add_enabled_ui(onion_skin.enabled, |ui| {
+            ui.add(
+                DragValue::new(&mut onion_skin.frames_before)
+                    .clamp_range(0..=10)
+                    .prefix("Before: "),
+            );
+            ui.add(
+                DragValue::new(&mut onion_skin.frames_after)
+                    .clamp_range(0..=10)
+                    .prefix("After: "),
+            );
+            ui.add(Slider::new(&mut onion_skin.opacity, 0.0..=1.0).text("Opacity"));
+        });
+    });
+}
+
+/// Makes the Timeline panel (across the bottom), for stepping through and
+/// editing the frames of an animation
+pub fn make_timeline_panel(app: &mut TrametesApp, ctx: &Context) {
+    if !app.windows.timeline {
+        // Don't keep playing where no one can stop it
+        app.timeline.frame_shown_at = None;
+        return;
+    }
+
+    advance_playback(app, ctx);
+    let now = ctx.input(|input| input.time);
+
+    TopBottomPanel::bottom("timeline_panel")
+        .resizable(true)
+        .show(ctx, |ui| {
+            make_frame_controls(app, ui, now);
+            make_onion_skin_controls(app, ui);
+
+            let mut selected = None;
+            ScrollArea::horizontal().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    for (index, frame) in app.document.animation.frames.iter().enumerate() {
+                        let label = format!("{}\n{} ms", index + 1, frame.delay_ms);
+                        if ui
+                            .selectable_label(index == app.document.animation.current_frame, label)
+                            .clicked()
+                        {
+                            selected = Some(index);
+                        }
+                    }
+                });
+            });
+            if let Some(index) = selected {
+                commands::select_frame(app, index);
+            }
+
+            ui.label(format!(
+                "Frame {} of {} ({:.2} seconds in total)",
+                app.document.animation.current_frame + 1,
+                app.document.animation.frames.len(),
+                app.document.animation.duration_ms() as f64 / 1000.0
+            ));
+        });
+}
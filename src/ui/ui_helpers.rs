@@ -1,7 +1,9 @@
-use egui::{pos2, Pos2, Rect};
-use native_dialog::{MessageDialog, MessageType};
+use egui::{pos2, ColorImage, DragValue, Pos2, Rect, Response, Ui};
 
-use crate::app::ImageTransformations;
+use crate::{
+    animation::LoopCount, color_management::ColorProfile, document::ImageTransformations,
+    pixel_buffer::PixelBuffer,
+};
 
 /// Makes a Rect with given (x, y) (top left corner) and width x height
 pub fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
@@ -28,7 +30,7 @@ pub fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
 /// - `image_transformations` - the transformations applied to the image
 /// - `image_size` - the dimensions (in pixels) of the image
 /// - `image_panel_bounds` - the bounding box (in egui "points") of the panel in
-/// the UI that contains the image (excluding the menu bar, control panel, etc.)
+///   the UI that contains the image (excluding the menu bar, control panel, etc.)
 pub fn image_to_screen_coords(
     image_pos: Pos2,
     image_transformations: &ImageTransformations,
@@ -72,7 +74,7 @@ pub fn image_to_screen_coords(
 /// - `image_transformations` - the transformations applied to the image
 /// - `image_size` - the dimensions (in pixels) of the image
 /// - `image_panel_bounds` - the bounding box (in egui "points") of the panel in
-/// the UI that contains the image (excluding the menu bar, control panel, etc.)
+///   the UI that contains the image (excluding the menu bar, control panel, etc.)
 pub fn screen_to_image_coords(
     screen_pos: Pos2,
     image_transformations: &ImageTransformations,
@@ -104,18 +106,46 @@ pub fn screen_to_image_coords(
     pos2(image_x, image_y)
 }
 
-/// Displays a popup message to the user
-// TODO this probably doesn't work on web, need web-specific workaround?
-pub fn message_popup(msg: &str, msg_type: MessageType) {
-    let title = match msg_type {
-        MessageType::Info => "Info",
-        MessageType::Warning => "Warning",
-        MessageType::Error => "Something went wrong",
-    };
-    MessageDialog::new()
-        .set_type(msg_type)
-        .set_title(title)
-        .set_text(msg)
-        .show_alert()
-        .unwrap(); // TODO handle errors here
+/// Converts an image to an egui image for display, converting from its color
+/// profile (if it has one) to sRGB, since the screen is assumed to be sRGB
+pub fn to_display_image(image: &PixelBuffer, color_profile: Option<&ColorProfile>) -> ColorImage {
+    let mut pixels = std::borrow::Cow::Borrowed(&image.pixels);
+    if let Some(profile) = color_profile.filter(|profile| !profile.is_srgb()) {
+        if let Err(err) = profile.convert_to_srgb(pixels.to_mut()) {
+            eprintln!("failed to convert image to sRGB for display: {err:?}");
+        }
+    }
+
+    ColorImage::from_rgba_unmultiplied([image.width, image.height], &pixels)
+}
+
+/// Shows controls for how many times an animation plays
+pub fn edit_loop_count(ui: &mut Ui, loop_count: &mut LoopCount) -> Response {
+    ui.horizontal(|ui| {
+        let mut forever = *loop_count == LoopCount::Infinite;
+        let mut response = ui.checkbox(&mut forever, "Loop forever");
+        if response.changed() {
+            *loop_count = if forever {
+                LoopCount::Infinite
+            } else {
+                LoopCount::Finite(1)
+            };
+        }
+
+        if let LoopCount::Finite(plays) = loop_count {
+            response |= ui.add(
+                DragValue::new(plays)
+                    .clamp_range(1..=u16::MAX)
+                    .prefix("Plays: "),
+            );
+        }
+        response
+    })
+    .inner
+}
+
+/// Whether the user just started interacting with a widget, so changes made
+/// with it shouldn't be merged with earlier ones in the undo history
+pub fn interaction_started(response: &Response) -> bool {
+    response.drag_started() || response.gained_focus() || response.clicked()
 }
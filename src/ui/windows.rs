@@ -1,14 +1,18 @@
-use eframe::Frame;
-use egui::{Context, Window};
+use egui::{
+    Button, CollapsingHeader, ComboBox, Context, Grid, RichText, ScrollArea, Slider, Ui, Window,
+};
 
-use crate::{tools::Tool, TrametesApp};
+use crate::{
+    color_mode::ColorMode, commands, layers::BlendMode, metadata::MetadataSummary, tools::Tool,
+    TrametesApp,
+};
 
-use super::rect;
+use super::{interaction_started, rect};
 
 /// Makes the draggable windows (Tools, Colors, History, Layers)
-pub fn make_draggable_windows(app: &mut TrametesApp, ctx: &Context, frame: &mut Frame) {
-    let width = frame.info().window_info.size.x;
-    let height = frame.info().window_info.size.y;
+pub fn make_draggable_windows(app: &mut TrametesApp, ctx: &Context) {
+    let width = ctx.screen_rect().width();
+    let height = ctx.screen_rect().height();
 
     // The Tools window
     Window::new("Tools")
@@ -49,29 +53,319 @@ pub fn make_draggable_windows(app: &mut TrametesApp, ctx: &Context, frame: &mut
             ui.allocate_space(ui.available_size());
         });
 
-    // The Workspaces window
-    Window::new("Workspaces")
+    // The History window
+    // (How many steps to undo (negative) or redo (positive) to get to the step
+    // the user clicked on)
+    let mut steps = 0;
+    Window::new("History")
         .resizable(true)
         .default_rect(rect(9999.0, 0.0, width * 0.12, height * 0.30)) // TODO not just hardcode 9999
         .open(&mut app.windows.history)
         .show(ctx, |ui| {
-            // TODO put stuff here
+            let history = &app.document.history;
+            ScrollArea::vertical().show(ui, |ui| {
+                // Each step is shown as the state of the image just after it
+                let undo_count = history.undo.len() as isize;
+                if ui
+                    .selectable_label(history.undo.is_empty(), "Original")
+                    .clicked()
+                {
+                    steps = -undo_count;
+                }
+                for (index, step) in history.undo.iter().enumerate() {
+                    let is_current = index + 1 == history.undo.len();
+                    if ui.selectable_label(is_current, &step.name).clicked() {
+                        steps = index as isize + 1 - undo_count;
+                    }
+                }
+                for (index, step) in history.redo.iter().rev().enumerate() {
+                    let label = RichText::new(&step.name).weak();
+                    if ui.selectable_label(false, label).clicked() {
+                        steps = index as isize + 1;
+                    }
+                }
+            });
 
             // Allow filling extra room with empty space (prevents automatic
             // shrinking after resizing)
             ui.allocate_space(ui.available_size());
         });
+    for _ in steps..0 {
+        commands::undo(app);
+    }
+    for _ in 0..steps {
+        commands::redo(app);
+    }
 
     // The Layers window
+    let mut layers_window_open = app.windows.layers;
     Window::new("Layers")
         .resizable(true)
         .default_rect(rect(9999.0, 9999.0, width * 0.12, width * 0.12)) // TODO not just hardcode 9999
-        .open(&mut app.windows.layers)
+        .open(&mut layers_window_open)
         .show(ctx, |ui| {
-            // TODO put stuff here
+            // Settings for the active layer (edited on copies, so the
+            // document can be checkpointed before they change)
+            let layer = app.document.image.active();
+            let mut name = layer.name.clone();
+            let mut opacity = layer.opacity;
+            let mut blend_mode = layer.blend_mode;
+
+            // (Each time a field is picked up again is a separate step)
+            let response = ui.text_edit_singleline(&mut name);
+            if interaction_started(&response) {
+                app.document.end_merging();
+            }
+            if response.changed() {
+                app.document.checkpoint_merged("Rename Layer");
+                app.document.image.active_mut().name = name;
+            }
+            let response = ui.add(Slider::new(&mut opacity, 0.0..=1.0).text("Opacity"));
+            if interaction_started(&response) {
+                app.document.end_merging();
+            }
+            if response.changed() {
+                app.document.checkpoint_merged("Layer Opacity");
+                app.document.image.active_mut().opacity = opacity;
+            }
+            ComboBox::from_label("Blend Mode")
+                .selected_text(blend_mode.to_string())
+                .show_ui(ui, |ui| {
+                    for option in BlendMode::ALL {
+                        if ui
+                            .selectable_value(&mut blend_mode, option, option.to_string())
+                            .changed()
+                        {
+                            app.document.checkpoint("Blend Mode");
+                            app.document.image.active_mut().blend_mode = blend_mode;
+                        }
+                    }
+                });
+
+            ui.separator();
+
+            // The layers themselves, top layer first
+            for index in (0..app.document.image.layers.len()).rev() {
+                ui.horizontal(|ui| {
+                    let mut visible = app.document.image.layers[index].visible;
+                    if ui.checkbox(&mut visible, "").changed() {
+                        app.document
+                            .checkpoint(if visible { "Show Layer" } else { "Hide Layer" });
+                        app.document.image.layers[index].visible = visible;
+                    }
+                    let is_active = index == app.document.image.active_layer;
+                    if ui
+                        .selectable_label(is_active, &app.document.image.layers[index].name)
+                        .clicked()
+                    {
+                        app.document.image.active_layer = index;
+                    }
+                });
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button("New").clicked() {
+                    commands::new_layer(app);
+                }
+                if ui.button("Duplicate").clicked() {
+                    commands::duplicate_layer(app);
+                }
+                if ui
+                    .add_enabled(app.document.image.layers.len() > 1, Button::new("Delete"))
+                    .clicked()
+                {
+                    commands::delete_layer(app);
+                }
+                if ui.button("⬆").on_hover_text("Move Up").clicked() {
+                    commands::move_layer_up(app);
+                }
+                if ui.button("⬇").on_hover_text("Move Down").clicked() {
+                    commands::move_layer_down(app);
+                }
+            });
 
             // Allow filling extra room with empty space (prevents automatic
             // shrinking after resizing)
             ui.allocate_space(ui.available_size());
         });
+    app.windows.layers = layers_window_open;
+
+    // The Settings window
+    Window::new("Settings")
+        .resizable(false)
+        .collapsible(false)
+        .open(&mut app.windows.settings)
+        .show(ctx, |ui| {
+            ui.checkbox(
+                &mut app.settings.blend_in_linear_light,
+                "Blend colors in linear light",
+            )
+            .on_hover_text(
+                "Mix colors the way light does when painting and resizing, which \
+                 avoids dark fringes between very different colors",
+            );
+        });
+
+    // The Image Properties window
+    let mut image_properties_open = app.windows.image_properties;
+    Window::new("Image Properties")
+        .resizable(true)
+        .collapsible(false)
+        .open(&mut image_properties_open)
+        .show(ctx, |ui| make_image_properties(app, ui));
+    app.windows.image_properties = image_properties_open;
+
+    // The Palette window (only for indexed images)
+    if let ColorMode::Indexed(palette) = &app.document.color_mode {
+        let mut palette_colors = palette.colors.clone();
+        let mut removed_index = None;
+
+        Window::new("Palette")
+            .resizable(true)
+            .default_rect(rect(9999.0, height * 0.35, width * 0.12, width * 0.12)) // TODO not just hardcode 9999
+            .open(&mut app.windows.palette)
+            .show(ctx, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for (index, color) in palette_colors.iter_mut().enumerate() {
+                        ui.color_edit_button_srgba_unmultiplied(color)
+                            .context_menu(|ui| {
+                                if ui.button("Use as Primary Color").clicked() {
+                                    app.colors.primary = *color;
+                                    ui.close_menu();
+                                }
+                                if ui.button("Remove").clicked() {
+                                    removed_index = Some(index);
+                                    ui.close_menu();
+                                }
+                            });
+                    }
+                });
+
+                ui.label(format!("{} colors", palette_colors.len()));
+                if ui.button("Add Primary Color").clicked() {
+                    palette_colors.push(app.colors.primary);
+                }
+
+                // Allow filling extra room with empty space (prevents automatic
+                // shrinking after resizing)
+                ui.allocate_space(ui.available_size());
+            });
+
+        // Apply any edits made to the palette
+        let original_colors = palette.colors.clone();
+        for (index, (original, edited)) in original_colors.iter().zip(&palette_colors).enumerate() {
+            if original != edited {
+                commands::set_palette_color(app, index, *edited);
+            }
+        }
+        for color in palette_colors.into_iter().skip(original_colors.len()) {
+            commands::add_palette_color(app, color);
+        }
+        if let Some(index) = removed_index {
+            commands::remove_palette_color(app, index);
+        }
+    }
+}
+
+/// Fills in the Image Properties window, showing the image's size, color
+/// information, and metadata
+fn make_image_properties(app: &mut TrametesApp, ui: &mut Ui) {
+    Grid::new("image properties").num_columns(2).show(ui, |ui| {
+        ui.label("File");
+        ui.label(match &app.document.path {
+            Some(path) => path.display().to_string(),
+            None => "(not saved)".to_string(),
+        });
+        ui.end_row();
+
+        ui.label("Size");
+        ui.label(format!(
+            "{} x {} pixels",
+            app.document.image.width, app.document.image.height
+        ));
+        ui.end_row();
+
+        ui.label("Layers");
+        ui.label(app.document.image.layers.len().to_string());
+        ui.end_row();
+
+        ui.label("Color mode");
+        ui.label(match &app.document.color_mode {
+            ColorMode::Rgba => "RGB".to_string(),
+            ColorMode::Grayscale => "Grayscale".to_string(),
+            ColorMode::Indexed(palette) => format!("Indexed ({} colors)", palette.colors.len()),
+        });
+        ui.end_row();
+
+        ui.label("Color profile");
+        ui.label(match &app.document.color_profile {
+            Some(profile) => profile.name.clone(),
+            None => "None (sRGB)".to_string(),
+        });
+        ui.end_row();
+    });
+
+    ui.separator();
+
+    if app.document.metadata.is_empty() {
+        ui.label("This image has no metadata");
+        return;
+    }
+
+    if app
+        .metadata_summary
+        .as_ref()
+        .is_none_or(|summary| summary.metadata != app.document.metadata)
+    {
+        app.metadata_summary = Some(MetadataSummary::new(&app.document.metadata));
+    }
+    let Some(summary) = &app.metadata_summary else {
+        return;
+    };
+    let (fields, has_gps) = (&summary.fields, summary.has_gps);
+
+    ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+        let mut groups: Vec<&str> = fields.iter().map(|field| field.group).collect();
+        groups.dedup();
+
+        for group in groups {
+            CollapsingHeader::new(group)
+                .default_open(true)
+                .show(ui, |ui| {
+                    Grid::new(group)
+                        .num_columns(2)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for field in fields.iter().filter(|field| field.group == group) {
+                                ui.label(&field.name);
+                                ui.label(&field.value);
+                                ui.end_row();
+                            }
+                        });
+                });
+        }
+
+        if let Some(xmp) = &app.document.metadata.xmp {
+            CollapsingHeader::new("XMP").show(ui, |ui| {
+                ui.monospace(xmp);
+            });
+        }
+    });
+
+    ui.separator();
+
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(has_gps, Button::new("Remove GPS Location"))
+            .on_hover_text("Remove where the image was taken, e.g. before publishing it")
+            .clicked()
+        {
+            commands::remove_gps_metadata(app);
+        }
+        if ui.button("Remove All Metadata").clicked() {
+            commands::remove_all_metadata(app);
+        }
+    });
 }
@@ -0,0 +1,310 @@
+//! The parts of the editor that need the browser on the web, where there's no
+//! file system, and file pickers and the clipboard don't block. Anything that
+//! finishes later is queued up as a [`Completion`] for the app to pick up on
+//! its next frame
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+};
+
+use js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, HtmlInputElement, Url};
+
+use crate::{
+    error::Error,
+    fileio::{self, FilePurpose, PickedFile},
+    openraster,
+    pixel_buffer::PixelBuffer,
+    project, psd,
+};
+
+/// Something the browser finished doing in the background
+#[derive(Debug)]
+pub enum Completion {
+    /// The user picked a file, and it has been read
+    FilePicked(FilePurpose, PickedFile),
+
+    /// An image was read from the clipboard
+    ImagePasted {
+        /// The pixels of the image
+        pixels: PixelBuffer,
+
+        /// Whether to paste it into a new image (rather than a new layer)
+        into_new_image: bool,
+    },
+
+    /// Something couldn't be done, with a message for the user
+    Failed(String),
+}
+
+/// How long to wait before revoking the URL of a downloaded file, in
+/// milliseconds (revoking it right away can cancel the download in some
+/// browsers)
+const DOWNLOAD_URL_LIFETIME_MS: i32 = 60_000;
+
+thread_local! {
+    /// The egui context, to repaint once something finishes (otherwise it
+    /// wouldn't show up until the user moves the mouse)
+    static CONTEXT: RefCell<Option<egui::Context>> = const { RefCell::new(None) };
+
+    /// Things that have finished, waiting for the app to act on them
+    static COMPLETIONS: RefCell<Vec<Completion>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Remembers the egui context, so finishing things in the background can wake
+/// up the app
+pub fn init(ctx: &egui::Context) {
+    CONTEXT.with(|context| *context.borrow_mut() = Some(ctx.clone()));
+}
+
+/// Queues something that finished for the app to act on
+fn complete(completion: Completion) {
+    COMPLETIONS.with(|completions| completions.borrow_mut().push(completion));
+    CONTEXT.with(|context| {
+        if let Some(ctx) = &*context.borrow() {
+            ctx.request_repaint();
+        }
+    });
+}
+
+/// Takes everything that has finished since the last time this was called
+pub fn take_completions() -> Vec<Completion> {
+    COMPLETIONS.with(|completions| std::mem::take(&mut *completions.borrow_mut()))
+}
+
+/// Describes an error thrown by JavaScript
+fn describe(err: JsValue) -> String {
+    match err.dyn_ref::<js_sys::Error>() {
+        Some(err) => err.message().into(),
+        None => err.as_string().unwrap_or_else(|| format!("{err:?}")),
+    }
+}
+
+impl From<JsValue> for Error {
+    fn from(err: JsValue) -> Self {
+        Self::Browser(describe(err))
+    }
+}
+
+/// The browser window's document
+fn document() -> Result<web_sys::Document, JsValue> {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| "there's no document".into())
+}
+
+/// Reads the contents of a file (or any other blob)
+async fn read_blob(blob: &Blob) -> Result<Vec<u8>, JsValue> {
+    let buffer = JsFuture::from(blob.array_buffer()).await?;
+    Ok(Uint8Array::new(&buffer).to_vec())
+}
+
+/// Calls a JavaScript method that returns a promise, and waits for it. Used for
+/// APIs that `web-sys` only has behind unstable flags (like the clipboard)
+async fn call_async(target: &JsValue, method: &str, args: &Array) -> Result<JsValue, JsValue> {
+    let function: Function = Reflect::get(target, &method.into())?.dyn_into()?;
+    let promise: Promise = Reflect::apply(&function, target, args)?.dyn_into()?;
+    JsFuture::from(promise).await
+}
+
+/// Shows the browser's file picker (using an `<input type="file">`), then
+/// reads the chosen file and queues it up for whatever it's for
+pub fn pick_file(purpose: FilePurpose) {
+    if let Err(err) = try_pick_file(purpose) {
+        complete(Completion::Failed(format!(
+            "Failed to show the file picker:\n{}",
+            describe(err)
+        )));
+    }
+}
+
+fn try_pick_file(purpose: FilePurpose) -> Result<(), JsValue> {
+    let input: HtmlInputElement = document()?.create_element("input")?.dyn_into()?;
+    input.set_type("file");
+    input.set_accept(&if purpose.is_icc_profile() {
+        ".icc,.icm".to_string()
+    } else {
+        let layered = [project::EXTENSION, openraster::EXTENSION]
+            .into_iter()
+            .chain(psd::EXTENSIONS);
+        let extensions: Vec<String> = layered.map(|extension| format!(".{extension}")).collect();
+        format!("image/*,{}", extensions.join(","))
+    });
+
+    // (The input isn't in the page, so the closure is the only thing keeping
+    // it alive until the user picks a file)
+    let picker = input.clone();
+    let on_change = Closure::once(move || {
+        let Some(file) = picker.files().and_then(|files| files.get(0)) else {
+            return;
+        };
+        wasm_bindgen_futures::spawn_local(async move {
+            match read_blob(&file).await {
+                Ok(bytes) => {
+                    let path = PathBuf::from(file.name());
+                    complete(Completion::FilePicked(purpose, PickedFile { path, bytes }));
+                }
+                Err(err) => complete(Completion::Failed(format!(
+                    "Failed to read {}:\n{}",
+                    file.name(),
+                    describe(err)
+                ))),
+            }
+        });
+    });
+    input.set_onchange(Some(on_change.as_ref().unchecked_ref()));
+    on_change.forget();
+
+    input.click();
+    Ok(())
+}
+
+/// Asks the user for a file name to save an image as (the extension deciding
+/// its format)
+pub fn prompt_file_name() -> Result<Option<PathBuf>, Error> {
+    let window = web_sys::window().ok_or_else(|| Error::Browser("there's no window".into()))?;
+    let name = window.prompt_with_message_and_default(
+        "Save as (the extension decides the format, like .png or .trametes):",
+        "Untitled.png",
+    )?;
+    Ok(name
+        .filter(|name| !name.trim().is_empty())
+        .map(PathBuf::from))
+}
+
+/// Makes a blob of bytes, with a MIME type
+fn blob(bytes: &[u8], mime_type: &str) -> Result<Blob, JsValue> {
+    let parts = Array::of1(&Uint8Array::from(bytes));
+    let mut options = BlobPropertyBag::new();
+    options.type_(mime_type);
+    Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+}
+
+/// Has the browser download a file (named after the path's file name)
+pub fn download(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let name = path
+        .file_name()
+        .map_or_else(|| "Untitled".into(), |name| name.to_string_lossy());
+    let url = Url::create_object_url_with_blob(&blob(bytes, "application/octet-stream")?)?;
+
+    // Some browsers only download from links that are in the page
+    let document = document()?;
+    let body = document
+        .body()
+        .ok_or_else(|| Error::Browser("there's no body".into()))?;
+    let link: HtmlAnchorElement = document.create_element("a")?.unchecked_into();
+    link.set_href(&url);
+    link.set_download(&name);
+    body.append_child(&link)?;
+    link.click();
+    body.remove_child(&link)?;
+
+    let window = web_sys::window().ok_or_else(|| Error::Browser("there's no window".into()))?;
+    let revoke = Closure::once_into_js(move || {
+        // (There's nothing to be done if this fails, the URL just lives until
+        // the page is closed)
+        let _ = Url::revoke_object_url(&url);
+    });
+    window.set_timeout_with_callback_and_timeout_and_arguments_0(
+        revoke.unchecked_ref(),
+        DOWNLOAD_URL_LIFETIME_MS,
+    )?;
+    Ok(())
+}
+
+/// The browser's asynchronous clipboard (`navigator.clipboard`)
+fn clipboard() -> Result<JsValue, JsValue> {
+    let window = web_sys::window().ok_or("there's no window")?;
+    let clipboard = Reflect::get(&window.navigator(), &"clipboard".into())?;
+    if clipboard.is_undefined() {
+        return Err("the clipboard isn't available (the page may not be secure)".into());
+    }
+    Ok(clipboard)
+}
+
+/// Copies an image into the clipboard (as a PNG) in the background
+pub fn copy_image(image: &PixelBuffer) {
+    let png = match fileio::encode_png(image) {
+        Ok(png) => png,
+        Err(err) => {
+            complete(Completion::Failed(format!(
+                "Failed to copy the image:\n{err}"
+            )));
+            return;
+        }
+    };
+
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Err(err) = write_png_to_clipboard(&png).await {
+            complete(Completion::Failed(format!(
+                "Failed to copy the image:\n{}",
+                describe(err)
+            )));
+        }
+    });
+}
+
+async fn write_png_to_clipboard(png: &[u8]) -> Result<(), JsValue> {
+    let window = web_sys::window().ok_or("there's no window")?;
+
+    let data = Object::new();
+    Reflect::set(&data, &"image/png".into(), &blob(png, "image/png")?.into())?;
+    let clipboard_item: Function = Reflect::get(&window, &"ClipboardItem".into())?.dyn_into()?;
+    let item = Reflect::construct(&clipboard_item, &Array::of1(&data))?;
+
+    call_async(&clipboard()?, "write", &Array::of1(&Array::of1(&item))).await?;
+    Ok(())
+}
+
+/// Reads an image from the clipboard in the background, then queues it up to
+/// be pasted
+pub fn paste_image(into_new_image: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        match read_image_from_clipboard().await {
+            Ok(pixels) => complete(Completion::ImagePasted {
+                pixels,
+                into_new_image,
+            }),
+            Err(err) => complete(Completion::Failed(format!(
+                "Failed to paste an image:\n{}",
+                describe(err)
+            ))),
+        }
+    });
+}
+
+async fn read_image_from_clipboard() -> Result<PixelBuffer, JsValue> {
+    let items: Array = call_async(&clipboard()?, "read", &Array::new())
+        .await?
+        .dyn_into()?;
+
+    for item in items.iter() {
+        let types: Array = Reflect::get(&item, &"types".into())?.dyn_into()?;
+        for mime_type in types.iter() {
+            if !mime_type
+                .as_string()
+                .is_some_and(|mime_type| mime_type.starts_with("image/"))
+            {
+                continue;
+            }
+
+            let blob: Blob = call_async(&item, "getType", &Array::of1(&mime_type))
+                .await?
+                .dyn_into()?;
+            let bytes = read_blob(&blob).await?;
+            return fileio::decode_image(&bytes).map_err(|err| err.to_string().into());
+        }
+    }
+
+    Err("there's no image in the clipboard".into())
+}
+
+/// Sets the title of the browser tab
+pub fn set_title(title: &str) {
+    if let Ok(document) = document() {
+        document.set_title(title);
+    }
+}
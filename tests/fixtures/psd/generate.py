@@ -0,0 +1,219 @@
+#!/usr/bin/env python3
+"""Generates the small Photoshop files used by the PSD reader's tests.
+
+They're written by hand (following Adobe's file format specification) so each
+one exercises specific parts of the format. Run from this directory:
+
+    python3 generate.py
+"""
+
+import struct
+import zlib
+
+
+def u8(v): return struct.pack(">B", v)
+def u16(v): return struct.pack(">H", v)
+def i16(v): return struct.pack(">h", v)
+def u32(v): return struct.pack(">I", v)
+def i32(v): return struct.pack(">i", v)
+def u64(v): return struct.pack(">Q", v)
+
+
+def length(data, psb):
+    return (u64 if psb else u32)(len(data)) + data
+
+
+def packbits(row):
+    """PackBits, using repeat runs for repeated bytes and literals otherwise"""
+    out = b""
+    i = 0
+    while i < len(row):
+        run = 1
+        while i + run < len(row) and row[i + run] == row[i] and run < 128:
+            run += 1
+        if run > 1:
+            out += u8(257 - run) + u8(row[i])
+            i += run
+        else:
+            out += u8(0) + u8(row[i])
+            i += 1
+    return out
+
+
+def rle(rows, psb):
+    packed = [packbits(row) for row in rows]
+    counts = b"".join((u32 if psb else u16)(len(p)) for p in packed)
+    return u16(1) + counts + b"".join(packed)
+
+
+def raw(rows):
+    return u16(0) + b"".join(rows)
+
+
+def deflate(rows):
+    return u16(2) + zlib.compress(b"".join(rows))
+
+
+def deflate_predicted_16(rows):
+    out = b""
+    for row in rows:
+        values = struct.unpack(">%dH" % (len(row) // 2), row)
+        previous = 0
+        for value in values:
+            out += u16((value - previous) & 0xFFFF)
+            previous = value
+    return u16(3) + zlib.compress(out)
+
+
+def rows_of(plane, width):
+    return [bytes(plane[i:i + width]) for i in range(0, len(plane), width)]
+
+
+def header(channels, width, height, depth, mode, psb=False):
+    return b"8BPS" + u16(2 if psb else 1) + b"\0" * 6 + u16(channels) + u32(height) + u32(width) + u16(depth) + u16(mode)
+
+
+def pascal(name, padding):
+    data = u8(len(name)) + name
+    return data + b"\0" * ((padding - len(data) % padding) % padding)
+
+
+def tagged(key, data):
+    return b"8BIM" + key + u32(len(data)) + data
+
+
+def luni(name):
+    encoded = name.encode("utf-16-be")
+    return tagged(b"luni", u32(len(name)) + encoded + b"\0" * (len(encoded) % 4))
+
+
+def layer(name, rect, channels, blend=b"norm", opacity=255, hidden=False, extra=b""):
+    """A layer record plus its channel data. `channels` maps IDs to encoded data"""
+    top, left, bottom, right = rect
+    record = i32(top) + i32(left) + i32(bottom) + i32(right) + u16(len(channels))
+    for channel_id, data in channels:
+        record += i16(channel_id) + u32(len(data))
+    record += b"8BIM" + blend + u8(opacity) + u8(0) + u8(0x0A if hidden else 0x08) + u8(0)
+    extra_data = u32(0) + u32(0) + pascal(name.encode("ascii", "replace"), 4) + extra
+    record += u32(len(extra_data)) + extra_data
+    return record, b"".join(data for _, data in channels)
+
+
+def layer_info(layers, merged_alpha):
+    count = -len(layers) if merged_alpha else len(layers)
+    data = i16(count) + b"".join(r for r, _ in layers) + b"".join(d for _, d in layers)
+    if len(data) % 2:
+        data += b"\0"
+    return data
+
+
+def layers_psd():
+    """RGB, 4x3, with offsets, opacity, blend modes, visibility, groups,
+    Unicode names, and every compression method"""
+    w, h = 4, 3
+    bg = [[(x * 60, y * 80, 100) for x in range(w)] for y in range(h)]
+    background = layer("Background", (0, 0, h, w), [
+        (c, raw(rows_of([bg[y][x][c] for y in range(h) for x in range(w)], w))) for c in range(3)
+    ])
+
+    red = [255, 255, 255, 255]
+    green = [0, 0, 0, 0]
+    alpha = [255, 128, 255, 0]
+    square = layer("Red square", (1, 1, 3, 3), [
+        (-1, rle(rows_of(alpha, 2), False)),
+        (0, rle(rows_of(red, 2), False)),
+        (1, rle(rows_of(green, 2), False)),
+        (2, rle(rows_of(green, 2), False)),
+    ], blend=b"mul ", opacity=128, extra=luni("Red square ✓"))
+
+    group_end = layer("</Layer group>", (0, 0, 0, 0), [], hidden=True,
+                      extra=tagged(b"lsct", u32(3)))
+    grouped = layer("Inside hidden group", (2, 3, 3, 4), [
+        (c, raw([bytes([10 * (c + 1)])])) for c in range(3)
+    ])
+    group_start = layer("Group", (0, 0, 0, 0), [], blend=b"pass", hidden=True,
+                        extra=tagged(b"lsct", u32(1) + b"8BIMpass"))
+
+    hidden = layer("Hidden layer", (-1, -2, 0, -1), [
+        (c, deflate([bytes([200])])) for c in range(3)
+    ], blend=b"scrn", hidden=True)
+
+    empty = layer("Empty", (0, 0, 0, 0), [(c, raw([])) for c in (-1, 0, 1, 2)])
+
+    info = layer_info([background, square, group_end, grouped, group_start, hidden, empty], True)
+    layer_and_mask = u32(len(info)) + info + u32(0)
+
+    merged = [bg[y][x][c] for c in range(3) for y in range(h) for x in range(w)] + [255] * (w * h)
+    return (
+        header(4, w, h, 8, 3)
+        + u32(0)
+        + u32(0)
+        + u32(len(layer_and_mask)) + layer_and_mask
+        + rle(rows_of(merged, w), False)
+    )
+
+
+def fallback_psd():
+    """RGB, 2x2, with a layer using a blend mode we don't support (linear
+    dodge), so only the merged image can be used"""
+    w, h = 2, 2
+    dodge = layer("Linear dodge", (0, 0, h, w), [
+        (c, raw(rows_of([1, 2, 3, 4], w))) for c in range(3)
+    ], blend=b"lddg")
+    info = layer_info([dodge], False)
+    layer_and_mask = u32(len(info)) + info + u32(0)
+
+    merged = [10, 20, 30, 40] + [50, 60, 70, 80] + [90, 100, 110, 120]
+    return (
+        header(3, w, h, 8, 3)
+        + u32(0)
+        + u32(0)
+        + u32(len(layer_and_mask)) + layer_and_mask
+        + raw(rows_of(merged, w))
+    )
+
+
+def gray16_psb():
+    """A flat 16-bit grayscale large document (PSB), compressed with zip and
+    prediction"""
+    w, h = 3, 2
+    values = [0x0000, 0x1234, 0xFFFF, 0x8000, 0x7FFF, 0x4100]
+    merged = b"".join(u16(v) for v in values)
+    return (
+        header(1, w, h, 16, 1, psb=True)
+        + u32(0)
+        + u32(0)
+        + u64(0)
+        + deflate_predicted_16([merged[i:i + w * 2] for i in range(0, len(merged), w * 2)])
+    )
+
+
+def resource(resource_id, data):
+    return b"8BIM" + u16(resource_id) + pascal(b"", 2) + u32(len(data)) + data + b"\0" * (len(data) % 2)
+
+
+def indexed_psd():
+    """A flat indexed image with a 4 color palette, one of them transparent"""
+    palette = [(255, 0, 0), (0, 255, 0), (0, 0, 255), (9, 9, 9)]
+    color_data = bytearray(768)
+    for index, color in enumerate(palette):
+        for channel in range(3):
+            color_data[channel * 256 + index] = color[channel]
+    resources = resource(1046, u16(4)) + resource(1047, u16(3))
+    return (
+        header(1, 3, 1, 8, 2)
+        + u32(len(color_data)) + bytes(color_data)
+        + u32(len(resources)) + resources
+        + u32(0)
+        + rle([bytes([0, 1, 3])], False)
+    )
+
+
+for name, data in [
+    ("layers.psd", layers_psd()),
+    ("fallback.psd", fallback_psd()),
+    ("gray16.psb", gray16_psb()),
+    ("indexed.psd", indexed_psd()),
+]:
+    with open(name, "wb") as f:
+        f.write(data)